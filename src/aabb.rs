@@ -0,0 +1,150 @@
+use std::f64;
+use point::Point;
+
+/// An axis aligned box in `N` dimensions.  `BBox` is `Aabb<3>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Aabb<const N: usize> {
+    pub min: Point<N>,
+    pub max: Point<N>
+}
+
+impl<const N: usize> Aabb<N> {
+    /// A box containing nothing, with `min` at positive and `max` at
+    /// negative infinity.  Its union with any box is that box, so bounds can
+    /// be accumulated starting from it.
+    pub fn empty() -> Aabb<N> {
+        Aabb {
+            min: Point::splat(f64::INFINITY),
+            max: Point::splat(f64::NEG_INFINITY)
+        }
+    }
+
+    /// The zero-sized box at `p`.
+    pub fn from_point(p: Point<N>) -> Aabb<N> {
+        Aabb {
+            min: p,
+            max: p
+        }
+    }
+
+    /// The smallest box containing all of `points`, or `Aabb::empty()` if
+    /// there are none.
+    pub fn from_points<I>(points: I) -> Aabb<N> where I: IntoIterator<Item=Point<N>> {
+        points.into_iter().fold(Aabb::empty(), |b, p| b.union(&Aabb::from_point(p)))
+    }
+
+    /// Whether the box contains nothing, as `Aabb::empty()` does.  A box
+    /// around a single point is not empty.
+    pub fn is_empty(&self) -> bool {
+        !(0..N).all(|i| self.min.coords[i] <= self.max.coords[i])
+    }
+
+    pub fn overlaps(&self, other: &Aabb<N>) -> bool {
+        (0..N).all(|i| self.max.coords[i] >= other.min.coords[i] && self.min.coords[i] <= other.max.coords[i])
+    }
+
+    /// The region both boxes cover, or `None` if they are disjoint.  Boxes
+    /// which only touch, and so `overlaps` each other, meet in a flat box.
+    pub fn intersection(&self, other: &Aabb<N>) -> Option<Aabb<N>> {
+        if !self.overlaps(other) {
+            return None;
+        }
+        Some(Aabb {
+            min: self.min.zip_map(&other.min, f64::max),
+            max: self.max.zip_map(&other.max, f64::min)
+        })
+    }
+
+    pub fn inside(&self, p: &Point<N>) -> bool {
+        (0..N).all(|i| p.coords[i] >= self.min.coords[i] && p.coords[i] <= self.max.coords[i])
+    }
+
+    pub fn contains(&self, other: &Aabb<N>) -> bool {
+        (0..N).all(|i| other.min.coords[i] >= self.min.coords[i] && other.max.coords[i] <= self.max.coords[i])
+    }
+
+    /// Pad bounding box by a constant factor.
+    pub fn expand(&self, delta: f64) -> Aabb<N> {
+        Aabb {
+            min: self.min - delta,
+            max: self.max + delta
+        }
+    }
+
+    pub fn union(&self, other: &Aabb<N>) -> Aabb<N> {
+        Aabb {
+            min: self.min.zip_map(&other.min, f64::min),
+            max: self.max.zip_map(&other.max, f64::max)
+        }
+    }
+
+    pub fn len(&self) -> Point<N> {
+        self.max - self.min
+    }
+
+    /// The product of the box's extents: its area in two dimensions, its
+    /// volume in three.
+    pub fn volume(&self) -> f64 {
+        (0..N).map(|i| self.max.coords[i] - self.min.coords[i]).product()
+    }
+
+    /// The squared distance from `p` to the nearest point of the box, or
+    /// zero if `p` is inside it.
+    pub fn distance_squared_to_point(&self, p: &Point<N>) -> f64 {
+        (0..N).map(|i| {
+            let d = (self.min.coords[i] - p.coords[i]).max(0.0).max(p.coords[i] - self.max.coords[i]);
+            d * d
+        }).sum()
+    }
+
+    /// The squared distance between the nearest points of the two boxes, or
+    /// zero if they overlap.
+    pub fn min_distance_squared(&self, other: &Aabb<N>) -> f64 {
+        (0..N).map(|i| {
+            let d = (self.min.coords[i] - other.max.coords[i]).max(0.0).max(other.min.coords[i] - self.max.coords[i]);
+            d * d
+        }).sum()
+    }
+
+    /// The sum of one edge length along each axis.  In three dimensions this
+    /// is a quarter of the total over all twelve edges.  This is the margin
+    /// R* splits minimise.
+    pub fn margin(&self) -> f64 {
+        (0..N).map(|i| self.max.coords[i] - self.min.coords[i]).sum()
+    }
+
+    /// The total size of the box's faces: the area of its six faces in
+    /// three dimensions, its perimeter in two.  This is what the Surface
+    /// Area Heuristic weighs, as the chance of a random ray hitting a box
+    /// grows with it.
+    pub fn surface_area(&self) -> f64 {
+        let len = self.len();
+        2.0 * (0..N).map(|i| (0..N).filter(|&j| j != i).map(|j| len.coords[j]).product::<f64>()).sum::<f64>()
+    }
+
+    /// The point halfway between `min` and `max`.
+    pub fn center(&self) -> Point<N> {
+        (self.min + self.max).scale(0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::point::Point;
+    use super::Aabb;
+
+    #[test]
+    fn test_rectangles() {
+        let a = Aabb { min: Point::xy(0.0, 0.0), max: Point::xy(4.0, 2.0) };
+        let b = Aabb { min: Point::xy(3.0, 1.0), max: Point::xy(5.0, 5.0) };
+        assert_eq!((a.volume(), a.margin(), a.center()), (8.0, 6.0, Point::xy(2.0, 1.0)));
+        assert_eq!(a.surface_area(), 12.0);
+        assert!(a.overlaps(&b) && !a.contains(&b));
+        assert_eq!(a.intersection(&b).unwrap().volume(), 1.0);
+        let union = a.union(&b);
+        assert_eq!((union.min, union.max), (Point::xy(0.0, 0.0), Point::xy(5.0, 5.0)));
+        assert_eq!(b.distance_squared_to_point(&Point::xy(0.0, 0.0)), 9.0 + 1.0);
+        assert!(Aabb::<2>::empty().is_empty() && Aabb::<2>::from_points(None).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use std::mem;
+use std::ops::{Index, IndexMut};
+
+use aabb::Aabb;
+use super::{Mbr, RTreeNode, NodeStorage, LeafItem, leaf_heap_capacity};
+
+/// The index of a node in its tree's `Arena`.
+pub type NodeId = usize;
+
+/// Every node of a tree, stored in one vector and linked to their children
+/// by index.  The slots of nodes which were split away or dissolved are
+/// remembered and reused before the vector grows, and the tree is freed
+/// all at once.
+///
+/// Free slots hold empty leaves, so scans over every slot, rather than
+/// down from the root, see only the live items.
+#[derive(Clone)]
+pub(crate) struct Arena<T, const N: usize> where T: Mbr<N> {
+    slots: Vec<RTreeNode<T, N>>,
+    free: Vec<NodeId>,
+}
+
+impl<T, const N: usize> Arena<T, N> where T: Mbr<N> {
+    pub fn new() -> Arena<T, N> {
+        Arena {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Store `node` in a free slot, or a new one if there are none.
+    pub fn alloc(&mut self, node: RTreeNode<T, N>) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.slots[id] = node;
+                id
+            },
+            None => {
+                self.slots.push(node);
+                self.slots.len() - 1
+            },
+        }
+    }
+
+    /// Store a node holding `storage`, whose bounding box is `bbox`.
+    pub fn add(&mut self, bbox: Aabb<N>, storage: NodeStorage<T, N>) -> NodeId {
+        let count = self.deep_len(&storage);
+        self.alloc(RTreeNode { bbox, count, storage })
+    }
+
+    /// Move the node `id` out of the arena, freeing its slot.  Its children
+    /// stay where they are.
+    pub fn take(&mut self, id: NodeId) -> RTreeNode<T, N> {
+        self.free.push(id);
+        mem::replace(&mut self.slots[id], RTreeNode::vacant())
+    }
+
+    /// Make room for `additional` more nodes, beyond the free slots.
+    pub fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional.saturating_sub(self.free.len()));
+    }
+
+    /// The number of nodes the arena can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Move the nodes in use to the front of the arena, keeping their
+    /// order, and release every free slot and spare vector capacity.
+    /// Node ids change, so the new id of `root` is returned.
+    pub fn compact(&mut self, root: NodeId) -> NodeId {
+        let mut renumbered = vec![0; self.slots.len()];
+        let mut live = vec![true; self.slots.len()];
+        for &id in self.free.iter() {
+            live[id] = false;
+        }
+        let mut next = 0;
+        for (old, &is_live) in live.iter().enumerate() {
+            if is_live {
+                renumbered[old] = next;
+                next += 1;
+            }
+        }
+
+        let mut old = 0;
+        self.slots.retain(|_| {
+            old += 1;
+            live[old - 1]
+        });
+        for node in self.slots.iter_mut() {
+            match node.storage {
+                NodeStorage::Interior(ref mut children) => {
+                    for child in children.iter_mut() {
+                        *child = renumbered[*child];
+                    }
+                    children.shrink_to_fit();
+                },
+                NodeStorage::Leaf(ref mut items) => items.shrink_to_fit(),
+            }
+        }
+        self.slots.shrink_to_fit();
+        self.free = Vec::new();
+        renumbered[root]
+    }
+
+    /// The bytes allocated for the slots, the free list, and every node's
+    /// children or leaf entries.
+    pub fn heap_size_bytes(&self) -> usize {
+        let nodes: usize = self.slots.iter().map(|node| match node.storage {
+            NodeStorage::Interior(ref children) => children.capacity() * mem::size_of::<NodeId>(),
+            NodeStorage::Leaf(ref items) => leaf_heap_capacity(items) * mem::size_of::<LeafItem<T, N>>(),
+        }).sum();
+        self.slots.capacity() * mem::size_of::<RTreeNode<T, N>>() + self.free.capacity() * mem::size_of::<NodeId>() + nodes
+    }
+
+    /// Drop every node.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+    }
+
+    /// The number of nodes in use.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// The nodes `ids` refers to.
+    pub fn children<'a>(&'a self, ids: &'a [NodeId]) -> impl Iterator<Item=&'a RTreeNode<T, N>> + 'a {
+        ids.iter().map(move |&id| &self.slots[id])
+    }
+
+    /// The children of the node `id`, or nothing if it is a leaf.
+    pub fn child_ids(&self, id: NodeId) -> &[NodeId] {
+        match self.slots[id].storage {
+            NodeStorage::Interior(ref children) => children,
+            NodeStorage::Leaf(_) => &[],
+        }
+    }
+
+    /// The number of items below `storage`, from the children's cached
+    /// counts.
+    pub fn deep_len(&self, storage: &NodeStorage<T, N>) -> usize {
+        match *storage {
+            NodeStorage::Interior(ref children) => children.iter().map(|&c| self.slots[c].count).sum(),
+            NodeStorage::Leaf(ref items) => items.len(),
+        }
+    }
+
+    /// Every slot, free ones included, in storage order.
+    pub fn slots(&self) -> &[RTreeNode<T, N>] {
+        &self.slots
+    }
+
+    pub fn slots_mut(&mut self) -> &mut [RTreeNode<T, N>] {
+        &mut self.slots
+    }
+
+    pub fn into_slots(self) -> Vec<RTreeNode<T, N>> {
+        self.slots
+    }
+}
+
+impl<T, const N: usize> Index<NodeId> for Arena<T, N> where T: Mbr<N> {
+    type Output = RTreeNode<T, N>;
+
+    fn index(&self, id: NodeId) -> &RTreeNode<T, N> {
+        &self.slots[id]
+    }
+}
+
+impl<T, const N: usize> IndexMut<NodeId> for Arena<T, N> where T: Mbr<N> {
+    fn index_mut(&mut self, id: NodeId) -> &mut RTreeNode<T, N> {
+        &mut self.slots[id]
+    }
+}
@@ -2,93 +2,53 @@
 
 use std::f64;
 use ::ray::Ray;
+use transform::Transform;
+use aabb::Aabb;
+use point::Point;
 use vec3::Vec3;
 
-#[derive(Clone, Copy)]
-pub struct BBox {
-    pub min: Vec3,
-    pub max: Vec3
-}
+pub type BBox = Aabb<3>;
 
 pub trait BoundingBox {
     fn bounding_box(&self) -> BBox;
 }
 
-/// Given a bounding box and a point, compute and return a new BBox that
+/// Given a bounding box and a point, compute and return a new box that
 /// encompasses the point and the space the original box encompassed.
-pub fn union_point(b: &BBox, p: &Vec3) -> BBox {
-    BBox {
-        min: Vec3 {
-            x: b.min.x.min(p.x),
-            y: b.min.y.min(p.y),
-            z: b.min.z.min(p.z)
-        },
-        max: Vec3 {
-            x: b.max.x.max(p.x),
-            y: b.max.y.max(p.y),
-            z: b.max.z.max(p.z)
-        }
-    }
+pub fn union_point<const N: usize>(b: &Aabb<N>, p: &Point<N>) -> Aabb<N> {
+    b.union(&Aabb::from_point(*p))
 }
 
-/// Given two points, compute and return a new BBox that encompasses both points
-pub fn union_points(p1: &Vec3, p2: &Vec3) -> BBox {
-    BBox {
-        min: Vec3 {
-            x: p1.x.min(p2.x),
-            y: p1.y.min(p2.y),
-            z: p1.z.min(p2.z)
-        },
-        max: Vec3 {
-            x: p1.x.max(p2.x),
-            y: p1.y.max(p2.y),
-            z: p1.z.max(p2.z)
-        }
+/// Given two points, compute and return a new box that encompasses both points
+pub fn union_points<const N: usize>(p1: &Point<N>, p2: &Point<N>) -> Aabb<N> {
+    Aabb {
+        min: p1.zip_map(p2, f64::min),
+        max: p1.zip_map(p2, f64::max)
     }
 }
 
-/// Given two bounding boxes, compute and return a new BBox that encompasses
+/// Given two bounding boxes, compute and return a new box that encompasses
 /// both spaces the original two boxes encompassed.
-pub fn union_bbox(b1: &BBox, b2: &BBox) -> BBox {
-    BBox {
-        min: Vec3 {
-            x: b1.min.x.min(b2.min.x),
-            y: b1.min.y.min(b2.min.y),
-            z: b1.min.z.min(b2.min.z)
-        },
-        max: Vec3 {
-            x: b1.max.x.max(b2.max.x),
-            y: b1.max.y.max(b2.max.y),
-            z: b1.max.z.max(b2.max.z)
-        }
-    }
+pub fn union_bbox<const N: usize>(b1: &Aabb<N>, b2: &Aabb<N>) -> Aabb<N> {
+    b1.union(b2)
 }
 
 /// Given a vector of prims, compute and return a new BBox that encompasses
 /// all finite prims (ie. not including planes) in that vector.
-pub fn get_bounds_from_objects(prims: &Vec<Box<BoundingBox+Send+Sync>>) -> BBox {
-    let mut max = Vec3 { x: f64::MIN, y: f64::MIN, z: f64::MIN };
-    let mut min = Vec3 { x: f64::MAX, y: f64::MAX, z: f64::MAX };
-
-    for prim in prims.iter() {
-        let bounding = prim.bounding_box();
-        min.x = min.x.min(bounding.min.x);
-        min.y = min.y.min(bounding.min.y);
-        min.z = min.z.min(bounding.min.z);
-
-        max.x = max.x.max(bounding.max.x);
-        max.y = max.y.max(bounding.max.y);
-        max.z = max.z.max(bounding.max.z);
-    }
-
-    BBox {
-        min: min,
-        max: max
-    }
+pub fn get_bounds_from_objects(prims: &Vec<Box<dyn BoundingBox+Send+Sync>>) -> BBox {
+    prims.iter().fold(BBox::empty(), |bounds, prim| bounds.union(&prim.bounding_box()))
 }
 
-impl BBox {
+impl Aabb<3> {
     pub fn intersects(&self, ray: &Ray) -> bool {
+        self.intersect_ray_t(ray).is_some()
+    }
+
+    /// The ray parameters at which `ray` enters and leaves the box, or
+    /// `None` if it misses.  The entry is negative when the ray starts
+    /// inside the box; boxes entirely outside the ray's `min_t..max_t`
+    /// interval, such as those behind its origin, are misses.
+    pub fn intersect_ray_t(&self, ray: &Ray) -> Option<(f64, f64)> {
         // Using ray.inverse_dir is an optimisation. Normally, for simplicity we would do
         //
         //     let d = -ray.direction;
@@ -106,86 +66,43 @@ impl BBox {
         //
         // See: https://truesculpt.googlecode.com/hg-history/Release%25200.8/Doc/ray_box_intersect.pdf
 
-        let o = ray.origin;
-
-        let (min_bound, max_bound) = if ray.signs[0] {
-            (self.min, self.max)
-        } else {
-            (self.max, self.min)
-        };
-        let mut t_min = (min_bound.x - o.x) * ray.inverse_dir.x;
-        let mut t_max = (max_bound.x - o.x) * ray.inverse_dir.x;
-
-        let (min_y_bound, max_y_bound) = if ray.signs[1] {
-            (self.min, self.max)
-        } else {
-            (self.max, self.min)
-        };
-        let ty_min = (min_y_bound.y - o.y) * ray.inverse_dir.y;
-        let ty_max = (max_y_bound.y - o.y) * ray.inverse_dir.y;
-
-        if t_min > ty_max || ty_min > t_max {
-            return false
+        // When a direction component is zero its inverse is infinite, and a
+        // ray starting exactly on that axis' face gives 0 * inf = NaN.  The
+        // comparisons below are false for NaN, so such a slab leaves the
+        // running bounds alone and the ray counts as inside it, matching the
+        // inclusive `overlaps`.  See Williams et al., "An Efficient and
+        // Robust Ray-Box Intersection Algorithm".  NaN rays would slip
+        // through the same way, so they are turned away first.
+        if ray.has_nan() {
+            return None;
         }
-        if ty_min > t_min {
-            t_min = ty_min;
-        }
-        if ty_max < t_max {
-            t_max = ty_max;
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let inverse = [ray.inverse_dir.x, ray.inverse_dir.y, ray.inverse_dir.z];
+        let mins = [self.min.x, self.min.y, self.min.z];
+        let maxs = [self.max.x, self.max.y, self.max.z];
+
+        let mut t_min = f64::NEG_INFINITY;
+        let mut t_max = f64::INFINITY;
+        for axis in 0..3 {
+            let (near, far) = if ray.signs[axis] {
+                (mins[axis], maxs[axis])
+            } else {
+                (maxs[axis], mins[axis])
+            };
+            let t0 = (near - origin[axis]) * inverse[axis];
+            let t1 = (far - origin[axis]) * inverse[axis];
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
         }
 
-        let (min_z_bound, max_z_bound) = if ray.signs[2] {
-            (self.min, self.max)
+        if t_min <= t_max && t_max > ray.min_t && t_min <= ray.max_t && t_min < f64::INFINITY {
+            Some((t_min, t_max))
         } else {
-            (self.max, self.min)
-        };
-        let tz_min = (min_z_bound.z - o.z) * ray.inverse_dir.z;
-        let tz_max = (max_z_bound.z - o.z) * ray.inverse_dir.z;
-
-        if t_min > tz_max || tz_min > t_max {
-            return false
-        }
-        if tz_min > t_min {
-            t_min = tz_min;
-        }
-        if tz_max < t_max {
-            t_max = tz_max;
-        }
-
-        // tmin < t1 && tmax > t0
-        t_min < ::std::f64::INFINITY && t_max > 0.0
-    }
-
-    pub fn overlaps(&self, other: &BBox) -> bool {
-        let x = self.max.x >= other.min.x && self.min.x <= other.max.x;
-        let y = self.max.y >= other.min.y && self.min.y <= other.max.y;
-        let z = self.max.z >= other.min.z && self.min.z <= other.max.z;
-
-        x && y && z
-    }
-
-    pub fn inside(&self, p: &Vec3) -> bool {
-        p.x >= self.min.x && p.x <= self.max.x &&
-        p.y >= self.min.y && p.y <= self.max.y &&
-        p.z >= self.min.z && p.z <= self.max.z
-    }
-
-    pub fn contains(&self, other: &BBox) -> bool {
-        other.min.x >= self.min.x &&
-        other.min.y >= self.min.y &&
-        other.min.z >= self.min.z &&
-        other.max.x <= self.max.x &&
-        other.max.y <= self.max.y &&
-        other.max.z <= self.max.z
-    }
-
-    /// Pad bounding box by a constant factor.
-    pub fn expand(&self, delta: f64) -> BBox {
-        let delta_vec3 = Vec3 { x: delta, y: delta, z: delta };
-
-        BBox {
-            min: self.min - delta_vec3,
-            max: self.max + delta_vec3
+            None
         }
     }
 
@@ -204,25 +121,19 @@ impl BBox {
     /// Interpolate between corners of the box.
     pub fn lerp(&self, t_x: f64, t_y: f64, t_z: f64) -> Vec3 {
         let diag = self.max - self.min;
-        Vec3 {
-            x: self.min.x + diag.x * t_x,
-            y: self.min.y + diag.y * t_y,
-            z: self.min.z + diag.z * t_z
-        }
+        Vec3::xyz(
+            self.min.x + diag.x * t_x,
+            self.min.y + diag.y * t_y,
+            self.min.z + diag.z * t_z)
     }
 
     /// Offset from minimum corner point
     pub fn offset(&self, offset: &Vec3) -> Vec3 {
         let diag = self.max - self.min;
-        Vec3 {
-            x: (offset.x - self.min.x) / diag.x,
-            y: (offset.y - self.min.y) / diag.y,
-            z: (offset.z - self.min.z) / diag.z
-        }
-    }
-
-    pub fn union(&self, other: &BBox) -> BBox {
-        union_bbox(self, other)
+        Vec3::xyz(
+            (offset.x - self.min.x) / diag.x,
+            (offset.y - self.min.y) / diag.y,
+            (offset.z - self.min.z) / diag.z)
     }
 
     pub fn x_len(&self) -> f64 {
@@ -237,13 +148,148 @@ impl BBox {
         self.max.z - self.min.z
     }
 
-    pub fn len(&self) -> Vec3 {
-        self.max - self.min
+    /// The smallest box around this one once moved by `transform`, found
+    /// by transforming all eight corners.  Rotations make it larger than the
+    /// transformed contents need.
+    pub fn transformed(&self, transform: &Transform) -> BBox {
+        BBox::from_points((0..8).map(|i| {
+            transform.transform_point(&self.lerp((i & 1) as f64, ((i >> 1) & 1) as f64, ((i >> 2) & 1) as f64))
+        }))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::f64;
+    use std::f64::consts::FRAC_PI_4;
+
+    use ::ray::Ray;
+    use ::transform::Transform;
+    use ::vec3::Vec3;
+    use super::BBox;
+
+    #[test]
+    fn test_intersect_ray_t() {
+        let bbox = BBox {
+            min: Vec3::xyz(2.0, -1.0, -1.0),
+            max: Vec3::xyz(4.0, 1.0, 1.0),
+        };
+
+        let ray = Ray::new(Vec3::zero(), Vec3::xyz(2.0, 0.1, 0.1));
+        assert_eq!(bbox.intersect_ray_t(&ray), Some((1.0, 2.0)));
+        assert!(bbox.intersects(&ray));
+
+        let inside = Ray::new(Vec3::xyz(3.0, 0.0, 0.0), Vec3::xyz(1.0, 0.1, 0.1));
+        assert_eq!(bbox.intersect_ray_t(&inside), Some((-1.0, 1.0)));
+
+        let behind = Ray::new(Vec3::xyz(5.0, 0.0, 0.0), Vec3::xyz(1.0, 0.1, 0.1));
+        assert_eq!(bbox.intersect_ray_t(&behind), None);
+        assert!(!bbox.intersects(&behind));
     }
 
-    pub fn volume(&self) -> f64 {
-        (self.max.x - self.min.x) * 
-        (self.max.y - self.min.y) * 
-        (self.max.z - self.min.z)
+    #[test]
+    fn test_transformed() {
+        let bbox = BBox {
+            min: Vec3::xyz(-1.0, -1.0, 0.0),
+            max: Vec3::xyz(1.0, 1.0, 2.0),
+        };
+        let moved = bbox.transformed(&(Transform::translation(Vec3::xyz(5.0, 0.0, 0.0)) * Transform::scale(Vec3::xyz(2.0, 1.0, -1.0))));
+        assert_eq!(moved.min, Vec3::xyz(3.0, -1.0, -2.0));
+        assert_eq!(moved.max, Vec3::xyz(7.0, 1.0, 0.0));
+
+        let turned = bbox.transformed(&Transform::rotation(Vec3::xyz(0.0, 0.0, 1.0), FRAC_PI_4));
+        let half_diagonal = 2.0f64.sqrt();
+        assert!((turned.max.x - half_diagonal).abs() < 1e-12 && (turned.min.y + half_diagonal).abs() < 1e-12);
+        assert_eq!((turned.min.z, turned.max.z), (0.0, 2.0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_intersection() {
+        let a = BBox { min: Vec3::zero(), max: Vec3::xyz(4.0, 4.0, 4.0) };
+        let b = BBox { min: Vec3::xyz(2.0, -1.0, 3.0), max: Vec3::xyz(6.0, 1.0, 5.0) };
+        let both = a.intersection(&b).unwrap();
+        assert_eq!(both.min, Vec3::xyz(2.0, 0.0, 3.0));
+        assert_eq!(both.max, Vec3::xyz(4.0, 1.0, 4.0));
+        assert_eq!(b.intersection(&a).unwrap().min, both.min);
+
+        let touching = BBox { min: Vec3::xyz(4.0, 0.0, 0.0), max: Vec3::xyz(5.0, 1.0, 1.0) };
+        assert_eq!(a.intersection(&touching).unwrap().volume(), 0.0);
+        let apart = BBox { min: Vec3::xyz(4.5, 0.0, 0.0), max: Vec3::xyz(5.0, 1.0, 1.0) };
+        assert!(a.intersection(&apart).is_none());
+        assert_eq!(a.intersection(&a).unwrap().max, a.max);
+    }
+
+    #[test]
+    fn test_measures() {
+        let bbox = BBox { min: Vec3::xyz(-1.0, 0.0, 2.0), max: Vec3::xyz(1.0, 3.0, 6.0) };
+        assert_eq!(bbox.surface_area(), 2.0 * (2.0 * 3.0 + 3.0 * 4.0 + 4.0 * 2.0));
+        assert_eq!(bbox.margin(), 9.0);
+        assert_eq!(bbox.center(), Vec3::xyz(0.0, 1.5, 4.0));
+
+        let point = BBox { min: Vec3::one(), max: Vec3::one() };
+        assert_eq!((point.surface_area(), point.margin(), point.center()), (0.0, 0.0, Vec3::one()));
+    }
+
+    #[test]
+    fn test_empty_and_points() {
+        let empty = BBox::empty();
+        assert!(empty.is_empty());
+        let bbox = BBox { min: Vec3::xyz(-1.0, 0.0, 2.0), max: Vec3::xyz(1.0, 3.0, 6.0) };
+        let union = empty.union(&bbox);
+        assert_eq!((union.min, union.max), (bbox.min, bbox.max));
+        assert!(!empty.overlaps(&bbox) && bbox.intersection(&empty).is_none());
+
+        let point = BBox::from_point(Vec3::one());
+        assert!(!point.is_empty());
+        assert_eq!((point.min, point.max), (Vec3::one(), Vec3::one()));
+
+        let points = vec![Vec3::xyz(1.0, -2.0, 0.0), Vec3::xyz(-1.0, 5.0, 3.0), Vec3::zero()];
+        let around = BBox::from_points(points);
+        assert_eq!((around.min, around.max), (Vec3::xyz(-1.0, -2.0, 0.0), Vec3::xyz(1.0, 5.0, 3.0)));
+        assert!(BBox::from_points(None).is_empty());
+    }
+
+    #[test]
+    fn test_distances() {
+        let bbox = BBox { min: Vec3::zero(), max: Vec3::xyz(2.0, 2.0, 2.0) };
+        assert_eq!(bbox.distance_squared_to_point(&Vec3::one()), 0.0);
+        assert_eq!(bbox.distance_squared_to_point(&Vec3::xyz(2.0, 2.0, 2.0)), 0.0);
+        assert_eq!(bbox.distance_squared_to_point(&Vec3::xyz(5.0, 1.0, 1.0)), 9.0);
+        assert_eq!(bbox.distance_squared_to_point(&Vec3::xyz(-1.0, 4.0, 1.0)), 5.0);
+
+        let near = BBox { min: Vec3::xyz(1.0, 1.0, 1.0), max: Vec3::xyz(3.0, 3.0, 3.0) };
+        assert_eq!(bbox.min_distance_squared(&near), 0.0);
+        let apart = BBox { min: Vec3::xyz(3.0, -4.0, 0.5), max: Vec3::xyz(4.0, -3.0, 1.5) };
+        assert_eq!(bbox.min_distance_squared(&apart), 1.0 + 9.0);
+        assert_eq!(apart.min_distance_squared(&bbox), 10.0);
+    }
+
+    #[test]
+    fn test_axis_aligned_rays_on_faces() {
+        let bbox = BBox {
+            min: Vec3::xyz(2.0, -1.0, -1.0),
+            max: Vec3::xyz(4.0, 1.0, 1.0),
+        };
+
+        // Along x, lying exactly in the y and z faces and on the edges.
+        for &(y, z) in [(-1.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.0, 1.0), (1.0, 1.0), (-1.0, -1.0)].iter() {
+            for &dy in [0.0, -0.0].iter() {
+                let ray = Ray::new(Vec3::xyz(0.0, y, z), Vec3::xyz(1.0, dy, 0.0));
+                assert_eq!(bbox.intersect_ray_t(&ray), Some((2.0, 4.0)), "y {} z {} dy {}", y, z, dy);
+            }
+        }
+        // Just outside a face.
+        assert!(!bbox.intersects(&Ray::new(Vec3::xyz(0.0, 1.0 + 1e-9, 0.0), Vec3::xyz(1.0, 0.0, 0.0))));
+        // Backwards along a face, and starting on the far face.
+        assert!(!bbox.intersects(&Ray::new(Vec3::xyz(0.0, 1.0, 0.0), Vec3::xyz(-1.0, -0.0, 0.0))));
+        assert_eq!(bbox.intersect_ray_t(&Ray::new(Vec3::xyz(4.0, 1.0, 1.0), Vec3::xyz(-1.0, 0.0, -0.0))), Some((0.0, 2.0)));
+
+        // A flat box is hit by a ray in its plane, and crossing it.
+        let flat = BBox { min: Vec3::xyz(0.0, 0.0, 5.0), max: Vec3::xyz(1.0, 1.0, 5.0) };
+        assert!(flat.intersects(&Ray::new(Vec3::xyz(-1.0, 0.5, 5.0), Vec3::xyz(1.0, 0.0, 0.0))));
+        assert_eq!(flat.intersect_ray_t(&Ray::new(Vec3::xyz(0.5, 0.5, 0.0), Vec3::xyz(0.0, 0.0, 1.0))), Some((5.0, 5.0)));
+
+        // Nothing hits an empty box, and NaN rays hit nothing.
+        assert!(!BBox::empty().intersects(&Ray::new(Vec3::zero(), Vec3::xyz(1.0, 1.0, 1.0))));
+        assert!(!bbox.intersects(&Ray::new(Vec3::xyz(f64::NAN, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0))));
+    }
+}
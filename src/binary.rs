@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use arena::{Arena, NodeId};
+use bbox::BBox;
+use vec3::Vec3;
+use super::{Mbr, RTree, RTreeConfig, SplitStrategy, ReinsertPolicy, ReinsertOrder, BulkLoadStrategy, RebalancePolicy, LeafCapacity, NodeStorage, LeafItem};
+
+// Layout, all integers and floats little-endian:
+//
+//     magic "RTRE", version: u32
+//     max_entries: u32, min_entries: u32, split: u8, bulk load: u8
+//     reinsert: u8, then the percentage: u8 if it is 2 (outermost)
+//     reinsert order: u8 (0 close first, 1 far first)
+//     rebalance: u8, then the percentage: u8 if it is 1 (below fill)
+//     leaf capacity: u8, then the fewest entries: u32 if it is 1 (adaptive)
+//     next_id: u64, item count: u64, has root: u8
+//     root node, if present
+//
+// A node is a tag (0 interior, 1 leaf), an entry count: u32 and its bbox as
+// six f64s, followed by its children, or for leaves by each entry's id: u64,
+// bbox and encoded item.
+
+const MAGIC: &[u8; 4] = b"RTRE";
+const VERSION: u32 = 1;
+
+const INTERIOR: u8 = 0;
+const LEAF: u8 = 1;
+
+// Deeper than any tree with two or more entries per node can grow, and
+// shallow enough to read recursively.
+const MAX_DEPTH: usize = 64;
+
+const BAD_MAGIC: &str = "not an rtree file";
+const BAD_VERSION: &str = "unsupported rtree file version";
+const BAD_SPLIT: &str = "unknown split strategy";
+const BAD_BULK_LOAD: &str = "unknown bulk load strategy";
+const BAD_REINSERT: &str = "unknown reinsert policy";
+const BAD_REINSERT_ORDER: &str = "unknown reinsert order";
+const BAD_REBALANCE: &str = "unknown rebalance policy";
+const BAD_LEAF_CAPACITY: &str = "unknown leaf capacity";
+const BAD_NODE: &str = "unknown node tag";
+const BAD_COUNT: &str = "node entry count out of range";
+const UNBALANCED: &str = "leaves are not all at the same depth";
+const BAD_ITEM_COUNT: &str = "item count does not match the leaves";
+const TOO_DEEP: &str = "tree is too deep";
+const DUPLICATE_ID: &str = "item id stored more than once";
+const BAD_NEXT_ID: &str = "next id is not above every stored id";
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Items stored in a tree saved with `RTree::write_to`.  `decode` must read
+/// back exactly what `encode` wrote.
+pub trait Codec: Sized {
+    fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write;
+    fn decode<R>(r: &mut R) -> io::Result<Self> where R: Read;
+}
+
+fn read_array<R, const N: usize>(r: &mut R) -> io::Result<[u8; N]> where R: Read {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+impl Codec for u8 {
+    fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write {
+        w.write_all(&[*self])
+    }
+
+    fn decode<R>(r: &mut R) -> io::Result<u8> where R: Read {
+        read_array::<R, 1>(r).map(|buf| buf[0])
+    }
+}
+
+impl Codec for u32 {
+    fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write {
+        w.write_all(&self.to_le_bytes())
+    }
+
+    fn decode<R>(r: &mut R) -> io::Result<u32> where R: Read {
+        read_array(r).map(u32::from_le_bytes)
+    }
+}
+
+impl Codec for u64 {
+    fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write {
+        w.write_all(&self.to_le_bytes())
+    }
+
+    fn decode<R>(r: &mut R) -> io::Result<u64> where R: Read {
+        read_array(r).map(u64::from_le_bytes)
+    }
+}
+
+impl Codec for f64 {
+    fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write {
+        w.write_all(&self.to_le_bytes())
+    }
+
+    fn decode<R>(r: &mut R) -> io::Result<f64> where R: Read {
+        read_array(r).map(f64::from_le_bytes)
+    }
+}
+
+impl Codec for Vec3 {
+    fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write {
+        self.x.encode(w)?;
+        self.y.encode(w)?;
+        self.z.encode(w)
+    }
+
+    fn decode<R>(r: &mut R) -> io::Result<Vec3> where R: Read {
+        Ok(Vec3::xyz(f64::decode(r)?, f64::decode(r)?, f64::decode(r)?))
+    }
+}
+
+impl Codec for BBox {
+    fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write {
+        self.min.encode(w)?;
+        self.max.encode(w)
+    }
+
+    fn decode<R>(r: &mut R) -> io::Result<BBox> where R: Read {
+        Ok(BBox {
+            min: Vec3::decode(r)?,
+            max: Vec3::decode(r)?,
+        })
+    }
+}
+
+fn encode_count<W>(w: &mut W, count: usize) -> io::Result<()> where W: Write {
+    if u32::MAX as usize <= count {
+        return Err(invalid(BAD_COUNT));
+    }
+    (count as u32).encode(w)
+}
+
+fn write_node<T, W>(w: &mut W, nodes: &Arena<T, 3>, node: NodeId) -> io::Result<()> where T: Mbr + Codec, W: Write {
+    let node = &nodes[node];
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            INTERIOR.encode(w)?;
+            encode_count(w, children.len())?;
+            node.bbox.encode(w)?;
+            for &child in children.iter() {
+                write_node(w, nodes, child)?;
+            }
+        },
+        NodeStorage::Leaf(ref items) => {
+            LEAF.encode(w)?;
+            encode_count(w, items.len())?;
+            node.bbox.encode(w)?;
+            for leaf in items.iter() {
+                leaf.id.encode(w)?;
+                leaf.bbox.encode(w)?;
+                leaf.item.encode(w)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+struct NodeReader<'a, T, R> where T: Mbr + 'a, R: 'a {
+    r: &'a mut R,
+    nodes: &'a mut Arena<T, 3>,
+    config: RTreeConfig,
+    leaf_depth: Option<usize>,
+}
+
+impl<'a, T, R> NodeReader<'a, T, R> where T: Mbr + Codec, R: Read {
+    fn read_node(&mut self, depth: usize) -> io::Result<NodeId> {
+        if MAX_DEPTH <= depth {
+            return Err(invalid(TOO_DEEP));
+        }
+        let tag = u8::decode(self.r)?;
+        // Counts are checked against the capacity before anything is
+        // allocated, so corrupt input cannot request huge buffers.
+        let count = u32::decode(self.r)? as usize;
+        if count == 0 || self.config.max_entries() < count {
+            return Err(invalid(BAD_COUNT));
+        }
+        let bbox = BBox::decode(self.r)?;
+
+        let storage = match tag {
+            INTERIOR => {
+                let mut children = Vec::with_capacity(count);
+                for _ in 0..count {
+                    children.push(self.read_node(depth + 1)?);
+                }
+                NodeStorage::Interior(children)
+            },
+            LEAF => {
+                if *self.leaf_depth.get_or_insert(depth) != depth {
+                    return Err(invalid(UNBALANCED));
+                }
+                let mut items = Vec::with_capacity(count);
+                for _ in 0..count {
+                    items.push(LeafItem {
+                        id: u64::decode(self.r)?,
+                        bbox: BBox::decode(self.r)?,
+                        item: T::decode(self.r)?,
+                    });
+                }
+                NodeStorage::leaf(items)
+            },
+            _ => return Err(invalid(BAD_NODE)),
+        };
+        Ok(self.nodes.add(bbox, storage))
+    }
+}
+
+impl<T> RTree<T> where T: Mbr + Codec {
+    /// Save the tree, nodes included, in a compact binary format that
+    /// `read_from` loads without rebuilding.  Items are written with their
+    /// `Codec` implementation.
+    pub fn write_to<W>(&self, mut w: W) -> io::Result<()> where W: Write {
+        w.write_all(MAGIC)?;
+        VERSION.encode(&mut w)?;
+        encode_count(&mut w, self.config.max_entries())?;
+        encode_count(&mut w, self.config.min_entries())?;
+        let split: u8 = match self.config.split_strategy() {
+            SplitStrategy::Quadratic => 0,
+            SplitStrategy::RStar => 1,
+            SplitStrategy::Hilbert => 2,
+            SplitStrategy::Sah => 3,
+            SplitStrategy::Linear => 4,
+        };
+        split.encode(&mut w)?;
+        let bulk_load: u8 = match self.config.bulk_load_strategy() {
+            BulkLoadStrategy::Str => 0,
+            BulkLoadStrategy::Omt => 1,
+            BulkLoadStrategy::Sah => 2,
+        };
+        bulk_load.encode(&mut w)?;
+        match self.config.reinsert_policy() {
+            ReinsertPolicy::Strategy => 0u8.encode(&mut w)?,
+            ReinsertPolicy::Never => 1u8.encode(&mut w)?,
+            ReinsertPolicy::Outermost(percent) => {
+                2u8.encode(&mut w)?;
+                percent.encode(&mut w)?;
+            },
+        }
+        let reinsert_order: u8 = match self.config.reinsert_order() {
+            ReinsertOrder::CloseFirst => 0,
+            ReinsertOrder::FarFirst => 1,
+        };
+        reinsert_order.encode(&mut w)?;
+        match self.config.rebalance_policy() {
+            RebalancePolicy::Never => 0u8.encode(&mut w)?,
+            RebalancePolicy::BelowFill(percent) => {
+                1u8.encode(&mut w)?;
+                percent.encode(&mut w)?;
+            },
+        }
+        match self.config.leaf_capacity() {
+            LeafCapacity::Fixed => 0u8.encode(&mut w)?,
+            LeafCapacity::Adaptive(fewest) => {
+                1u8.encode(&mut w)?;
+                encode_count(&mut w, fewest)?;
+            },
+        }
+        self.next_id.encode(&mut w)?;
+        (self.len() as u64).encode(&mut w)?;
+        match self.root {
+            Some(root) => {
+                1u8.encode(&mut w)?;
+                write_node(&mut w, &self.nodes, root)?;
+            },
+            None => 0u8.encode(&mut w)?,
+        }
+        w.flush()
+    }
+
+    /// Load a tree saved by `write_to`.  The stored node hierarchy is used
+    /// as is, so no split heuristics run, but the header and node layout
+    /// are checked and malformed input is reported as `InvalidData`.
+    pub fn read_from<R>(mut r: R) -> io::Result<RTree<T>> where R: Read {
+        if &read_array::<R, 4>(&mut r)? != MAGIC {
+            return Err(invalid(BAD_MAGIC));
+        }
+        if u32::decode(&mut r)? != VERSION {
+            return Err(invalid(BAD_VERSION));
+        }
+        let max_entries = u32::decode(&mut r)? as usize;
+        let min_entries = u32::decode(&mut r)? as usize;
+        let split = match u8::decode(&mut r)? {
+            0 => SplitStrategy::Quadratic,
+            1 => SplitStrategy::RStar,
+            2 => SplitStrategy::Hilbert,
+            3 => SplitStrategy::Sah,
+            4 => SplitStrategy::Linear,
+            _ => return Err(invalid(BAD_SPLIT)),
+        };
+        let bulk_load = match u8::decode(&mut r)? {
+            0 => BulkLoadStrategy::Str,
+            1 => BulkLoadStrategy::Omt,
+            2 => BulkLoadStrategy::Sah,
+            _ => return Err(invalid(BAD_BULK_LOAD)),
+        };
+        let reinsert = match u8::decode(&mut r)? {
+            0 => ReinsertPolicy::Strategy,
+            1 => ReinsertPolicy::Never,
+            2 => ReinsertPolicy::Outermost(u8::decode(&mut r)?),
+            _ => return Err(invalid(BAD_REINSERT)),
+        };
+        let reinsert_order = match u8::decode(&mut r)? {
+            0 => ReinsertOrder::CloseFirst,
+            1 => ReinsertOrder::FarFirst,
+            _ => return Err(invalid(BAD_REINSERT_ORDER)),
+        };
+        let rebalance = match u8::decode(&mut r)? {
+            0 => RebalancePolicy::Never,
+            1 => RebalancePolicy::BelowFill(u8::decode(&mut r)?),
+            _ => return Err(invalid(BAD_REBALANCE)),
+        };
+        let leaf_capacity = match u8::decode(&mut r)? {
+            0 => LeafCapacity::Fixed,
+            1 => LeafCapacity::Adaptive(u32::decode(&mut r)? as usize),
+            _ => return Err(invalid(BAD_LEAF_CAPACITY)),
+        };
+        let config = RTreeConfig::new(max_entries, min_entries)
+            .map_err(invalid)?
+            .with_split_strategy(split)
+            .with_reinsert_policy(reinsert)
+            .with_reinsert_order(reinsert_order)
+            .with_bulk_load_strategy(bulk_load)
+            .with_rebalance_policy(rebalance)
+            .with_leaf_capacity(leaf_capacity);
+        let next_id = u64::decode(&mut r)?;
+        let count = u64::decode(&mut r)?;
+
+        let mut nodes = Arena::new();
+        let root = match u8::decode(&mut r)? {
+            0 => None,
+            _ => {
+                let mut reader = NodeReader {
+                    r: &mut r,
+                    nodes: &mut nodes,
+                    config,
+                    leaf_depth: None,
+                };
+                Some(reader.read_node(0)?)
+            },
+        };
+        let mut tree = RTree {
+            root,
+            nodes,
+            config,
+            ids: HashMap::new(),
+            next_id,
+        };
+        tree.reindex();
+        // Ids are unique and below `next_id`, or `len` would miscount and
+        // inserts would hand out ids already in use.
+        if tree.len() != root.map_or(0, |root| tree.nodes[root].count) {
+            return Err(invalid(DUPLICATE_ID));
+        }
+        if tree.ids.keys().any(|&id| next_id <= id) {
+            return Err(invalid(BAD_NEXT_ID));
+        }
+        if tree.len() as u64 != count {
+            return Err(invalid(BAD_ITEM_COUNT));
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::{Codec, INTERIOR, LEAF, TOO_DEEP, DUPLICATE_ID, BAD_NEXT_ID};
+    use super::super::{Mbr, RTree, RTreeConfig, SplitStrategy, ReinsertPolicy, ReinsertOrder, BulkLoadStrategy, RebalancePolicy, LeafCapacity};
+    use super::super::test_helpers::{Lcg, check_tree};
+
+    #[derive(Debug, PartialEq)]
+    struct Labelled {
+        origin: Vec3,
+        label: u32,
+    }
+
+    impl Mbr for Labelled {
+        fn mbr(&self) -> BBox {
+            BBox {
+                min: self.origin - 0.5,
+                max: self.origin + 0.5,
+            }
+        }
+    }
+
+    impl Codec for Labelled {
+        fn encode<W>(&self, w: &mut W) -> io::Result<()> where W: Write {
+            self.origin.encode(w)?;
+            self.label.encode(w)
+        }
+
+        fn decode<R>(r: &mut R) -> io::Result<Labelled> where R: Read {
+            Ok(Labelled {
+                origin: Vec3::decode(r)?,
+                label: u32::decode(r)?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let mut out = Vec::new();
+        RTree::<Labelled>::new().write_to(&mut out).unwrap();
+        let empty = RTree::<Labelled>::read_from(&out[..]).unwrap();
+        assert!(empty.is_empty());
+
+        let config = RTreeConfig::new(6, 2).unwrap()
+            .with_split_strategy(SplitStrategy::RStar)
+            .with_reinsert_policy(ReinsertPolicy::Outermost(20))
+            .with_reinsert_order(ReinsertOrder::FarFirst)
+            .with_bulk_load_strategy(BulkLoadStrategy::Omt)
+            .with_rebalance_policy(RebalancePolicy::BelowFill(40))
+            .with_leaf_capacity(LeafCapacity::Adaptive(4));
+        let mut tree = RTree::with_config(config);
+        let mut rng = Lcg::new(31);
+        let ids: Vec<_> = (0..800).map(|label| {
+            let origin = Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 100.0;
+            tree.insert(Labelled { origin, label })
+        }).collect();
+
+        let mut out = Vec::new();
+        tree.write_to(&mut out).unwrap();
+        let mut loaded = RTree::<Labelled>::read_from(&out[..]).unwrap();
+        check_tree(&loaded);
+        assert_eq!(loaded.config(), tree.config());
+        assert_eq!(loaded.len(), 800);
+        assert_eq!(loaded.depth(), tree.depth());
+        for id in ids.iter() {
+            assert_eq!(loaded.get(*id), tree.get(*id));
+        }
+
+        let mut again = Vec::new();
+        loaded.write_to(&mut again).unwrap();
+        assert_eq!(again, out);
+
+        let fresh = loaded.insert(Labelled { origin: Vec3::zero(), label: 800 });
+        assert!(!ids.contains(&fresh));
+
+        assert!(RTree::<Labelled>::read_from(&b"RTRX"[..]).is_err());
+        assert!(RTree::<Labelled>::read_from(&out[..out.len() - 1]).is_err());
+        out[4] = 2;
+        assert!(RTree::<Labelled>::read_from(&out[..]).is_err());
+    }
+
+    /// The start of a file, up to where its root node goes.
+    fn header(next_id: u64, count: u64) -> Vec<u8> {
+        // The configuration is that of an empty tree, followed by its
+        // next id, item count and whether it has a root.
+        let mut out = Vec::new();
+        RTree::<Labelled>::new().write_to(&mut out).unwrap();
+        out.truncate(out.len() - 17);
+        next_id.encode(&mut out).unwrap();
+        count.encode(&mut out).unwrap();
+        1u8.encode(&mut out).unwrap();
+        out
+    }
+
+    /// A file holding a single leaf of items with the given ids.
+    fn single_leaf(ids: &[u64], next_id: u64) -> Vec<u8> {
+        let mut out = header(next_id, ids.len() as u64);
+        let items: Vec<Labelled> = (0..ids.len()).map(|label| Labelled { origin: Vec3::xyz(label as f64, 0.0, 0.0), label: label as u32 }).collect();
+        LEAF.encode(&mut out).unwrap();
+        (items.len() as u32).encode(&mut out).unwrap();
+        items.iter().fold(BBox::empty(), |bbox, item| bbox.union(&item.mbr())).encode(&mut out).unwrap();
+        for (&id, item) in ids.iter().zip(items.iter()) {
+            id.encode(&mut out).unwrap();
+            item.mbr().encode(&mut out).unwrap();
+            item.encode(&mut out).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        let error = |bytes: &[u8]| RTree::<Labelled>::read_from(bytes).err().map(|err| err.to_string());
+        assert!(RTree::<Labelled>::read_from(&single_leaf(&[0, 3], 4)[..]).is_ok_and(|tree| tree.len() == 2));
+        assert_eq!(error(&single_leaf(&[3, 3], 4)), Some(DUPLICATE_ID.to_string()));
+        assert_eq!(error(&single_leaf(&[0, 3], 3)), Some(BAD_NEXT_ID.to_string()));
+
+        // A long chain of interior nodes is rejected before it runs deep.
+        let mut deep = header(0, 0);
+        for _ in 0..1000 {
+            INTERIOR.encode(&mut deep).unwrap();
+            1u32.encode(&mut deep).unwrap();
+            BBox::from_point(Vec3::zero()).encode(&mut deep).unwrap();
+        }
+        assert_eq!(error(&deep), Some(TOO_DEEP.to_string()));
+    }
+}
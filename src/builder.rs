@@ -0,0 +1,339 @@
+use super::{Mbr, RTree, RTreeConfig, SplitStrategy, ReinsertPolicy, ReinsertOrder, BulkLoadStrategy, RebalancePolicy, LeafCapacity, SplitObserver, NODE_SIZE};
+use diagnostics::Observer;
+
+/// Configures and creates trees, one setting at a time:
+///
+/// ```
+/// use rtree::{BBox, RTree, RTreeBuilder, SplitStrategy};
+///
+/// let tree: RTree<BBox> = RTreeBuilder::new()
+///     .max_entries(16)
+///     .split_strategy(SplitStrategy::RStar)
+///     .build()
+///     .unwrap();
+/// assert_eq!(tree.config().max_entries(), 16);
+/// ```
+///
+/// Settings left alone keep their defaults: nodes of 64 entries, a minimum
+/// fill of 40% of the capacity, the quadratic split, the split strategy's
+/// own reinsertion, closest first, Sort-Tile-Recursive bulk loading, no
+/// rebalancing, and leaves as large as other nodes.  The settings are only
+/// checked when a tree is made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RTreeBuilder {
+    max_entries: usize,
+    min_entries: Option<usize>,
+    split: SplitStrategy,
+    reinsert: ReinsertPolicy,
+    reinsert_order: ReinsertOrder,
+    bulk_load: BulkLoadStrategy,
+    rebalance: RebalancePolicy,
+    leaf_capacity: LeafCapacity,
+    split_observer: Observer,
+}
+
+impl RTreeBuilder {
+    pub fn new() -> RTreeBuilder {
+        RTreeBuilder {
+            max_entries: NODE_SIZE,
+            min_entries: None,
+            split: SplitStrategy::Quadratic,
+            reinsert: ReinsertPolicy::Strategy,
+            reinsert_order: ReinsertOrder::CloseFirst,
+            bulk_load: BulkLoadStrategy::Str,
+            rebalance: RebalancePolicy::Never,
+            leaf_capacity: LeafCapacity::Fixed,
+            split_observer: Observer(None),
+        }
+    }
+
+    /// Nodes hold at most `max_entries`, which must be at least 4.
+    pub fn max_entries(mut self, max_entries: usize) -> RTreeBuilder {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Every node other than the root holds at least `min_entries`, which
+    /// must not exceed half the maximum.  Unless set, it is 40% of the
+    /// maximum.
+    pub fn min_entries(mut self, min_entries: usize) -> RTreeBuilder {
+        self.min_entries = Some(min_entries);
+        self
+    }
+
+    /// How nodes are chosen and split during insertion.
+    pub fn split_strategy(mut self, split: SplitStrategy) -> RTreeBuilder {
+        self.split = split;
+        self
+    }
+
+    /// Whether overflowing leaves reinsert some entries before splitting.
+    pub fn reinsert_policy(mut self, reinsert: ReinsertPolicy) -> RTreeBuilder {
+        self.reinsert = reinsert;
+        self
+    }
+
+    /// Which evicted entries are reinserted first.
+    pub fn reinsert_order(mut self, reinsert_order: ReinsertOrder) -> RTreeBuilder {
+        self.reinsert_order = reinsert_order;
+        self
+    }
+
+    /// How `bulk_load` and `RTree::rebuild` pack items into nodes.
+    pub fn bulk_load_strategy(mut self, bulk_load: BulkLoadStrategy) -> RTreeBuilder {
+        self.bulk_load = bulk_load;
+        self
+    }
+
+    /// Whether insertions and removals repack sparse regions of the tree.
+    pub fn rebalance_policy(mut self, rebalance: RebalancePolicy) -> RTreeBuilder {
+        self.rebalance = rebalance;
+        self
+    }
+
+    /// How many entries leaves take, e.g. fewer in dense regions.
+    pub fn leaf_capacity(mut self, leaf_capacity: LeafCapacity) -> RTreeBuilder {
+        self.leaf_capacity = leaf_capacity;
+        self
+    }
+
+    /// A function to call on every node split, e.g. to compare split
+    /// strategies; see `RTreeConfig::with_split_observer`.
+    pub fn split_observer(mut self, observer: SplitObserver) -> RTreeBuilder {
+        self.split_observer = Observer(Some(observer));
+        self
+    }
+
+    /// The configuration of the trees this builder makes, or why the
+    /// settings are out of range.
+    pub fn config(&self) -> Result<RTreeConfig, &'static str> {
+        let config = match self.min_entries {
+            Some(min_entries) => RTreeConfig::new(self.max_entries, min_entries)?,
+            None => RTreeConfig::with_max_entries(self.max_entries)?,
+        };
+        let config = config
+            .with_split_strategy(self.split)
+            .with_reinsert_policy(self.reinsert)
+            .with_reinsert_order(self.reinsert_order)
+            .with_bulk_load_strategy(self.bulk_load)
+            .with_rebalance_policy(self.rebalance)
+            .with_leaf_capacity(self.leaf_capacity);
+        Ok(match self.split_observer.0 {
+            Some(observer) => config.with_split_observer(observer),
+            None => config,
+        })
+    }
+
+    /// An empty tree with these settings.
+    pub fn build<T, const N: usize>(&self) -> Result<RTree<T, N>, &'static str> where T: Mbr<N> {
+        Ok(RTree::with_config(self.config()?))
+    }
+
+    /// A tree of `items` with these settings, packed by the bulk load
+    /// strategy.
+    pub fn bulk_load<T, const N: usize>(&self, items: Vec<T>) -> Result<RTree<T, N>, &'static str> where T: Mbr<N> {
+        Ok(RTree::bulk_load_with_config(items, self.config()?))
+    }
+}
+
+impl Default for RTreeBuilder {
+    fn default() -> RTreeBuilder {
+        RTreeBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::RTreeBuilder;
+    use super::super::{Mbr, RTree, RTreeConfig, SplitStrategy, ReinsertPolicy, ReinsertOrder, BulkLoadStrategy, RebalancePolicy, LeafCapacity};
+    use super::super::test_helpers::{Lcg, Sphere, random_spheres, check_tree};
+
+    #[test]
+    fn test_builder() {
+        assert_eq!(RTreeBuilder::new().config(), Ok(RTreeConfig::default()));
+        assert_eq!(RTreeBuilder::new().max_entries(20).config(), RTreeConfig::with_max_entries(20));
+        assert!(RTreeBuilder::new().max_entries(3).config().is_err());
+        assert!(RTreeBuilder::new().max_entries(8).min_entries(5).config().is_err());
+        assert!(RTreeBuilder::new().min_entries(0).build::<BBox, 3>().is_err());
+
+        let builder = RTreeBuilder::new()
+            .max_entries(8)
+            .min_entries(2)
+            .split_strategy(SplitStrategy::Sah)
+            .reinsert_policy(ReinsertPolicy::Outermost(25))
+            .reinsert_order(ReinsertOrder::FarFirst)
+            .bulk_load_strategy(BulkLoadStrategy::Omt)
+            .rebalance_policy(RebalancePolicy::BelowFill(50))
+            .leaf_capacity(LeafCapacity::Adaptive(4));
+        let config = builder.config().unwrap();
+        assert_eq!((config.max_entries(), config.min_entries()), (8, 2));
+        assert_eq!(config.split_strategy(), SplitStrategy::Sah);
+        assert_eq!(config.reinsert_policy(), ReinsertPolicy::Outermost(25));
+        assert_eq!(config.reinsert_order(), ReinsertOrder::FarFirst);
+        assert_eq!(config.bulk_load_strategy(), BulkLoadStrategy::Omt);
+        assert_eq!(config.rebalance_policy(), RebalancePolicy::BelowFill(50));
+        assert_eq!(config.leaf_capacity(), LeafCapacity::Adaptive(4));
+
+        let mut spheres: RTree<Sphere> = builder.build().unwrap();
+        spheres.extend(random_spheres(1000, 13));
+        check_tree(&spheres);
+        assert_eq!(spheres.config(), &config);
+        assert_eq!(spheres.validate(), Ok(()));
+
+        let packed: RTree<Sphere> = builder.bulk_load(random_spheres(1000, 13)).unwrap();
+        check_tree(&packed);
+        assert_eq!(packed.config(), &config);
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(400.0, 500.0, 600.0),
+        };
+        let brute_force = random_spheres(1000, 13).iter().filter(|s| s.mbr().overlaps(&envelope)).count();
+        assert_eq!(packed.locate_in_envelope(&envelope).count(), brute_force);
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), brute_force);
+    }
+
+    #[test]
+    fn test_reinsert_policy() {
+        // Reinsertion works with any split strategy, and R* can do without.
+        let policies = [
+            (SplitStrategy::Quadratic, ReinsertPolicy::Outermost(30)),
+            (SplitStrategy::Linear, ReinsertPolicy::Strategy),
+            (SplitStrategy::RStar, ReinsertPolicy::Never),
+            (SplitStrategy::RStar, ReinsertPolicy::Outermost(0)),
+            (SplitStrategy::Sah, ReinsertPolicy::Outermost(100)),
+            (SplitStrategy::Hilbert, ReinsertPolicy::Outermost(30)),
+        ];
+        for &(split, reinsert) in policies.iter() {
+            let builder = RTreeBuilder::new().max_entries(8).split_strategy(split).reinsert_policy(reinsert);
+            let mut spheres: RTree<Sphere> = builder.build().unwrap();
+            spheres.extend(random_spheres(2000, 17));
+            check_tree(&spheres);
+            assert_eq!(spheres.validate(), Ok(()), "{:?} with {:?}", split, reinsert);
+            for sphere in random_spheres(2000, 17).iter().step_by(2) {
+                assert!(spheres.remove(sphere).is_some());
+            }
+            check_tree(&spheres);
+        }
+    }
+
+    #[test]
+    fn test_reinsert_order() {
+        // Tight clusters far apart, their points arriving interleaved.
+        let mut rng = Lcg::new(23);
+        let centers: Vec<Vec3> = (0..20).map(|_| Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 1000.0).collect();
+        let points: Vec<Vec3> = (0..2000)
+            .map(|idx| centers[idx % centers.len()] + Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()))
+            .collect();
+        let builder = RTreeBuilder::new().max_entries(8).split_strategy(SplitStrategy::RStar).reinsert_policy(ReinsertPolicy::Outermost(40));
+        let mut close: RTree<Vec3> = builder.build().unwrap();
+        close.extend(points.iter().cloned());
+        let mut far: RTree<Vec3> = builder.reinsert_order(ReinsertOrder::FarFirst).build().unwrap();
+        far.extend(points.iter().cloned());
+        check_tree(&far);
+        assert_eq!(far.validate(), Ok(()));
+
+        // The same items, arranged differently.
+        assert!(close.quality() != far.quality());
+        let cluster = BBox { min: centers[3] - Vec3::one(), max: centers[3] + Vec3::xyz(2.0, 2.0, 2.0) };
+        assert_eq!(far.locate_in_envelope(&cluster).count(), close.locate_in_envelope(&cluster).count());
+        assert!(far.locate_in_envelope(&cluster).count() >= 100);
+        for point in points.iter().step_by(3) {
+            assert!(far.remove(point).is_some());
+        }
+        check_tree(&far);
+    }
+
+    #[test]
+    fn test_rebalance_policy() {
+        // Points on a grid, inserted a row at a time.
+        let rows = |tree: &mut RTree<Vec3>| {
+            for y in 0..60 {
+                for x in 0..60 {
+                    tree.insert(Vec3::xyz(x as f64, y as f64, 0.0));
+                }
+            }
+        };
+        for &split in [SplitStrategy::Quadratic, SplitStrategy::RStar, SplitStrategy::Hilbert].iter() {
+            let builder = RTreeBuilder::new().max_entries(16).split_strategy(split);
+            let mut plain: RTree<Vec3> = builder.build().unwrap();
+            rows(&mut plain);
+            let mut rebalanced: RTree<Vec3> = builder.rebalance_policy(RebalancePolicy::BelowFill(70)).build().unwrap();
+            rows(&mut rebalanced);
+            check_tree(&rebalanced);
+            assert_eq!(rebalanced.validate(), Ok(()), "{:?}", split);
+
+            let (plain, quality) = (plain.quality(), rebalanced.quality());
+            assert!(quality.leaf_count < plain.leaf_count, "{:?}: {} leaves, {} without rebalancing", split, quality.leaf_count, plain.leaf_count);
+            assert!(quality.average_leaf_fill > 0.6, "{:?}", split);
+
+            // Removals rebalance too.
+            for y in 0..60 {
+                for x in (0..60).filter(|x| x % 3 != 0) {
+                    assert!(rebalanced.remove(&Vec3::xyz(x as f64, y as f64, 0.0)).is_some());
+                }
+            }
+            check_tree(&rebalanced);
+            assert_eq!(rebalanced.validate(), Ok(()), "{:?}", split);
+            let row = BBox { min: Vec3::xyz(-1.0, 9.5, -1.0), max: Vec3::xyz(61.0, 10.5, 1.0) };
+            assert_eq!(rebalanced.locate_in_envelope(&row).count(), 20);
+        }
+    }
+
+    #[test]
+    fn test_leaf_capacity() {
+        // A dense cluster in a corner of a sparse map, in random order.
+        let mut rng = Lcg::new(19);
+        let points: Vec<Vec3> = (0..4000)
+            .map(|idx| {
+                let scale = if idx % 2 == 0 { 10.0 } else { 1000.0 };
+                Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * scale
+            })
+            .collect();
+        // The average number of entries of the leaves inside the cluster,
+        // and of those well away from it.
+        let leaf_sizes = |tree: &RTree<Vec3>| {
+            let (mut dense, mut sparse) = ((0, 0), (0, 0));
+            for node in tree.nodes.slots().iter().filter(|node| node.is_leaf() && node.shallow_len() > 0) {
+                if node.bbox.max.x.max(node.bbox.max.y).max(node.bbox.max.z) <= 50.0 {
+                    dense = (dense.0 + node.shallow_len(), dense.1 + 1);
+                } else if 100.0 <= node.bbox.min.x.max(node.bbox.min.y).max(node.bbox.min.z) {
+                    sparse = (sparse.0 + node.shallow_len(), sparse.1 + 1);
+                }
+            }
+            (dense.0 as f64 / dense.1 as f64, sparse.0 as f64 / sparse.1 as f64)
+        };
+
+        for &split in [SplitStrategy::Quadratic, SplitStrategy::RStar, SplitStrategy::Sah].iter() {
+            let builder = RTreeBuilder::new().max_entries(32).min_entries(4).split_strategy(split);
+            let mut fixed: RTree<Vec3> = builder.build().unwrap();
+            fixed.extend(points.iter().cloned());
+            let mut adaptive: RTree<Vec3> = builder.leaf_capacity(LeafCapacity::Adaptive(8)).build().unwrap();
+            adaptive.extend(points.iter().cloned());
+            check_tree(&adaptive);
+            assert_eq!(adaptive.validate(), Ok(()), "{:?}", split);
+
+            // Leaves in the cluster are smaller, those elsewhere as large.
+            let ((fixed_dense, fixed_sparse), (dense, sparse)) = (leaf_sizes(&fixed), leaf_sizes(&adaptive));
+            assert!(dense * 1.5 < fixed_dense, "{:?}: {} entries in dense leaves, {} when fixed", split, dense, fixed_dense);
+            assert!(fixed_sparse * 0.8 < sparse, "{:?}: {} entries in sparse leaves, {} when fixed", split, sparse, fixed_sparse);
+
+            let hotspot = BBox { min: Vec3::xyz(4.0, 4.0, 4.0), max: Vec3::xyz(6.0, 6.0, 6.0) };
+            let expected = points.iter().filter(|p| hotspot.contains(&p.mbr())).count();
+            assert_eq!(adaptive.locate_in_envelope(&hotspot).count(), expected);
+            for point in points.iter().step_by(2) {
+                assert!(adaptive.remove(point).is_some());
+            }
+            check_tree(&adaptive);
+        }
+
+        // Hilbert trees keep their leaves full.
+        let builder = RTreeBuilder::new().max_entries(32).min_entries(4).split_strategy(SplitStrategy::Hilbert);
+        let mut fixed: RTree<Vec3> = builder.build().unwrap();
+        fixed.extend(points.iter().cloned());
+        let mut adaptive: RTree<Vec3> = builder.leaf_capacity(LeafCapacity::Adaptive(8)).build().unwrap();
+        adaptive.extend(points.iter().cloned());
+        assert_eq!(fixed.quality(), adaptive.quality());
+    }
+}
@@ -0,0 +1,573 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use aabb::Aabb;
+use arena::{Arena, NodeId};
+use super::{Mbr, RTree, RTreeConfig, SplitStrategy, BulkLoadStrategy, NodeStorage, LeafItem};
+use super::util;
+
+/// The center of an entry's bounding box along `axis`.
+fn center<E, const N: usize>(entry: &E, axis: usize) -> f64 where E: Mbr<N> {
+    entry.mbr().center().coords[axis]
+}
+
+fn sort_by_center<E, const N: usize>(entries: &mut [E], axis: usize) where E: Mbr<N> {
+    entries.sort_by(|a, b| {
+        let (a, b) = (center(a, axis), center(b, axis));
+        a.partial_cmp(&b).unwrap_or_else(|| a.total_cmp(&b))
+    });
+}
+
+#[cfg(feature = "parallel")]
+fn par_sort_by_center<E, const N: usize>(entries: &mut [E], axis: usize) where E: Mbr<N> + Send {
+    entries.par_sort_by(|a, b| {
+        let (a, b) = (center(a, axis), center(b, axis));
+        a.partial_cmp(&b).unwrap_or_else(|| a.total_cmp(&b))
+    });
+}
+
+/// Split `entries`, which are to fill `groups` nodes, into `parts` runs.
+/// Each run is assigned a whole number of those nodes, and a proportional
+/// share of the entries.
+fn split_groups<E>(entries: Vec<E>, groups: usize, parts: usize) -> Vec<(Vec<E>, usize)> {
+    let parts = parts.clamp(1, groups.max(1));
+    let total = entries.len();
+    let mut iter = entries.into_iter();
+    let mut taken = 0;
+    let mut out = Vec::with_capacity(parts);
+    for i in 0..parts {
+        let (start, end) = (i * groups / parts, (i + 1) * groups / parts);
+        let until = end * total / groups.max(1);
+        out.push((iter.by_ref().take(until - taken).collect(), end - start));
+        taken = until;
+    }
+    out
+}
+
+/// The number of slabs to cut `groups` nodes into along the first of `dims`
+/// remaining axes, so that every axis is cut about equally often: the
+/// `dims`-th root of `groups`, rounded up.
+fn slab_count(groups: usize, dims: usize) -> usize {
+    (1..).find(|&slabs: &usize| groups <= slabs.saturating_pow(dims as u32)).unwrap()
+}
+
+/// Sort-Tile-Recursive: tile `entries` into slabs along x, each slab into
+/// slabs along y, and so on, until the last axis cuts them into groups of
+/// at most `capacity`.
+fn str_pack<E, const N: usize>(entries: Vec<E>, capacity: usize) -> Vec<Vec<E>> where E: Mbr<N> {
+    let groups = entries.len().div_ceil(capacity);
+    let mut packed = Vec::with_capacity(groups);
+    tile(entries, groups, 0, &mut packed);
+    packed
+}
+
+/// Like `str_pack`, but sorting along x in parallel and then tiling the
+/// slabs, which are independent of one another, in parallel as well.
+#[cfg(feature = "parallel")]
+fn par_str_pack<E, const N: usize>(mut entries: Vec<E>, capacity: usize) -> Vec<Vec<E>> where E: Mbr<N> + Send {
+    if N < 2 {
+        return str_pack(entries, capacity);
+    }
+    let groups = entries.len().div_ceil(capacity);
+    let slabs = slab_count(groups, N);
+
+    par_sort_by_center(&mut entries, 0);
+    let tiled: Vec<Vec<Vec<E>>> = split_groups(entries, groups, slabs)
+        .into_par_iter()
+        .map(|(slab, slab_groups)| {
+            let mut packed = Vec::with_capacity(slab_groups);
+            tile(slab, slab_groups, 1, &mut packed);
+            packed
+        })
+        .collect();
+    tiled.into_iter().flatten().collect()
+}
+
+/// Tile `entries`, which are to fill `groups` nodes, into slabs along
+/// `axis` and each slab along the axes after it, appending the groups to
+/// `packed`.
+fn tile<E, const N: usize>(mut entries: Vec<E>, groups: usize, axis: usize, packed: &mut Vec<Vec<E>>) where E: Mbr<N> {
+    sort_by_center(&mut entries, axis);
+    if N <= axis + 1 {
+        packed.extend(split_groups(entries, groups, groups).into_iter().map(|(g, _)| g));
+        return;
+    }
+    for (slab, slab_groups) in split_groups(entries, groups, slab_count(groups, N - axis)) {
+        tile(slab, slab_groups, axis + 1, packed);
+    }
+}
+
+/// Store an interior node over `children`, returning its box and id.
+fn pack_interior<T, const N: usize>(nodes: &mut Arena<T, N>, children: Vec<(Aabb<N>, NodeId)>) -> (Aabb<N>, NodeId) where T: Mbr<N> {
+    let bbox = util::union_all(children.iter().map(|&(bbox, _)| bbox)).unwrap();
+    let ids = children.into_iter().map(|(_, id)| id).collect();
+    (bbox, nodes.add(bbox, NodeStorage::Interior(ids)))
+}
+
+/// Cut `entries`, which are in Hilbert order, into runs of at most
+/// `capacity`, for the nodes of a Hilbert packed tree.
+fn hilbert_pack<E>(entries: Vec<E>, capacity: usize) -> Vec<Vec<E>> {
+    let groups = entries.len().div_ceil(capacity);
+    util::spread(entries, groups)
+}
+
+/// The height of the shortest tree of nodes holding up to `capacity`
+/// entries which fits `items`.
+fn tree_height(items: usize, capacity: usize) -> u32 {
+    let mut height = 1;
+    let mut reach = capacity;
+    while reach < items {
+        reach = reach.saturating_mul(capacity);
+        height += 1;
+    }
+    height
+}
+
+/// How many bins of item centers binned SAH tries cutting between.
+const SAH_BINS: usize = 16;
+
+/// Cut `entries` into `groups` runs of between `lo` and `hi` entries each,
+/// by binned SAH, appending them to `packed`.
+///
+/// The entries are split in two, half the groups to each side, and each
+/// side is cut again the same way.  Each split sorts the entries' centers
+/// into bins along every axis and takes the boundary between bins whose
+/// two sides have the smallest surface areas weighted by their entry
+/// counts, skipping boundaries that leave either side too many or too few
+/// entries to make up its groups.  If every boundary does, the entries
+/// are cut at the median of their widest axis instead.
+fn sah_partition<E, const N: usize>(mut entries: Vec<E>, groups: usize, lo: usize, hi: usize, packed: &mut Vec<Vec<E>>)
+    where
+        E: Mbr<N> {
+
+    if groups <= 1 {
+        packed.push(entries);
+        return;
+    }
+    let len = entries.len();
+    let (left_groups, right_groups) = (groups / 2, groups - groups / 2);
+    let fewest = (left_groups * lo).max(len.saturating_sub(right_groups * hi));
+    let most = (left_groups * hi).min(len - right_groups * lo);
+
+    let centers = util::union_all(entries.iter().map(|e| Aabb::from_point(e.mbr().center()))).unwrap();
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..N {
+        let (start, extent) = (centers.min.coords[axis], centers.max.coords[axis] - centers.min.coords[axis]);
+        if extent <= 0.0 || !extent.is_finite() {
+            continue;
+        }
+        let bin_of = |entry: &E| (((center(entry, axis) - start) / extent * SAH_BINS as f64) as usize).min(SAH_BINS - 1);
+        let mut counts = [0; SAH_BINS];
+        let mut bounds = [Aabb::empty(); SAH_BINS];
+        for entry in entries.iter() {
+            let bin = bin_of(entry);
+            counts[bin] += 1;
+            bounds[bin] = bounds[bin].union(&entry.mbr());
+        }
+
+        let mut suffixes = bounds;
+        for bin in (0..SAH_BINS - 1).rev() {
+            suffixes[bin] = suffixes[bin].union(&suffixes[bin + 1]);
+        }
+        let (mut prefix, mut taken) = (Aabb::empty(), 0);
+        for bin in 1..SAH_BINS {
+            prefix = prefix.union(&bounds[bin - 1]);
+            taken += counts[bin - 1];
+            if taken < fewest || most < taken {
+                continue;
+            }
+            let cost = prefix.surface_area() * taken as f64 + suffixes[bin].surface_area() * (len - taken) as f64;
+            if best.is_none_or(|b| cost < b.0) {
+                best = Some((cost, axis, taken));
+            }
+        }
+    }
+
+    let (axis, k) = match best {
+        Some((_, axis, k)) => (axis, k),
+        None => {
+            let axis = (0..N).max_by(|&a, &b| centers.len().coords[a].total_cmp(&centers.len().coords[b])).unwrap_or(0);
+            (axis, (len / 2).clamp(fewest, most))
+        },
+    };
+    if 0 < k && k < len {
+        entries.select_nth_unstable_by(k, |a, b| center(a, axis).total_cmp(&center(b, axis)));
+    }
+    let rights = entries.split_off(k);
+    sah_partition(entries, left_groups, lo, hi, packed);
+    sah_partition(rights, right_groups, lo, hi, packed);
+}
+
+/// Store a subtree of `height` levels over `items`, returning its box and
+/// id.  The items are cut into as many groups as the subtree's root needs
+/// children, given how many items each child's own subtree can hold, and
+/// every group becomes a child.
+///
+/// OMT tiles the items into even groups.  Binned SAH lets the groups vary,
+/// but keeps each one big enough for its subtree's nodes to reach the
+/// minimum fill.  Either way, all leaves are at the same depth.
+fn top_down_pack<T, const N: usize>(nodes: &mut Arena<T, N>, items: Vec<LeafItem<T, N>>, config: &RTreeConfig, height: u32) -> (Aabb<N>, NodeId)
+    where
+        T: Mbr<N> {
+
+    if height <= 1 {
+        let bbox = util::union_all(items.iter().map(|i| i.bbox)).unwrap();
+        return (bbox, nodes.add(bbox, NodeStorage::leaf(items)));
+    }
+    let capacity = config.max_entries();
+    let subtree = capacity.pow(height - 1);
+    let groups = items.len().div_ceil(subtree);
+    let mut parts = Vec::with_capacity(groups);
+    match config.bulk_load_strategy() {
+        BulkLoadStrategy::Sah => {
+            let fewest = config.min_entries() * capacity.pow(height - 2);
+            sah_partition(items, groups, fewest, subtree, &mut parts);
+        },
+        _ => tile(items, groups, 0, &mut parts),
+    }
+    let children = parts
+        .into_iter()
+        .map(|group| top_down_pack(nodes, group, config, height - 1))
+        .collect();
+    pack_interior(nodes, children)
+}
+
+impl<T, const N: usize> Arena<T, N> where T: Mbr<N> {
+    /// Bulk load the items of the leaves below `node` into as few leaves as
+    /// they fit, but no fewer than `fewest`, and put those in place of the
+    /// old ones.  Returns false, changing nothing, if that would not save
+    /// a leaf.
+    ///
+    /// The items are tiled as by Sort-Tile-Recursive, or in Hilbert trees
+    /// kept in Hilbert order, and each new leaf gets an even share of them,
+    /// which is at least the minimum fill because each old leaf held that
+    /// much.
+    pub fn repack_leaves(&mut self, node: NodeId, config: &RTreeConfig, fewest: usize) -> bool {
+        let leaves = self.child_ids(node).len();
+        let groups = self[node].count.div_ceil(config.max_entries()).max(fewest);
+        if leaves <= groups {
+            return false;
+        }
+
+        let old = match self[node].storage {
+            NodeStorage::Interior(ref mut children) => ::std::mem::take(children),
+            NodeStorage::Leaf(_) => return false,
+        };
+        let mut items = Vec::with_capacity(self[node].count);
+        for leaf in old {
+            self.take_items(leaf, &mut items);
+        }
+        let packed = if config.split_strategy() == SplitStrategy::Hilbert {
+            items.sort_by_cached_key(util::hilbert_value);
+            util::spread(items, groups)
+        } else {
+            // Rounding in each slab can leave the tiles a little uneven, so
+            // even them out, keeping their order.
+            let mut packed = Vec::with_capacity(groups);
+            tile(items, groups, 0, &mut packed);
+            util::spread(packed.into_iter().flatten().collect(), groups)
+        };
+        let children = packed
+            .into_iter()
+            .map(|items| {
+                let bbox = util::union_all(items.iter().map(|i| i.bbox)).unwrap();
+                self.add(bbox, NodeStorage::leaf(items))
+            })
+            .collect();
+        self[node].storage = NodeStorage::Interior(children);
+        self.refit(node);
+        true
+    }
+}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    /// Build a tree from `items` in one go using Sort-Tile-Recursive
+    /// packing.
+    ///
+    /// Items are sorted by the centers of their bounding boxes and packed
+    /// into nearly full leaves, which are then packed the same way level by
+    /// level up to the root.  This is much faster than inserting items one
+    /// at a time, and the resulting tree has less overlap and answers
+    /// queries faster.
+    pub fn bulk_load(items: Vec<T>) -> RTree<T, N> {
+        RTree::bulk_load_with_config(items, RTreeConfig::default())
+    }
+
+    /// Build a tree from `items` in one go using Overlap-Minimizing
+    /// Top-down packing; see `BulkLoadStrategy::Omt`.
+    ///
+    /// Leaves are less full than with `bulk_load`, but on skewed data they
+    /// are less elongated, and overlap less.
+    pub fn bulk_load_omt(items: Vec<T>) -> RTree<T, N> {
+        let config = RTreeConfig::default().with_bulk_load_strategy(BulkLoadStrategy::Omt);
+        RTree::bulk_load_with_config(items, config)
+    }
+
+    /// Like `bulk_load`, but packing nodes up to `config.max_entries()`
+    /// with `config.bulk_load_strategy()`.  With `SplitStrategy::Hilbert`,
+    /// items are packed in the order of their Hilbert values instead, as a
+    /// Hilbert R-tree.
+    pub fn bulk_load_with_config(items: Vec<T>, config: RTreeConfig) -> RTree<T, N> {
+        let mut tree = RTree::with_config(config);
+        if items.is_empty() {
+            return tree;
+        }
+
+        let leaf_items: Vec<LeafItem<T, N>> = items.into_iter().map(|item| tree.new_leaf(item)).collect();
+        tree.pack(leaf_items);
+        tree
+    }
+
+    /// Rebuild the tree with the bulk loader, restoring the query
+    /// performance lost to a long run of insertions and removals.
+    ///
+    /// The result is the tree `bulk_load` would build from the same items,
+    /// except that every item keeps its id, so `ItemId`s handed out earlier
+    /// stay valid.  This takes about as long as bulk loading.
+    pub fn rebuild(&mut self) {
+        let mut leaf_items = Vec::with_capacity(self.len());
+        if let Some(root) = self.root.take() {
+            self.nodes.take_items(root, &mut leaf_items);
+        }
+        self.nodes = Arena::new();
+        self.pack(leaf_items);
+    }
+
+    /// Pack `leaf_items`, which are already in the id index, into a new
+    /// hierarchy in the empty arena.
+    fn pack(&mut self, mut leaf_items: Vec<LeafItem<T, N>>) {
+        if leaf_items.is_empty() {
+            return;
+        }
+
+        let capacity = self.config.max_entries();
+        let hilbert = self.config.split_strategy() == SplitStrategy::Hilbert;
+        if !hilbert && self.config.bulk_load_strategy() != BulkLoadStrategy::Str {
+            let height = tree_height(leaf_items.len(), capacity);
+            self.root = Some(top_down_pack(&mut self.nodes, leaf_items, &self.config, height).1);
+            return;
+        }
+        let packed = if hilbert {
+            leaf_items.sort_by_cached_key(util::hilbert_value);
+            hilbert_pack(leaf_items, capacity)
+        } else {
+            str_pack(leaf_items, capacity)
+        };
+        let nodes = &mut self.nodes;
+        let mut level: Vec<(Aabb<N>, NodeId)> = packed
+            .into_iter()
+            .map(|items| {
+                let bbox = util::union_all(items.iter().map(|i| i.bbox)).unwrap();
+                (bbox, nodes.add(bbox, NodeStorage::leaf(items)))
+            })
+            .collect();
+
+        while level.len() > 1 {
+            // Hilbert order carries over from the leaves to their parents.
+            let packed = if hilbert { hilbert_pack(level, capacity) } else { str_pack(level, capacity) };
+            level = packed
+                .into_iter()
+                .map(|children| pack_interior(nodes, children))
+                .collect();
+        }
+
+        self.root = level.pop().map(|(_, root)| root);
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> + Send {
+    /// Like `bulk_load`, but sorting and packing on the rayon thread pool.
+    ///
+    /// The tree is the same one `bulk_load` builds, with the same item
+    /// ids.  Only available with the `parallel` feature.
+    pub fn bulk_load_par(items: Vec<T>) -> RTree<T, N> {
+        RTree::bulk_load_par_with_config(items, RTreeConfig::default())
+    }
+
+    /// Like `bulk_load_par`, but packing nodes up to `config.max_entries()`
+    /// with `config.bulk_load_strategy()`.  Only Sort-Tile-Recursive and
+    /// Hilbert packing run in parallel; the top-down strategies fall back
+    /// to `bulk_load_with_config`.
+    pub fn bulk_load_par_with_config(items: Vec<T>, config: RTreeConfig) -> RTree<T, N> {
+        let hilbert = config.split_strategy() == SplitStrategy::Hilbert;
+        if !hilbert && config.bulk_load_strategy() != BulkLoadStrategy::Str {
+            return RTree::bulk_load_with_config(items, config);
+        }
+        let mut tree = RTree::with_config(config);
+        if items.is_empty() {
+            return tree;
+        }
+
+        let first_id = tree.next_id;
+        let mut leaf_items: Vec<LeafItem<T, N>> = items
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, item)| LeafItem::new(item, first_id + i as u64))
+            .collect();
+        tree.next_id += leaf_items.len() as u64;
+        tree.ids.extend(leaf_items.iter().map(|leaf| (leaf.id, leaf.bbox)));
+
+        let packed = if hilbert {
+            leaf_items.par_sort_by_cached_key(util::hilbert_value);
+            hilbert_pack(leaf_items, config.max_entries())
+        } else {
+            par_str_pack(leaf_items, config.max_entries())
+        };
+
+        // The leaves are bounded in parallel, but stored in the arena in
+        // order, so that they land in the same slots as with `bulk_load`.
+        let leaves: Vec<(Aabb<N>, Vec<LeafItem<T, N>>)> = packed
+            .into_par_iter()
+            .map(|items| (util::union_all(items.iter().map(|i| i.bbox)).unwrap(), items))
+            .collect();
+        let nodes = &mut tree.nodes;
+        let mut level: Vec<(Aabb<N>, NodeId)> = leaves
+            .into_iter()
+            .map(|(bbox, items)| (bbox, nodes.add(bbox, NodeStorage::leaf(items))))
+            .collect();
+
+        while level.len() > 1 {
+            let packed = if hilbert { hilbert_pack(level, config.max_entries()) } else { par_str_pack(level, config.max_entries()) };
+            level = packed
+                .into_iter()
+                .map(|children| pack_interior(nodes, children))
+                .collect();
+        }
+
+        tree.root = level.pop().map(|(_, root)| root);
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree, RTreeConfig, BulkLoadStrategy, NODE_SIZE};
+    use super::super::test_helpers::{Sphere, random_spheres, check_tree};
+    use super::split_groups;
+
+    #[test]
+    fn test_split_groups() {
+        let sizes: Vec<(usize, usize)> = split_groups((0..130).collect(), 3, 2)
+            .iter()
+            .map(|&(ref run, groups)| (run.len(), groups))
+            .collect();
+        assert_eq!(sizes, vec![(43, 1), (87, 2)]);
+    }
+
+    #[test]
+    fn test_bulk_load_sizes() {
+        for &count in [0, 1, NODE_SIZE, NODE_SIZE + 1, 130, 1000, 4097].iter() {
+            let spheres = RTree::bulk_load(random_spheres(count, 3));
+            check_tree(&spheres);
+            let leaves = spheres.quality().leaf_count;
+            assert_eq!(leaves, count.div_ceil(NODE_SIZE), "{} items", count);
+        }
+    }
+
+    #[test]
+    fn test_bulk_load_queries() {
+        let spheres = RTree::bulk_load(random_spheres(5000, 4));
+        assert_eq!(check_tree(&spheres), 2);
+        assert!(spheres.quality().average_leaf_fill > 0.9);
+
+        let expected = random_spheres(5000, 4);
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(400.0, 500.0, 600.0),
+        };
+        let brute_force = expected.iter().filter(|s| s.mbr().overlaps(&envelope)).count();
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), brute_force);
+
+        let mut spheres: RTree<Sphere> = spheres;
+        for sphere in expected.iter().take(100) {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        spheres.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        check_tree(&spheres);
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let ids: Vec<_> = random_spheres(3000, 7).into_iter().map(|s| spheres.insert(s)).collect();
+        for sphere in random_spheres(3000, 7).iter().step_by(2) {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(400.0, 500.0, 600.0),
+        };
+        let found = spheres.locate_in_envelope(&envelope).count();
+
+        spheres.rebuild();
+        check_tree(&spheres);
+        assert_eq!(spheres.len(), 1500);
+        assert_eq!(spheres.quality().leaf_count, 1500usize.div_ceil(NODE_SIZE));
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), found);
+        assert!(spheres.get(ids[0]).is_none());
+        assert_eq!(spheres.get(ids[1]).unwrap().mbr(), random_spheres(2, 7)[1].mbr());
+
+        let fresh = spheres.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        assert!(!ids.contains(&fresh));
+        check_tree(&spheres);
+
+        let mut empty: RTree<Sphere> = RTree::new();
+        empty.rebuild();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_bulk_load_top_down() {
+        // Every size keeps nodes above the minimum fill and leaves at one
+        // depth, including sizes just past a full level.
+        for &strategy in [BulkLoadStrategy::Omt, BulkLoadStrategy::Sah].iter() {
+            let config = RTreeConfig::new(8, 3).unwrap().with_bulk_load_strategy(strategy);
+            for count in (0..600).step_by(7).chain([64, 65, 512, 513]) {
+                let spheres = RTree::bulk_load_with_config(random_spheres(count, 6), config);
+                check_tree(&spheres);
+                assert_eq!(spheres.validate(), Ok(()), "{:?}, {} items", strategy, count);
+                assert_eq!(spheres.len(), count);
+            }
+        }
+
+        let spheres = RTree::bulk_load_omt(random_spheres(5000, 4));
+        check_tree(&spheres);
+        assert_eq!(spheres.depth(), RTree::bulk_load(random_spheres(5000, 4)).depth());
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(400.0, 500.0, 600.0),
+        };
+        let brute_force = random_spheres(5000, 4).iter().filter(|s| s.mbr().overlaps(&envelope)).count();
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), brute_force);
+
+        // Rebuilding packs the same way again.
+        let mut spheres: RTree<Sphere> = spheres;
+        for sphere in random_spheres(5000, 4).iter().take(1000) {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        spheres.rebuild();
+        check_tree(&spheres);
+        assert_eq!(spheres.config().bulk_load_strategy(), BulkLoadStrategy::Omt);
+        assert_eq!(spheres.validate(), Ok(()));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_bulk_load_par_matches() {
+        for &count in [0, 1, NODE_SIZE + 1, 5000].iter() {
+            let serial = RTree::bulk_load(random_spheres(count, 5));
+            let parallel = RTree::bulk_load_par(random_spheres(count, 5));
+            check_tree(&parallel);
+            assert_eq!(parallel.len(), count);
+            assert_eq!(parallel.quality().leaf_count, serial.quality().leaf_count);
+
+            assert!(serial.iter().eq(parallel.iter()));
+            let mut ids: Vec<u64> = parallel.ids.keys().cloned().collect();
+            ids.sort();
+            assert_eq!(ids, (0..count as u64).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use bbox::BBox;
+use fragmented::FragmentedRTree;
+use hit::{Intersectable, Intersection, Mailbox};
+use ray::Ray;
+use super::{ItemId, Mbr, RTreeConfig};
+
+const THRESHOLD_OUT_OF_RANGE: &str = "clipping threshold must be at least 1";
+
+/// The most fragments an item is cut into, unless set otherwise.
+const MAX_FRAGMENTS: usize = 16;
+
+/// Clipping an item's box against a leaf leaves the part inside the leaf
+/// and at most two slabs per axis outside it.
+const PIECES_PER_CLIP: usize = 1 + 2 * 3;
+
+/// A tree for scenes which mix many small items with a few huge ones,
+/// such as ground planes or sky domes.  In an `RTree` those blow up the
+/// box of every node they land in, up to the root, and every query has to
+/// descend into them.  Here an item whose bounding box is larger than the
+/// leaf it would be inserted into is cut along that leaf's bounds instead:
+/// the part inside the leaf goes there, and the rest is inserted again,
+/// piece by piece, so each leaf only covers the part of the item near its
+/// other entries.
+///
+/// The pieces of an item together cover exactly its bounding box, and are
+/// stored as the boxes of one item of a `FragmentedRTree`, so queries find
+/// the same items as an `RTree` would, each of them once.  Ray queries use
+/// a `Mailbox` so that an item is tested at most once per ray, however
+/// many of its pieces the ray passes through.
+///
+/// Items are cut against the tree as it is when they are inserted, so
+/// insert the small items first.
+#[derive(Debug)]
+pub struct ClippedRTree<T> where T: Mbr {
+    tree: FragmentedRTree<T>,
+    threshold: f64,
+    max_fragments: usize,
+}
+
+impl<T> ClippedRTree<T> where T: Mbr {
+    /// An empty tree cutting items whose bounding box has more than
+    /// `threshold` times the surface area of their target leaf, which must
+    /// be at least 1.
+    pub fn new(threshold: f64) -> Result<ClippedRTree<T>, &'static str> {
+        ClippedRTree::with_config(RTreeConfig::default(), threshold)
+    }
+
+    /// Like `new`, with nodes following `config`.
+    pub fn with_config(config: RTreeConfig, threshold: f64) -> Result<ClippedRTree<T>, &'static str> {
+        if threshold.is_nan() || threshold < 1.0 {
+            return Err(THRESHOLD_OUT_OF_RANGE);
+        }
+        Ok(ClippedRTree {
+            tree: FragmentedRTree::with_config(config),
+            threshold,
+            max_fragments: MAX_FRAGMENTS,
+        })
+    }
+
+    /// Cut no item into more than `max_fragments` pieces, instead of 16.
+    /// With fewer than 7, which one cut can produce, nothing is cut.
+    pub fn with_max_fragments(mut self, max_fragments: usize) -> ClippedRTree<T> {
+        self.max_fragments = max_fragments;
+        self
+    }
+
+    /// The number of items in the tree.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// The number of pieces stored for all items, at least one per item.
+    pub fn fragment_count(&self) -> usize {
+        self.tree.fragment_count()
+    }
+
+    /// Add `item` to the tree, cut into as many pieces as it needs,
+    /// returning a handle to it.
+    pub fn insert(&mut self, item: T) -> ItemId {
+        // Each piece is cut against the tree holding the pieces before it.
+        let mut pieces = vec![item.mbr()];
+        let id = self.tree.insert(item, None);
+        let mut fragments = 0;
+        while let Some(piece) = pieces.pop() {
+            let room = fragments + pieces.len() + PIECES_PER_CLIP <= self.max_fragments;
+            let bbox = match self.leaf_for(&piece) {
+                Some(leaf) if room && self.should_clip(&piece, &leaf) => {
+                    let (inside, outside) = clip(&piece, &leaf);
+                    pieces.extend(outside);
+                    inside
+                },
+                _ => piece,
+            };
+            self.tree.add_fragment(id, bbox);
+            fragments += 1;
+        }
+        id
+    }
+
+    /// The bounding box of the leaf `piece` would be inserted into.
+    fn leaf_for(&self, piece: &BBox) -> Option<BBox> {
+        let fragments = &self.tree.fragments;
+        let leaf = fragments.nodes.choose_leaf(fragments.root?, piece, &fragments.config);
+        Some(fragments.nodes[leaf].bbox)
+    }
+
+    /// Whether `piece` is large enough, next to `leaf`, to be cut along it.
+    /// Leaves without area, such as those of a single point, and leaves
+    /// the piece doesn't reach, are no use for cutting.
+    fn should_clip(&self, piece: &BBox, leaf: &BBox) -> bool {
+        let area = leaf.surface_area();
+        area > 0.0 && piece.surface_area() > self.threshold * area && piece.overlaps(leaf) && !leaf.contains(piece)
+    }
+
+    /// Remove the item `id` refers to, and all its pieces.
+    pub fn remove(&mut self, id: ItemId) -> Option<T> {
+        self.tree.remove(id)
+    }
+
+    /// The item `id` refers to, or None if it has been removed.
+    pub fn get(&self, id: ItemId) -> Option<&T> {
+        self.tree.get(id)
+    }
+
+    /// Every item, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item=&T> + '_ {
+        self.tree.iter()
+    }
+
+    /// The items whose bounding box overlaps `query`, each once.
+    pub fn iter_bbox(&self, query: &BBox) -> impl Iterator<Item=&T> + '_ {
+        self.tree.iter_bbox(query)
+    }
+
+    /// The items whose bounding box intersects `ray`, each once.
+    pub fn iter_ray<'a>(&'a self, ray: &'a Ray) -> impl Iterator<Item=&'a T> + 'a {
+        self.tree.iter_ray(ray)
+    }
+
+    /// Like `RTree::closest_hit_with`: the nearest item hit by `ray`
+    /// according to `intersect`, which is called at most once per item.
+    pub fn closest_hit_with<F>(&self, ray: &Ray, intersect: F) -> Option<(f64, &T)>
+        where
+            F: Fn(&T, &Ray) -> Option<f64> {
+
+        self.tree.closest_hit_with(ray, intersect)
+    }
+
+    /// Like `closest_hit_with`, reusing `mailbox` rather than allocating a
+    /// new one for every ray.
+    pub fn closest_hit_with_mailbox<F>(&self, ray: &Ray, mailbox: &mut Mailbox, intersect: F) -> Option<(f64, &T)>
+        where
+            F: Fn(&T, &Ray) -> Option<f64> {
+
+        self.tree.closest_hit_with_mailbox(ray, mailbox, intersect)
+    }
+}
+
+impl<T> ClippedRTree<T> where T: Mbr + Intersectable {
+    /// Like `RTree::closest_hit`, testing each item at most once.
+    pub fn closest_hit(&self, ray: &Ray) -> Option<(f64, &T, Intersection)> {
+        self.tree.closest_hit(ray)
+    }
+}
+
+/// Cut `piece` into the part inside `leaf`, which it must overlap, and
+/// the slabs of it outside `leaf`, taken axis by axis so that none of the
+/// parts overlap.
+fn clip(piece: &BBox, leaf: &BBox) -> (BBox, Vec<BBox>) {
+    let mut inside = *piece;
+    let mut outside = Vec::new();
+    for axis in 0..3 {
+        if inside.min.coords[axis] < leaf.min.coords[axis] {
+            let mut below = inside;
+            below.max.coords[axis] = leaf.min.coords[axis];
+            inside.min.coords[axis] = leaf.min.coords[axis];
+            outside.push(below);
+        }
+        if leaf.max.coords[axis] < inside.max.coords[axis] {
+            let mut above = inside;
+            above.min.coords[axis] = leaf.max.coords[axis];
+            inside.max.coords[axis] = leaf.max.coords[axis];
+            outside.push(above);
+        }
+    }
+    (inside, outside)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::{ClippedRTree, clip};
+    use super::super::{Intersectable, Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_clip() {
+        let piece = BBox { min: Vec3::zero(), max: Vec3::xyz(10.0, 10.0, 10.0) };
+        let leaf = BBox { min: Vec3::xyz(2.0, -5.0, 2.0), max: Vec3::xyz(4.0, 5.0, 20.0) };
+        let (inside, outside) = clip(&piece, &leaf);
+        assert_eq!(inside, BBox { min: Vec3::xyz(2.0, 0.0, 2.0), max: Vec3::xyz(4.0, 5.0, 10.0) });
+        assert_eq!(outside.len(), 4);
+        let volume: f64 = outside.iter().map(BBox::volume).sum();
+        assert_eq!(volume + inside.volume(), piece.volume());
+        let union = outside.iter().fold(inside, |union, part| union.union(part));
+        assert_eq!(union, piece);
+    }
+
+    #[test]
+    fn test_clipped_tree() {
+        assert!(ClippedRTree::<Sphere>::new(0.5).is_err());
+        assert!(ClippedRTree::<Sphere>::new(f64::NAN).is_err());
+
+        // Small spheres first, then a few that span the whole scene.
+        let mut spheres = random_spheres(2000, 19);
+        spheres.push(Sphere::new(Vec3::xyz(500.0, 500.0, 500.0), 600.0).unwrap());
+        spheres.push(Sphere::new(Vec3::xyz(0.0, 500.0, 500.0), 300.0).unwrap());
+        let mut clipped: ClippedRTree<&Sphere> = ClippedRTree::new(4.0).unwrap();
+        clipped.insert(&spheres[0]);
+        assert_eq!(clipped.fragment_count(), 1);
+        for sphere in spheres[1..2000].iter() {
+            clipped.insert(sphere);
+        }
+        assert_eq!(clipped.fragment_count(), 2000);
+        let giants: Vec<_> = spheres[2000..].iter().map(|giant| clipped.insert(giant)).collect();
+        let mut plain: RTree<&Sphere> = RTree::new();
+        plain.extend(spheres.iter());
+        assert_eq!(clipped.len(), spheres.len());
+        assert!(clipped.fragment_count() > spheres.len());
+        assert!(clipped.fragment_count() <= 2000 + 2 * 16);
+        assert_eq!(clipped.tree.fragments.validate(), Ok(()));
+
+        // The same items turn up as in a plain tree, once each.
+        let sorted = |mut found: Vec<&&Sphere>| -> Vec<*const Sphere> {
+            let mut found: Vec<*const Sphere> = found.drain(..).map(|&s| s as *const Sphere).collect();
+            found.sort();
+            found
+        };
+        let query = BBox { min: Vec3::xyz(100.0, 200.0, 300.0), max: Vec3::xyz(300.0, 400.0, 500.0) };
+        let found = sorted(clipped.iter_bbox(&query).collect());
+        assert_eq!(found, sorted(plain.iter_bbox(&query).collect()));
+        assert!(found.len() > 2);
+        let ray = Ray::new(Vec3::xyz(-10.0, 500.0, 500.0), Vec3::xyz(1.0, 0.1, 0.05));
+        assert_eq!(sorted(clipped.iter_ray(&ray).collect()), sorted(plain.iter_ray(&ray).collect()));
+        assert_eq!(sorted(clipped.iter().collect()), sorted(plain.iter().collect()));
+
+        // Each item is tested at most once per ray.
+        let tested = RefCell::new(Vec::new());
+        let intersect = |sphere: &&Sphere, ray: &Ray| {
+            tested.borrow_mut().push(*sphere as *const Sphere);
+            sphere.intersect(ray).map(|hit| hit.t)
+        };
+        let origin = Vec3::xyz(-1000.0, 0.0, 0.0);
+        let rays: Vec<Ray> = random_spheres(30, 19).iter().map(|s| Ray::new(origin, s.mbr().center() - origin)).collect();
+        for ray in rays.iter() {
+            let expected = plain.closest_hit_with(ray, |sphere, ray| sphere.intersect(ray).map(|hit| hit.t));
+            tested.borrow_mut().clear();
+            let found = clipped.closest_hit_with(ray, intersect);
+            assert_eq!(found.map(|(t, _)| t), expected.map(|(t, _)| t));
+            assert!(found.is_some());
+            let mut tested = tested.borrow_mut();
+            let count = tested.len();
+            tested.sort();
+            tested.dedup();
+            assert_eq!(tested.len(), count);
+        }
+
+        // Removing a giant removes all its pieces.
+        let before = clipped.fragment_count();
+        assert_eq!(clipped.remove(giants[0]).map(|s| s as *const Sphere), Some(&spheres[2000] as *const Sphere));
+        assert!(clipped.remove(giants[0]).is_none());
+        assert!(clipped.get(giants[0]).is_none());
+        assert!(clipped.get(giants[1]).is_some());
+        assert!(clipped.fragment_count() < before - 1);
+        assert_eq!(clipped.len(), spheres.len() - 1);
+        assert_eq!(clipped.tree.fragments.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_clipped_closest_hit() {
+        let mut clipped = ClippedRTree::new(2.0).unwrap();
+        for sphere in random_spheres(1000, 20) {
+            clipped.insert(sphere);
+        }
+        clipped.insert(Sphere::new(Vec3::xyz(500.0, -2000.0, 500.0), 2050.0).unwrap());
+        assert!(clipped.fragment_count() > clipped.len());
+        let mut plain: RTree<Sphere> = RTree::new();
+        plain.extend(random_spheres(1000, 20));
+        plain.insert(Sphere::new(Vec3::xyz(500.0, -2000.0, 500.0), 2050.0).unwrap());
+
+        let origin = Vec3::xyz(500.0, 1500.0, 500.0);
+        let mut hits = 0;
+        for sphere in random_spheres(20, 21) {
+            let ray = Ray::new(origin, sphere.mbr().center() - origin);
+            let expected = plain.closest_hit(&ray).map(|(t, sphere, hit)| (t, sphere.mbr(), hit));
+            hits += expected.is_some() as usize;
+            assert_eq!(clipped.closest_hit(&ray).map(|(t, sphere, hit)| (t, sphere.mbr(), hit)), expected);
+        }
+        assert!(hits >= 10);
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+use std::slice::Iter as SliceIter;
+
+use arena::Arena;
+use bbox::BBox;
+use shapes::Plane;
+use vec3::Vec3;
+use super::{Mbr, RTree, RTreeNode, NodeStorage, LeafItem};
+
+/// Where a box lies relative to a convex region.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Side {
+    Outside,
+    Straddling,
+    Inside,
+}
+
+/// Classify `bbox` against the region in front of all of `planes`.  A box
+/// is only `Outside` when it lies entirely behind one plane, so a box just
+/// outside a corner of the region can still be `Straddling`.
+pub fn classify(planes: &[Plane], bbox: &BBox) -> Side {
+    let mut side = Side::Inside;
+    for plane in planes.iter() {
+        let n = plane.normal();
+        // The corners furthest along and against the normal.
+        let corner = |towards: bool| {
+            let pick = |along: f64, min: f64, max: f64| if (along >= 0.0) == towards { max } else { min };
+            Vec3::xyz(
+                pick(n.x, bbox.min.x, bbox.max.x),
+                pick(n.y, bbox.min.y, bbox.max.y),
+                pick(n.z, bbox.min.z, bbox.max.z))
+        };
+        if plane.signed_distance(&corner(true)) < 0.0 {
+            return Side::Outside;
+        }
+        if plane.signed_distance(&corner(false)) < 0.0 {
+            side = Side::Straddling;
+        }
+    }
+    side
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Iterate over all items whose bounding box reaches in front of
+    /// `plane`, on the side its normal points to.
+    pub fn iter_halfspace(&self, plane: Plane) -> ConvexIter<'_, T> {
+        self.iter_convex(&[plane])
+    }
+
+    /// Iterate over all items whose bounding box may overlap the convex
+    /// region in front of all of `planes`.  Boxes are only rejected when
+    /// they lie entirely behind one plane, so boxes just outside a corner
+    /// of the region can still be yielded.  Subtrees entirely inside the
+    /// region are yielded without testing each item.
+    pub fn iter_convex(&self, planes: &[Plane]) -> ConvexIter<'_, T> {
+        let mut stack = Vec::new();
+        if let Some(root) = self.root_node() {
+            stack.push((root, false));
+        }
+        ConvexIter {
+            nodes: &self.nodes,
+            stack,
+            leaf_iter: None,
+            planes: planes.to_vec(),
+        }
+    }
+}
+
+/// A query against a convex region.  Created by `RTree::iter_halfspace`,
+/// `RTree::iter_convex` and `RTree::iter_frustum`.
+pub struct ConvexIter<'a, T> where T: Mbr+'a {
+    nodes: &'a Arena<T, 3>,
+    // Nodes still to visit, paired with whether the node is already known
+    // to lie inside the region.
+    stack: Vec<(&'a RTreeNode<T>, bool)>,
+    leaf_iter: Option<(SliceIter<'a, LeafItem<T>>, bool)>,
+    planes: Vec<Plane>,
+}
+
+impl<'a, T> fmt::Debug for ConvexIter<'a, T> where T: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConvexIter").field("planes", &self.planes).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> Iterator for ConvexIter<'a, T> where T: Mbr+'a {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            let planes = &self.planes;
+            if let Some((ref mut leaf_iter, inside)) = self.leaf_iter {
+                if let Some(val) = leaf_iter.find(|x| inside || classify(planes, &x.bbox) != Side::Outside) {
+                    return Some(&val.item);
+                }
+            }
+
+            let (node, inside) = self.stack.pop()?;
+            let inside = inside || match classify(planes, &node.bbox) {
+                Side::Outside => continue,
+                Side::Straddling => false,
+                Side::Inside => true,
+            };
+            match node.storage {
+                NodeStorage::Interior(ref children) => {
+                    for child in self.nodes.children(children) {
+                        self.stack.push((child, inside));
+                    }
+                }
+                NodeStorage::Leaf(ref items) => {
+                    self.leaf_iter = Some((items.iter(), inside))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::shapes::Plane;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_iter_halfspace() {
+        let spheres = random_spheres(3000, 29);
+        let plane = Plane::new(Vec3::xyz(300.0, 0.0, 0.0), Vec3::xyz(-1.0, -1.0, 0.0)).unwrap();
+        let expected = spheres.iter()
+            .filter(|s| {
+                let bbox = s.mbr();
+                plane.signed_distance(&Vec3::xyz(bbox.min.x, bbox.min.y, 0.0)) >= 0.0
+            })
+            .count();
+        assert!(0 < expected && expected < spheres.len());
+
+        let tree: RTree<Sphere> = RTree::bulk_load(spheres);
+        assert_eq!(tree.iter_halfspace(plane).count(), expected);
+    }
+
+    #[test]
+    fn test_iter_convex() {
+        let spheres = random_spheres(3000, 31);
+        // The slab 200 <= x <= 400, cut diagonally by y <= z.
+        let planes = [
+            Plane::new(Vec3::xyz(200.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0)).unwrap(),
+            Plane::new(Vec3::xyz(400.0, 0.0, 0.0), Vec3::xyz(-1.0, 0.0, 0.0)).unwrap(),
+            Plane::new(Vec3::zero(), Vec3::xyz(0.0, -1.0, 1.0)).unwrap(),
+        ];
+        let reaches = |s: &Sphere| {
+            let bbox = s.mbr();
+            bbox.max.x >= 200.0 && bbox.min.x <= 400.0 && bbox.min.y <= bbox.max.z
+        };
+        let expected = spheres.iter().filter(|s| reaches(s)).count();
+        assert!(0 < expected && expected < spheres.len());
+
+        let tree: RTree<Sphere> = RTree::bulk_load(spheres);
+        assert_eq!(tree.iter_convex(&planes).count(), expected);
+        assert!(tree.iter_convex(&planes).all(reaches));
+        assert_eq!(tree.iter_convex(&[]).count(), tree.len());
+        let below = Plane::new(Vec3::xyz(100.0, 0.0, 0.0), Vec3::xyz(-1.0, 0.0, 0.0)).unwrap();
+        assert_eq!(tree.iter_convex(&[planes[0], below]).count(), 0);
+    }
+}
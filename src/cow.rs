@@ -0,0 +1,392 @@
+use std::fmt;
+use std::mem;
+use std::slice::Iter as SliceIter;
+use std::sync::Arc;
+
+use arena::{Arena, NodeId};
+use bbox::BBox;
+use iter_mut::Query;
+use ray::Ray;
+use super::{Mbr, RTree, RTreeConfig, NodeStorage};
+use super::util;
+
+/// A child of a copy-on-write node: a shared node or item, along with its
+/// bounding box.
+struct Entry<N> {
+    bbox: BBox,
+    node: Arc<N>,
+}
+
+impl<N> Clone for Entry<N> {
+    fn clone(&self) -> Entry<N> {
+        Entry {
+            bbox: self.bbox,
+            node: self.node.clone(),
+        }
+    }
+}
+
+impl<N> Mbr for Entry<N> {
+    fn mbr(&self) -> BBox {
+        self.bbox
+    }
+}
+
+enum CowNode<T> {
+    Interior(Vec<Entry<CowNode<T>>>),
+    Leaf(Vec<Entry<T>>),
+}
+
+// Cloning a node only clones its list of children, never the children
+// themselves.
+impl<T> Clone for CowNode<T> {
+    fn clone(&self) -> CowNode<T> {
+        match *self {
+            CowNode::Interior(ref children) => CowNode::Interior(children.clone()),
+            CowNode::Leaf(ref items) => CowNode::Leaf(items.clone()),
+        }
+    }
+}
+
+/// Split the overfull `entries` of the node `into`, returning the new
+/// sibling.
+fn split_entries<E>(config: &RTreeConfig, into: &mut BBox, entries: &mut Vec<E>) -> (BBox, Vec<E>) where E: Mbr {
+    let (lbox, lefts, rbox, rights) = util::split(config, mem::take(entries));
+    *into = lbox;
+    *entries = lefts;
+    (rbox, rights)
+}
+
+/// Insert `item` below `entry`, copying each node on the way down that a
+/// snapshot still shares.  Returns the new sibling if `entry` had to split.
+fn insert<T>(entry: &mut Entry<CowNode<T>>, item: Entry<T>, config: &RTreeConfig) -> Option<Entry<CowNode<T>>> {
+    entry.bbox = entry.bbox.union(&item.bbox);
+    match *Arc::make_mut(&mut entry.node) {
+        CowNode::Interior(ref mut children) => {
+            let best = util::best_fit(item.bbox, children.iter().map(|c| c.bbox)).expect("interior nodes must have children");
+            let sibling = insert(&mut children[best], item, config)?;
+            children.push(sibling);
+            if children.len() <= config.max_entries() {
+                return None;
+            }
+            let (bbox, rights) = split_entries(config, &mut entry.bbox, children);
+            Some(Entry { bbox, node: Arc::new(CowNode::Interior(rights)) })
+        },
+        CowNode::Leaf(ref mut items) => {
+            items.push(item);
+            if items.len() <= config.max_entries() {
+                return None;
+            }
+            let (bbox, rights) = split_entries(config, &mut entry.bbox, items);
+            Some(Entry { bbox, node: Arc::new(CowNode::Leaf(rights)) })
+        },
+    }
+}
+
+fn from_node<T>(nodes: &mut Arena<T, 3>, node: NodeId) -> Entry<CowNode<T>> where T: Mbr {
+    let node = nodes.take(node);
+    let cow = match node.storage {
+        NodeStorage::Interior(children) => CowNode::Interior(children.into_iter().map(|c| from_node(nodes, c)).collect()),
+        NodeStorage::Leaf(items) => CowNode::Leaf(items.into_iter()
+            .map(|leaf| Entry { bbox: leaf.bbox, node: Arc::new(leaf.item) })
+            .collect()),
+    };
+    Entry {
+        bbox: node.bbox,
+        node: Arc::new(cow),
+    }
+}
+
+/// A persistent R-tree whose nodes are shared between versions.
+///
+/// `snapshot` is a cheap copy of the tree, which can be handed to readers
+/// on other threads while the writer keeps inserting.  An insertion copies
+/// only the nodes on the path from the root to the item's leaf that are
+/// still shared with a snapshot; everything else stays shared.  Created
+/// empty or by `RTree::into_cow`.
+pub struct CowRTree<T> where T: Mbr {
+    root: Option<Entry<CowNode<T>>>,
+    config: RTreeConfig,
+    len: usize,
+}
+
+impl<T> Clone for CowRTree<T> where T: Mbr {
+    fn clone(&self) -> CowRTree<T> {
+        CowRTree {
+            root: self.root.clone(),
+            config: self.config,
+            len: self.len,
+        }
+    }
+}
+
+impl<T> CowRTree<T> where T: Mbr {
+    pub fn new() -> CowRTree<T> {
+        CowRTree::with_config(RTreeConfig::default())
+    }
+
+    pub fn with_config(config: RTreeConfig) -> CowRTree<T> {
+        CowRTree {
+            root: None,
+            config,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// A read-only copy of the tree as it is now, unaffected by later
+    /// insertions into this one.  Takes constant time.
+    pub fn snapshot(&self) -> CowRTree<T> {
+        self.clone()
+    }
+
+    /// Add `item` to the tree.
+    pub fn insert(&mut self, item: T) {
+        let item = Entry { bbox: item.mbr(), node: Arc::new(item) };
+        self.len += 1;
+        let root = match self.root {
+            Some(ref mut root) => root,
+            None => {
+                self.root = Some(Entry { bbox: item.bbox, node: Arc::new(CowNode::Leaf(vec![item])) });
+                return;
+            },
+        };
+        if let Some(sibling) = insert(root, item, &self.config) {
+            // The root split, so grow the tree by one level.
+            let bbox = root.bbox.union(&sibling.bbox);
+            *root = Entry { bbox, node: Arc::new(CowNode::Interior(vec![root.clone(), sibling])) };
+        }
+    }
+
+    /// Iterate over every item in the tree.
+    pub fn iter(&self) -> CowIter<'_, T> {
+        CowIter::new(self, Query::All)
+    }
+
+    /// Iterate over the items whose bounding box `ray` intersects.
+    pub fn iter_ray(&self, ray: &Ray) -> CowIter<'_, T> {
+        CowIter::new(self, Query::Ray(*ray))
+    }
+
+    /// Iterate over the items whose bounding box overlaps `query`.
+    pub fn iter_bbox(&self, query: &BBox) -> CowIter<'_, T> {
+        CowIter::new(self, Query::Envelope(*query))
+    }
+}
+
+// The nodes are left out: they may be shared with any number of snapshots.
+impl<T> fmt::Debug for CowRTree<T> where T: Mbr+fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CowRTree")
+            .field("len", &self.len)
+            .field("config", &self.config)
+            .field("items", &self.iter().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<T> Default for CowRTree<T> where T: Mbr {
+    fn default() -> CowRTree<T> {
+        CowRTree::new()
+    }
+}
+
+/// Items of a `CowRTree` matching a query.  Created by `CowRTree::iter`,
+/// `CowRTree::iter_ray` and `CowRTree::iter_bbox`.
+pub struct CowIter<'a, T> where T: Mbr+'a {
+    stack: Vec<&'a CowNode<T>>,
+    leaf_iter: Option<SliceIter<'a, Entry<T>>>,
+    query: Query,
+}
+
+impl<'a, T> CowIter<'a, T> where T: Mbr+'a {
+    fn new(tree: &'a CowRTree<T>, query: Query) -> CowIter<'a, T> {
+        let mut stack = Vec::new();
+        if let Some(ref root) = tree.root {
+            if query.matches(&root.bbox) {
+                stack.push(&*root.node);
+            }
+        }
+        CowIter {
+            stack,
+            leaf_iter: None,
+            query,
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for CowIter<'a, T> where T: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CowIter").field("query", &self.query).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> Iterator for CowIter<'a, T> where T: Mbr+'a {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            let query = &self.query;
+            if let Some(item) = self.leaf_iter.as_mut().and_then(|iter| iter.find(|e| query.matches(&e.bbox))) {
+                return Some(&item.node);
+            }
+
+            match *self.stack.pop()? {
+                CowNode::Interior(ref children) => {
+                    self.stack.extend(children.iter().filter(|c| query.matches(&c.bbox)).map(|c| &*c.node));
+                },
+                CowNode::Leaf(ref items) => self.leaf_iter = Some(items.iter()),
+            }
+        }
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Convert the tree into a `CowRTree`, keeping its node hierarchy.
+    /// Item ids are dropped.
+    pub fn into_cow(mut self) -> CowRTree<T> {
+        let len = self.len();
+        let nodes = &mut self.nodes;
+        CowRTree {
+            len,
+            config: self.config,
+            root: self.root.map(|root| from_node(nodes, root)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::{CowNode, CowRTree, Entry};
+    use super::super::{Mbr, RTree, RTreeConfig};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    fn collect_nodes<T>(entry: &Entry<CowNode<T>>, out: &mut Vec<*const CowNode<T>>) {
+        out.push(Arc::as_ptr(&entry.node));
+        if let CowNode::Interior(ref children) = *entry.node {
+            for child in children.iter() {
+                collect_nodes(child, out);
+            }
+        }
+    }
+
+    /// The number of nodes in `a` which are not shared with `b`.
+    fn unshared<T>(a: &Entry<CowNode<T>>, b: &Entry<CowNode<T>>) -> usize {
+        let (mut xs, mut ys) = (Vec::new(), Vec::new());
+        collect_nodes(a, &mut xs);
+        collect_nodes(b, &mut ys);
+        let ys: HashSet<_> = ys.into_iter().collect();
+        xs.iter().filter(|x| !ys.contains(x)).count()
+    }
+
+    fn check<T>(entry: &Entry<CowNode<T>>, config: &RTreeConfig) -> usize where T: Mbr {
+        match *entry.node {
+            CowNode::Interior(ref children) => {
+                assert!(children.len() <= config.max_entries());
+                children.iter().map(|child| {
+                    assert!(entry.bbox.contains(&child.bbox));
+                    check(child, config)
+                }).sum()
+            },
+            CowNode::Leaf(ref items) => {
+                assert!(items.len() <= config.max_entries());
+                for item in items.iter() {
+                    assert!(entry.bbox.contains(&item.bbox));
+                    assert!(item.bbox.contains(&item.node.mbr()));
+                }
+                items.len()
+            },
+        }
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let config = RTreeConfig::with_max_entries(8).unwrap();
+        let mut tree = CowRTree::with_config(config);
+        for sphere in random_spheres(1000, 16) {
+            tree.insert(sphere);
+        }
+        assert_eq!(check(tree.root.as_ref().unwrap(), &config), 1000);
+
+        let snapshot = tree.snapshot();
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 100.0, 100.0),
+            max: Vec3::xyz(700.0, 700.0, 700.0),
+        };
+        let before = snapshot.iter_bbox(&envelope).count();
+
+        for sphere in random_spheres(500, 17) {
+            tree.insert(sphere);
+        }
+        assert_eq!(check(tree.root.as_ref().unwrap(), &config), 1500);
+        assert_eq!(check(snapshot.root.as_ref().unwrap(), &config), 1000);
+        assert_eq!(snapshot.len(), 1000);
+        assert_eq!(snapshot.iter().count(), 1000);
+        assert_eq!(snapshot.iter_bbox(&envelope).count(), before);
+        assert_eq!(tree.iter().count(), 1500);
+        assert!(tree.iter_bbox(&envelope).count() > before);
+    }
+
+    #[test]
+    fn test_insert_copies_path() {
+        let mut tree = CowRTree::with_config(RTreeConfig::with_max_entries(8).unwrap());
+        for sphere in random_spheres(1000, 18) {
+            tree.insert(sphere);
+        }
+        let snapshot = tree.snapshot();
+        let sphere = Sphere::new(Vec3::xyz(500.0, 500.0, 500.0), 1.0).unwrap();
+        tree.insert(sphere);
+
+        let copied = unshared(tree.root.as_ref().unwrap(), snapshot.root.as_ref().unwrap());
+        let mut depth = 0;
+        let mut node = &*tree.root.as_ref().unwrap().node;
+        loop {
+            depth += 1;
+            match *node {
+                CowNode::Interior(ref children) => node = &*children[0].node,
+                CowNode::Leaf(_) => break,
+            }
+        }
+        // One copy per level, plus a new node for each split on the way up.
+        assert!(depth <= copied && copied <= 2 * depth + 1, "{} nodes copied, depth {}", copied, depth);
+    }
+
+    #[test]
+    fn test_concurrent_snapshot_readers() {
+        let mut tree = RTree::bulk_load(random_spheres(2000, 19)).into_cow();
+        assert_eq!(tree.len(), 2000);
+        let envelope = BBox {
+            min: Vec3::zero(),
+            max: Vec3::xyz(500.0, 500.0, 500.0),
+        };
+        let snapshot = tree.snapshot();
+        let expected = snapshot.iter_bbox(&envelope).count();
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let snapshot = snapshot.clone();
+            thread::spawn(move || {
+                (0..20).all(|_| snapshot.iter_bbox(&envelope).count() == expected)
+            })
+        }).collect();
+        for sphere in random_spheres(1000, 20) {
+            tree.insert(sphere);
+        }
+        for reader in readers {
+            assert!(reader.join().unwrap());
+        }
+        assert_eq!(tree.len(), 3000);
+    }
+}
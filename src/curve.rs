@@ -0,0 +1,283 @@
+//! Space-filling curves, for ordering points so that ones near each other
+//! in space tend to be near each other in the order.  The tree uses them
+//! for Hilbert packing and `iter_sorted`; chunking or streaming items in
+//! the same order keeps them in step with it:
+//!
+//! ```
+//! use rtree::prelude::*;
+//! use rtree::curve::{hilbert_key, morton_decode_3d, morton_encode_3d};
+//!
+//! let boxes = [BBox::from_point(Vec3::xyz(9.0, 9.0, 9.0)), BBox::from_point(Vec3::zero())];
+//! let mut sorted = boxes.to_vec();
+//! sorted.sort_by_key(|bbox| hilbert_key(&bbox.center()));
+//! assert_eq!(sorted[0], boxes[1]);
+//!
+//! let code = morton_encode_3d(5, 0, 3);
+//! assert_eq!(morton_decode_3d(code), (5, 0, 3));
+//! ```
+//!
+//! `hilbert_key` and `morton_key` quantize coordinates without needing
+//! bounds; `quantize_in` fits a grid to known bounds instead, for use with
+//! `hilbert_index`, `morton_index` and the 3D Morton functions.
+
+use aabb::Aabb;
+use point::Point;
+
+/// Map `x` to an integer with the same order, so that coordinates can be
+/// quantized without knowing their range: negative numbers sort below
+/// positive ones, and larger magnitudes further from zero.
+fn ordered_bits(x: f64) -> u64 {
+    let bits = x.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | 1 << 63
+    }
+}
+
+/// The position of the cell `coords` along a Hilbert curve filling a grid
+/// of `2^bits` cells per axis.  Consecutive positions are adjacent cells.
+/// `bits * N` must not exceed 64.
+///
+/// This is Skilling's transposition: the coordinates are turned into the
+/// curve position's bits in place, then interleaved.
+pub fn hilbert_index<const N: usize>(mut coords: [u64; N], bits: u32) -> u64 {
+    if N == 0 || bits == 0 {
+        return 0;
+    }
+    let top = 1 << (bits - 1);
+
+    let mut q = top;
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..N {
+            if coords[i] & q != 0 {
+                coords[0] ^= p;
+            } else {
+                let t = (coords[0] ^ coords[i]) & p;
+                coords[0] ^= t;
+                coords[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+
+    for i in 1..N {
+        coords[i] ^= coords[i - 1];
+    }
+    let mut t = 0;
+    let mut q = top;
+    while q > 1 {
+        if coords[N - 1] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    for coord in coords.iter_mut() {
+        *coord ^= t;
+    }
+
+    let mut index = 0;
+    for bit in (0..bits).rev() {
+        for coord in coords.iter() {
+            index = index << 1 | (coord >> bit & 1);
+        }
+    }
+    index
+}
+
+/// The position of the cell `coords` along a Morton (Z-order) curve
+/// filling a grid of `2^bits` cells per axis: the coordinates' bits
+/// interleaved, with the first coordinate's in the lowest position of each
+/// group.  `bits * N` must not exceed 64.
+pub fn morton_index<const N: usize>(coords: [u64; N], bits: u32) -> u64 {
+    let mut index = 0;
+    for bit in (0..bits).rev() {
+        for coord in coords.iter().rev() {
+            index = index << 1 | (coord >> bit & 1);
+        }
+    }
+    index
+}
+
+/// The Morton code of `point`, quantized like `hilbert_key`.
+pub fn morton_key<const N: usize>(point: &Point<N>) -> u64 {
+    let bits = (64 / N.max(1)) as u32;
+    morton_index(quantize(point, bits), bits)
+}
+
+/// Every coordinate of `point` quantized to `bits` bits, keeping their
+/// order, as `hilbert_key` and `morton_key` do.  Cells are finer near the
+/// origin and coarser far from it.  `bits` must not exceed 64.
+pub fn quantize<const N: usize>(point: &Point<N>, bits: u32) -> [u64; N] {
+    let mut coords = [0; N];
+    if bits > 0 {
+        for (coord, &x) in coords.iter_mut().zip(point.coords.iter()) {
+            *coord = ordered_bits(x) >> (64 - bits);
+        }
+    }
+    coords
+}
+
+/// The cell of a grid of `2^bits` equal cells per axis over `bounds`
+/// which holds `point`.  Points outside `bounds` are clamped to its
+/// nearest cell.  `bits` must be below 64.
+pub fn quantize_in<const N: usize>(point: &Point<N>, bounds: &Aabb<N>, bits: u32) -> [u64; N] {
+    let top = (1u64 << bits) - 1;
+    let mut coords = [0; N];
+    for (i, coord) in coords.iter_mut().enumerate() {
+        let (min, max) = (bounds.min.coords[i], bounds.max.coords[i]);
+        let fraction = if max > min { (point.coords[i] - min) / (max - min) } else { 0.0 };
+        *coord = ((fraction * (top + 1) as f64) as u64).min(top);
+    }
+    coords
+}
+
+/// Spread the low 21 bits of `x` out to every third bit.
+fn spread_3(x: u32) -> u64 {
+    let mut x = x as u64 & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+/// Gather every third bit of `x` into the low 21 bits, undoing `spread_3`.
+fn compact_3(x: u64) -> u32 {
+    let mut x = x & 0x1249_2492_4924_9249;
+    x = (x | x >> 2) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x >> 4) & 0x100f_00f0_0f00_f00f;
+    x = (x | x >> 8) & 0x001f_0000_ff00_00ff;
+    x = (x | x >> 16) & 0x001f_0000_0000_ffff;
+    x = (x | x >> 32) & 0x1f_ffff;
+    x as u32
+}
+
+/// The Morton code of the cell `(x, y, z)` of a grid of `2^21` cells per
+/// axis, with `x` in the lowest bit of each group.  Only the low 21 bits
+/// of each coordinate are used.  This is `morton_index([x, y, z], 21)`,
+/// computed without a loop.
+pub fn morton_encode_3d(x: u32, y: u32, z: u32) -> u64 {
+    spread_3(x) | spread_3(y) << 1 | spread_3(z) << 2
+}
+
+/// The cell whose Morton code is `code`, undoing `morton_encode_3d`.
+pub fn morton_decode_3d(code: u64) -> (u32, u32, u32) {
+    (compact_3(code), compact_3(code >> 1), compact_3(code >> 2))
+}
+
+/// The Hilbert value of `point`, for ordering items along the curve.
+///
+/// Every coordinate is quantized through its order-preserving bit pattern,
+/// so no bounds are needed and the value of an item never changes as the
+/// tree grows.  Cells are finer near the origin and coarser far from it,
+/// with the same number of cells between each power of two.
+pub fn hilbert_key<const N: usize>(point: &Point<N>) -> u64 {
+    let bits = (64 / N.max(1)) as u32;
+    hilbert_index(quantize(point, bits), bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::point::Point;
+    use ::aabb::Aabb;
+    use super::{hilbert_index, hilbert_key, morton_index, morton_key, ordered_bits};
+    use super::{morton_decode_3d, morton_encode_3d, quantize, quantize_in};
+
+    /// Walk the whole curve on a grid of `2^bits` cells per axis and check
+    /// that it visits every cell once, moving one step at a time.
+    fn check_curve<const N: usize>(bits: u32) {
+        let side = 1u64 << bits;
+        let cells = side.pow(N as u32);
+        let mut path = vec![None; cells as usize];
+        for cell in 0..cells {
+            let mut coords = [0; N];
+            let mut rest = cell;
+            for coord in coords.iter_mut() {
+                *coord = rest % side;
+                rest /= side;
+            }
+            let index = hilbert_index(coords, bits) as usize;
+            assert!(path[index].is_none(), "{:?} reuses index {}", coords, index);
+            path[index] = Some(coords);
+        }
+        for step in path.windows(2) {
+            let (a, b) = (step[0].unwrap(), step[1].unwrap());
+            let distance: u64 = a.iter().zip(b.iter()).map(|(&x, &y)| x.abs_diff(y)).sum();
+            assert_eq!(distance, 1, "{:?} -> {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_hilbert_index() {
+        check_curve::<1>(4);
+        check_curve::<2>(1);
+        check_curve::<2>(4);
+        check_curve::<3>(3);
+        check_curve::<4>(2);
+        assert_eq!(hilbert_index([0, 0], 1), 0);
+        assert_eq!(hilbert_index::<0>([], 8), 0);
+    }
+
+    #[test]
+    fn test_hilbert_key() {
+        let xs = [f64::NEG_INFINITY, -1e9, -2.5, -0.0, 0.0, 1e-300, 0.75, 3.0, 1e9, f64::INFINITY];
+        for pair in xs.windows(2) {
+            assert!(ordered_bits(pair[0]) <= ordered_bits(pair[1]), "{} > {}", pair[0], pair[1]);
+        }
+
+        // Nearby points are nearby on the curve.
+        let a = hilbert_key(&Point::new([100.0, 200.0, 300.0]));
+        let b = hilbert_key(&Point::new([100.1, 200.0, 300.0]));
+        let far = hilbert_key(&Point::new([-100.0, 200.0, 300.0]));
+        assert!(a.abs_diff(b) < a.abs_diff(far));
+        assert_eq!(hilbert_key(&Point::new([1.5])), ordered_bits(1.5));
+    }
+
+    #[test]
+    fn test_morton() {
+        assert_eq!(morton_index([0b11, 0b00], 2), 0b0101);
+        assert_eq!(morton_index([0b01, 0b10, 0b11], 2), 0b110_101);
+        assert_eq!(morton_index::<0>([], 8), 0);
+
+        // Quadrants come in Z order, whatever the signs.
+        let keys: Vec<u64> = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].iter()
+            .map(|&(x, y)| morton_key(&Point::xy(x, y)))
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_morton_3d() {
+        assert_eq!(morton_encode_3d(1, 0, 0), 0b001);
+        assert_eq!(morton_encode_3d(0, 0, 1), 0b100);
+        assert_eq!(morton_encode_3d(3, 1, 2), 0b101_011);
+        let max = (1 << 21) - 1;
+        assert_eq!(morton_encode_3d(max, max, max), (1 << 63) - 1);
+        assert_eq!(morton_encode_3d(max + 1, 0, 0), 0);
+
+        let cells = [(0, 0, 0), (5, 0, 3), (123_456, 7, 2_000_000), (max, 0, max), (max, max, max)];
+        for &(x, y, z) in cells.iter() {
+            let code = morton_encode_3d(x, y, z);
+            assert_eq!(morton_decode_3d(code), (x, y, z));
+            assert_eq!(code, morton_index([x as u64, y as u64, z as u64], 21));
+        }
+
+        let point = Point::new([-3.5, 0.25, 1e6]);
+        let [x, y, z] = quantize(&point, 21);
+        assert_eq!(morton_encode_3d(x as u32, y as u32, z as u32), morton_key(&point));
+    }
+
+    #[test]
+    fn test_quantize_in() {
+        let bounds = Aabb { min: Point::xy(0.0, -10.0), max: Point::xy(8.0, 10.0) };
+        assert_eq!(quantize_in(&Point::xy(0.0, -10.0), &bounds, 3), [0, 0]);
+        assert_eq!(quantize_in(&Point::xy(1.0, 0.0), &bounds, 3), [1, 4]);
+        assert_eq!(quantize_in(&Point::xy(8.0, 10.0), &bounds, 3), [7, 7]);
+        assert_eq!(quantize_in(&Point::xy(-5.0, 50.0), &bounds, 3), [0, 7]);
+        let flat = Aabb { min: Point::xy(1.0, 1.0), max: Point::xy(1.0, 2.0) };
+        assert_eq!(quantize_in(&Point::xy(1.0, 2.0), &flat, 4), [0, 15]);
+    }
+}
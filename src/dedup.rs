@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Wraps a query iterator so that each item is yielded at most once.
+///
+/// Items are identified by their address inside the tree, so this is
+/// useful when the same item is reachable several times, e.g. when the
+/// results of several tiled or wrapped-around queries are chained together.
+pub struct Dedup<'a, T, I> where T: 'a, I: Iterator<Item=&'a T> {
+    inner: I,
+    seen: HashSet<usize>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T, I> Dedup<'a, T, I> where T: 'a, I: Iterator<Item=&'a T> {
+    pub fn new(inner: I) -> Dedup<'a, T, I> {
+        Dedup {
+            inner,
+            seen: HashSet::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, I> fmt::Debug for Dedup<'a, T, I> where T: 'a, I: Iterator<Item=&'a T>+fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dedup").field("inner", &self.inner).field("seen", &self.seen.len()).finish()
+    }
+}
+
+impl<'a, T, I> Iterator for Dedup<'a, T, I> where T: 'a, I: Iterator<Item=&'a T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let seen = &mut self.seen;
+        self.inner.find(|item| seen.insert(*item as *const T as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::vec3::Vec3;
+    use ::ray::Ray;
+    use super::super::RTree;
+    use super::super::test_helpers::Sphere;
+    use super::Dedup;
+
+    #[test]
+    fn test_dedup_chained_queries() {
+        let ray = Ray::new(Vec3::xyz(0.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0));
+
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..4 {
+            let x = 10.0 * (i + 1) as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), 1.0).unwrap());
+        }
+
+        let chained = spheres.iter_ray(&ray).chain(spheres.iter_ray(&ray));
+        assert_eq!(chained.count(), 8);
+
+        let chained = spheres.iter_ray(&ray).chain(spheres.iter_ray(&ray));
+        assert_eq!(Dedup::new(chained).count(), 4);
+    }
+}
@@ -0,0 +1,120 @@
+use std::ptr;
+
+use aabb::Aabb;
+use super::SplitStrategy;
+
+/// A function called on every node split of trees whose configuration
+/// names it; see `RTreeConfig::with_split_observer`.  It is a plain
+/// function, so that configurations stay `Copy`: gather what it is told in
+/// a static or a thread local.
+pub type SplitObserver = fn(&SplitEvent);
+
+/// The observer of a configuration, if any.  Observers compare by
+/// address, which is the best that can be done for functions: a function
+/// may have several.
+#[derive(Clone, Copy, Debug, Default, Eq)]
+pub(crate) struct Observer(pub Option<SplitObserver>);
+
+impl PartialEq for Observer {
+    fn eq(&self, other: &Observer) -> bool {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => ptr::fn_addr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+/// The bounds of a box involved in a split, one coordinate per dimension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SplitBox<'a> {
+    pub min: &'a [f64],
+    pub max: &'a [f64],
+}
+
+impl<'a> SplitBox<'a> {
+    pub(crate) fn new<const N: usize>(bbox: &'a Aabb<N>) -> SplitBox<'a> {
+        SplitBox { min: &bbox.min.coords, max: &bbox.max.coords }
+    }
+}
+
+/// What a node split did, as told to a `SplitObserver`, for comparing
+/// split strategies on real data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SplitEvent<'a> {
+    pub strategy: SplitStrategy,
+    /// The boxes of the two entries the groups were started from, for the
+    /// strategies which pick seeds: `Quadratic` and `Linear`.
+    pub seeds: Option<(SplitBox<'a>, SplitBox<'a>)>,
+    /// The box of the node kept, and the number of entries left in it.
+    pub left: SplitBox<'a>,
+    pub left_len: usize,
+    /// The box of the new sibling, and the number of entries moved to it.
+    pub right: SplitBox<'a>,
+    pub right_len: usize,
+    /// The volume, or in two dimensions the area, the two boxes share.
+    pub overlap: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::ptr;
+
+    use super::{SplitEvent, SplitObserver};
+    use super::super::{RTree, RTreeBuilder, SplitStrategy};
+    use super::super::test_helpers::{Sphere, check_tree, random_spheres};
+
+    /// Whether a split had seeds, the sizes of its halves, and the overlap
+    /// it reported and the one its boxes give.
+    type Recorded = (bool, usize, usize, f64, f64);
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<Recorded>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Record the seeds, sizes and overlap of a split, and check the
+    /// overlap against the boxes.
+    fn record(event: &SplitEvent) {
+        let shared: f64 = (0..event.left.min.len())
+            .map(|axis| {
+                let low = event.left.min[axis].max(event.right.min[axis]);
+                let high = event.left.max[axis].min(event.right.max[axis]);
+                (high - low).max(0.0)
+            })
+            .product();
+        EVENTS.with(|events| {
+            events.borrow_mut().push((event.seeds.is_some(), event.left_len, event.right_len, event.overlap, shared));
+        });
+    }
+
+    #[test]
+    fn test_split_observer() {
+        for &(split, seeded) in [(SplitStrategy::Quadratic, true), (SplitStrategy::Linear, true), (SplitStrategy::RStar, false)].iter() {
+            EVENTS.with(|events| events.borrow_mut().clear());
+            let builder = RTreeBuilder::new().max_entries(8).split_strategy(split).split_observer(record);
+            let mut spheres: RTree<Sphere> = builder.build().unwrap();
+            spheres.extend(random_spheres(1000, 18));
+            check_tree(&spheres);
+            assert!(spheres.config().split_observer().is_some_and(|observer| ptr::fn_addr_eq(observer, record as SplitObserver)));
+
+            let events = EVENTS.with(|events| events.borrow().clone());
+            // Every node but the first leaf came out of a split.
+            assert_eq!(events.len() + 1, spheres.nodes.len() - (spheres.depth() - 1), "{:?}", split);
+            for &(has_seeds, left_len, right_len, overlap, shared) in events.iter() {
+                assert_eq!(has_seeds, seeded);
+                assert!(left_len >= 3 && right_len >= 3);
+                assert_eq!(left_len + right_len, 8);
+                assert!((overlap - shared).abs() <= 1e-9 * shared.max(1.0));
+            }
+        }
+
+        // Trees without an observer split as before.
+        let mut spheres: RTree<Sphere> = RTreeBuilder::new().max_entries(8).build().unwrap();
+        spheres.extend(random_spheres(1000, 18));
+        assert!(spheres.config().split_observer().is_none());
+        assert!(EVENTS.with(|events| events.borrow().len()) > 0);
+        EVENTS.with(|events| events.borrow_mut().clear());
+        spheres.extend(random_spheres(1000, 19));
+        assert!(EVENTS.with(|events| events.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::{self, Write};
+
+use arena::Arena;
+use super::{Mbr, RTree, RTreeConfig, RTreeNode, NodeStorage};
+
+struct DotWriter<W> {
+    w: W,
+    node_count: usize,
+}
+
+impl<W> DotWriter<W> where W: Write {
+    fn write_node<T>(&mut self, nodes: &Arena<T, 3>, node: &RTreeNode<T>, config: &RTreeConfig, parent: Option<usize>) -> io::Result<()>
+        where
+            T: Mbr {
+
+        let id = self.node_count;
+        self.node_count += 1;
+        let kind = if node.is_leaf() { "leaf" } else { "node" };
+        writeln!(self.w, "    n{} [label=\"{} {}/{}\\n{} items\\nvolume {}\"];",
+                 id, kind, node.shallow_len(), config.max_entries(), node.deep_len(), node.bbox.volume())?;
+        if let Some(parent) = parent {
+            writeln!(self.w, "    n{} -> n{};", parent, id)?;
+        }
+
+        if let NodeStorage::Interior(ref children) = node.storage {
+            for child in nodes.children(children) {
+                self.write_node(nodes, child, config, Some(id))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Write the node hierarchy as a Graphviz DOT graph, for rendering with
+    /// `dot -Tsvg`.  Each node is labelled with how full it is out of the
+    /// node capacity, the number of items below it, and the volume of its
+    /// bounding box.  Items themselves are left out.  Only available with
+    /// the `dot` feature.
+    pub fn to_dot<W>(&self, w: W) -> io::Result<()> where W: Write {
+        let mut dot = DotWriter {
+            w,
+            node_count: 0,
+        };
+        writeln!(dot.w, "digraph rtree {{")?;
+        writeln!(dot.w, "    node [shape=box];")?;
+        if let Some(root) = self.root_node() {
+            dot.write_node(&self.nodes, root, &self.config, None)?;
+        }
+        writeln!(dot.w, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::vec3::Vec3;
+    use super::super::{RTree, RTreeConfig};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_to_dot() {
+        let mut out = Vec::new();
+        RTree::<Sphere>::new().to_dot(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "digraph rtree {\n    node [shape=box];\n}\n");
+
+        let mut spheres = RTree::with_config(RTreeConfig::with_max_entries(8).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(0.0, 0.0, 0.0), 1.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(2.5, 0.0, 0.0), 0.5).unwrap());
+        let mut out = Vec::new();
+        spheres.to_dot(&mut out).unwrap();
+        let expected = concat!(
+            "digraph rtree {\n",
+            "    node [shape=box];\n",
+            "    n0 [label=\"leaf 2/8\\n2 items\\nvolume 16\"];\n",
+            "}\n");
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        let spheres = RTree::bulk_load_with_config(random_spheres(1000, 59), RTreeConfig::with_max_entries(8).unwrap());
+        let mut out = Vec::new();
+        spheres.to_dot(&mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        let nodes = dot.matches("[label=").count();
+        assert_eq!(dot.matches(" -> ").count(), nodes - 1);
+        assert_eq!(dot.matches("leaf ").count(), spheres.quality().leaf_count);
+        assert!(dot.contains("n0 [label=\"node ") && dot.contains("\\n1000 items\\n"));
+    }
+}
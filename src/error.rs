@@ -0,0 +1,25 @@
+use std::error;
+use std::fmt;
+
+/// Why an item could not be added to a tree by `RTree::try_insert`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The item's bounding box has a NaN coordinate.
+    NanBounds,
+    /// The item's bounding box, or its union with the tree's bounds, has an
+    /// infinite or undefined volume.  This happens with infinite
+    /// coordinates, or finite ones so far apart that the volume overflows,
+    /// and leaves insertion unable to compare candidate nodes.
+    UnboundedVolume,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NanBounds => write!(f, "bounding box has a NaN coordinate"),
+            Error::UnboundedVolume => write!(f, "bounding box volume is not finite"),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,223 @@
+use std::io::{self, Write};
+
+use arena::Arena;
+use bbox::BBox;
+use super::{Mbr, RTree, RTreeNode, NodeStorage};
+
+/// Write a float as a JSON number, or `null` if it has no JSON spelling.
+fn write_number<W>(w: &mut W, value: f64) -> io::Result<()> where W: Write {
+    if value.is_finite() {
+        write!(w, "{}", value)
+    } else {
+        write!(w, "null")
+    }
+}
+
+fn write_bbox<W>(w: &mut W, bbox: &BBox) -> io::Result<()> where W: Write {
+    let corners = [
+        ("min", [bbox.min.x, bbox.min.y, bbox.min.z]),
+        ("max", [bbox.max.x, bbox.max.y, bbox.max.z]),
+    ];
+    for (i, &(name, ref coords)) in corners.iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "\"{}\":[", name)?;
+        for (j, &c) in coords.iter().enumerate() {
+            if j > 0 {
+                write!(w, ",")?;
+            }
+            write_number(w, c)?;
+        }
+        write!(w, "]")?;
+    }
+    Ok(())
+}
+
+struct SceneWriter<W> {
+    w: W,
+    node_count: usize,
+    items: Vec<(usize, BBox)>,
+}
+
+impl<W> SceneWriter<W> where W: Write {
+    fn write_node<T>(&mut self, nodes: &Arena<T, 3>, node: &RTreeNode<T>, parent: Option<usize>, depth: usize) -> io::Result<()>
+        where
+            T: Mbr {
+
+        let id = self.node_count;
+        self.node_count += 1;
+        if id > 0 {
+            write!(self.w, ",")?;
+        }
+        write!(self.w, "\n    {{\"id\":{},\"parent\":", id)?;
+        match parent {
+            Some(parent) => write!(self.w, "{}", parent)?,
+            None => write!(self.w, "null")?,
+        }
+        let is_leaf = match node.storage {
+            NodeStorage::Interior(_) => false,
+            NodeStorage::Leaf(_) => true,
+        };
+        write!(self.w, ",\"depth\":{},\"leaf\":{},", depth, is_leaf)?;
+        write_bbox(&mut self.w, &node.bbox)?;
+        write!(self.w, "}}")?;
+
+        match node.storage {
+            NodeStorage::Interior(ref children) => {
+                for child in nodes.children(children) {
+                    self.write_node(nodes, child, Some(id), depth + 1)?;
+                }
+            },
+            NodeStorage::Leaf(ref items) => {
+                self.items.extend(items.iter().map(|i| (id, i.bbox)));
+            },
+        }
+        Ok(())
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Write the structure of the tree as a JSON scene, for loading into an
+    /// external viewer or attaching to bug reports.
+    ///
+    /// The document has a `nodes` array, where each node records its `id`,
+    /// its `parent` id (`null` for the root), its `depth`, whether it is a
+    /// `leaf`, and its `min`/`max` corners. It also has an `items` array,
+    /// where each item records its `id` (depth-first order), the `node`
+    /// holding it, and its bounding box.
+    pub fn write_json_scene<W>(&self, w: W) -> io::Result<()> where W: Write {
+        let mut scene = SceneWriter {
+            w,
+            node_count: 0,
+            items: Vec::new(),
+        };
+
+        write!(scene.w, "{{\n  \"nodes\": [")?;
+        if let Some(root) = self.root_node() {
+            scene.write_node(&self.nodes, root, None, 0)?;
+        }
+        write!(scene.w, "\n  ],\n  \"items\": [")?;
+        for (id, &(node, ref bbox)) in scene.items.iter().enumerate() {
+            if id > 0 {
+                write!(scene.w, ",")?;
+            }
+            write!(scene.w, "\n    {{\"id\":{},\"node\":{},", id, node)?;
+            write_bbox(&mut scene.w, bbox)?;
+            write!(scene.w, "}}")?;
+        }
+        writeln!(scene.w, "\n  ]\n}}")
+    }
+
+    /// Write every node's bounding box as a wireframe in Wavefront OBJ
+    /// format, for viewing next to the scene in a 3D modelling tool.
+    ///
+    /// Each box becomes eight vertices joined by twelve line elements, and
+    /// the boxes are grouped by depth as `depth_0` (the root), `depth_1`
+    /// and so on, so that levels can be shown or hidden separately.  Levels
+    /// deeper than `max_depth` are left out, as are boxes which are not
+    /// finite.
+    pub fn export_bboxes_obj<W>(&self, mut w: W, max_depth: Option<usize>) -> io::Result<()> where W: Write {
+        let mut level: Vec<&RTreeNode<T>> = self.root_node().into_iter().collect();
+        let mut depth = 0;
+        let mut vertices = 0;
+        while !level.is_empty() && max_depth.is_none_or(|max| depth <= max) {
+            writeln!(w, "g depth_{}", depth)?;
+            let mut next = Vec::new();
+            for node in level {
+                if let NodeStorage::Interior(ref children) = node.storage {
+                    next.extend(self.nodes.children(children));
+                }
+                let (min, max) = (node.bbox.min, node.bbox.max);
+                if ![min.x, min.y, min.z, max.x, max.y, max.z].iter().all(|c| c.is_finite()) {
+                    continue;
+                }
+                // Corner i takes max along x, y and z for bits 0, 1 and 2.
+                for i in 0..8 {
+                    let pick = |bit: usize, lo: f64, hi: f64| if i & bit == 0 { lo } else { hi };
+                    writeln!(w, "v {} {} {}", pick(1, min.x, max.x), pick(2, min.y, max.y), pick(4, min.z, max.z))?;
+                }
+                // Join corners which differ in a single bit.
+                for i in 0..8 {
+                    for bit in [1, 2, 4] {
+                        if i & bit == 0 {
+                            writeln!(w, "l {} {}", vertices + i + 1, vertices + (i | bit) + 1)?;
+                        }
+                    }
+                }
+                vertices += 8;
+            }
+            level = next;
+            depth += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::vec3::Vec3;
+    use super::super::{RTree, RTreeConfig};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_write_json_scene() {
+        let mut out = Vec::new();
+        RTree::<Sphere>::new().write_json_scene(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\n  \"nodes\": [\n  ],\n  \"items\": [\n  ]\n}\n");
+
+        let mut spheres = RTree::new();
+        spheres.insert(Sphere::new(Vec3::xyz(0.0, 0.0, 0.0), 1.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(2.5, 0.0, 0.0), 0.5).unwrap());
+
+        let mut out = Vec::new();
+        spheres.write_json_scene(&mut out).unwrap();
+        let expected = concat!(
+            "{\n",
+            "  \"nodes\": [\n",
+            "    {\"id\":0,\"parent\":null,\"depth\":0,\"leaf\":true,\"min\":[-1,-1,-1],\"max\":[3,1,1]}\n",
+            "  ],\n",
+            "  \"items\": [\n",
+            "    {\"id\":0,\"node\":0,\"min\":[-1,-1,-1],\"max\":[1,1,1]},\n",
+            "    {\"id\":1,\"node\":0,\"min\":[2,-0.5,-0.5],\"max\":[3,0.5,0.5]}\n",
+            "  ]\n",
+            "}\n");
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_export_bboxes_obj() {
+        let mut out = Vec::new();
+        RTree::<Sphere>::new().export_bboxes_obj(&mut out, None).unwrap();
+        assert!(out.is_empty());
+
+        let mut spheres = RTree::new();
+        spheres.insert(Sphere::new(Vec3::xyz(0.0, 0.0, 0.0), 1.0).unwrap());
+        let mut out = Vec::new();
+        spheres.export_bboxes_obj(&mut out, None).unwrap();
+        let obj = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = obj.lines().collect();
+        assert_eq!(lines.len(), 1 + 8 + 12);
+        assert_eq!(lines[0], "g depth_0");
+        assert_eq!(lines[1], "v -1 -1 -1");
+        assert_eq!(lines[8], "v 1 1 1");
+        assert_eq!(&lines[9..12], &["l 1 2", "l 1 3", "l 1 5"]);
+
+        let spheres = RTree::bulk_load_with_config(random_spheres(1000, 61), RTreeConfig::with_max_entries(8).unwrap());
+        let stats = spheres.stats();
+        let mut out = Vec::new();
+        spheres.export_bboxes_obj(&mut out, None).unwrap();
+        let obj = String::from_utf8(out).unwrap();
+        let nodes: usize = stats.nodes_per_level.iter().sum();
+        assert_eq!(obj.matches("g depth_").count(), stats.height);
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), nodes * 8);
+        assert_eq!(obj.lines().filter(|l| l.starts_with("l ")).count(), nodes * 12);
+        assert!(obj.contains(&format!("l {} {}", nodes * 8 - 1, nodes * 8)));
+
+        let mut out = Vec::new();
+        spheres.export_bboxes_obj(&mut out, Some(1)).unwrap();
+        let obj = String::from_utf8(out).unwrap();
+        assert!(obj.contains("g depth_1\n") && !obj.contains("g depth_2"));
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), (1 + stats.nodes_per_level[1]) * 8);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::{HashMap, HashSet};
+
+use aabb::Aabb;
+use hit::{Intersectable, Intersection, Mailbox};
+use ray::Ray;
+use super::{ItemId, Mbr, RTree, RTreeConfig};
+
+/// One of the bounding boxes of an item, as stored in the tree.
+#[derive(Debug)]
+pub(crate) struct Fragment<const N: usize> {
+    bbox: Aabb<N>,
+    id: u64,
+}
+
+impl<const N: usize> Mbr<N> for Fragment<N> {
+    fn mbr(&self) -> Aabb<N> {
+        self.bbox
+    }
+}
+
+/// An item and the ids of its fragments in the tree.
+#[derive(Debug)]
+struct Fragmented<T> {
+    item: T,
+    fragments: Vec<ItemId>,
+}
+
+/// A tree of items which are each found through several bounding boxes,
+/// such as a long polyline split into its segments.  A single box around
+/// such an item is mostly empty space, and every query passing through
+/// that space would turn it up; a box per piece only covers the item
+/// itself.
+///
+/// The item is stored once and shared by its boxes.  Queries yield each
+/// item once however many of its boxes they touch, and removing an item
+/// removes all of its boxes.
+#[derive(Debug)]
+pub struct FragmentedRTree<T, const N: usize = 3> {
+    pub(crate) fragments: RTree<Fragment<N>, N>,
+    items: HashMap<u64, Fragmented<T>>,
+    next_id: u64,
+}
+
+impl<T, const N: usize> FragmentedRTree<T, N> {
+    pub fn new() -> FragmentedRTree<T, N> {
+        FragmentedRTree::with_config(RTreeConfig::default())
+    }
+
+    pub fn with_config(config: RTreeConfig) -> FragmentedRTree<T, N> {
+        FragmentedRTree { fragments: RTree::with_config(config), items: HashMap::new(), next_id: 0 }
+    }
+
+    /// The number of items in the tree.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The number of boxes stored for all items.
+    pub fn fragment_count(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// Add `item` to the tree under each of `boxes`, returning a handle to
+    /// it.  An item without boxes is kept, but no query finds it until
+    /// boxes are added with `add_fragment`.
+    pub fn insert<I>(&mut self, item: T, boxes: I) -> ItemId where I: IntoIterator<Item=Aabb<N>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let fragments = boxes.into_iter()
+            .map(|bbox| self.fragments.insert(Fragment { bbox, id }))
+            .collect();
+        self.items.insert(id, Fragmented { item, fragments });
+        ItemId(id)
+    }
+
+    /// Add another box for the item `id` refers to, e.g. for a segment
+    /// appended to a polyline.  Returns false if the item has been removed.
+    pub fn add_fragment(&mut self, id: ItemId, bbox: Aabb<N>) -> bool {
+        match self.items.get_mut(&id.0) {
+            Some(fragmented) => {
+                fragmented.fragments.push(self.fragments.insert(Fragment { bbox, id: id.0 }));
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Remove the item `id` refers to, and all of its boxes.
+    pub fn remove(&mut self, id: ItemId) -> Option<T> {
+        let fragmented = self.items.remove(&id.0)?;
+        self.fragments.remove_many(&fragmented.fragments);
+        Some(fragmented.item)
+    }
+
+    /// The item `id` refers to, or None if it has been removed.
+    pub fn get(&self, id: ItemId) -> Option<&T> {
+        self.items.get(&id.0).map(|fragmented| &fragmented.item)
+    }
+
+    /// The item `id` refers to, for changing in place.  Its boxes stay as
+    /// they are.
+    pub fn get_mut(&mut self, id: ItemId) -> Option<&mut T> {
+        self.items.get_mut(&id.0).map(|fragmented| &mut fragmented.item)
+    }
+
+    /// The boxes the item `id` refers to is stored under.
+    pub fn boxes(&self, id: ItemId) -> impl Iterator<Item=Aabb<N>> + '_ {
+        self.items.get(&id.0).into_iter()
+            .flat_map(|fragmented| fragmented.fragments.iter())
+            .map(move |fragment| self.fragments.ids[&fragment.0])
+    }
+
+    /// Every item, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item=&T> + '_ {
+        self.items.values().map(|fragmented| &fragmented.item)
+    }
+
+    /// The items with a box overlapping `query`, each once.
+    pub fn iter_bbox(&self, query: &Aabb<N>) -> impl Iterator<Item=&T> + '_ {
+        let mut seen = HashSet::new();
+        self.fragments.iter_bbox(query)
+            .filter(move |fragment| seen.insert(fragment.id))
+            .map(move |fragment| &self.items[&fragment.id].item)
+    }
+}
+
+impl<T> FragmentedRTree<T> {
+    /// The items with a box intersecting `ray`, each once.
+    pub fn iter_ray<'a>(&'a self, ray: &'a Ray) -> impl Iterator<Item=&'a T> + 'a {
+        let mut seen = HashSet::new();
+        self.fragments.iter_ray(ray)
+            .filter(move |fragment| seen.insert(fragment.id))
+            .map(move |fragment| &self.items[&fragment.id].item)
+    }
+
+    /// Like `RTree::closest_hit_with`: the nearest item hit by `ray`
+    /// according to `intersect`, which is called at most once per item.
+    pub fn closest_hit_with<F>(&self, ray: &Ray, intersect: F) -> Option<(f64, &T)>
+        where
+            F: Fn(&T, &Ray) -> Option<f64> {
+
+        self.closest_hit_with_mailbox(ray, &mut Mailbox::new(), intersect)
+    }
+
+    /// Like `closest_hit_with`, reusing `mailbox` rather than allocating a
+    /// new one for every ray.
+    pub fn closest_hit_with_mailbox<F>(&self, ray: &Ray, mailbox: &mut Mailbox, intersect: F) -> Option<(f64, &T)>
+        where
+            F: Fn(&T, &Ray) -> Option<f64> {
+
+        self.closest_by(ray, mailbox, |item| intersect(item, ray).map(|t| (t, ())))
+            .map(|(t, item, ())| (t, item))
+    }
+
+    fn closest_by<F, H>(&self, ray: &Ray, mailbox: &mut Mailbox, mut hit: F) -> Option<(f64, &T, H)>
+        where
+            F: FnMut(&T) -> Option<(f64, H)> {
+
+        mailbox.next_ray();
+        let mut best: Option<(f64, &T, H)> = None;
+        let mut candidates = self.fragments.iter_ray_ordered(ray);
+        while let Some(fragment) = candidates.next() {
+            if !mailbox.first_visit(fragment.id) {
+                continue;
+            }
+            let item = &self.items[&fragment.id].item;
+            if let Some((t, found)) = hit(item).filter(|&(t, _)| ray.in_range(t)) {
+                if best.as_ref().is_none_or(|&(best_t, _, _)| t < best_t) {
+                    best = Some((t, item, found));
+                    candidates.set_max_t(t);
+                }
+            }
+        }
+        best
+    }
+}
+
+impl<T> FragmentedRTree<T> where T: Intersectable {
+    /// Like `RTree::closest_hit`, testing each item at most once.
+    pub fn closest_hit(&self, ray: &Ray) -> Option<(f64, &T, Intersection)> {
+        self.closest_by(ray, &mut Mailbox::new(), |item| item.intersect(ray).map(|hit| (hit.t, hit)))
+    }
+}
+
+impl<T, const N: usize> Default for FragmentedRTree<T, N> {
+    fn default() -> FragmentedRTree<T, N> {
+        FragmentedRTree::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::FragmentedRTree;
+
+    /// A polyline through `points`, with a box per segment, thickened so
+    /// that rays along the plane of the line hit it.
+    fn segments(points: &[Vec3]) -> Vec<BBox> {
+        let thickness = Vec3::xyz(0.0, 0.0, 0.5);
+        points.windows(2)
+            .map(|pair| BBox::from_point(pair[0] - thickness).union(&BBox::from_point(pair[1] + thickness)))
+            .collect()
+    }
+
+    #[test]
+    fn test_fragmented_tree() {
+        let mut lines = FragmentedRTree::new();
+        // A staircase from the origin to (100, 100, 0), whose single box
+        // would cover the whole square.
+        let stairs: Vec<Vec3> = (0..=20).map(|step| Vec3::xyz((step / 2 * 10) as f64, ((step + 1) / 2 * 10) as f64, 0.0)).collect();
+        let staircase = lines.insert("stairs", segments(&stairs));
+        let ridge = lines.insert("ridge", segments(&[Vec3::xyz(0.0, 200.0, 0.0), Vec3::xyz(100.0, 200.0, 0.0)]));
+        for idx in 0..200 {
+            let x = (idx % 20) as f64 * 5.0;
+            let y = 300.0 + (idx / 20) as f64 * 5.0;
+            lines.insert("dash", segments(&[Vec3::xyz(x, y, 0.0), Vec3::xyz(x + 1.0, y, 0.0)]));
+        }
+        assert_eq!(lines.len(), 202);
+        assert_eq!(lines.fragment_count(), 20 + 1 + 200);
+        assert_eq!(lines.fragments.validate(), Ok(()));
+
+        // The corner of the square away from the stairs finds nothing,
+        // and a box across several steps finds the stairs once.
+        let corner = BBox { min: Vec3::xyz(60.0, 5.0, -1.0), max: Vec3::xyz(95.0, 40.0, 1.0) };
+        assert_eq!(lines.iter_bbox(&corner).count(), 0);
+        let across = BBox { min: Vec3::xyz(0.0, 0.0, -1.0), max: Vec3::xyz(50.0, 50.0, 1.0) };
+        assert_eq!(lines.iter_bbox(&across).collect::<Vec<_>>(), [&"stairs"]);
+        let ray = Ray::new(Vec3::xyz(-10.0, -10.0, 0.0), Vec3::xyz(1.0, 1.0, 0.0));
+        assert_eq!(lines.iter_ray(&ray).collect::<Vec<_>>(), [&"stairs"]);
+        let hit = lines.closest_hit_with(&ray, |_, _| Some(1.0));
+        assert_eq!(hit.map(|(_, &line)| line), Some("stairs"));
+
+        // Growing the ridge makes it turn up further along.
+        let further = BBox { min: Vec3::xyz(140.0, 190.0, -1.0), max: Vec3::xyz(160.0, 210.0, 1.0) };
+        assert_eq!(lines.iter_bbox(&further).count(), 0);
+        assert!(lines.add_fragment(ridge, segments(&[Vec3::xyz(100.0, 200.0, 0.0), Vec3::xyz(200.0, 200.0, 0.0)])[0]));
+        assert_eq!(lines.iter_bbox(&further).collect::<Vec<_>>(), [&"ridge"]);
+        assert_eq!(lines.boxes(ridge).count(), 2);
+
+        // Removing the stairs removes every step.
+        assert_eq!(lines.remove(staircase), Some("stairs"));
+        assert!(lines.remove(staircase).is_none());
+        assert!(!lines.add_fragment(staircase, corner));
+        assert_eq!(lines.boxes(staircase).count(), 0);
+        assert_eq!(lines.iter_bbox(&across).count(), 0);
+        assert_eq!(lines.fragment_count(), 2 + 200);
+        assert_eq!(lines.len(), 201);
+        assert_eq!(lines.fragments.validate(), Ok(()));
+    }
+}
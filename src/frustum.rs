@@ -0,0 +1,169 @@
+use bbox::BBox;
+use convex::{classify, ConvexIter, Side};
+use shapes::Plane;
+use vec3::Vec3;
+use super::{Mbr, RTree};
+
+const FRUSTUM_MATRIX_DEGENERATE: &str = "view-projection matrix must give six proper planes";
+
+/// A camera's view volume, bounded by six planes whose normals point
+/// inwards, for culling a scene before rasterising it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Frustum {
+    planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// The frustum between `planes`, each of which keeps the side its
+    /// normal points to.
+    pub fn new(planes: [Plane; 6]) -> Frustum {
+        Frustum { planes }
+    }
+
+    /// The frustum seen through `matrix`, a row-major view-projection
+    /// matrix taking world space to clip space.  Clip space follows
+    /// OpenGL, where the visible volume is `-w <= x, y, z <= w`.
+    ///
+    /// The planes come out in the order left, right, bottom, top, near,
+    /// far.  Matrices with an infinite far plane have no far plane to
+    /// extract, so are refused.
+    pub fn from_matrix(matrix: &[[f64; 4]; 4]) -> Result<Frustum, &'static str> {
+        let w = matrix[3];
+        let plane = |row: usize, sign: f64| {
+            let [a, b, c, d] = [0, 1, 2, 3].map(|i| w[i] + sign * matrix[row][i]);
+            let normal = Vec3::xyz(a, b, c);
+            let length_squared = normal.dot(&normal);
+            if !length_squared.is_finite() || !d.is_finite() {
+                return Err(FRUSTUM_MATRIX_DEGENERATE);
+            }
+            // The point on the plane nearest the origin.
+            Plane::new(normal.scale(-d / length_squared), normal).map_err(|_| FRUSTUM_MATRIX_DEGENERATE)
+        };
+        Ok(Frustum::new([
+            plane(0, 1.0)?,
+            plane(0, -1.0)?,
+            plane(1, 1.0)?,
+            plane(1, -1.0)?,
+            plane(2, 1.0)?,
+            plane(2, -1.0)?,
+        ]))
+    }
+
+    pub fn planes(&self) -> &[Plane; 6] {
+        &self.planes
+    }
+
+    pub fn contains_point(&self, point: &Vec3) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Whether `bbox` may overlap the frustum.  A box is only rejected when
+    /// it lies entirely behind one plane, so a box just outside a corner of
+    /// the frustum can still be accepted.
+    pub fn intersects_bbox(&self, bbox: &BBox) -> bool {
+        classify(&self.planes, bbox) != Side::Outside
+    }
+
+    /// Whether `bbox` lies entirely inside the frustum.
+    pub fn contains_bbox(&self, bbox: &BBox) -> bool {
+        classify(&self.planes, bbox) == Side::Inside
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Iterate over all items whose bounding box may overlap `frustum`, as
+    /// decided by `Frustum::intersects_bbox`.  Subtrees entirely inside the
+    /// frustum are yielded without testing each item.
+    pub fn iter_frustum<'a>(&'a self, frustum: &Frustum) -> ConvexIter<'a, T> {
+        self.iter_convex(frustum.planes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+    use super::Frustum;
+
+    fn mul(a: &[[f64; 4]; 4], b: &[[f64; 4]; 4]) -> [[f64; 4]; 4] {
+        let mut m = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                m[i][j] = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+        m
+    }
+
+    /// A camera at `eye` looking down -z with a 90 degree field of view,
+    /// seeing from 1 to 100 units away.
+    fn camera(eye: Vec3) -> Frustum {
+        let (near, far) = (1.0, 100.0);
+        let projection = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, (far + near) / (near - far), 2.0 * far * near / (near - far)],
+            [0.0, 0.0, -1.0, 0.0],
+        ];
+        let view = [
+            [1.0, 0.0, 0.0, -eye.x],
+            [0.0, 1.0, 0.0, -eye.y],
+            [0.0, 0.0, 1.0, -eye.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        Frustum::from_matrix(&mul(&projection, &view)).unwrap()
+    }
+
+    #[test]
+    fn test_from_matrix() {
+        let frustum = camera(Vec3::zero());
+        let near = frustum.planes()[4];
+        assert!((near.normal() - Vec3::xyz(0.0, 0.0, -1.0)).len() < 1e-12);
+        assert!((near.signed_distance(&Vec3::zero()) + 1.0).abs() < 1e-9);
+
+        assert!(frustum.contains_point(&Vec3::xyz(0.0, 0.0, -10.0)));
+        assert!(frustum.contains_point(&Vec3::xyz(9.0, -9.0, -10.0)));
+        assert!(!frustum.contains_point(&Vec3::xyz(11.0, 0.0, -10.0)));
+        assert!(!frustum.contains_point(&Vec3::xyz(0.0, 0.0, 10.0)));
+        assert!(!frustum.contains_point(&Vec3::xyz(0.0, 0.0, -0.5)));
+        assert!(!frustum.contains_point(&Vec3::xyz(0.0, 0.0, -101.0)));
+
+        let bbox = |min: Vec3, max: Vec3| BBox { min, max };
+        let ahead = bbox(Vec3::xyz(-1.0, -1.0, -11.0), Vec3::xyz(1.0, 1.0, -9.0));
+        assert!(frustum.contains_bbox(&ahead) && frustum.intersects_bbox(&ahead));
+        let across_near = bbox(Vec3::xyz(-1.0, -1.0, -2.0), Vec3::xyz(1.0, 1.0, 2.0));
+        assert!(!frustum.contains_bbox(&across_near) && frustum.intersects_bbox(&across_near));
+        let behind = bbox(Vec3::xyz(-1.0, -1.0, 1.0), Vec3::xyz(1.0, 1.0, 2.0));
+        assert!(!frustum.intersects_bbox(&behind));
+
+        // An infinite far plane cannot be extracted.
+        let mut infinite = [[0.0; 4]; 4];
+        infinite[0][0] = 1.0;
+        infinite[1][1] = 1.0;
+        infinite[2] = [0.0, 0.0, -1.0, -2.0];
+        infinite[3] = [0.0, 0.0, -1.0, 0.0];
+        assert!(Frustum::from_matrix(&infinite).is_err());
+    }
+
+    #[test]
+    fn test_iter_frustum() {
+        let spheres = random_spheres(3000, 17);
+        let frustum = camera(Vec3::xyz(500.0, 500.0, 1050.0));
+        let mut expected: Vec<Vec3> = spheres.iter()
+            .filter(|sphere| frustum.intersects_bbox(&sphere.mbr()))
+            .map(|sphere| sphere.mbr().min)
+            .collect();
+        assert!(!expected.is_empty() && expected.len() < spheres.len());
+
+        let tree: RTree<Sphere> = RTree::bulk_load(spheres);
+        let by_x = |a: &Vec3, b: &Vec3| a.x.partial_cmp(&b.x).unwrap();
+        let mut found: Vec<Vec3> = tree.iter_frustum(&frustum).map(|sphere| sphere.mbr().min).collect();
+        expected.sort_by(by_x);
+        found.sort_by(by_x);
+        assert_eq!(found, expected);
+        assert_eq!(RTree::<Sphere>::new().iter_frustum(&frustum).count(), 0);
+    }
+}
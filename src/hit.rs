@@ -0,0 +1,683 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+
+use arena::{Arena, NodeId};
+use bbox::BBox;
+use ray::Ray;
+use slab::{RayPacket, LANES};
+use vec3::Vec3;
+use super::{ItemId, Mbr, RTree, RTreeNode, NodeStorage, LeafItem};
+
+/// Where a ray meets a primitive.  `point` is `ray.origin + ray.direction * t`
+/// and `normal` is the unit surface normal at that point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intersection {
+    pub t: f64,
+    pub point: Vec3,
+    pub normal: Vec3,
+}
+
+/// The closest item a ray hits, found by `RTree::closest_hits`.
+#[derive(Debug, PartialEq)]
+pub struct Hit<'a, T> {
+    pub t: f64,
+    pub item: &'a T,
+    pub intersection: Intersection,
+}
+
+// Derived, these would need `T: Copy`, though only a reference is held.
+impl<'a, T> Clone for Hit<'a, T> {
+    fn clone(&self) -> Hit<'a, T> {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Hit<'a, T> {}
+
+/// The closest hits of a batch of rays, one array per field, as filled by
+/// `RTree::closest_hits_soa`.  Entry `i` of every array belongs to ray
+/// `i`, so renderers can shade straight from them, a lane per ray.
+///
+/// Where a ray misses, its `t` is infinite, its `item` None, and its
+/// `point` and `normal` zero.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HitBuffers {
+    pub t: Vec<f64>,
+    pub item: Vec<Option<ItemId>>,
+    pub point: Vec<Vec3>,
+    pub normal: Vec<Vec3>,
+}
+
+impl HitBuffers {
+    pub fn new() -> HitBuffers {
+        HitBuffers::default()
+    }
+
+    /// The number of rays held.
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Drop every hit, keeping the arrays' capacity.
+    pub fn clear(&mut self) {
+        self.t.clear();
+        self.item.clear();
+        self.point.clear();
+        self.normal.clear();
+    }
+
+    fn push(&mut self, hit: Option<(ItemId, &Intersection)>) {
+        match hit {
+            Some((id, hit)) => {
+                self.t.push(hit.t);
+                self.item.push(Some(id));
+                self.point.push(hit.point);
+                self.normal.push(hit.normal);
+            },
+            None => {
+                self.t.push(f64::INFINITY);
+                self.item.push(None);
+                self.point.push(Vec3::zero());
+                self.normal.push(Vec3::zero());
+            },
+        }
+    }
+}
+
+/// Geometry which can be tested exactly against a ray.
+pub trait Intersectable {
+    /// The nearest intersection in front of the ray's origin, if any.
+    fn intersect(&self, ray: &Ray) -> Option<Intersection>;
+}
+
+/// The ray parameter at which `ray` enters `bbox`, clamped to the start of
+/// the ray's interval, or `None` if the ray misses it.
+fn ray_entry(bbox: &BBox, ray: &Ray) -> Option<f64> {
+    bbox.intersect_ray_t(ray).map(|(t_min, _)| t_min.max(ray.min_t))
+}
+
+enum Candidate<'a, T> where T: Mbr+'a {
+    Node(&'a RTreeNode<T>),
+    Item(&'a LeafItem<T>),
+}
+
+struct Entry<'a, T> where T: Mbr+'a {
+    t: f64,
+    candidate: Candidate<'a, T>,
+}
+
+impl<'a, T> PartialEq for Entry<'a, T> where T: Mbr+'a {
+    fn eq(&self, other: &Entry<'a, T>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a, T> Eq for Entry<'a, T> where T: Mbr+'a {}
+
+impl<'a, T> PartialOrd for Entry<'a, T> where T: Mbr+'a {
+    fn partial_cmp(&self, other: &Entry<'a, T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T> Ord for Entry<'a, T> where T: Mbr+'a {
+    // Reversed, so that the BinaryHeap pops the nearest entry first.
+    fn cmp(&self, other: &Entry<'a, T>) -> Ordering {
+        other.t.partial_cmp(&self.t).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Remembers which items the current ray has been tested against, so
+/// that an item reached through several leaves is only intersected once
+/// per ray.  Each query through the mailbox is a new ray, so one mailbox
+/// can be kept and reused for any number of queries, on any tree, without
+/// clearing it in between.
+#[derive(Clone, Debug, Default)]
+pub struct Mailbox {
+    ray: u64,
+    tested: HashMap<u64, u64>,
+}
+
+impl Mailbox {
+    pub fn new() -> Mailbox {
+        Mailbox::default()
+    }
+
+    /// Forget every item, releasing the memory held for them.
+    pub fn clear(&mut self) {
+        self.tested = HashMap::new();
+    }
+
+    /// Start a new ray, for which no item has been tested yet.  Queries
+    /// taking a mailbox call this themselves; it is only needed when
+    /// mailboxing a traversal of your own.
+    pub fn next_ray(&mut self) {
+        self.ray += 1;
+    }
+
+    /// Whether the current ray has not yet been tested against the item
+    /// `id`, which from now on it has.
+    pub fn first_visit(&mut self, id: u64) -> bool {
+        self.tested.insert(id, self.ray) != Some(self.ray)
+    }
+}
+
+/// Ray candidates in increasing order of where the ray enters their
+/// bounding box.  Created by `RTree::iter_ray_ordered`.
+pub struct OrderedIter<'a, T> where T: Mbr+'a {
+    nodes: &'a Arena<T, 3>,
+    heap: BinaryHeap<Entry<'a, T>>,
+    ray: Ray,
+    max_t: f64,
+}
+
+impl<'a, T> OrderedIter<'a, T> where T: Mbr+'a {
+    fn new(rtree: &'a RTree<T>, ray: &Ray) -> OrderedIter<'a, T> {
+        let mut heap = BinaryHeap::new();
+        if let Some(root) = rtree.root_node() {
+            if let Some(t) = ray_entry(&root.bbox, ray) {
+                heap.push(Entry { t, candidate: Candidate::Node(root) });
+            }
+        }
+        OrderedIter {
+            nodes: &rtree.nodes,
+            heap,
+            ray: *ray,
+            max_t: ray.max_t,
+        }
+    }
+
+    /// The entry distance of the next candidate, or `None` if the traversal
+    /// is finished.  No later candidate can be entered before this.
+    pub fn next_t(&mut self) -> Option<f64> {
+        self.descend();
+        self.heap.peek().map(|entry| entry.t)
+    }
+
+    /// Stop the traversal at `t`: nodes and items the ray enters beyond
+    /// it are skipped.  Call this with the distance of a confirmed hit to
+    /// end the search once nothing nearer remains.
+    pub fn set_max_t(&mut self, t: f64) {
+        self.max_t = self.max_t.min(t);
+    }
+
+    /// The next candidate, with its id.
+    fn next_leaf(&mut self) -> Option<&'a LeafItem<T>> {
+        self.descend();
+        match self.heap.pop() {
+            Some(Entry { candidate: Candidate::Item(leaf), .. }) => Some(leaf),
+            Some(Entry { candidate: Candidate::Node(_), .. }) => unreachable!(),
+            None => None,
+        }
+    }
+
+    // Expand nodes until an item, or nothing within `max_t`, is on top.
+    fn descend(&mut self) {
+        while let Some(&Entry { t, candidate: Candidate::Node(_) }) = self.heap.peek() {
+            if self.max_t < t {
+                break;
+            }
+            let node = match self.heap.pop() {
+                Some(Entry { candidate: Candidate::Node(node), .. }) => node,
+                _ => unreachable!(),
+            };
+            match node.storage {
+                NodeStorage::Interior(ref children) => {
+                    for child in self.nodes.children(children) {
+                        match ray_entry(&child.bbox, &self.ray) {
+                            Some(t) if t <= self.max_t => {
+                                self.heap.push(Entry { t, candidate: Candidate::Node(child) });
+                            },
+                            _ => (),
+                        }
+                    }
+                },
+                NodeStorage::Leaf(ref items) => {
+                    for leaf in items.iter() {
+                        match ray_entry(&leaf.bbox, &self.ray) {
+                            Some(t) if t <= self.max_t => {
+                                self.heap.push(Entry { t, candidate: Candidate::Item(leaf) });
+                            },
+                            _ => (),
+                        }
+                    }
+                },
+            }
+        }
+        if self.heap.peek().is_some_and(|entry| self.max_t < entry.t) {
+            self.heap.clear();
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for OrderedIter<'a, T> where T: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OrderedIter").field("ray", &self.ray).field("max_t", &self.max_t).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> Iterator for OrderedIter<'a, T> where T: Mbr+'a {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.next_leaf().map(|leaf| &leaf.item)
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Like `iter_ray`, but candidates are yielded nearest first, ordered
+    /// by where the ray enters their bounding box.  Use
+    /// `OrderedIter::set_max_t` once a hit is found to skip everything
+    /// behind it.  The ray is copied, so the iterator only borrows the tree.
+    pub fn iter_ray_ordered<'a>(&'a self, ray: &Ray) -> OrderedIter<'a, T> {
+        OrderedIter::new(self, ray)
+    }
+
+    /// Like `closest_hit`, for items which are not `Intersectable`:
+    /// `intersect` gives the ray parameter at which `ray` meets an item, if
+    /// it does, and the nearest item hit is returned with its parameter.
+    /// The tree does the ordered traversal and pruning, and `intersect` is
+    /// only called on items whose bounding box the ray enters before the
+    /// closest hit found so far.
+    pub fn closest_hit_with<F>(&self, ray: &Ray, intersect: F) -> Option<(f64, &T)>
+        where
+            F: Fn(&T, &Ray) -> Option<f64> {
+
+        self.closest_by(ray, None, |item| intersect(item, ray).map(|t| (t, ())))
+            .map(|(t, item, ())| (t, item))
+    }
+
+    /// Like `closest_hit_with`, but `mailbox` makes sure `intersect` is
+    /// called at most once per item for this ray, however many leaves the
+    /// item is reached through.
+    pub fn closest_hit_with_mailbox<F>(&self, ray: &Ray, mailbox: &mut Mailbox, intersect: F) -> Option<(f64, &T)>
+        where
+            F: Fn(&T, &Ray) -> Option<f64> {
+
+        self.closest_by(ray, Some(mailbox), |item| intersect(item, ray).map(|t| (t, ())))
+            .map(|(t, item, ())| (t, item))
+    }
+
+    /// The item for which `hit` gives the smallest ray parameter within
+    /// the ray's interval, with that parameter and whatever else `hit`
+    /// returned for it.  Items `mailbox` has already seen for this ray are
+    /// skipped.
+    fn closest_by<F, H>(&self, ray: &Ray, mut mailbox: Option<&mut Mailbox>, mut hit: F) -> Option<(f64, &T, H)>
+        where
+            F: FnMut(&T) -> Option<(f64, H)> {
+
+        if let Some(ref mut mailbox) = mailbox {
+            mailbox.next_ray();
+        }
+        let mut best: Option<(f64, &T, H)> = None;
+        let mut candidates = self.iter_ray_ordered(ray);
+        while let Some(leaf) = candidates.next_leaf() {
+            if !mailbox.as_mut().is_none_or(|mailbox| mailbox.first_visit(leaf.id)) {
+                continue;
+            }
+            if let Some((t, found)) = hit(&leaf.item).filter(|&(t, _)| ray.in_range(t)) {
+                if best.as_ref().is_none_or(|&(best_t, _, _)| t < best_t) {
+                    best = Some((t, &leaf.item, found));
+                    candidates.set_max_t(t);
+                }
+            }
+        }
+        best
+    }
+}
+
+impl<T> RTree<T> where T: Mbr + Intersectable {
+    /// The first item hit by `ray`, along with the ray parameter and the
+    /// intersection itself.  Hits outside the ray's `min_t..max_t` interval
+    /// are ignored.
+    ///
+    /// Candidates come from `iter_ray_ordered`, which is cut off at each
+    /// new closest hit, so the search ends as soon as nothing left could be
+    /// nearer.
+    pub fn closest_hit(&self, ray: &Ray) -> Option<(f64, &T, Intersection)> {
+        self.closest_by(ray, None, |item| item.intersect(ray).map(|hit| (hit.t, hit)))
+    }
+
+    /// The closest hit of each of `rays`, as `closest_hit` would find it,
+    /// in the same order.  Where two items are hit at exactly the same
+    /// distance either may be returned.
+    ///
+    /// Rays are traced four at a time, in the order given, sharing one walk
+    /// down the tree: every bounding box is tested against the whole packet
+    /// at once, and a node is visited if any of its rays could still hit
+    /// something nearer inside it.  This pays off for coherent rays, which
+    /// mostly visit the same nodes, such as neighbouring primary rays from a
+    /// camera; rays heading off in different directions are better served
+    /// by `closest_hit`.
+    pub fn closest_hits(&self, rays: &[Ray]) -> Vec<Option<Hit<'_, T>>> {
+        let mut hits = Vec::with_capacity(rays.len());
+        let mut stack = Vec::new();
+        for rays in rays.chunks(LANES) {
+            let best = self.trace_packet(rays, &mut stack);
+            hits.extend(best.iter().take(rays.len()).map(|hit| {
+                hit.map(|(leaf, intersection)| Hit { t: intersection.t, item: &leaf.item, intersection })
+            }));
+        }
+        hits
+    }
+
+    /// Like `closest_hits`, but laid out as one array per field, replacing
+    /// whatever `hits` held.  The items hit are given by id, for
+    /// `RTree::get`.  Reusing `hits` from one batch to the next saves
+    /// allocating the arrays every frame.
+    pub fn closest_hits_soa(&self, rays: &[Ray], hits: &mut HitBuffers) {
+        hits.clear();
+        let mut stack = Vec::new();
+        for rays in rays.chunks(LANES) {
+            let best = self.trace_packet(rays, &mut stack);
+            for hit in best.iter().take(rays.len()) {
+                hits.push(hit.as_ref().map(|&(leaf, ref intersection)| (ItemId(leaf.id), intersection)));
+            }
+        }
+    }
+
+    /// The nearest leaf each of up to `LANES` rays hits, and where.
+    fn trace_packet<'a>(&'a self, rays: &[Ray], stack: &mut Vec<(NodeId, [f64; LANES])>) -> [Option<(&'a LeafItem<T>, Intersection)>; LANES] {
+        let mut packet = RayPacket::new(rays);
+        let mut best: [Option<(&'a LeafItem<T>, Intersection)>; LANES] = [None; LANES];
+        // Whether a lane entering a box at `entry` could still find a
+        // nearer hit there.  Lanes which missed are infinite, and lanes
+        // whose `max_t` is NaN never pass.
+        let open = |packet: &RayPacket, entry: &[f64; LANES], lane: usize| {
+            entry[lane] < f64::INFINITY && entry[lane] <= packet.max_t[lane]
+        };
+
+        stack.clear();
+        if let Some(root) = self.root {
+            stack.push((root, packet.intersect_box(&self.nodes[root].bbox)));
+        }
+        while let Some((node, entry)) = stack.pop() {
+            // Hits found since the node was pushed may have closed it.
+            if !(0..LANES).any(|lane| open(&packet, &entry, lane)) {
+                continue;
+            }
+            match self.nodes[node].storage {
+                NodeStorage::Interior(ref children) => {
+                    let first = stack.len();
+                    for &child in children.iter() {
+                        let entry = packet.intersect_box(&self.nodes[child].bbox);
+                        if (0..LANES).any(|lane| open(&packet, &entry, lane)) {
+                            stack.push((child, entry));
+                        }
+                    }
+                    // Farthest first, so the nearest child is visited next
+                    // and its hits close off the others.
+                    let nearest = |entry: &[f64; LANES]| entry.iter().cloned().fold(f64::INFINITY, f64::min);
+                    stack[first..].sort_by(|a, b| nearest(&b.1).total_cmp(&nearest(&a.1)));
+                },
+                NodeStorage::Leaf(ref items) => {
+                    for leaf in items.iter() {
+                        let entry = packet.intersect_box(&leaf.bbox);
+                        for (lane, ray) in rays.iter().enumerate() {
+                            if !open(&packet, &entry, lane) {
+                                continue;
+                            }
+                            if let Some(hit) = leaf.item.intersect(ray).filter(|hit| ray.in_range(hit.t)) {
+                                if best[lane].as_ref().is_none_or(|best| hit.t < best.1.t) {
+                                    best[lane] = Some((leaf, hit));
+                                    packet.max_t[lane] = hit.t;
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::f64;
+    use std::ptr;
+
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::{HitBuffers, Intersectable, Mailbox};
+    use super::super::{ItemId, Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_iter_ray_ordered() {
+        let spheres = random_spheres(2000, 3);
+        // Aim through one sphere's centre so the ray has candidates.
+        let target = spheres[0].mbr().lerp(0.5, 0.5, 0.5);
+        let ray = Ray::new(Vec3::xyz(0.0, 0.0, 0.0), target);
+
+        let mut tree: RTree<Sphere> = RTree::new();
+        for sphere in spheres {
+            tree.insert(sphere);
+        }
+
+        let mut ordered = tree.iter_ray_ordered(&ray);
+        let mut entries = vec![];
+        while let Some(t) = ordered.next_t() {
+            assert!(entries.last().is_none_or(|&last| last <= t));
+            entries.push(t);
+            ordered.next().unwrap();
+        }
+        assert!(!entries.is_empty());
+        assert_eq!(entries.len(), tree.iter_ray(&ray).count());
+
+        let mut cut = tree.iter_ray_ordered(&ray);
+        cut.set_max_t(entries[0]);
+        assert_eq!(cut.count(), entries.iter().filter(|&&t| t <= entries[0]).count());
+    }
+
+    #[test]
+    fn test_closest_hit_matches_brute_force() {
+        let spheres = random_spheres(3000, 11);
+        let rays: Vec<Ray> = (0..50).map(|i| {
+            let f = i as f64 / 50.0;
+            Ray::new(Vec3::xyz(-10.0, 500.0 * f, 1000.0 * f), Vec3::xyz(1.0, 0.3 - f * 0.2, 0.1 * f))
+        }).collect();
+
+        let expected: Vec<Option<f64>> = rays.iter().map(|ray| {
+            spheres.iter()
+                .filter_map(|s| s.intersect(ray))
+                .map(|hit| hit.t)
+                .fold(None, |best: Option<f64>, t| Some(best.map_or(t, |b| b.min(t))))
+        }).collect();
+        assert!(expected.iter().any(Option::is_some));
+
+        let mut tree: RTree<Sphere> = RTree::new();
+        for sphere in spheres {
+            tree.insert(sphere);
+        }
+
+        for (ray, expected) in rays.iter().zip(expected) {
+            let found = tree.closest_hit(ray).map(|(t, sphere, hit)| {
+                assert_eq!(sphere.intersect(ray), Some(hit));
+                t
+            });
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_closest_hit_with() {
+        // Spheres tested through a closure, as if they were foreign
+        // primitives, agree with `closest_hit`.
+        let mut tree: RTree<Sphere> = RTree::new();
+        tree.extend(random_spheres(3000, 12));
+        let calls = Cell::new(0);
+        let intersect = |sphere: &Sphere, ray: &Ray| {
+            calls.set(calls.get() + 1);
+            sphere.intersect(ray).map(|hit| hit.t)
+        };
+
+        // Rays from a corner of the cube through the spheres, which have
+        // others behind them.
+        let rays: Vec<Ray> = random_spheres(50, 12).iter().map(|s| Ray::new(Vec3::zero(), s.mbr().center())).collect();
+        let mut hits = 0;
+        for ray in rays.iter() {
+            let expected = tree.closest_hit(ray).map(|(t, sphere, _)| (t, sphere as *const Sphere));
+            let found = tree.closest_hit_with(ray, intersect).map(|(t, sphere)| (t, sphere as *const Sphere));
+            assert_eq!(found, expected);
+            hits += found.is_some() as usize;
+        }
+        assert_eq!(hits, rays.len());
+        // Pruning spares most candidates the exact test.
+        let candidates: usize = rays.iter().map(|ray| tree.iter_ray(ray).count()).sum();
+        assert!(calls.get() < candidates);
+
+        // Hits outside the ray's interval do not count.
+        let ray = Ray::with_max_t(Vec3::xyz(-10.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0), 5.0);
+        assert!(tree.closest_hit_with(&ray, |_, _| Some(6.0)).is_none());
+        assert!(RTree::<Sphere>::new().closest_hit_with(&ray, intersect).is_none());
+    }
+
+    #[test]
+    fn test_mailbox() {
+        let mut mailbox = Mailbox::new();
+        mailbox.next_ray();
+        assert!(mailbox.first_visit(7));
+        assert!(!mailbox.first_visit(7));
+        assert!(mailbox.first_visit(8));
+        mailbox.next_ray();
+        assert!(mailbox.first_visit(7));
+        mailbox.clear();
+        assert!(mailbox.first_visit(8));
+
+        // One mailbox serves every ray, each testing the same items as
+        // without it.
+        let mut tree: RTree<Sphere> = RTree::new();
+        tree.extend(random_spheres(3000, 15));
+        let calls = Cell::new(0);
+        let intersect = |sphere: &Sphere, ray: &Ray| {
+            calls.set(calls.get() + 1);
+            sphere.intersect(ray).map(|hit| hit.t)
+        };
+        let rays: Vec<Ray> = random_spheres(30, 15).iter().map(|s| Ray::new(Vec3::zero(), s.mbr().center())).collect();
+        for ray in rays.iter().chain(rays.iter()) {
+            let expected = tree.closest_hit_with(ray, intersect).map(|(t, sphere)| (t, sphere as *const Sphere));
+            let unboxed = calls.replace(0);
+            let found = tree.closest_hit_with_mailbox(ray, &mut mailbox, intersect).map(|(t, sphere)| (t, sphere as *const Sphere));
+            assert_eq!(found, expected);
+            assert!(found.is_some());
+            assert_eq!(calls.replace(0), unboxed);
+        }
+    }
+
+    #[test]
+    fn test_closest_hits() {
+        let mut tree: RTree<Sphere> = RTree::new();
+        tree.extend(random_spheres(3000, 14));
+
+        // A small camera looking into the cube: neighbouring rays are
+        // coherent, and some miss everything.
+        let origin = Vec3::xyz(-200.0, 500.0, 500.0);
+        let mut rays: Vec<Ray> = Vec::new();
+        for row in 0..15 {
+            for column in 0..15 {
+                let target = Vec3::xyz(0.0, column as f64 * 100.0 - 200.0, row as f64 * 100.0 - 200.0);
+                rays.push(Ray::new(origin, target - origin));
+            }
+        }
+        // Rays from the same point through spheres, segments, a ray
+        // starting past its first hit, and a NaN ray.
+        let targets = random_spheres(50, 14);
+        rays.extend(targets.iter().map(|s| Ray::new(origin, s.mbr().center() - origin)));
+        rays.push(Ray::with_max_t(Vec3::zero(), Vec3::one(), 1.0));
+        let past = Ray::new(Vec3::zero(), targets[0].mbr().center());
+        let (t, _, _) = tree.closest_hit(&past).unwrap();
+        rays.push(Ray { min_t: t, ..past });
+        rays.push(Ray::new(Vec3::zero(), Vec3::xyz(f64::NAN, 1.0, 1.0)));
+
+        let hits = tree.closest_hits(&rays);
+        assert_eq!(hits.len(), rays.len());
+        for (ray, hit) in rays.iter().zip(hits.iter()) {
+            let expected = tree.closest_hit(ray).map(|(t, sphere, hit)| (t, sphere as *const Sphere, hit));
+            let found = hit.map(|hit| (hit.t, hit.item as *const Sphere, hit.intersection));
+            assert_eq!(found, expected);
+        }
+        let found = hits.iter().filter(|hit| hit.is_some()).count();
+        assert!(found >= targets.len() && found < rays.len());
+
+        assert!(tree.closest_hits(&[]).is_empty());
+        assert_eq!(RTree::<Sphere>::new().closest_hits(&rays[..5]), vec![None; 5]);
+    }
+
+    #[test]
+    fn test_closest_hits_soa() {
+        let mut tree: RTree<Sphere> = RTree::new();
+        let ids: Vec<ItemId> = random_spheres(3000, 16).into_iter().map(|sphere| tree.insert(sphere)).collect();
+
+        let origin = Vec3::xyz(-200.0, 500.0, 500.0);
+        let mut rays: Vec<Ray> = (0..90)
+            .map(|idx| {
+                let target = Vec3::xyz(0.0, (idx % 9) as f64 * 150.0 - 100.0, (idx / 9) as f64 * 150.0 - 100.0);
+                Ray::new(origin, target - origin)
+            })
+            .collect();
+        rays.push(Ray::new(origin, -Vec3::one()));
+
+        // Every array agrees with `closest_hits`, ray by ray.
+        let mut hits = HitBuffers::new();
+        tree.closest_hits_soa(&rays, &mut hits);
+        assert_eq!(hits.len(), rays.len());
+        assert_eq!((hits.item.len(), hits.point.len(), hits.normal.len()), (rays.len(), rays.len(), rays.len()));
+        for (idx, expected) in tree.closest_hits(&rays).iter().enumerate() {
+            match *expected {
+                Some(hit) => {
+                    assert_eq!(hits.t[idx], hit.t);
+                    assert!(ptr::eq(tree.get(hits.item[idx].unwrap()).unwrap(), hit.item));
+                    assert_eq!((hits.point[idx], hits.normal[idx]), (hit.intersection.point, hit.intersection.normal));
+                },
+                None => {
+                    assert_eq!(hits.t[idx], f64::INFINITY);
+                    assert_eq!((hits.item[idx], hits.point[idx], hits.normal[idx]), (None, Vec3::zero(), Vec3::zero()));
+                },
+            }
+        }
+        assert!(hits.item.iter().any(Option::is_some));
+        assert!(hits.item.last().unwrap().is_none());
+        assert!(hits.item.iter().flatten().all(|id| ids.contains(id)));
+
+        // Buffers are refilled, not appended to.
+        tree.closest_hits_soa(&rays[..3], &mut hits);
+        assert_eq!(hits.len(), 3);
+        tree.closest_hits_soa(&[], &mut hits);
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn test_bounded_rays() {
+        let spheres = random_spheres(2000, 5);
+        let target = spheres[0].mbr().lerp(0.5, 0.5, 0.5);
+        let ray = Ray::new(Vec3::zero(), target);
+
+        let mut tree: RTree<Sphere> = RTree::new();
+        for sphere in spheres {
+            tree.insert(sphere);
+        }
+        let (t, _, _) = tree.closest_hit(&ray).unwrap();
+
+        // A segment that stops short of the first hit sees nothing; one that
+        // reaches it sees the same hit, and starting past it skips it.
+        assert!(tree.closest_hit(&Ray::with_max_t(ray.origin, ray.direction, t * 0.99)).is_none());
+        assert_eq!(tree.closest_hit(&Ray::with_max_t(ray.origin, ray.direction, t)).map(|(t, _, _)| t), Some(t));
+        let past = Ray { min_t: t, ..ray };
+        assert!(tree.closest_hit(&past).is_none_or(|(next, _, _)| next > t));
+
+        let short = Ray::with_max_t(ray.origin, ray.direction, t * 0.5);
+        assert!(tree.iter_ray(&short).count() < tree.iter_ray(&ray).count());
+        for sphere in tree.iter_ray(&short) {
+            let (entry, _) = sphere.mbr().intersect_ray_t(&ray).unwrap();
+            assert!(entry <= t * 0.5);
+        }
+        assert_eq!(tree.iter_ray_ordered(&short).count(), tree.iter_ray(&short).count());
+    }
+}
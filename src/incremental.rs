@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use arena::NodeId;
+use bbox::BBox;
+use super::{Mbr, RTree, NodeStorage};
+
+/// Queues items for insertion and feeds them into a tree a bounded amount at
+/// a time, so real-time applications can ingest large batches spread across
+/// several frames instead of stalling on one.
+///
+/// The work is done in steps, each of which either splits a single node or
+/// inserts a single item into a leaf with room for it.  An insertion into a
+/// full leaf, which could otherwise split every node up to the root, is
+/// deferred: the nodes it would split are split one step at a time, from
+/// the top of the cascade down, and the item only goes in once its leaf has
+/// room.  A step therefore costs at most one descent of the tree and one
+/// split, however large the tree is.  Leaves which fill up this way never
+/// evict entries for reinsertion.
+///
+/// Queued items are not visible to queries until a `tick` has inserted
+/// them.
+#[derive(Debug)]
+pub struct IncrementalInserter<T> where T: Mbr {
+    pending: VecDeque<T>,
+}
+
+impl<T> IncrementalInserter<T> where T: Mbr {
+    pub fn new() -> IncrementalInserter<T> {
+        IncrementalInserter {
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queue an item for insertion.
+    pub fn push(&mut self, item: T) {
+        self.pending.push_back(item);
+    }
+
+    /// The number of items still waiting to be inserted.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Take at most `max_steps` steps, returning how many items were
+    /// inserted.
+    pub fn tick(&mut self, tree: &mut RTree<T>, max_steps: usize) -> usize {
+        let mut count = 0;
+        for _ in 0..max_steps {
+            if self.pending.is_empty() {
+                break;
+            }
+            count += self.step(tree) as usize;
+        }
+        count
+    }
+
+    /// Take steps until `budget` has elapsed, returning how many items
+    /// were inserted.  The budget is checked after each step, so it is
+    /// overrun by at most one step.  At least one step is taken per call
+    /// so that progress is always made.
+    pub fn tick_for(&mut self, tree: &mut RTree<T>, budget: Duration) -> usize {
+        let start = Instant::now();
+        let mut count = 0;
+        while !self.pending.is_empty() {
+            count += self.step(tree) as usize;
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        count
+    }
+
+    /// Split a node on the way to the next item's leaf, or if there is
+    /// nothing to split insert the item.  Returns whether it was inserted.
+    fn step(&mut self, tree: &mut RTree<T>) -> bool {
+        let bbox = match self.pending.front() {
+            Some(item) => item.mbr(),
+            None => return false,
+        };
+        if tree.split_ahead(&bbox) {
+            return false;
+        }
+        if let Some(item) = self.pending.pop_front() {
+            tree.insert(item);
+        }
+        true
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Split the topmost of the full nodes which inserting an item with
+    /// bounding box `bbox` would split, so that after enough calls the
+    /// insertion splits nothing.  Returns false once none is left.
+    fn split_ahead(&mut self, bbox: &BBox) -> bool {
+        let root = match self.root {
+            Some(root) => root,
+            None => return false,
+        };
+        let config = self.config;
+        let full = |tree: &RTree<T>, node: NodeId| {
+            if tree.nodes[node].is_leaf() {
+                tree.leaf_capacity_of(node) <= tree.nodes[node].shallow_len()
+            } else {
+                tree.nodes[node].is_full(&config)
+            }
+        };
+
+        let mut path = vec![root];
+        while !self.nodes[path[path.len() - 1]].is_leaf() {
+            path.push(self.nodes.choose_child(path[path.len() - 1], bbox, &config));
+        }
+        // The full nodes at the end of the path are the ones the insertion
+        // would split, each making room in its parent's place for the
+        // sibling of the one below.
+        let mut top = path.len();
+        while top > 0 && full(self, path[top - 1]) {
+            top -= 1;
+        }
+        if top == path.len() {
+            return false;
+        }
+
+        let node = path[top];
+        let sibling = self.nodes.split(node, &config);
+        if top == 0 {
+            // The root is full, so grow the tree by one level.
+            let bbox = self.nodes[node].bbox.union(&self.nodes[sibling].bbox);
+            self.root = Some(self.nodes.add(bbox, NodeStorage::Interior(vec![node, sibling])));
+        } else if let NodeStorage::Interior(ref mut children) = self.nodes[path[top - 1]].storage {
+            // Next to the node split, which keeps Hilbert trees in order.
+            let at = children.iter().position(|&child| child == node).expect("child must belong to node");
+            children.insert(at + 1, sibling);
+        }
+        true
+    }
+}
+
+impl<T> Default for IncrementalInserter<T> where T: Mbr {
+    fn default() -> IncrementalInserter<T> {
+        IncrementalInserter::new()
+    }
+}
+
+impl<T> Extend<T> for IncrementalInserter<T> where T: Mbr {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item=T> {
+        self.pending.extend(iter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::super::{RTree, RTreeBuilder, SplitStrategy};
+    use super::super::test_helpers::{Sphere, check_tree, random_spheres};
+    use super::IncrementalInserter;
+
+    #[test]
+    fn test_tick() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let mut inserter = IncrementalInserter::new();
+        inserter.extend((0..10).map(|i| {
+            Sphere::new(Vec3::xyz(10.0 * i as f64, 0.0, 0.0), 1.0).unwrap()
+        }));
+        assert_eq!(inserter.pending(), 10);
+
+        let ray = Ray::new(Vec3::xyz(-10.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0));
+        assert_eq!(inserter.tick(&mut spheres, 4), 4);
+        assert_eq!(spheres.iter_ray(&ray).count(), 4);
+        assert_eq!(inserter.pending(), 6);
+
+        assert_eq!(inserter.tick(&mut spheres, 100), 6);
+        assert!(inserter.is_done());
+        assert_eq!(inserter.tick(&mut spheres, 100), 0);
+        assert_eq!(spheres.iter_ray(&ray).count(), 10);
+    }
+
+    #[test]
+    fn test_tick_for() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let mut inserter = IncrementalInserter::new();
+        inserter.push(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        inserter.push(Sphere::new(Vec3::one(), 1.0).unwrap());
+
+        // A zero budget still makes progress.
+        assert_eq!(inserter.tick_for(&mut spheres, Duration::from_secs(0)), 1);
+        assert_eq!(inserter.tick_for(&mut spheres, Duration::from_secs(60)), 1);
+        assert!(inserter.is_done());
+    }
+
+    #[test]
+    fn test_steps_are_bounded() {
+        for &split in [SplitStrategy::Quadratic, SplitStrategy::RStar, SplitStrategy::Hilbert].iter() {
+            let mut spheres: RTree<Sphere> = RTreeBuilder::new().max_entries(4).split_strategy(split).build().unwrap();
+            let mut inserter = IncrementalInserter::new();
+            inserter.extend(random_spheres(1500, 71));
+
+            // A step inserts an item without adding nodes, other than the
+            // first leaf, or adds a node by splitting one, or two when the
+            // root grows a level.
+            let mut steps = 0;
+            while !inserter.is_done() {
+                let (len, nodes, depth) = (spheres.len(), spheres.nodes.len(), spheres.depth());
+                let inserted = inserter.tick(&mut spheres, 1);
+                let added = spheres.nodes.len() - nodes;
+                if inserted == 1 {
+                    let first = (len == 0) as usize;
+                    assert_eq!((spheres.len(), added, spheres.depth()), (len + 1, first, depth + first), "{:?}", split);
+                } else {
+                    assert_eq!(added, 1 + (spheres.depth() - depth), "{:?}", split);
+                }
+                steps += 1;
+            }
+            assert!(steps > 1500);
+            check_tree(&spheres);
+            assert_eq!(spheres.validate(), Ok(()), "{:?}", split);
+            for sphere in random_spheres(1500, 71).iter() {
+                assert!(spheres.remove(sphere).is_some());
+            }
+        }
+    }
+}
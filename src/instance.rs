@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use bbox::BBox;
+use hit::{Intersectable, Intersection};
+use ray::Ray;
+use transform::Transform;
+use super::{Mbr, RTree};
+
+const INSTANCE_TREE_EMPTY: &str = "instanced tree must not be empty";
+const INSTANCE_TRANSFORM_SINGULAR: &str = "instance transform must be invertible";
+
+/// A shared tree placed in the world by a transform, so that one mesh can
+/// appear many times without copying its geometry.
+///
+/// Instances go in a top-level tree of their own, indexed by their
+/// transformed bounds.  Ray queries against an instance move the ray into
+/// the inner tree's object space rather than moving the geometry into the
+/// world.  Use `RTree::closest_instance_hit` to learn which inner item was
+/// hit as well as which instance.
+#[derive(Debug)]
+pub struct Instance<T> where T: Mbr {
+    tree: Arc<RTree<T>>,
+    to_world: Transform,
+    to_object: Transform,
+    bbox: BBox,
+}
+
+impl<T> Clone for Instance<T> where T: Mbr {
+    fn clone(&self) -> Instance<T> {
+        Instance {
+            tree: self.tree.clone(),
+            to_world: self.to_world,
+            to_object: self.to_object,
+            bbox: self.bbox,
+        }
+    }
+}
+
+impl<T> Instance<T> where T: Mbr {
+    /// Place `tree` in the world by `transform`, which maps object space
+    /// to world space.
+    pub fn new(tree: Arc<RTree<T>>, transform: Transform) -> Result<Instance<T>, &'static str> {
+        let bbox = match tree.root_node() {
+            Some(root) => root.bbox.transformed(&transform),
+            None => return Err(INSTANCE_TREE_EMPTY),
+        };
+        let to_object = transform.inverse().ok_or(INSTANCE_TRANSFORM_SINGULAR)?;
+        Ok(Instance {
+            tree,
+            to_world: transform,
+            to_object,
+            bbox,
+        })
+    }
+
+    pub fn tree(&self) -> &Arc<RTree<T>> {
+        &self.tree
+    }
+
+    /// The transform from object space to world space.
+    pub fn transform(&self) -> &Transform {
+        &self.to_world
+    }
+}
+
+impl<T> Instance<T> where T: Mbr + Intersectable {
+    /// The first inner item hit by the world space `ray`, like
+    /// `RTree::closest_hit`.  The intersection is in world space.
+    pub fn closest_hit(&self, ray: &Ray) -> Option<(f64, &T, Intersection)> {
+        let local = ray.transformed(&self.to_object);
+        self.tree.closest_hit(&local).map(|(t, item, hit)| {
+            let hit = Intersection {
+                t,
+                point: self.to_world.transform_point(&hit.point),
+                normal: self.to_object.transform_vector_transposed(&hit.normal).unit(),
+            };
+            (t, item, hit)
+        })
+    }
+}
+
+impl<T> Mbr for Instance<T> where T: Mbr {
+    fn mbr(&self) -> BBox {
+        self.bbox
+    }
+}
+
+impl<T> Intersectable for Instance<T> where T: Mbr + Intersectable {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        self.closest_hit(ray).map(|(_, _, hit)| hit)
+    }
+}
+
+impl<T> RTree<Instance<T>> where T: Mbr + Intersectable {
+    /// The first item hit by `ray` in any instance, along with the instance
+    /// it belongs to.  The intersection is in world space.
+    pub fn closest_instance_hit(&self, ray: &Ray) -> Option<(f64, &Instance<T>, &T, Intersection)> {
+        let mut best: Option<(f64, &Instance<T>, &T, Intersection)> = None;
+        let mut candidates = self.iter_ray_ordered(ray);
+        while let Some(instance) = candidates.next() {
+            if let Some((t, item, hit)) = instance.closest_hit(ray) {
+                if best.is_none_or(|(best_t, _, _, _)| t < best_t) {
+                    best = Some((t, instance, item, hit));
+                    candidates.set_max_t(t);
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ::ray::Ray;
+    use ::shapes::Sphere;
+    use ::transform::Transform;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree};
+    use super::Instance;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).len() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_instances() {
+        let mut ball = RTree::new();
+        ball.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        let ball = Arc::new(ball);
+        assert!(Instance::new(Arc::new(RTree::<Sphere>::new()), Transform::identity()).is_err());
+        assert!(Instance::new(ball.clone(), Transform::scale(Vec3::zero())).is_err());
+
+        // A row of balls along x, each twice the size of the last.
+        let mut scene = RTree::new();
+        for i in 0..10 {
+            let transform = Transform::translation(Vec3::xyz(i as f64 * 100.0, 0.0, 0.0))
+                * Transform::scale(Vec3::one() * (1 << i) as f64);
+            scene.insert(Instance::new(ball.clone(), transform).unwrap());
+        }
+        assert_eq!(Arc::strong_count(&ball), 11);
+        let bbox = scene.iter().nth(3).unwrap().mbr();
+        assert_near(bbox.min, Vec3::xyz(292.0, -8.0, -8.0));
+        assert_near(bbox.max, Vec3::xyz(308.0, 8.0, 8.0));
+
+        let ray = Ray::new(Vec3::xyz(300.0, 50.0, 0.0), Vec3::xyz(0.0, -1.0, 0.0));
+        let (t, instance, _, hit) = scene.closest_instance_hit(&ray).unwrap();
+        assert!((t - 42.0).abs() < 1e-9);
+        assert_near(instance.transform().transform_point(&Vec3::zero()), Vec3::xyz(300.0, 0.0, 0.0));
+        assert_near(hit.point, Vec3::xyz(300.0, 8.0, 0.0));
+        assert_near(hit.normal, Vec3::xyz(0.0, 1.0, 0.0));
+        assert_eq!(scene.closest_hit(&ray).map(|(t, _, _)| t), Some(t));
+
+        // Along the row, the first ball is hit first.
+        let along = Ray::new(Vec3::xyz(-10.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0));
+        let (t, _, _, hit) = scene.closest_instance_hit(&along).unwrap();
+        assert!((t - 9.0).abs() < 1e-9);
+        assert_near(hit.normal, Vec3::xyz(-1.0, 0.0, 0.0));
+        assert!(scene.closest_instance_hit(&Ray::new(Vec3::xyz(0.0, 50.0, 0.0), Vec3::xyz(0.0, 1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn test_instance_normals_under_squash() {
+        // Squash a unit ball into a disc: its top normal must stay vertical.
+        let mut ball = RTree::new();
+        ball.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        let disc = Instance::new(Arc::new(ball), Transform::scale(Vec3::xyz(4.0, 0.5, 4.0))).unwrap();
+        let ray = Ray::new(Vec3::xyz(0.0, 5.0, 0.0), Vec3::xyz(0.0, -1.0, 0.0));
+        let (t, _, hit) = disc.closest_hit(&ray).unwrap();
+        assert!((t - 4.5).abs() < 1e-9);
+        assert_near(hit.normal, Vec3::xyz(0.0, 1.0, 0.0));
+
+        let slanted = Ray::new(Vec3::xyz(2.0, 5.0, 0.0), Vec3::xyz(0.0, -1.0, 0.0));
+        let (_, _, hit) = disc.closest_hit(&slanted).unwrap();
+        // The surface is x^2 / 16 + 4 y^2 = 1, whose gradient (x / 8, 8 y)
+        // is along the normal.
+        let y = 0.5 * (1.0f64 - 0.25).sqrt();
+        assert_near(hit.point, Vec3::xyz(2.0, y, 0.0));
+        assert_near(hit.normal, Vec3::xyz(2.0 / 8.0, 8.0 * y, 0.0).unit());
+    }
+}
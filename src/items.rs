@@ -0,0 +1,161 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::slice::Iter as SliceIter;
+use std::vec::IntoIter as VecIntoIter;
+
+use super::{Mbr, RTree, RTreeNode, NodeStorage, LeafItem, LeafVec};
+
+/// Every item in a tree, in no particular order.  Created by `RTree::iter`.
+pub struct Items<'a, T, const N: usize = 3> where T: Mbr<N>+'a {
+    // The arena's slots are scanned in order; interior nodes and free
+    // slots have no items of their own.
+    slots: SliceIter<'a, RTreeNode<T, N>>,
+    leaf_iter: Option<SliceIter<'a, LeafItem<T, N>>>,
+    remaining: usize,
+}
+
+impl<'a, T, const N: usize> fmt::Debug for Items<'a, T, N> where T: Mbr<N>+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Items").field("remaining", &self.remaining).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T, const N: usize> Iterator for Items<'a, T, N> where T: Mbr<N>+'a {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(leaf) = self.leaf_iter.as_mut().and_then(Iterator::next) {
+                self.remaining -= 1;
+                return Some(&leaf.item);
+            }
+
+            if let NodeStorage::Leaf(ref items) = self.slots.next()?.storage {
+                self.leaf_iter = Some(items.iter());
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T, const N: usize> ExactSizeIterator for Items<'a, T, N> where T: Mbr<N>+'a {}
+
+/// Every item in a tree, by value.  Created by `RTree::into_iter`.
+pub struct IntoIter<T, const N: usize = 3> where T: Mbr<N> {
+    slots: VecIntoIter<RTreeNode<T, N>>,
+    leaf_iter: Option<<LeafVec<T, N> as IntoIterator>::IntoIter>,
+    remaining: usize,
+}
+
+impl<T, const N: usize> fmt::Debug for IntoIter<T, N> where T: Mbr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoIter").field("remaining", &self.remaining).finish_non_exhaustive()
+    }
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> where T: Mbr<N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(leaf) = self.leaf_iter.as_mut().and_then(Iterator::next) {
+                self.remaining -= 1;
+                return Some(leaf.item);
+            }
+
+            if let NodeStorage::Leaf(items) = self.slots.next()?.storage {
+                self.leaf_iter = Some(items.into_iter());
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T, const N: usize> ExactSizeIterator for IntoIter<T, N> where T: Mbr<N> {}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    /// Iterate over every item in the tree, leaf by leaf.
+    pub fn iter(&self) -> Items<'_, T, N> {
+        Items {
+            slots: self.nodes.slots().iter(),
+            leaf_iter: None,
+            remaining: self.len(),
+        }
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a RTree<T, N> where T: Mbr<N> {
+    type Item = &'a T;
+    type IntoIter = Items<'a, T, N>;
+
+    fn into_iter(self) -> Items<'a, T, N> {
+        self.iter()
+    }
+}
+
+impl<T, const N: usize> IntoIterator for RTree<T, N> where T: Mbr<N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    fn into_iter(self) -> IntoIter<T, N> {
+        IntoIter {
+            remaining: self.len(),
+            slots: self.nodes.into_slots().into_iter(),
+            leaf_iter: None,
+        }
+    }
+}
+
+impl<T, const N: usize> Extend<T> for RTree<T, N> where T: Mbr<N> {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item=T> {
+        for item in iter {
+            self.insert(item);
+        }
+    }
+}
+
+/// Collecting into a tree bulk loads it, which is much faster than
+/// inserting items one by one.
+impl<T, const N: usize> FromIterator<T> for RTree<T, N> where T: Mbr<N> {
+    fn from_iter<I>(iter: I) -> RTree<T, N> where I: IntoIterator<Item=T> {
+        RTree::bulk_load(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres, check_tree};
+
+    #[test]
+    fn test_iteration() {
+        let mut spheres: RTree<Sphere> = random_spheres(1000, 10).into_iter().collect();
+        check_tree(&spheres);
+        assert_eq!(spheres.len(), 1000);
+
+        spheres.extend(random_spheres(500, 11));
+        assert_eq!(spheres.iter().len(), 1500);
+
+        let expected = random_spheres(1000, 10);
+        let centre = |s: &Sphere| {
+            let c = s.mbr().lerp(0.5, 0.5, 0.5);
+            (c.x, c.y, c.z)
+        };
+        let mut found: Vec<_> = (&spheres).into_iter().map(centre).collect();
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut all: Vec<_> = expected.iter().chain(random_spheres(500, 11).iter()).map(centre).collect();
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(found, all);
+
+        let mut owned: Vec<Sphere> = spheres.into_iter().collect();
+        assert_eq!(owned.len(), 1500);
+        owned.retain(|s| expected.contains(s));
+        assert_eq!(owned.len(), 1000);
+    }
+}
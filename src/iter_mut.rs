@@ -0,0 +1,176 @@
+use std::any::Any;
+use std::fmt;
+use std::slice::IterMut as SliceIterMut;
+use std::vec;
+
+use aabb::Aabb;
+use arena::NodeId;
+use bbox::BBox;
+use ray::Ray;
+use super::{Mbr, RTree, RTreeNode, NodeStorage, LeafItem};
+
+/// Which bounding boxes a query iterator descends into and yields.
+#[derive(Debug)]
+pub enum Query<const N: usize = 3> {
+    All,
+    Envelope(Aabb<N>),
+    Ray(Ray),
+}
+
+impl<const N: usize> Query<N> {
+    pub fn matches(&self, bbox: &Aabb<N>) -> bool {
+        match *self {
+            Query::All => true,
+            Query::Envelope(ref envelope) => bbox.overlaps(envelope),
+            // Ray queries are only created for three dimensional trees, so
+            // the box is always a `BBox` here.
+            Query::Ray(ref ray) => (bbox as &dyn Any).downcast_ref::<BBox>().is_some_and(|bbox| bbox.intersects(ray)),
+        }
+    }
+}
+
+/// Mutable access to the items matching a query.  Created by
+/// `RTree::iter_mut`, `RTree::iter_bbox_mut` and `RTree::iter_ray_mut`.
+///
+/// The stored bounding boxes are not updated, so the items' `mbr()` must
+/// not change while they are borrowed.  Use `RTree::for_each_mut_in_envelope`
+/// to edit geometry.
+pub struct IterMut<'a, T, const N: usize = 3> where T: Mbr<N>+'a {
+    // The matching leaves, in slot order, so that the arena can be walked
+    // once with a single mutable borrow.
+    leaves: vec::IntoIter<NodeId>,
+    slots: SliceIterMut<'a, RTreeNode<T, N>>,
+    position: usize,
+    leaf_iter: Option<SliceIterMut<'a, LeafItem<T, N>>>,
+    query: Query<N>,
+}
+
+impl<'a, T, const N: usize> IterMut<'a, T, N> where T: Mbr<N>+'a {
+    fn new(rtree: &'a mut RTree<T, N>, query: Query<N>) -> IterMut<'a, T, N> {
+        let mut leaves = Vec::new();
+        let mut stack = Vec::new();
+        if let Some(root) = rtree.root {
+            if query.matches(&rtree.nodes[root].bbox) {
+                stack.push(root);
+            }
+        }
+        while let Some(node) = stack.pop() {
+            match rtree.nodes[node].storage {
+                NodeStorage::Interior(ref children) => {
+                    for &child in children.iter() {
+                        if query.matches(&rtree.nodes[child].bbox) {
+                            stack.push(child);
+                        }
+                    }
+                },
+                NodeStorage::Leaf(_) => leaves.push(node),
+            }
+        }
+        leaves.sort_unstable();
+        IterMut {
+            leaves: leaves.into_iter(),
+            slots: rtree.nodes.slots_mut().iter_mut(),
+            position: 0,
+            leaf_iter: None,
+            query,
+        }
+    }
+}
+
+impl<'a, T, const N: usize> fmt::Debug for IterMut<'a, T, N> where T: Mbr<N>+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IterMut").field("query", &self.query).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T, const N: usize> Iterator for IterMut<'a, T, N> where T: Mbr<N>+'a {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<&'a mut T> {
+        loop {
+            let query = &self.query;
+            if let Some(leaf_iter) = self.leaf_iter.as_mut() {
+                if let Some(val) = leaf_iter.find(|x| query.matches(&x.bbox)) {
+                    return Some(&mut val.item);
+                }
+            }
+
+            let leaf = self.leaves.next()?;
+            let node = self.slots.nth(leaf - self.position).expect("leaf id out of range");
+            self.position = leaf + 1;
+            if let NodeStorage::Leaf(ref mut items) = node.storage {
+                self.leaf_iter = Some(items.iter_mut());
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    /// Mutable references to every item, in no particular order.  The
+    /// items' bounding boxes must not change.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, N> {
+        IterMut::new(self, Query::All)
+    }
+
+    /// Mutable references to the items whose bounding box overlaps
+    /// `query`.  The items' bounding boxes must not change.
+    pub fn iter_bbox_mut(&mut self, query: &Aabb<N>) -> IterMut<'_, T, N> {
+        IterMut::new(self, Query::Envelope(*query))
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Mutable references to the items whose bounding box `ray` passes
+    /// through.  The items' bounding boxes must not change.
+    pub fn iter_ray_mut(&mut self, ray: &Ray) -> IterMut<'_, T> {
+        IterMut::new(self, Query::Ray(*ray))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree};
+
+    struct Tagged {
+        origin: Vec3,
+        hits: usize,
+    }
+
+    impl Mbr for Tagged {
+        fn mbr(&self) -> BBox {
+            BBox {
+                min: self.origin - 1.0,
+                max: self.origin + 1.0,
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut tagged: RTree<Tagged> = RTree::new();
+        for i in 0..300 {
+            tagged.insert(Tagged { origin: Vec3::xyz(3.0 * i as f64, 0.0, 0.0), hits: 0 });
+        }
+
+        let envelope = BBox {
+            min: Vec3::xyz(0.0, -1.0, -1.0),
+            max: Vec3::xyz(30.0, 1.0, 1.0),
+        };
+        for item in tagged.iter_bbox_mut(&envelope) {
+            item.hits += 1;
+        }
+        let ray = Ray::new(Vec3::xyz(-10.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0001, 0.0001));
+        for item in tagged.iter_ray_mut(&ray) {
+            item.hits += 1;
+        }
+
+        assert_eq!(tagged.iter_mut().count(), 300);
+        let twice = tagged.iter_mut().filter(|t| t.hits == 2).count();
+        let once = tagged.iter_mut().filter(|t| t.hits == 1).count();
+        assert_eq!(twice, 11);
+        assert_eq!(twice + once, 300);
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+
+use arena::Arena;
+use super::{Mbr, RTree, RTreeNode, NodeStorage};
+
+/// Pairs of items from two trees whose bounding boxes overlap.  Created by
+/// `RTree::join`.
+///
+/// Both trees are walked together: a pair of nodes is only expanded when
+/// their boxes overlap, so disjoint regions of the two trees are never
+/// compared item by item.
+pub struct JoinIter<'a, T, U> where T: Mbr+'a, U: Mbr+'a {
+    left: &'a Arena<T, 3>,
+    right: &'a Arena<U, 3>,
+    stack: Vec<(&'a RTreeNode<T>, &'a RTreeNode<U>)>,
+    found: Vec<(&'a T, &'a U)>,
+}
+
+impl<'a, T, U> JoinIter<'a, T, U> where T: Mbr+'a, U: Mbr+'a {
+    fn new(left: &'a RTree<T>, right: &'a RTree<U>) -> JoinIter<'a, T, U> {
+        let mut stack = Vec::new();
+        if let (Some(l), Some(r)) = (left.root_node(), right.root_node()) {
+            if l.bbox.overlaps(&r.bbox) {
+                stack.push((l, r));
+            }
+        }
+        JoinIter {
+            left: &left.nodes,
+            right: &right.nodes,
+            stack,
+            found: Vec::new(),
+        }
+    }
+
+    /// Expand one overlapping pair of nodes.
+    fn expand(&mut self, left: &'a RTreeNode<T>, right: &'a RTreeNode<U>) {
+        let (left_nodes, right_nodes) = (self.left, self.right);
+        match (&left.storage, &right.storage) {
+            (NodeStorage::Interior(ls), NodeStorage::Interior(rs)) => {
+                for l in left_nodes.children(ls).filter(|l| l.bbox.overlaps(&right.bbox)) {
+                    for r in right_nodes.children(rs).filter(|r| r.bbox.overlaps(&l.bbox)) {
+                        self.stack.push((l, r));
+                    }
+                }
+            },
+            (NodeStorage::Interior(ls), NodeStorage::Leaf(_)) => {
+                for l in left_nodes.children(ls).filter(|l| l.bbox.overlaps(&right.bbox)) {
+                    self.stack.push((l, right));
+                }
+            },
+            (NodeStorage::Leaf(_), NodeStorage::Interior(rs)) => {
+                for r in right_nodes.children(rs).filter(|r| r.bbox.overlaps(&left.bbox)) {
+                    self.stack.push((left, r));
+                }
+            },
+            (NodeStorage::Leaf(ls), NodeStorage::Leaf(rs)) => {
+                for l in ls.iter().filter(|l| l.bbox.overlaps(&right.bbox)) {
+                    for r in rs.iter().filter(|r| r.bbox.overlaps(&l.bbox)) {
+                        self.found.push((&l.item, &r.item));
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl<'a, T, U> fmt::Debug for JoinIter<'a, T, U> where T: Mbr+'a, U: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JoinIter").field("pending", &self.stack.len()).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T, U> Iterator for JoinIter<'a, T, U> where T: Mbr+'a, U: Mbr+'a {
+    type Item = (&'a T, &'a U);
+
+    fn next(&mut self) -> Option<(&'a T, &'a U)> {
+        loop {
+            if let Some(pair) = self.found.pop() {
+                return Some(pair);
+            }
+            let (left, right) = self.stack.pop()?;
+            self.expand(left, right);
+        }
+    }
+}
+
+/// Distinct pairs of items from one tree whose bounding boxes overlap.
+/// Created by `RTree::overlapping_pairs`.
+///
+/// Each node is paired with itself and with each of its overlapping
+/// siblings.  A node paired with itself only yields pairs of distinct
+/// children, and pairs of distinct nodes are handed to a `JoinIter`.  This
+/// way every unordered pair is reported exactly once.
+pub struct SelfJoinIter<'a, T> where T: Mbr+'a {
+    nodes: Vec<&'a RTreeNode<T>>,
+    pairs: JoinIter<'a, T, T>,
+}
+
+impl<'a, T> fmt::Debug for SelfJoinIter<'a, T> where T: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SelfJoinIter").field("pairs", &self.pairs).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> Iterator for SelfJoinIter<'a, T> where T: Mbr+'a {
+    type Item = (&'a T, &'a T);
+
+    fn next(&mut self) -> Option<(&'a T, &'a T)> {
+        loop {
+            if let Some(pair) = self.pairs.next() {
+                return Some(pair);
+            }
+            let arena = self.pairs.left;
+            match self.nodes.pop()?.storage {
+                NodeStorage::Interior(ref children) => {
+                    for (i, a) in arena.children(children).enumerate() {
+                        self.nodes.push(a);
+                        for b in arena.children(&children[i + 1..]).filter(|b| b.bbox.overlaps(&a.bbox)) {
+                            self.pairs.stack.push((a, b));
+                        }
+                    }
+                },
+                NodeStorage::Leaf(ref items) => {
+                    for (i, a) in items.iter().enumerate() {
+                        for b in items[i + 1..].iter().filter(|b| b.bbox.overlaps(&a.bbox)) {
+                            self.pairs.found.push((&a.item, &b.item));
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Every unordered pair of distinct items in this tree whose bounding
+    /// boxes overlap, i.e. the broad phase of collision detection within
+    /// one set of objects.
+    pub fn overlapping_pairs(&self) -> SelfJoinIter<'_, T> {
+        SelfJoinIter {
+            nodes: self.root_node().into_iter().collect(),
+            pairs: JoinIter {
+                left: &self.nodes,
+                right: &self.nodes,
+                stack: Vec::new(),
+                found: Vec::new(),
+            },
+        }
+    }
+
+    /// Every pair of items, one from each tree, whose bounding boxes
+    /// overlap.  Useful as the broad phase of collision detection between
+    /// two sets of objects.
+    pub fn join<'a, U>(&'a self, other: &'a RTree<U>) -> JoinIter<'a, T, U> where U: Mbr {
+        JoinIter::new(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_join() {
+        let left_items = random_spheres(1500, 14);
+        let right_items = random_spheres(700, 15);
+        let expected = left_items.iter()
+            .map(|l| right_items.iter().filter(|r| l.mbr().overlaps(&r.mbr())).count())
+            .sum::<usize>();
+        assert!(expected > 0);
+
+        let left: RTree<Sphere> = random_spheres(1500, 14).into_iter().collect();
+        let mut right: RTree<Sphere> = RTree::new();
+        for sphere in random_spheres(700, 15) {
+            right.insert(sphere);
+        }
+
+        let pairs: Vec<_> = left.join(&right).collect();
+        assert_eq!(pairs.len(), expected);
+        assert!(pairs.iter().all(|&(l, r)| l.mbr().overlaps(&r.mbr())));
+        assert_eq!(right.join(&left).count(), expected);
+        assert_eq!(left.join(&RTree::<Sphere>::new()).count(), 0);
+    }
+
+    #[test]
+    fn test_overlapping_pairs() {
+        let items = random_spheres(3000, 16);
+        let mut expected = 0;
+        for (i, a) in items.iter().enumerate() {
+            expected += items[i + 1..].iter().filter(|b| a.mbr().overlaps(&b.mbr())).count();
+        }
+        assert!(expected > 0);
+
+        let tree: RTree<Sphere> = random_spheres(3000, 16).into_iter().collect();
+        let pairs: Vec<_> = tree.overlapping_pairs().collect();
+        assert_eq!(pairs.len(), expected);
+        assert!(pairs.iter().all(|&(a, b)| a.mbr().overlaps(&b.mbr()) && !::std::ptr::eq(a, b)));
+        assert_eq!(RTree::<Sphere>::new().overlapping_pairs().count(), 0);
+    }
+}
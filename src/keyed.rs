@@ -0,0 +1,382 @@
+//! A map from keys to geometry and values: keys are looked up through a
+//! `HashMap`, and the geometry is searched through an `RTree`.
+//!
+//! ```
+//! use rtree::prelude::*;
+//! use rtree::keyed::KeyedRTreeMap;
+//!
+//! let mut units = KeyedRTreeMap::new();
+//! units.insert("scout", Vec3::xyz(1.0, 2.0, 3.0), 40);
+//! units.insert("tank", Vec3::xyz(8.0, 8.0, 8.0), 300);
+//! units.insert("scout", Vec3::xyz(2.0, 2.0, 3.0), 35);
+//!
+//! let near = BBox { min: Vec3::zero(), max: Vec3::xyz(5.0, 5.0, 5.0) };
+//! let found: Vec<_> = units.iter_bbox(&near).collect();
+//! assert_eq!(found, [(&"scout", &35)]);
+//! assert_eq!(units.get("tank"), Some((&Vec3::xyz(8.0, 8.0, 8.0), &300)));
+//! ```
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+
+use aabb::Aabb;
+use arena::NodeId;
+use super::{ItemId, Mbr, RTree, RTreeConfig};
+
+const MISSING_RECORD: &str = "keyed entry is missing from the tree";
+
+/// A key with its geometry and value, as stored in the tree.
+#[derive(Debug)]
+struct Record<K, G, V> {
+    key: K,
+    geometry: G,
+    value: V,
+}
+
+impl<K, G, V, const N: usize> Mbr<N> for Record<K, G, V> where G: Mbr<N> {
+    fn mbr(&self) -> Aabb<N> {
+        self.geometry.mbr()
+    }
+}
+
+/// Values stored under keys of their own, each with geometry to search
+/// them by.  Unlike `RTreeMap`, whose keys are the geometry, a key here
+/// stays the same as its geometry moves, e.g. an entity id, and finding it
+/// is a hash lookup rather than a search of the tree.
+///
+/// Every key is also kept in its tree entry, so that spatial queries can
+/// yield it, which is why keys must be `Clone`.
+#[derive(Debug)]
+pub struct KeyedRTreeMap<K, G, V, const N: usize = 3> where G: Mbr<N> {
+    tree: RTree<Record<K, G, V>, N>,
+    ids: HashMap<K, ItemId>,
+}
+
+impl<K, G, V, const N: usize> KeyedRTreeMap<K, G, V, N> where K: Eq + Hash + Clone, G: Mbr<N> {
+    pub fn new() -> KeyedRTreeMap<K, G, V, N> {
+        KeyedRTreeMap::with_config(RTreeConfig::default())
+    }
+
+    pub fn with_config(config: RTreeConfig) -> KeyedRTreeMap<K, G, V, N> {
+        KeyedRTreeMap { tree: RTree::with_config(config), ids: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Store `geometry` and `value` under `key`, returning what they
+    /// replace if `key` was already present.  An existing entry is updated
+    /// in place when its new geometry still fits its leaf.
+    pub fn insert(&mut self, key: K, geometry: G, value: V) -> Option<(G, V)> {
+        match self.ids.get(&key) {
+            Some(&id) => {
+                let old = self.tree.insert_or_update(id, Record { key, geometry, value });
+                old.map(|record| (record.geometry, record.value))
+            },
+            None => {
+                let id = self.tree.insert(Record { key: key.clone(), geometry, value });
+                self.ids.insert(key, id);
+                None
+            },
+        }
+    }
+
+    /// The geometry and value stored under `key`.
+    pub fn get<Q>(&self, key: &Q) -> Option<(&G, &V)> where K: Borrow<Q>, Q: Eq + Hash + ?Sized {
+        let record = self.tree.get(*self.ids.get(key)?)?;
+        Some((&record.geometry, &record.value))
+    }
+
+    /// The value stored under `key`, for changing in place.  The geometry
+    /// can only be changed through `insert`, which moves the entry.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q>, Q: Eq + Hash + ?Sized {
+        let id = *self.ids.get(key)?;
+        self.record_mut(id).map(|record| &mut record.value)
+    }
+
+    /// The entry for `key`, for looking up, inserting or changing its
+    /// value in one lookup.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, G, V, N> {
+        let tree = &mut self.tree;
+        match self.ids.entry(key) {
+            hash_map::Entry::Occupied(entry) => {
+                let (leaf, index) = tree.locate_id(*entry.get()).expect(MISSING_RECORD);
+                Entry::Occupied(OccupiedEntry { tree, entry, leaf, index })
+            },
+            hash_map::Entry::Vacant(entry) => Entry::Vacant(VacantEntry { tree, entry }),
+        }
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool where K: Borrow<Q>, Q: Eq + Hash + ?Sized {
+        self.ids.contains_key(key)
+    }
+
+    /// Take `key` and its geometry and value out of the map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<(G, V)> where K: Borrow<Q>, Q: Eq + Hash + ?Sized {
+        let id = self.ids.remove(key)?;
+        self.tree.remove_by_id(id).map(|record| (record.geometry, record.value))
+    }
+
+    /// Every key and value, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item=(&K, &V)> + '_ {
+        self.tree.iter().map(|record| (&record.key, &record.value))
+    }
+
+    /// The keys whose geometry's bounding box overlaps `query`, and their
+    /// values.
+    pub fn iter_bbox(&self, query: &Aabb<N>) -> impl Iterator<Item=(&K, &V)> + '_ {
+        self.tree.iter_bbox(query).map(|record| (&record.key, &record.value))
+    }
+
+    /// The keys whose geometry's bounding box overlaps `query`, with
+    /// mutable access to their values.
+    pub fn iter_bbox_mut(&mut self, query: &Aabb<N>) -> impl Iterator<Item=(&K, &mut V)> + '_ {
+        self.tree.iter_bbox_mut(query).map(|record| (&record.key, &mut record.value))
+    }
+
+    /// The tree entry of the item `id` refers to.
+    fn record_mut(&mut self, id: ItemId) -> Option<&mut Record<K, G, V>> {
+        let (leaf, index) = self.tree.locate_id(id)?;
+        Some(&mut self.tree.nodes.leaf_entry_mut(leaf, index).item)
+    }
+}
+
+impl<K, G, V, const N: usize> Default for KeyedRTreeMap<K, G, V, N> where K: Eq + Hash + Clone, G: Mbr<N> {
+    fn default() -> KeyedRTreeMap<K, G, V, N> {
+        KeyedRTreeMap::new()
+    }
+}
+
+/// A key's place in a `KeyedRTreeMap`, which may or may not hold a value.
+/// Created by `KeyedRTreeMap::entry`.
+#[derive(Debug)]
+pub enum Entry<'a, K, G, V, const N: usize = 3> where K: 'a, G: Mbr<N>+'a, V: 'a {
+    Occupied(OccupiedEntry<'a, K, G, V, N>),
+    Vacant(VacantEntry<'a, K, G, V, N>),
+}
+
+impl<'a, K, G, V, const N: usize> Entry<'a, K, G, V, N> where K: Eq + Hash + Clone + 'a, G: Mbr<N>+'a, V: 'a {
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    /// The value, after inserting `geometry` and `value` if there was none.
+    pub fn or_insert(self, geometry: G, value: V) -> &'a mut V {
+        self.or_insert_with(|| (geometry, value))
+    }
+
+    /// The value, after inserting the geometry and value made by `default`
+    /// if there was none.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut V where F: FnOnce() -> (G, V) {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let (geometry, value) = default();
+                entry.insert(geometry, value)
+            },
+        }
+    }
+
+    /// Apply `f` to the value if there is one.
+    pub fn and_modify<F>(self, f: F) -> Entry<'a, K, G, V, N> where F: FnOnce(&mut V) {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            },
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+/// A key which has geometry and a value.
+pub struct OccupiedEntry<'a, K, G, V, const N: usize = 3> where K: 'a, G: Mbr<N>+'a, V: 'a {
+    tree: &'a mut RTree<Record<K, G, V>, N>,
+    entry: hash_map::OccupiedEntry<'a, K, ItemId>,
+    // Where the record is stored, so that it need not be searched for again.
+    leaf: NodeId,
+    index: usize,
+}
+
+impl<'a, K, G, V, const N: usize> OccupiedEntry<'a, K, G, V, N> where K: Eq + Hash + Clone + 'a, G: Mbr<N>+'a, V: 'a {
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    pub fn get(&self) -> (&G, &V) {
+        let record = &self.tree.nodes.leaf_entry(self.leaf, self.index).item;
+        (&record.geometry, &record.value)
+    }
+
+    /// The value, for changing in place.  The geometry can only be changed
+    /// through `KeyedRTreeMap::insert`, which moves the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.tree.nodes.leaf_entry_mut(self.leaf, self.index).item.value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.tree.nodes.leaf_entry_mut(self.leaf, self.index).item.value
+    }
+
+    /// Replace the value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    /// Take the key's geometry and value out of the map.
+    pub fn remove(self) -> (G, V) {
+        let record = self.tree.remove_by_id(self.entry.remove()).expect(MISSING_RECORD);
+        (record.geometry, record.value)
+    }
+}
+
+impl<'a, K, G, V, const N: usize> fmt::Debug for OccupiedEntry<'a, K, G, V, N>
+    where
+        K: fmt::Debug+'a,
+        G: Mbr<N>+fmt::Debug+'a,
+        V: fmt::Debug+'a {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let record = &self.tree.nodes.leaf_entry(self.leaf, self.index).item;
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.entry.key())
+            .field("geometry", &record.geometry)
+            .field("value", &record.value)
+            .finish()
+    }
+}
+
+/// A key which has no geometry or value.
+pub struct VacantEntry<'a, K, G, V, const N: usize = 3> where K: 'a, G: Mbr<N>+'a, V: 'a {
+    tree: &'a mut RTree<Record<K, G, V>, N>,
+    entry: hash_map::VacantEntry<'a, K, ItemId>,
+}
+
+impl<'a, K, G, V, const N: usize> VacantEntry<'a, K, G, V, N> where K: Eq + Hash + Clone + 'a, G: Mbr<N>+'a, V: 'a {
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    pub fn into_key(self) -> K {
+        self.entry.into_key()
+    }
+
+    /// Store `geometry` and `value` under the key, returning a reference to
+    /// the value.
+    pub fn insert(self, geometry: G, value: V) -> &'a mut V {
+        let VacantEntry { tree, entry } = self;
+        let (id, record) = tree.insert_mut(Record { key: entry.key().clone(), geometry, value });
+        entry.insert(id);
+        &mut record.value
+    }
+}
+
+impl<'a, K, G, V, const N: usize> fmt::Debug for VacantEntry<'a, K, G, V, N> where K: fmt::Debug+'a, G: Mbr<N>+'a, V: 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(self.entry.key()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::aabb::Aabb;
+    use ::point::Point;
+    use super::{Entry, KeyedRTreeMap};
+    use super::super::Mbr;
+    use super::super::test_helpers::{Sphere, check_tree, random_spheres};
+
+    #[test]
+    fn test_keyed_map() {
+        let mut spheres = KeyedRTreeMap::new();
+        for (idx, sphere) in random_spheres(1000, 91).into_iter().enumerate() {
+            assert!(spheres.insert(format!("s{}", idx), sphere, idx).is_none());
+        }
+        assert_eq!(spheres.len(), 1000);
+        assert_eq!(spheres.get("s5").map(|(_, &v)| v), Some(5));
+        assert!(spheres.get("s1000").is_none());
+
+        // Moving an entry keeps its key, and the old geometry and value
+        // come back.
+        let far = Sphere::new(Point::new([-500.0, 0.0, 0.0]), 1.0).unwrap();
+        let (old, value) = spheres.insert("s5".to_string(), far, 55).unwrap();
+        assert_eq!(old, random_spheres(6, 91).pop().unwrap());
+        assert_eq!(value, 5);
+        assert_eq!(spheres.len(), 1000);
+        let around = Aabb { min: Point::new([-510.0, -10.0, -10.0]), max: Point::new([-490.0, 10.0, 10.0]) };
+        assert_eq!(spheres.iter_bbox(&around).collect::<Vec<_>>(), [(&"s5".to_string(), &55)]);
+
+        for (_, value) in spheres.iter_bbox_mut(&around) {
+            *value += 1;
+        }
+        *spheres.get_mut("s6").unwrap() += 1;
+        assert_eq!(spheres.get("s5").map(|(_, &v)| v), Some(56));
+        assert_eq!(spheres.get("s6").map(|(_, &v)| v), Some(7));
+
+        // Queries agree with a scan of every entry.
+        let query = Aabb { min: Point::new([100.0, 200.0, 300.0]), max: Point::new([400.0, 500.0, 600.0]) };
+        let expected = random_spheres(1000, 91).iter().enumerate()
+            .filter(|&(idx, sphere)| idx != 5 && sphere.mbr().overlaps(&query))
+            .count();
+        assert_eq!(spheres.iter_bbox(&query).count(), expected);
+
+        assert_eq!(spheres.remove("s5").map(|(_, v)| v), Some(56));
+        assert!(spheres.remove("s5").is_none());
+        assert!(!spheres.contains_key("s5"));
+        assert_eq!(spheres.iter().count(), 999);
+    }
+
+    #[test]
+    fn test_keyed_map_2d() {
+        let mut stops: KeyedRTreeMap<u32, Point<2>, &str, 2> = KeyedRTreeMap::new();
+        stops.insert(7, Point::xy(1.0, 1.0), "mill");
+        stops.insert(9, Point::xy(6.0, 6.0), "ford");
+        let west = Aabb { min: Point::xy(0.0, 0.0), max: Point::xy(5.0, 5.0) };
+        assert_eq!(stops.iter_bbox(&west).collect::<Vec<_>>(), [(&7, &"mill")]);
+        stops.insert(9, Point::xy(2.0, 2.0), "ford");
+        assert_eq!(stops.iter_bbox(&west).count(), 2);
+    }
+
+    #[test]
+    fn test_keyed_entries() {
+        let mut spheres = KeyedRTreeMap::new();
+        for (idx, sphere) in random_spheres(500, 92).into_iter().enumerate() {
+            assert_eq!(*spheres.entry(idx).or_insert(sphere, 0), 0);
+        }
+        check_tree(&spheres.tree);
+        assert_eq!(spheres.len(), 500);
+
+        for idx in 0..500 {
+            let sphere = Sphere::new(Point::new([0.0, 0.0, 0.0]), 1.0).unwrap();
+            *spheres.entry(idx % 250).and_modify(|v| *v += 1).or_insert(sphere, 100) += 1;
+        }
+        assert_eq!(spheres.get(&3).map(|(_, &v)| v), Some(4));
+        assert_eq!(spheres.get(&300).map(|(_, &v)| v), Some(0));
+
+        match spheres.entry(7) {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.get().0, &random_spheres(8, 92).pop().unwrap());
+                assert_eq!(entry.insert(70), 4);
+                assert_eq!(entry.remove().1, 70);
+            },
+            Entry::Vacant(_) => panic!("key 7 was inserted"),
+        }
+        match spheres.entry(7) {
+            Entry::Occupied(_) => panic!("key 7 was removed"),
+            Entry::Vacant(entry) => assert_eq!(entry.into_key(), 7),
+        }
+        assert_eq!(spheres.len(), 499);
+        check_tree(&spheres.tree);
+    }
+}
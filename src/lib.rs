@@ -1,12 +1,107 @@
 #![allow(dead_code)]
+#![warn(missing_debug_implementations)]
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "inline")]
+extern crate smallvec;
+
+mod point;
+mod aabb;
+mod arena;
+mod error;
 mod bbox;
 mod vec3;
 mod ray;
+mod dedup;
+mod nearest;
+mod hit;
+mod iter_mut;
+mod items;
+mod join;
+mod stats;
+mod multi;
+mod export;
+mod occlusion;
+mod incremental;
+mod bulk;
+mod builder;
+mod binary;
+mod packed;
+mod cow;
+mod rebuild;
+mod transform;
+mod instance;
+mod frustum;
+mod convex;
+mod visit;
+mod sorted;
+mod clipped;
+mod fragmented;
+mod overflow;
+mod validate;
+mod diagnostics;
+mod slab;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "dot")]
+mod dot;
+pub mod prelude;
+pub mod shapes;
+pub mod map;
+pub mod keyed;
+pub mod curve;
+#[cfg(feature = "mesh")]
+pub mod mesh;
 
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::ops::ControlFlow;
+use std::rc::Rc;
 use std::slice::Iter as SliceIter;
-use std::f64;
+use std::sync::Arc;
+use arena::{Arena, NodeId};
+pub use point::{Point, Xy, Xyz};
+pub use aabb::Aabb;
+pub use vec3::Vec3;
 pub use ray::Ray;
-pub use bbox::{BBox};
+pub use bbox::{BBox, BoundingBox};
+pub use dedup::Dedup;
+pub use nearest::{ClosestPoint, WithinDistanceIter};
+pub use hit::{Hit, HitBuffers, Intersectable, Intersection, Mailbox, OrderedIter};
+pub use iter_mut::IterMut;
+pub use items::{Items, IntoIter};
+pub use sorted::SortedIter;
+pub use clipped::ClippedRTree;
+pub use fragmented::FragmentedRTree;
+pub use overflow::OverflowRTree;
+pub use join::{JoinIter, SelfJoinIter};
+pub use stats::{Histogram, QualityReport, TreeStats};
+pub use multi::MultiTree;
+pub use occlusion::BitVec;
+pub use incremental::IncrementalInserter;
+pub use binary::Codec;
+pub use packed::{PackedRTree, PackedIter};
+pub use cow::{CowRTree, CowIter};
+pub use rebuild::RebuildingIndex;
+pub use transform::Transform;
+pub use instance::Instance;
+pub use frustum::Frustum;
+pub use convex::ConvexIter;
+pub use validate::ValidationError;
+pub use diagnostics::{SplitBox, SplitEvent, SplitObserver};
+use diagnostics::Observer;
+pub use error::Error;
+pub use map::RTreeMap;
+pub use keyed::KeyedRTreeMap;
+pub use builder::RTreeBuilder;
 
 #[cfg(test)]
 mod test_helpers;
@@ -18,8 +113,431 @@ mod test_helpers;
 
 const NODE_SIZE: usize = 64;
 
-pub trait Mbr: Sized {
-    fn mbr(&self) -> BBox;
+/// The fewest entries a node is left with after a split.
+const MIN_NODE_SIZE: usize = NODE_SIZE * 2 / 5;
+
+const CAPACITY_TOO_SMALL: &str = "nodes must hold at least 4 entries";
+const MIN_FILL_OUT_OF_RANGE: &str = "minimum fill must be between 1 and half the node capacity";
+const MISSING_INDEXED: &str = "indexed item is missing from the tree";
+const NOT_A_LEAF: &str = "entries are only located in leaves";
+
+/// How nodes are chosen and split during insertion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SplitStrategy {
+    /// Guttman's quadratic split, descending into the child whose volume
+    /// grows least.
+    Quadratic,
+
+    /// Guttman's linear split, choosing children like `Quadratic`.  Its
+    /// seeds are the pair of entries furthest apart along some axis, and
+    /// the other entries join whichever seed's group grows least, in one
+    /// pass.  Splits cost far less than quadratic ones, which suits trees
+    /// that take many more insertions than queries, but nodes overlap
+    /// more.
+    Linear,
+
+    /// The R*-tree heuristics.  Just above the leaves, items go to the child
+    /// whose overlap with its siblings grows least.  The first leaf to
+    /// overflow during an insertion evicts its outermost 30% of entries for
+    /// reinsertion from the root instead of splitting, unless a
+    /// `ReinsertPolicy` says otherwise, and splits choose the axis with the
+    /// smallest total margin.
+    RStar,
+
+    /// The Hilbert R-tree.  Entries are kept in the order of the Hilbert
+    /// values of their centers, and items go to the first child holding
+    /// larger values.  A full node first shares its entries with a sibling,
+    /// and only when both are full are the two split into three, so nodes
+    /// stay fuller than with the other strategies.  Bulk loading packs
+    /// items in Hilbert order rather than by Sort-Tile-Recursive.  This
+    /// suits point data, whose boxes tell the other heuristics little.
+    Hilbert,
+
+    /// The Surface Area Heuristic, for trees queried mostly with rays.
+    /// Items go to the child whose surface area grows least, and splits
+    /// sort entries by center along each axis and cut where the summed
+    /// surface areas of the halves, weighted by their entry counts, are
+    /// smallest.  Pair it with `BulkLoadStrategy::Sah`.
+    Sah,
+}
+
+/// Whether a leaf which overflows during an insertion evicts some of its
+/// entries for reinsertion from the root before it is allowed to split.
+/// Reinserting moves entries to leaves which suit them better, at the cost
+/// of slower insertions.  It happens at most once per insertion, and never
+/// in Hilbert trees, whose entries stay in Hilbert order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ReinsertPolicy {
+    /// What the split strategy calls for: R* trees reinsert the outermost
+    /// 30% of entries, the others never reinsert.
+    #[default]
+    Strategy,
+
+    /// Always split straight away.
+    Never,
+
+    /// Reinsert the given percentage of the leaf's capacity, taking the
+    /// entries whose centers lie furthest from the leaf's center.  At
+    /// least one entry is reinserted, and never so many that the leaf
+    /// falls below its minimum fill.
+    Outermost(u8),
+}
+
+/// The order in which the entries a leaf evicts under a `ReinsertPolicy`
+/// are reinserted.  Beckmann et al. found reinserting the closest first
+/// works best on uniform data; reinserting the farthest first lets them
+/// claim new leaves before the rest settle back in, which can suit data
+/// made of tight, distant clusters better.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ReinsertOrder {
+    /// The entry whose center lies nearest the leaf's center first.
+    #[default]
+    CloseFirst,
+
+    /// The entry whose center lies farthest from the leaf's center first.
+    FarFirst,
+}
+
+/// Whether insertions and removals repack the region of the tree they
+/// touched once its leaves have grown too sparse.  Inserting items in
+/// order, such as a scanline at a time, keeps splitting the newest leaf
+/// and leaves behind a trail of half empty, overlapping leaves, which no
+/// later insertion fills.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RebalancePolicy {
+    /// Never repack.
+    #[default]
+    Never,
+
+    /// After each insertion or removal, look at the node just above the
+    /// leaf it went through.  If its leaves hold less than the given
+    /// percentage of what they could, and the same items fit into fewer
+    /// leaves, bulk load them into as few as they need, as
+    /// `BulkLoadStrategy::Str` or, in Hilbert trees, in Hilbert order.
+    /// Only that node's subtree changes, so this costs time in proportion
+    /// to the node's capacity, and the tree stays balanced.
+    BelowFill(u8),
+}
+
+/// How many entries a leaf takes before an insertion splits it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LeafCapacity {
+    /// `max_entries`, like every other node.
+    #[default]
+    Fixed,
+
+    /// Between the given number of entries and `max_entries`, depending on
+    /// how densely the leaf is packed compared to the tree as a whole:
+    /// a leaf whose items are crowded `k` times more closely than average
+    /// takes `max_entries / k`.  Dense hotspots end up in many small
+    /// leaves, which queries there can tell apart, and sparse regions in
+    /// few large ones.  This suits data whose density varies wildly, such
+    /// as points of interest across a country.
+    ///
+    /// The smallest capacity is raised to twice `min_entries` if need be,
+    /// so that both halves of a split are valid.  Hilbert trees, whose full
+    /// leaves share entries with their siblings, and bulk loading always
+    /// use `max_entries`.
+    Adaptive(usize),
+}
+
+/// How `bulk_load_with_config` and `rebuild` pack items into nodes.
+/// Trees using `SplitStrategy::Hilbert` are always packed in Hilbert
+/// order, which their insertion relies on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BulkLoadStrategy {
+    /// Sort-Tile-Recursive: pack the leaves bottom-up, nearly all of them
+    /// full, then pack each level above the same way.
+    #[default]
+    Str,
+
+    /// Overlap-Minimizing Top-down, after Lee and Lee: cut the items into
+    /// as many slabs as the root needs children, then each slab into as
+    /// many as its subtree needs, down to the leaves.  Nodes are filled
+    /// evenly rather than fully, but each subtree covers a compact region
+    /// of its own, which avoids the long, thin leaves STR can produce on
+    /// skewed data.
+    Omt,
+
+    /// Binned SAH, as used to build bounding volume hierarchies for ray
+    /// tracing: top-down like `Omt`, but each cut falls on the boundary
+    /// between 16 bins of item centers, along the axis where the Surface
+    /// Area Heuristic rates it cheapest, within what keeps the nodes on
+    /// both sides valid.
+    Sah,
+}
+
+/// Node capacity limits for a tree.  Small nodes make insertion and removal
+/// cheaper; large nodes give shallower trees for read-mostly workloads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RTreeConfig {
+    max_entries: usize,
+    min_entries: usize,
+    split: SplitStrategy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    reinsert: ReinsertPolicy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    reinsert_order: ReinsertOrder,
+    #[cfg_attr(feature = "serde", serde(default))]
+    bulk_load: BulkLoadStrategy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    rebalance: RebalancePolicy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    leaf_capacity: LeafCapacity,
+    // Functions cannot be serialized, so a loaded tree has no observer.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    split_observer: Observer,
+}
+
+impl RTreeConfig {
+    /// Nodes hold at most `max_entries`, and every node other than the root
+    /// holds at least `min_entries`, which must not exceed half the maximum.
+    pub fn new(max_entries: usize, min_entries: usize) -> Result<RTreeConfig, &'static str> {
+        if max_entries < 4 {
+            return Err(CAPACITY_TOO_SMALL);
+        }
+        if min_entries < 1 || max_entries / 2 < min_entries {
+            return Err(MIN_FILL_OUT_OF_RANGE);
+        }
+        Ok(RTreeConfig {
+            max_entries,
+            min_entries,
+            split: SplitStrategy::Quadratic,
+            reinsert: ReinsertPolicy::Strategy,
+            reinsert_order: ReinsertOrder::CloseFirst,
+            bulk_load: BulkLoadStrategy::Str,
+            rebalance: RebalancePolicy::Never,
+            leaf_capacity: LeafCapacity::Fixed,
+            split_observer: Observer(None),
+        })
+    }
+
+    /// A capacity of `max_entries`, with the minimum fill at 40% of it.
+    pub fn with_max_entries(max_entries: usize) -> Result<RTreeConfig, &'static str> {
+        RTreeConfig::new(max_entries, (max_entries * 2 / 5).max(1))
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    pub fn min_entries(&self) -> usize {
+        self.min_entries
+    }
+
+    /// Use `split` when inserting, instead of the default quadratic split.
+    pub fn with_split_strategy(mut self, split: SplitStrategy) -> RTreeConfig {
+        self.split = split;
+        self
+    }
+
+    pub fn split_strategy(&self) -> SplitStrategy {
+        self.split
+    }
+
+    /// Reinsert entries of overflowing leaves according to `reinsert`,
+    /// instead of as the split strategy calls for.
+    pub fn with_reinsert_policy(mut self, reinsert: ReinsertPolicy) -> RTreeConfig {
+        self.reinsert = reinsert;
+        self
+    }
+
+    pub fn reinsert_policy(&self) -> ReinsertPolicy {
+        self.reinsert
+    }
+
+    /// Reinsert evicted entries in `reinsert_order`, instead of closest
+    /// first.
+    pub fn with_reinsert_order(mut self, reinsert_order: ReinsertOrder) -> RTreeConfig {
+        self.reinsert_order = reinsert_order;
+        self
+    }
+
+    pub fn reinsert_order(&self) -> ReinsertOrder {
+        self.reinsert_order
+    }
+
+    /// How many entries a leaf which overflows at `capacity` entries
+    /// evicts for reinsertion, if any.
+    fn reinsert_count(&self, capacity: usize) -> Option<usize> {
+        match (self.reinsert, self.split) {
+            (_, SplitStrategy::Hilbert) | (ReinsertPolicy::Never, _) => None,
+            (ReinsertPolicy::Strategy, SplitStrategy::RStar) => {
+                Some((capacity * 3 / 10).min(capacity + 1 - self.min_entries))
+            },
+            (ReinsertPolicy::Strategy, _) => None,
+            (ReinsertPolicy::Outermost(percent), _) => {
+                let count = capacity * percent as usize / 100;
+                Some(count.clamp(1, capacity + 1 - self.min_entries))
+            },
+        }
+    }
+
+    /// Use `bulk_load` when bulk loading and rebuilding, instead of the
+    /// default Sort-Tile-Recursive packing.
+    pub fn with_bulk_load_strategy(mut self, bulk_load: BulkLoadStrategy) -> RTreeConfig {
+        self.bulk_load = bulk_load;
+        self
+    }
+
+    pub fn bulk_load_strategy(&self) -> BulkLoadStrategy {
+        self.bulk_load
+    }
+
+    /// Repack sparse regions of the tree after insertions and removals
+    /// according to `rebalance`, instead of never.
+    pub fn with_rebalance_policy(mut self, rebalance: RebalancePolicy) -> RTreeConfig {
+        self.rebalance = rebalance;
+        self
+    }
+
+    pub fn rebalance_policy(&self) -> RebalancePolicy {
+        self.rebalance
+    }
+
+    /// Let leaves take more or fewer entries according to `leaf_capacity`,
+    /// instead of always `max_entries`.
+    pub fn with_leaf_capacity(mut self, leaf_capacity: LeafCapacity) -> RTreeConfig {
+        self.leaf_capacity = leaf_capacity;
+        self
+    }
+
+    pub fn leaf_capacity(&self) -> LeafCapacity {
+        self.leaf_capacity
+    }
+
+    /// How many entries a leaf with bounding box `leaf` and `len` entries
+    /// takes, in a tree whose root has box `bounds` and holds `count`
+    /// items.
+    fn leaf_capacity_at<const N: usize>(&self, leaf: &Aabb<N>, len: usize, bounds: &Aabb<N>, count: usize) -> usize {
+        let fewest = match self.leaf_capacity {
+            LeafCapacity::Adaptive(fewest) if self.split != SplitStrategy::Hilbert && count > 0 => {
+                fewest.clamp(2 * self.min_entries, self.max_entries)
+            },
+            _ => return self.max_entries,
+        };
+        // The share of the tree's volume the leaf covers, counting only
+        // the axes the tree spans, so flat data is measured by area.
+        let mut share = 1.0;
+        for axis in 0..N {
+            let whole = bounds.max.coords[axis] - bounds.min.coords[axis];
+            if whole > 0.0 {
+                share *= (leaf.max.coords[axis] - leaf.min.coords[axis]) / whole;
+            }
+        }
+        let crowding = len as f64 / count as f64 / share;
+        let capacity = self.max_entries as f64 / crowding;
+        if capacity < self.max_entries as f64 {
+            (capacity as usize).max(fewest)
+        } else {
+            self.max_entries
+        }
+    }
+
+    /// Call `observer` on every node split, with the seeds, the two
+    /// resulting boxes and their overlap.  Splits cost a little more while
+    /// an observer is set, since the seeds are looked up again to report
+    /// them.  Observers are not saved along with a tree.
+    pub fn with_split_observer(mut self, observer: SplitObserver) -> RTreeConfig {
+        self.split_observer = Observer(Some(observer));
+        self
+    }
+
+    pub fn split_observer(&self) -> Option<SplitObserver> {
+        self.split_observer.0
+    }
+
+    /// Roughly how many nodes a tree of `items` built by insertion needs,
+    /// assuming nodes end up halfway between the minimum and maximum fill.
+    fn node_estimate(&self, items: usize) -> usize {
+        let fill = (self.min_entries + self.max_entries) / 2;
+        let mut level = items.div_ceil(fill);
+        let mut nodes = level;
+        while level > 1 {
+            level = level.div_ceil(fill);
+            nodes += level;
+        }
+        nodes
+    }
+}
+
+impl Default for RTreeConfig {
+    fn default() -> RTreeConfig {
+        RTreeConfig {
+            max_entries: NODE_SIZE,
+            min_entries: MIN_NODE_SIZE,
+            split: SplitStrategy::Quadratic,
+            reinsert: ReinsertPolicy::Strategy,
+            reinsert_order: ReinsertOrder::CloseFirst,
+            bulk_load: BulkLoadStrategy::Str,
+            rebalance: RebalancePolicy::Never,
+            leaf_capacity: LeafCapacity::Fixed,
+            split_observer: Observer(None),
+        }
+    }
+}
+
+/// Anything with an axis aligned bounding box.  Items live in three
+/// dimensions unless `N` says otherwise, e.g. `Mbr<2>` for map data.
+pub trait Mbr<const N: usize = 3>: Sized {
+    fn mbr(&self) -> Aabb<N>;
+}
+
+impl<const N: usize> Mbr<N> for Aabb<N> {
+    fn mbr(&self) -> Aabb<N> {
+        *self
+    }
+}
+
+/// A point is stored as a zero-sized box.
+impl<const N: usize> Mbr<N> for Point<N> {
+    fn mbr(&self) -> Aabb<N> {
+        Aabb::from_point(*self)
+    }
+}
+
+impl<const N: usize> Mbr<N> for [f64; N] {
+    fn mbr(&self) -> Aabb<N> {
+        Aabb::from_point(Point::new(*self))
+    }
+}
+
+/// A box with a payload, for items which need no geometry of their own.
+impl<T, const N: usize> Mbr<N> for (Aabb<N>, T) {
+    fn mbr(&self) -> Aabb<N> {
+        self.0
+    }
+}
+
+impl<T, const N: usize> Mbr<N> for &T where T: Mbr<N> {
+    fn mbr(&self) -> Aabb<N> {
+        (**self).mbr()
+    }
+}
+
+impl<T, const N: usize> Mbr<N> for Box<T> where T: Mbr<N> {
+    fn mbr(&self) -> Aabb<N> {
+        (**self).mbr()
+    }
+}
+
+impl<T, const N: usize> Mbr<N> for Rc<T> where T: Mbr<N> {
+    fn mbr(&self) -> Aabb<N> {
+        (**self).mbr()
+    }
+}
+
+impl<T, const N: usize> Mbr<N> for Arc<T> where T: Mbr<N> {
+    fn mbr(&self) -> Aabb<N> {
+        (**self).mbr()
+    }
 }
 
 #[must_use]
@@ -35,355 +553,3259 @@ pub enum InsertionResult<T> {
     /// The inserted element did not fit, so the node was split.  Re-insert 
     /// the following items.
     Split(Vec<T>),
+
+    /// A node overflowed and evicted the following items, which should be
+    /// reinserted from the root.  Bounding boxes along the path must be
+    /// refit.
+    Reinsert(Vec<T>),
+}
+
+/// What one insertion carries down the tree from the root.
+struct Insertion<const N: usize> {
+    /// Cleared once a leaf has used up the single forced reinsertion
+    /// allowed per insertion.
+    can_reinsert: bool,
+    /// The root's bounding box and item count, which leaves measure their
+    /// density against for `LeafCapacity::Adaptive`.
+    bounds: Aabb<N>,
+    count: usize,
 }
 
-struct LeafItem<T> {
-    bbox: BBox,
+/// A handle to an item in a tree, returned by `RTree::insert`.  It stays
+/// valid until the item is removed, including across `RTree::update`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ItemId(u64);
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct LeafItem<T, const N: usize = 3> {
+    bbox: Aabb<N>,
+    id: u64,
     item: T,
 }
 
-impl<T> LeafItem<T> where T: Mbr {
-    fn new(item: T) -> LeafItem<T> {
+impl<T, const N: usize> LeafItem<T, N> where T: Mbr<N> {
+    fn new(item: T, id: u64) -> LeafItem<T, N> {
         let bbox = item.mbr();
         LeafItem {
-            bbox: bbox,
-            item: item,
+            bbox,
+            id,
+            item,
         }
     }
 }
 
-enum NodeStorage<T> where T: Mbr {
-    Interior(Vec<RTreeNode<T>>),
-    Leaf(Vec<LeafItem<T>>),
+impl<T, const N: usize> Mbr<N> for LeafItem<T, N> {
+    fn mbr(&self) -> Aabb<N> {
+        self.bbox
+    }
+}
+
+/// How many entries a leaf holds inline with the `inline` feature.
+#[cfg(feature = "inline")]
+const INLINE_ENTRIES: usize = 4;
+
+/// The entries of a leaf.  With the `inline` feature, leaves of up to
+/// `INLINE_ENTRIES` keep them inside the node, sparing small trees and
+/// sparse regions an allocation and a pointer to follow per leaf, at the
+/// cost of larger nodes.  Fuller leaves move them to the heap as usual.
+#[cfg(feature = "inline")]
+type LeafVec<T, const N: usize> = smallvec::SmallVec<[LeafItem<T, N>; INLINE_ENTRIES]>;
+#[cfg(not(feature = "inline"))]
+type LeafVec<T, const N: usize> = Vec<LeafItem<T, N>>;
+
+/// The entries of a leaf as a `Vec`, for the splitting code.
+#[cfg(feature = "inline")]
+fn leaf_into_vec<T, const N: usize>(items: LeafVec<T, N>) -> Vec<LeafItem<T, N>> where T: Mbr<N> {
+    items.into_vec()
+}
+#[cfg(not(feature = "inline"))]
+fn leaf_into_vec<T, const N: usize>(items: LeafVec<T, N>) -> Vec<LeafItem<T, N>> where T: Mbr<N> {
+    items
+}
+
+/// How many entries the heap allocation of a leaf has room for, if it has
+/// one.
+#[cfg(feature = "inline")]
+fn leaf_heap_capacity<T, const N: usize>(items: &LeafVec<T, N>) -> usize where T: Mbr<N> {
+    if items.spilled() { items.capacity() } else { 0 }
+}
+#[cfg(not(feature = "inline"))]
+fn leaf_heap_capacity<T, const N: usize>(items: &LeafVec<T, N>) -> usize where T: Mbr<N> {
+    items.capacity()
+}
+
+#[derive(Clone)]
+enum NodeStorage<T, const N: usize = 3> where T: Mbr<N> {
+    Interior(Vec<NodeId>),
+    Leaf(LeafVec<T, N>),
 }
 
-impl<T> NodeStorage<T> where T: Mbr {
-    pub fn new_leaf_node(item: T) -> NodeStorage<T> {
-        NodeStorage::Leaf(vec![LeafItem::new(item)])
+impl<T, const N: usize> NodeStorage<T, N> where T: Mbr<N> {
+    pub fn new_leaf_node(leaf: LeafItem<T, N>) -> NodeStorage<T, N> {
+        NodeStorage::leaf(vec![leaf])
     }
 
-    pub fn shallow_len(&self) -> usize {
-        match *self {
-            NodeStorage::Interior(ref vec) => vec.len(),
-            NodeStorage::Leaf(ref vec) => vec.len(),
-        }
+    /// A leaf holding `items`.
+    pub fn leaf(items: Vec<LeafItem<T, N>>) -> NodeStorage<T, N> {
+        NodeStorage::Leaf(LeafVec::from(items))
     }
 
-    pub fn deep_len(&self) -> usize {
+    pub fn shallow_len(&self) -> usize {
         match *self {
-            NodeStorage::Interior(ref vec) => {
-                vec.iter().map(|v| v.deep_len()).fold(0, |a,x| a+x)
-            },
+            NodeStorage::Interior(ref vec) => vec.len(),
             NodeStorage::Leaf(ref vec) => vec.len(),
         }
     }
 }
 
-struct RTreeNode<T> where T: Mbr {
-    bbox: BBox,
-    storage: NodeStorage<T>,
+#[derive(Clone)]
+struct RTreeNode<T, const N: usize = 3> where T: Mbr<N> {
+    bbox: Aabb<N>,
+    // The number of items stored below this node.  Not serialized, but
+    // recomputed when a tree is loaded.
+    count: usize,
+    storage: NodeStorage<T, N>,
 }
 
-impl<T> Mbr for RTreeNode<T> where T: Mbr {
-    fn mbr(&self) -> BBox {
+impl<T, const N: usize> Mbr<N> for RTreeNode<T, N> where T: Mbr<N> {
+    fn mbr(&self) -> Aabb<N> {
         self.bbox
     }
 }
 
-impl<T> RTreeNode<T> where T: Mbr {
-    pub fn new(item: T) -> RTreeNode<T> {
-        let bbox = item.mbr();
+impl<T, const N: usize> RTreeNode<T, N> where T: Mbr<N> {
+    pub fn new(leaf: LeafItem<T, N>) -> RTreeNode<T, N> {
+        RTreeNode {
+            bbox: leaf.bbox,
+            count: 1,
+            storage: NodeStorage::new_leaf_node(leaf),
+        }
+    }
 
+    /// The contents of a free arena slot: an empty leaf.
+    fn vacant() -> RTreeNode<T, N> {
         RTreeNode {
-            bbox: bbox,
-            storage: NodeStorage::new_leaf_node(item),
+            bbox: Aabb::empty(),
+            count: 0,
+            storage: NodeStorage::Leaf(LeafVec::new()),
         }
     }
 
-    pub fn is_full(&self) -> bool {
-        NODE_SIZE <= self.storage.shallow_len()
+    pub fn is_full(&self, config: &RTreeConfig) -> bool {
+        config.max_entries <= self.storage.shallow_len()
     }
 
     pub fn shallow_len(&self) -> usize {
         self.storage.shallow_len()
     }
 
+    pub fn is_leaf(&self) -> bool {
+        match self.storage {
+            NodeStorage::Interior(_) => false,
+            NodeStorage::Leaf(_) => true,
+        }
+    }
+
+    /// The number of items below this node.  This is cached, so constant
+    /// time.
     pub fn deep_len(&self) -> usize {
-        self.storage.deep_len()
+        self.count
     }
+}
 
-    pub fn split(&mut self) -> RTreeNode<T> {
-        match self.storage {
-            NodeStorage::Interior(ref mut children) => {
-                let (lbox, lefts, rbox, rights) =
-                    util::quad_split(::std::mem::replace(children, Vec::new()));
+// The recursive algorithms walk from a node to its children in the arena,
+// so they live on the arena and take the node to start from.
+impl<T, const N: usize> Arena<T, N> where T: Mbr<N> {
+    /// Recompute the cached item count of `node` from its direct children,
+    /// after items were added below it or taken away.
+    pub fn recount(&mut self, node: NodeId) {
+        let count = self.deep_len(&self[node].storage);
+        self[node].count = count;
+    }
 
-                self.bbox = lbox;
-                drop(::std::mem::replace(children, lefts));
+    /// The number of items below `node` whose bounding box overlaps
+    /// `query`.  The node itself must overlap it.
+    pub fn count_in_bbox(&self, node: NodeId, query: &Aabb<N>) -> usize {
+        let node = &self[node];
+        if query.contains(&node.bbox) {
+            return node.deep_len();
+        }
+        match node.storage {
+            NodeStorage::Interior(ref children) => children.iter()
+                .filter(|&&child| self[child].bbox.overlaps(query))
+                .map(|&child| self.count_in_bbox(child, query))
+                .sum(),
+            NodeStorage::Leaf(ref items) => items.iter().filter(|item| item.bbox.overlaps(query)).count(),
+        }
+    }
 
-                unimplemented!();
-            },
-            NodeStorage::Leaf(ref mut nodes) => {
-                unimplemented!();
-            }
+    /// Recompute the bounding box of `node` from its direct children.
+    pub fn refit(&mut self, node: NodeId) {
+        let bbox = match self[node].storage {
+            NodeStorage::Interior(ref children) => util::union_all(children.iter().map(|&c| self[c].bbox)),
+            NodeStorage::Leaf(ref items) => util::union_all(items.iter().map(|i| i.bbox)),
         };
+        if let Some(bbox) = bbox {
+            self[node].bbox = bbox;
+        }
     }
 
-    pub fn insert(&mut self, item: T) -> InsertionResult<T> {
-        let item_bbox = item.mbr();
+    /// Apply `f` to every item below `node` overlapping `envelope`, then
+    /// refit the bounding boxes of the nodes that were touched.  Returns the
+    /// number of items visited.
+    pub fn for_each_mut_in_envelope<F>(&mut self, node: NodeId, envelope: &Aabb<N>, ids: &mut HashMap<u64, Aabb<N>>, f: &mut F) -> usize
+        where
+            F: FnMut(&mut T) {
 
-        match (self.is_full(), &mut self.storage) {
-            (false, &mut NodeStorage::Interior(ref mut children)) => {
-                let best_child = match util::best_fit(item_bbox, &children) {
-                    Some(best_child) => best_child,
-                    None => unimplemented!(),
-                };
-                match children[best_child].insert(item) {
-                    InsertionResult::Fit => InsertionResult::Fit,
-                    InsertionResult::Expanded => {
-                        unimplemented!(); // recompute bounding box
-                        // if we changed, emit Expanded, otherwise emit Fit.
-                        InsertionResult::Expanded
-                    },
-                    InsertionResult::Split(items) => {
-                        unimplemented!();
-                    },
-                }
-            },
-            (false, &mut NodeStorage::Leaf(ref mut nodes)) => {
-                self.bbox = self.bbox.union(&item.mbr());
-                nodes.push(LeafItem::new(item));
-                InsertionResult::Fit
-            },
-            (true, _) => InsertionResult::Split(vec![item])
+        let mut visited = 0;
+        for idx in 0..self.child_ids(node).len() {
+            let child = self.child_ids(node)[idx];
+            if self[child].bbox.overlaps(envelope) {
+                visited += self.for_each_mut_in_envelope(child, envelope, ids, f);
+            }
         }
+        if let NodeStorage::Leaf(ref mut items) = self[node].storage {
+            for leaf in items.iter_mut().filter(|i| i.bbox.overlaps(envelope)) {
+                f(&mut leaf.item);
+                leaf.bbox = leaf.item.mbr();
+                ids.insert(leaf.id, leaf.bbox);
+                visited += 1;
+            }
+        }
+        if visited > 0 {
+            self.refit(node);
+        }
+        visited
     }
-}
 
-pub struct RTree<T>(Option<RTreeNode<T>>) where T: Mbr;
+    /// Split `node` in two, keeping one half and returning the other as a
+    /// new sibling node.
+    pub fn split(&mut self, node: NodeId, config: &RTreeConfig) -> NodeId {
+        let storage = ::std::mem::replace(&mut self[node].storage, NodeStorage::Leaf(LeafVec::new()));
+        let (lbox, lefts, rbox, rights) = match storage {
+            NodeStorage::Interior(children) if config.split == SplitStrategy::Hilbert => {
+                // The children are in the order of their largest Hilbert
+                // values, which their boxes do not tell, so keep it.
+                let mut halves = util::spread(children, 2);
+                let rights = halves.pop().unwrap();
+                let lefts = halves.pop().unwrap();
+                let bounds = |ids: &[NodeId]| util::union_all(ids.iter().map(|&c| self[c].bbox)).unwrap();
+                (bounds(&lefts), NodeStorage::Interior(lefts), bounds(&rights), NodeStorage::Interior(rights))
+            },
+            NodeStorage::Interior(children) => {
+                // Split the children by their boxes, which live in their
+                // own nodes.
+                let entries: Vec<(Aabb<N>, NodeId)> = children.into_iter().map(|c| (self[c].bbox, c)).collect();
+                let (lbox, lefts, rbox, rights) = util::split(config, entries);
+                let ids = |entries: Vec<(Aabb<N>, NodeId)>| entries.into_iter().map(|(_, c)| c).collect();
+                (lbox, NodeStorage::Interior(ids(lefts)), rbox, NodeStorage::Interior(ids(rights)))
+            },
+            NodeStorage::Leaf(items) => {
+                let (lbox, lefts, rbox, rights) = util::split(config, leaf_into_vec(items));
+                (lbox, NodeStorage::leaf(lefts), rbox, NodeStorage::leaf(rights))
+            },
+        };
+
+        self[node].bbox = lbox;
+        self[node].storage = lefts;
+        self.recount(node);
+        self.add(rbox, rights)
+    }
 
-impl<T> RTree<T> where T: Mbr {
-    pub fn new() -> RTree<T> {
-        RTree(None)
+    /// Insert `item` below `node`.
+    fn insert(&mut self, node: NodeId, item: LeafItem<T, N>, config: &RTreeConfig, insertion: &mut Insertion<N>) -> InsertionResult<LeafItem<T, N>> {
+        let result = self.insert_entry(node, item, config, insertion);
+        self.recount(node);
+        result
     }
 
-    pub fn insert(&mut self, item: T) {
-        if self.0.is_none() {
-            self.0 = Some(RTreeNode::new(item));
-            return;
+    /// `insert`, without keeping the item count of `node` up to date.
+    fn insert_entry(&mut self, node: NodeId, item: LeafItem<T, N>, config: &RTreeConfig, insertion: &mut Insertion<N>) -> InsertionResult<LeafItem<T, N>> {
+        let item_bbox = item.bbox;
+
+        let leaf_bbox = self[node].bbox;
+        if let NodeStorage::Leaf(ref mut nodes) = self[node].storage {
+            let capacity = config.leaf_capacity_at(&leaf_bbox, nodes.len(), &insertion.bounds, insertion.count);
+            if capacity <= nodes.len() {
+                if let Some(count) = config.reinsert_count(capacity).filter(|_| insertion.can_reinsert) {
+                    insertion.can_reinsert = false;
+                    nodes.push(item);
+                    util::sort_outermost_last(nodes);
+                    let keep = nodes.len() - count.clamp(1, nodes.len());
+                    let mut evicted: Vec<_> = nodes.drain(keep..).collect();
+                    if config.reinsert_order == ReinsertOrder::FarFirst {
+                        evicted.reverse();
+                    }
+                    self.refit(node);
+                    return InsertionResult::Reinsert(evicted);
+                }
+                return InsertionResult::Split(vec![item]);
+            }
+            if config.split == SplitStrategy::Hilbert {
+                let key = util::hilbert_value(&item);
+                let at = nodes.partition_point(|i| util::hilbert_value(i) <= key);
+                nodes.insert(at, item);
+            } else {
+                nodes.push(item);
+            }
+            let bbox = self[node].bbox.union(&item_bbox);
+            return self.extend_bbox(node, bbox);
         }
 
-        let mut node = self.0.take().unwrap();
+        let best_child = self.choose_child(node, &item_bbox, config);
 
-        self.0 = Some(match node.insert(item) {
+        let items = match self.insert(best_child, item, config, insertion) {
+            InsertionResult::Fit => return InsertionResult::Fit,
             InsertionResult::Expanded => {
-                // recompute bounding box
-                unimplemented!();
+                let bbox = self[node].bbox.union(&self[best_child].bbox);
+                return self.extend_bbox(node, bbox);
             },
-            InsertionResult::Split(ref t) => {
-                // make a new root?
-                unimplemented!();
-            }
-            InsertionResult::Fit => node,
-        });
-    }
+            InsertionResult::Reinsert(items) => {
+                self.refit(node);
+                return InsertionResult::Reinsert(items);
+            },
+            InsertionResult::Split(items) => {
+                // The child may have absorbed some items before giving up
+                // on the rest.
+                self[node].bbox = self[node].bbox.union(&self[best_child].bbox);
 
-    pub fn iter_ray<'a>(&'a self, ray: &'a Ray) -> Iter<'a, T> {
-        Iter::new(self, ray)
-    }
-}
+                if config.split == SplitStrategy::Hilbert {
+                    if !self.share_with_sibling(node, best_child, config) {
+                        return InsertionResult::Split(items);
+                    }
+                    return self.reinsert(node, items, config, insertion);
+                }
 
-pub struct Iter<'a, T> where T: Mbr+'a{
-    stack: Vec<&'a RTreeNode<T>>,
-    leaf_iter: Option<SliceIter<'a, LeafItem<T>>>,
-    ray: &'a Ray,
-}
+                // There is no room for the child's new sibling, so we must
+                // be split by our parent first.
+                if config.max_entries <= self[node].shallow_len() {
+                    return InsertionResult::Split(items);
+                }
+                let sibling = self.split(best_child, config);
+                if let NodeStorage::Interior(ref mut children) = self[node].storage {
+                    children.push(sibling);
+                }
+                items
+            },
+        };
 
-impl<'a, T> Iter<'a, T> where T: Mbr+'a {
-    fn new(rtree: &'a RTree<T>, ray: &'a Ray) -> Iter<'a, T> {
-        let mut stack: Vec<&'a RTreeNode<T>> = Vec::new();
-        if let Some(ref root) = rtree.0 {
-            stack.push(root);
+        self.reinsert(node, items, config, insertion)
+    }
+
+    /// The child of the interior node `node` which an item with bounding
+    /// box `bbox` is inserted into.
+    fn choose_child(&self, node: NodeId, bbox: &Aabb<N>, config: &RTreeConfig) -> NodeId {
+        if config.split == SplitStrategy::Hilbert {
+            return self.hilbert_child(node, util::hilbert_value(bbox));
         }
-        Iter {
-            stack: stack,
-            leaf_iter: None,
-            ray: ray,
+        let children = self.child_ids(node);
+        let leaves_below = children.first().is_some_and(|&c| self[c].is_leaf());
+        let boxes = children.iter().map(|&c| self[c].bbox);
+        let best = util::choose_subtree(config, *bbox, boxes, leaves_below)
+            .expect("interior nodes must have children");
+        children[best]
+    }
+
+    /// The leaf below `node` which an item with bounding box `bbox` would
+    /// be inserted into.
+    pub fn choose_leaf(&self, mut node: NodeId, bbox: &Aabb<N>, config: &RTreeConfig) -> NodeId {
+        while !self[node].is_leaf() {
+            node = self.choose_child(node, bbox, config);
         }
+        node
     }
-}
 
-impl<'a, T> Iterator for Iter<'a, T> where T: Mbr+'a {
-    type Item = &'a T;
+    /// The child of `node` to insert an item with Hilbert value `key` into:
+    /// the first whose largest value is at least `key`, or the last.
+    fn hilbert_child(&self, node: NodeId, key: u64) -> NodeId {
+        let children = self.child_ids(node);
+        children.iter()
+            .cloned()
+            .find(|&child| self.largest_hilbert_value(child).is_some_and(|largest| key <= largest))
+            .or_else(|| children.last().cloned())
+            .expect("interior nodes must have children")
+    }
 
-    fn next(&mut self) -> Option<&'a T> {
+    /// The Hilbert value of the last item below `node`, which is the
+    /// largest while entries are in Hilbert order.
+    fn largest_hilbert_value(&self, mut node: NodeId) -> Option<u64> {
         loop {
-            let ray = self.ray;
-            if let Some(leaf_iter) = self.leaf_iter.as_mut() {
-                if let Some(val) = leaf_iter.filter(|x| x.bbox.intersects(ray)).next() {
-                    return Some(&val.item);
-                }
-            }
-
-            // iterator went empty, so we'll pop from the stack and
-            // iterate on the next node's children now,
-            if let Some(node) = self.stack.pop() {
-                match node.storage {
-                    NodeStorage::Interior(ref children) => {
-                        for child in children.iter() {
-                            if child.bbox.intersects(self.ray) {
-                                self.stack.push(child);
-                            }
-                        }
-                    }
-                    NodeStorage::Leaf(ref items) => {
-                        self.leaf_iter = Some(items.iter())
-                    }
-                }
-            } else {
-                return None;
+            match self[node].storage {
+                NodeStorage::Interior(ref children) => node = *children.last()?,
+                NodeStorage::Leaf(ref items) => return items.last().map(util::hilbert_value),
             }
         }
     }
-}
-
-mod util {
-    use std::f64;
-    use bbox::{BBox};
-    use std::cmp::{Ord, Ordering};
-    use super::Mbr;
 
-    fn pick_seeds<I>(children: I) -> Option<(BBox, BBox)>
-        where
-            I: Iterator<Item=(BBox, BBox)> {
+    /// Make room in `child`, a full child of `node`, the Hilbert R-tree
+    /// way: spread its entries evenly over it and its next sibling, or its
+    /// previous one if it is the last, keeping their order.  Only if that
+    /// would leave either full are the two split into three.  Returns false, changing
+    /// nothing, if that needs a new child but `node` is full.
+    fn share_with_sibling(&mut self, node: NodeId, child: NodeId, config: &RTreeConfig) -> bool {
+        let children = self.child_ids(node);
+        let full = config.max_entries <= children.len();
+        let idx = children.iter().position(|&c| c == child).expect("child must belong to node");
+        let first = if idx + 1 < children.len() { idx } else { idx.saturating_sub(1) };
+        if first + 1 == children.len() {
+            // An only child has nobody to share with.
+            if full {
+                return false;
+            }
+            let sibling = self.split(child, config);
+            if let NodeStorage::Interior(ref mut children) = self[node].storage {
+                children.insert(idx + 1, sibling);
+            }
+            return true;
+        }
 
-        let mut min_d = f64::MAX;
-        let mut best_box: Option<(BBox, BBox)> = None;
+        let (left, right) = (children[first], children[first + 1]);
+        // Two nodes only make room if neither ends up full.
+        let parts = if self[left].shallow_len() + self[right].shallow_len() + 1 < 2 * config.max_entries {
+            2
+        } else if full {
+            return false;
+        } else {
+            3
+        };
+        let lefts = ::std::mem::replace(&mut self[left].storage, NodeStorage::Leaf(LeafVec::new()));
+        let rights = ::std::mem::replace(&mut self[right].storage, NodeStorage::Leaf(LeafVec::new()));
+        let mut shares: Vec<NodeStorage<T, N>> = match (lefts, rights) {
+            (NodeStorage::Interior(mut lefts), NodeStorage::Interior(rights)) => {
+                lefts.extend(rights);
+                util::spread(lefts, parts).into_iter().map(NodeStorage::Interior).collect()
+            },
+            (NodeStorage::Leaf(mut lefts), NodeStorage::Leaf(rights)) => {
+                lefts.extend(rights);
+                util::spread(leaf_into_vec(lefts), parts).into_iter().map(NodeStorage::leaf).collect()
+            },
+            _ => unreachable!("siblings must sit at the same depth"),
+        };
 
-        for (e1, e2) in children {
-            let difference = e1.union(&e2).volume() - e1.volume() - e2.volume();
-            if difference < min_d {
-                min_d = difference;
-                best_box = Some((e1, e2));
+        let third = if parts == 3 { shares.pop() } else { None };
+        self[right].storage = shares.pop().unwrap();
+        self[left].storage = shares.pop().unwrap();
+        for &id in [left, right].iter() {
+            self.refit(id);
+            self.recount(id);
+        }
+        if let Some(storage) = third {
+            let id = self.add(Aabb::empty(), storage);
+            self.refit(id);
+            if let NodeStorage::Interior(ref mut children) = self[node].storage {
+                children.insert(first + 2, id);
             }
         }
+        true
+    }
 
-        best_box
+    /// Replace the bounding box of `node` with `bbox`, which must contain
+    /// it, and report whether it grew.
+    fn extend_bbox<I>(&mut self, node: NodeId, bbox: Aabb<N>) -> InsertionResult<I> {
+        let node = &mut self[node];
+        if node.bbox.contains(&bbox) {
+            InsertionResult::Fit
+        } else {
+            node.bbox = bbox;
+            InsertionResult::Expanded
+        }
     }
 
-    fn expansion(target: &BBox, adding: &BBox) -> f64 {
-        target.union(adding).volume() - target.volume()
+    /// Insert `items` into `node`, returning any which did not fit.  Items
+    /// evicted for reinsertion are passed up along with any rejected ones;
+    /// if nothing was rejected, they are passed up on their own.
+    fn reinsert(&mut self, node: NodeId, items: Vec<LeafItem<T, N>>, config: &RTreeConfig, insertion: &mut Insertion<N>) -> InsertionResult<LeafItem<T, N>> {
+        let mut expanded = false;
+        let mut rejected = Vec::new();
+        let mut evicted = Vec::new();
+        for item in items {
+            match self.insert(node, item, config, insertion) {
+                InsertionResult::Fit => (),
+                InsertionResult::Expanded => expanded = true,
+                InsertionResult::Split(mut items) => rejected.append(&mut items),
+                InsertionResult::Reinsert(mut items) => evicted.append(&mut items),
+            }
+        }
+        if !rejected.is_empty() {
+            rejected.append(&mut evicted);
+            InsertionResult::Split(rejected)
+        } else if !evicted.is_empty() {
+            self.refit(node);
+            InsertionResult::Reinsert(evicted)
+        } else if expanded {
+            InsertionResult::Expanded
+        } else {
+            InsertionResult::Fit
+        }
     }
+}
 
-    pub fn quad_split<T>(items: Vec<T>) -> (BBox, Vec<T>, BBox, Vec<T>)
+impl<T, const N: usize> Arena<T, N> where T: Mbr<N> {
+    /// Drop every item below `node` for which `keep` returns false,
+    /// recording its id in `removed`.  Children left underfull are
+    /// dissolved into `orphans`.
+    fn retain<F>(&mut self, node: NodeId, keep: &mut F, config: &RTreeConfig, removed: &mut Vec<u64>, orphans: &mut Vec<LeafItem<T, N>>)
         where
-            T: Mbr {
+            F: FnMut(&T) -> bool {
 
-        let (mut lbox, mut rbox) = pick_seeds(Iterator::zip(
-            items.iter().map(Mbr::mbr),
-            items.iter().map(Mbr::mbr),
-        )).expect("Unsufficient nodes");
+        for idx in 0..self.child_ids(node).len() {
+            let child = self.child_ids(node)[idx];
+            self.retain(child, keep, config, removed, orphans);
+        }
+        let mut idx = 0;
+        while idx < self.child_ids(node).len() {
+            let child = self.child_ids(node)[idx];
+            if self[child].shallow_len() < config.min_entries {
+                if let NodeStorage::Interior(ref mut children) = self[node].storage {
+                    util::move_to_end(config, children, idx);
+                    children.pop();
+                }
+                self.take_items(child, orphans);
+            } else {
+                idx += 1;
+            }
+        }
+        if let NodeStorage::Leaf(ref mut items) = self[node].storage {
+            items.retain(|leaf| {
+                let kept = keep(&leaf.item);
+                if !kept {
+                    removed.push(leaf.id);
+                }
+                kept
+            });
+        }
+        self.refit(node);
+        self.recount(node);
+    }
+
+    /// Remove the items below `node` whose ids are among `targets`, each
+    /// given with its bounding box, pushing them onto `removed`.  Children
+    /// are only visited for the targets whose box they contain, which
+    /// `targets` is reordered in place to pick out, so each leaf is visited
+    /// once however many of its items go.  Children left underfull are
+    /// dissolved into `orphans`.  Returns the number of items removed.
+    fn remove_many(&mut self, node: NodeId, targets: &mut [(u64, Aabb<N>)], config: &RTreeConfig,
+                   removed: &mut Vec<LeafItem<T, N>>, orphans: &mut Vec<LeafItem<T, N>>) -> usize {
+        let mut count = 0;
+        for idx in 0..self.child_ids(node).len() {
+            let child = self.child_ids(node)[idx];
+            let child_bbox = self[child].bbox;
+            let inside = util::partition(targets, |target| child_bbox.contains(&target.1));
+            if inside > 0 {
+                count += self.remove_many(child, &mut targets[..inside], config, removed, orphans);
+            }
+        }
+        if count > 0 {
+            let mut idx = 0;
+            while idx < self.child_ids(node).len() {
+                let child = self.child_ids(node)[idx];
+                if self[child].shallow_len() < config.min_entries {
+                    if let NodeStorage::Interior(ref mut children) = self[node].storage {
+                        util::move_to_end(config, children, idx);
+                        children.pop();
+                    }
+                    self.take_items(child, orphans);
+                } else {
+                    idx += 1;
+                }
+            }
+        }
+        if let NodeStorage::Leaf(ref mut items) = self[node].storage {
+            let mut idx = 0;
+            while idx < items.len() {
+                if targets.iter().any(|&(id, _)| id == items[idx].id) {
+                    util::move_to_end(config, items, idx);
+                    removed.push(items.pop().unwrap());
+                    count += 1;
+                } else {
+                    idx += 1;
+                }
+            }
+        }
+
+        if count > 0 {
+            self.refit(node);
+            self.recount(node);
+        }
+        count
+    }
+
+    /// The leaf below `node` holding the first item for which `is_target`
+    /// returns true, and the item's index in it, only descending into
+    /// nodes whose box contains `bbox`.
+    fn locate<F>(&self, node: NodeId, is_target: &F, bbox: &Aabb<N>) -> Option<(NodeId, usize)>
+        where
+            F: Fn(&LeafItem<T, N>) -> bool {
+
+        match self[node].storage {
+            NodeStorage::Interior(ref children) => children.iter()
+                .filter(|&&c| self[c].bbox.contains(bbox))
+                .filter_map(|&c| self.locate(c, is_target, bbox))
+                .next(),
+            NodeStorage::Leaf(ref items) => items.iter().position(is_target).map(|idx| (node, idx)),
+        }
+    }
+
+    /// The entry at `index` of the leaf `leaf`, as found by `locate`.
+    fn leaf_entry(&self, leaf: NodeId, index: usize) -> &LeafItem<T, N> {
+        match self[leaf].storage {
+            NodeStorage::Leaf(ref items) => &items[index],
+            NodeStorage::Interior(_) => panic!("{}", NOT_A_LEAF),
+        }
+    }
+
+    fn leaf_entry_mut(&mut self, leaf: NodeId, index: usize) -> &mut LeafItem<T, N> {
+        match self[leaf].storage {
+            NodeStorage::Leaf(ref mut items) => &mut items[index],
+            NodeStorage::Interior(_) => panic!("{}", NOT_A_LEAF),
+        }
+    }
+
+    /// The item with the given id, only descending into nodes whose box
+    /// contains `bbox`.
+    fn find(&self, node: NodeId, id: u64, bbox: &Aabb<N>) -> Option<&LeafItem<T, N>> {
+        let (leaf, index) = self.locate(node, &|leaf: &LeafItem<T, N>| leaf.id == id, bbox)?;
+        Some(self.leaf_entry(leaf, index))
+    }
+
+    fn find_mut(&mut self, node: NodeId, id: u64, bbox: &Aabb<N>) -> Option<&mut LeafItem<T, N>> {
+        let (leaf, index) = self.locate(node, &|leaf: &LeafItem<T, N>| leaf.id == id, bbox)?;
+        Some(self.leaf_entry_mut(leaf, index))
+    }
+
+    /// Write `node` and everything below it as lines of the `Debug`
+    /// outline, indented by `depth`.
+    fn fmt_outline(&self, node: NodeId, f: &mut fmt::Formatter, depth: usize) -> fmt::Result where T: fmt::Debug {
+        let indent = 2 * depth;
+        let node = &self[node];
+        match node.storage {
+            NodeStorage::Interior(ref children) => {
+                write!(f, "\n{:indent$}interior {:?}..{:?}, {} items", "", node.bbox.min, node.bbox.max, node.count, indent = indent)?;
+                for &child in children.iter() {
+                    self.fmt_outline(child, f, depth + 1)?;
+                }
+            },
+            NodeStorage::Leaf(ref items) => {
+                write!(f, "\n{:indent$}leaf {:?}..{:?}, {} items", "", node.bbox.min, node.bbox.max, node.count, indent = indent)?;
+                for leaf in items.iter() {
+                    write!(f, "\n{:indent$}#{} {:?}..{:?}: {:?}", "", leaf.id, leaf.bbox.min, leaf.bbox.max, leaf.item, indent = indent + 2)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Move every item stored below `node` into `out`, freeing the nodes.
+    fn take_items(&mut self, node: NodeId, out: &mut Vec<LeafItem<T, N>>) {
+        match self.take(node).storage {
+            NodeStorage::Interior(children) => {
+                for child in children {
+                    self.take_items(child, out);
+                }
+            },
+            NodeStorage::Leaf(items) => {
+                out.extend(items);
+            },
+        }
+    }
+
+    /// Remove the first item below `node` for which `is_target` returns
+    /// true, only descending into nodes whose box contains `bbox`.
+    /// Children left underfull are dissolved, and their items pushed onto
+    /// `orphans` for reinsertion.
+    ///
+    /// If `replacement` holds an item whose box fits inside the leaf the
+    /// removed item was found in, it is taken and stored in its place,
+    /// inheriting the removed item's id.
+    fn remove<F>(&mut self, node: NodeId, is_target: &F, bbox: &Aabb<N>, replacement: &mut Option<LeafItem<T, N>>,
+                 config: &RTreeConfig, orphans: &mut Vec<LeafItem<T, N>>) -> Option<LeafItem<T, N>>
+        where
+            F: Fn(&LeafItem<T, N>) -> bool {
+
+        let leaf_bbox = self[node].bbox;
+        let mut removed = None;
+        for idx in 0..self.child_ids(node).len() {
+            let child = self.child_ids(node)[idx];
+            if !self[child].bbox.contains(bbox) {
+                continue;
+            }
+            removed = self.remove(child, is_target, bbox, replacement, config, orphans);
+            if removed.is_some() {
+                if self[child].shallow_len() < config.min_entries {
+                    if let NodeStorage::Interior(ref mut children) = self[node].storage {
+                        util::move_to_end(config, children, idx);
+                        children.pop();
+                    }
+                    self.take_items(child, orphans);
+                }
+                break;
+            }
+        }
+        if let NodeStorage::Leaf(ref mut items) = self[node].storage {
+            removed = items.iter().position(is_target).map(|idx| {
+                match replacement.take() {
+                    // A Hilbert leaf only takes the replacement in place if
+                    // that keeps its order, which reinsertion sees to.
+                    Some(mut new) if leaf_bbox.contains(&new.bbox) && config.split != SplitStrategy::Hilbert => {
+                        new.id = items[idx].id;
+                        ::std::mem::replace(&mut items[idx], new)
+                    },
+                    new => {
+                        *replacement = new;
+                        util::move_to_end(config, items, idx);
+                        items.pop().unwrap()
+                    },
+                }
+            });
+        }
+
+        if removed.is_some() {
+            self.refit(node);
+            self.recount(node);
+        }
+        removed
+    }
+}
+
+/// Items are indexed in `N` dimensions, three by default.  Insertion,
+/// removal, bulk loading, box and distance queries work in any number of
+/// dimensions; rays, frustums and the other rendering helpers are only
+/// available on three dimensional trees.
+///
+/// With the `serde` feature the whole tree, nodes included, can be
+/// serialized and loaded back without rebuilding it.
+///
+/// A tree is `Send` and `Sync` whenever its items are.  Queries only need
+/// `&RTree`, so any number of threads can share one tree and query it at
+/// once, as long as nothing mutates it meanwhile.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "serialize::TreeParts<T, N>"))]
+pub struct RTree<T, const N: usize = 3> where T: Mbr<N> {
+    root: Option<NodeId>,
+    nodes: Arena<T, N>,
+    config: RTreeConfig,
+    // The bounding box of every item by id, so handles can be looked up
+    // without a full scan.  Not serialized, since it is rebuilt from the
+    // leaves on load.
+    ids: HashMap<u64, Aabb<N>>,
+    next_id: u64,
+}
+
+// Thread safety is only inferred by the compiler, so check it here: a
+// field that isn't `Send` or `Sync` should fail the build, not a user's.
+const _: () = {
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    #[allow(unused)]
+    fn assert_thread_safe<'a, T: Mbr + Send + Sync + 'a>() {
+        assert_send_sync::<RTree<T>>();
+        assert_send_sync::<PackedRTree<T>>();
+        assert_send_sync::<CowRTree<T>>();
+        assert_send_sync::<RebuildingIndex<T>>();
+        assert_send_sync::<MultiTree<'a, T>>();
+        assert_send_sync::<Iter<'a, T>>();
+        assert_send_sync::<EnvelopeIter<'a, T>>();
+        assert_send_sync::<ConvexIter<'a, T>>();
+        assert_send_sync::<WithinDistanceIter<'a, T>>();
+        assert_send_sync::<Items<'a, T>>();
+        assert_send_sync::<IntoIter<T>>();
+    }
+};
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    pub fn new() -> RTree<T, N> {
+        RTree::with_config(RTreeConfig::default())
+    }
+
+    /// An empty tree whose nodes follow `config` rather than the default
+    /// capacity of 64 entries.
+    pub fn with_config(config: RTreeConfig) -> RTree<T, N> {
+        RTree {
+            root: None,
+            nodes: Arena::new(),
+            config,
+            ids: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// An empty tree with the default configuration and room for
+    /// `capacity` items, like `reserve`.
+    pub fn with_capacity(capacity: usize) -> RTree<T, N> {
+        let mut tree = RTree::new();
+        tree.reserve(capacity);
+        tree
+    }
+
+    /// Make room for at least `additional` more items, so that inserting
+    /// them does not keep reallocating.
+    ///
+    /// The id index is sized exactly, and the node arena for an estimate of
+    /// the nodes the items will fill.  While the whole tree fits in its
+    /// root leaf, that leaf is sized up to a full node as well; leaves
+    /// created by splits are sized by the split.
+    pub fn reserve(&mut self, additional: usize) {
+        self.ids.reserve(additional);
+        let nodes = self.config.node_estimate(self.len() + additional);
+        self.nodes.reserve(nodes.saturating_sub(self.nodes.len()));
+        if let Some(root) = self.root {
+            let max_entries = self.config.max_entries;
+            if let NodeStorage::Leaf(ref mut items) = self.nodes[root].storage {
+                let wanted = (items.len() + additional).min(max_entries);
+                items.reserve(wanted - items.len());
+            }
+        }
+    }
+
+    /// Release the memory the tree no longer needs: spare room in the node
+    /// and leaf vectors, the arena slots freed by removals, and spare room
+    /// in the id index.  Use it after a burst of edits, e.g. before keeping
+    /// the tree around for queries only.
+    ///
+    /// Leaves are trimmed but not merged, so a tree whose leaves were left
+    /// mostly empty by removals is better rebuilt with `bulk_load`.
+    pub fn shrink_to_fit(&mut self) {
+        match self.root {
+            Some(root) => self.root = Some(self.nodes.compact(root)),
+            None => self.nodes = Arena::new(),
+        }
+        self.ids.shrink_to_fit();
+    }
+
+    /// An estimate of the heap memory the tree uses for its nodes, leaf
+    /// entries, arena and id index, in bytes.  Memory the items allocate
+    /// themselves is not included, and the id index is estimated from its
+    /// capacity.
+    pub fn heap_size_bytes(&self) -> usize {
+        // Each entry of the id index also costs a control byte.
+        let ids = self.ids.capacity() * (mem::size_of::<(u64, Aabb<N>)>() + 1);
+        self.nodes.heap_size_bytes() + ids
+    }
+
+    fn root_node(&self) -> Option<&RTreeNode<T, N>> {
+        self.root.map(|root| &self.nodes[root])
+    }
+
+    pub fn config(&self) -> &RTreeConfig {
+        &self.config
+    }
+
+    /// The number of items in the tree.  This is kept up to date by every
+    /// insertion and removal, so it is constant time.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// The number of levels in the tree: zero when empty, one while the
+    /// root is a leaf.  Only a single root-to-leaf path is walked, since
+    /// every leaf sits at the same depth.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut node = self.root;
+        while let Some(current) = node {
+            depth += 1;
+            node = self.nodes.child_ids(current).first().cloned();
+        }
+        depth
+    }
+
+    /// Rebuild the id index from the leaves, for trees whose nodes were
+    /// loaded directly rather than inserted.
+    fn reindex(&mut self) {
+        self.ids.clear();
+        for node in self.nodes.slots() {
+            if let NodeStorage::Leaf(ref items) = node.storage {
+                self.ids.extend(items.iter().map(|leaf| (leaf.id, leaf.bbox)));
+            }
+        }
+    }
+
+    /// Wrap `item` in a leaf entry with a fresh id.
+    fn new_leaf(&mut self, item: T) -> LeafItem<T, N> {
+        let leaf = LeafItem::new(item, self.next_id);
+        self.next_id += 1;
+        self.ids.insert(leaf.id, leaf.bbox);
+        leaf
+    }
+
+    /// Add `item` to the tree, returning a handle to it.
+    pub fn insert(&mut self, item: T) -> ItemId {
+        let leaf = self.new_leaf(item);
+        let id = ItemId(leaf.id);
+        self.insert_leaf(leaf);
+        id
+    }
+
+    /// Add `item` to the tree like `insert`, unless its bounding box would
+    /// leave insertion unable to compare the volumes of candidate nodes, in
+    /// which case the tree is left unchanged and `item` is dropped.
+    /// `insert` panics on such items.
+    pub fn try_insert(&mut self, item: T) -> Result<ItemId, Error> {
+        let leaf = LeafItem::new(item, self.next_id);
+        let bbox = leaf.bbox;
+        if (0..N).any(|i| bbox.min.coords[i].is_nan() || bbox.max.coords[i].is_nan()) {
+            return Err(Error::NanBounds);
+        }
+        // Every box that insertion compares lies within this union, so a
+        // finite volume here keeps all of theirs finite too.
+        let bounds = self.root_node().map_or(bbox, |root| root.bbox.union(&bbox));
+        if !bounds.volume().is_finite() {
+            return Err(Error::UnboundedVolume);
+        }
+        self.next_id += 1;
+        self.ids.insert(leaf.id, bbox);
+        let id = ItemId(leaf.id);
+        self.insert_leaf(leaf);
+        Ok(id)
+    }
+
+    /// Insert `leaf`, then rebalance around it.  Returns where the item was
+    /// stored, unless a split, reinsertion or the rebalancing may have
+    /// moved it since.
+    fn insert_leaf(&mut self, leaf: LeafItem<T, N>) -> Option<(NodeId, usize)> {
+        let bbox = leaf.bbox;
+        let placed = match self.place(leaf) {
+            Ok(placed) => Some(placed),
+            Err(leaf) => {
+                self.insert_leaf_unbalanced(leaf);
+                None
+            },
+        };
+        if self.rebalance_near(&bbox) {
+            return None;
+        }
+        placed
+    }
+
+    /// Store `leaf` in the leaf insertion descends to, if that leaf has
+    /// room for it, and return the leaf and the item's index there.  Only
+    /// the boxes and counts along the path need growing then.  Otherwise
+    /// `leaf` is handed back to take the usual path, through splits or
+    /// reinsertion.
+    fn place(&mut self, leaf: LeafItem<T, N>) -> Result<(NodeId, usize), LeafItem<T, N>> {
+        let root = match self.root {
+            Some(root) => root,
+            None => return Err(leaf),
+        };
+        let config = self.config;
+        let bbox = leaf.bbox;
+        let target = self.nodes.choose_leaf(root, &bbox, &config);
+        if self.leaf_capacity_of(target) <= self.nodes[target].shallow_len() {
+            return Err(leaf);
+        }
+
+        // Growing a node does not change which of its children is chosen,
+        // so the path can be grown on a second descent.
+        let mut node = root;
+        loop {
+            let grown = &mut self.nodes[node];
+            grown.bbox = grown.bbox.union(&bbox);
+            grown.count += 1;
+            if node == target {
+                break;
+            }
+            node = self.nodes.choose_child(node, &bbox, &config);
+        }
+        match self.nodes[target].storage {
+            NodeStorage::Leaf(ref mut items) => {
+                let at = if config.split == SplitStrategy::Hilbert {
+                    let key = util::hilbert_value(&leaf);
+                    items.partition_point(|i| util::hilbert_value(i) <= key)
+                } else {
+                    items.len()
+                };
+                items.insert(at, leaf);
+                Ok((target, at))
+            },
+            NodeStorage::Interior(_) => unreachable!("insertion descends to a leaf"),
+        }
+    }
+
+    /// How many entries the leaf `leaf` takes, following the
+    /// `LeafCapacity` of the tree's configuration.
+    fn leaf_capacity_of(&self, leaf: NodeId) -> usize {
+        let root = &self.nodes[self.root.expect(MISSING_INDEXED)];
+        let leaf = &self.nodes[leaf];
+        self.config.leaf_capacity_at(&leaf.bbox, leaf.shallow_len(), &root.bbox, root.count)
+    }
+
+    fn insert_leaf_unbalanced(&mut self, leaf: LeafItem<T, N>) {
+        let mut root = match self.root {
+            Some(root) => root,
+            None => {
+                // The id index was sized by any `reserve`, so use it to
+                // size the first leaf too.
+                let mut node = RTreeNode::new(leaf);
+                if let NodeStorage::Leaf(ref mut items) = node.storage {
+                    items.reserve(self.ids.capacity().min(self.config.max_entries).saturating_sub(1));
+                }
+                self.root = Some(self.nodes.alloc(node));
+                return;
+            },
+        };
+
+        let config = self.config;
+        let mut insertion = Insertion {
+            // A root leaf has nowhere to send evicted items but back to
+            // itself.
+            can_reinsert: !self.nodes[root].is_leaf(),
+            bounds: self.nodes[root].bbox,
+            count: self.nodes[root].count,
+        };
+        let mut pending = Vec::new();
+        let mut result = self.nodes.insert(root, leaf, &config, &mut insertion);
+        loop {
+            match result {
+                // The root keeps its own bounding box up to date.
+                InsertionResult::Fit | InsertionResult::Expanded => match pending.pop() {
+                    Some(leaf) => result = self.nodes.insert(root, leaf, &config, &mut insertion),
+                    None => break,
+                },
+                InsertionResult::Reinsert(items) => {
+                    // Evicted items come in the order the `ReinsertOrder`
+                    // calls for, so reinsert them in that order.
+                    pending.extend(items.into_iter().rev());
+                    result = InsertionResult::Fit;
+                },
+                InsertionResult::Split(items) => {
+                    // The root is full, so grow the tree by one level.
+                    let sibling = self.nodes.split(root, &config);
+                    let bbox = self.nodes[root].bbox.union(&self.nodes[sibling].bbox);
+                    root = self.nodes.add(bbox, NodeStorage::Interior(vec![root, sibling]));
+                    self.root = Some(root);
+                    result = self.nodes.reinsert(root, items, &config, &mut insertion);
+                },
+            }
+        }
+    }
+
+    /// Repack the leaves below the parent of the leaf an item with bounding
+    /// box `bbox` would be inserted into, if the `RebalancePolicy` finds
+    /// them too sparse.  Returns whether they were repacked.
+    fn rebalance_near(&mut self, bbox: &Aabb<N>) -> bool {
+        let percent = match self.config.rebalance {
+            RebalancePolicy::BelowFill(percent) => percent as usize,
+            RebalancePolicy::Never => return false,
+        };
+        let root = match self.root {
+            Some(root) => root,
+            None => return false,
+        };
+        let mut parent = None;
+        let mut node = root;
+        while !self.nodes[node].is_leaf() {
+            parent = Some(node);
+            node = self.nodes.choose_child(node, bbox, &self.config);
+        }
+        if let Some(parent) = parent {
+            let capacity = self.nodes[parent].shallow_len() * self.config.max_entries;
+            if self.nodes[parent].count * 100 < capacity * percent {
+                let fewest_leaves = if parent == root { 2 } else { self.config.min_entries };
+                self.nodes.repack_leaves(parent, &self.config, fewest_leaves);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Insert `item` like `insert`, and hand back its handle and where it
+    /// was stored.  An item which did not go straight into a leaf with room
+    /// for it is looked up again.
+    fn insert_mut(&mut self, item: T) -> (ItemId, &mut T) {
+        let leaf = self.new_leaf(item);
+        let (id, bbox) = (leaf.id, leaf.bbox);
+        let entry = match self.insert_leaf(leaf) {
+            Some((leaf, index)) => self.nodes.leaf_entry_mut(leaf, index),
+            None => {
+                let root = self.root.expect(MISSING_INDEXED);
+                self.nodes.find_mut(root, id, &bbox).expect(MISSING_INDEXED)
+            },
+        };
+        (ItemId(id), &mut entry.item)
+    }
+
+    /// Remove the first item matching `is_target` below nodes containing
+    /// `bbox`, then restore the tree's invariants.  A replacement left over
+    /// because it did not fit the item's leaf is inserted from the root.
+    fn remove_where<F>(&mut self, is_target: &F, bbox: &Aabb<N>, mut replacement: Option<LeafItem<T, N>>) -> Result<LeafItem<T, N>, Option<LeafItem<T, N>>>
+        where
+            F: Fn(&LeafItem<T, N>) -> bool {
+
+        let mut orphans = Vec::new();
+        let removed = match self.root {
+            Some(root) if self.nodes[root].bbox.contains(bbox) => {
+                self.nodes.remove(root, is_target, bbox, &mut replacement, &self.config, &mut orphans)
+            },
+            _ => None,
+        };
+        let removed = match removed {
+            Some(removed) => removed,
+            None => return Err(replacement),
+        };
+
+        self.collapse_root();
+        for orphan in orphans {
+            self.insert_leaf(orphan);
+        }
+        if let Some(mut replacement) = replacement {
+            replacement.id = removed.id;
+            self.insert_leaf(replacement);
+        }
+        self.rebalance_near(&removed.bbox);
+        Ok(removed)
+    }
+
+    /// Remove an item equal to `item` from the tree and return it, or None
+    /// if there is no such item.
+    ///
+    /// Only subtrees whose bounding box contains `item.mbr()` are searched.
+    /// Nodes left underfull are dissolved and their items reinserted, and
+    /// the root is collapsed while it has a single child.
+    pub fn remove(&mut self, item: &T) -> Option<T> where T: PartialEq {
+        let removed = self.remove_where(&|leaf: &LeafItem<T, N>| leaf.item == *item, &item.mbr(), None).ok()?;
+        self.ids.remove(&removed.id);
+        Some(removed.item)
+    }
+
+    /// Remove the item `id` refers to.  Only the subtrees containing its
+    /// bounding box are searched.
+    pub fn remove_by_id(&mut self, id: ItemId) -> Option<T> {
+        let bbox = *self.ids.get(&id.0)?;
+        let removed = self.remove_where(&|leaf: &LeafItem<T, N>| leaf.id == id.0, &bbox, None).ok()?;
+        self.ids.remove(&removed.id);
+        Some(removed.item)
+    }
+
+    /// Remove the items `ids` refer to, returning them in the order of
+    /// `ids`.  Ids of items already removed are skipped.
+    ///
+    /// This is much cheaper than calling `remove_by_id` for each: the tree
+    /// is walked once for all of them, every leaf losing items is visited
+    /// once, and nodes left underfull are only dissolved, and their items
+    /// reinserted, after everything has been removed.
+    pub fn remove_many(&mut self, ids: &[ItemId]) -> Vec<T> {
+        let mut targets: Vec<(u64, Aabb<N>)> = ids.iter()
+            .filter_map(|id| self.ids.remove(&id.0).map(|bbox| (id.0, bbox)))
+            .collect();
+        let root = match self.root {
+            Some(root) if !targets.is_empty() => root,
+            _ => return Vec::new(),
+        };
+        let mut removed = Vec::with_capacity(targets.len());
+        let mut orphans = Vec::new();
+        self.nodes.remove_many(root, &mut targets, &self.config, &mut removed, &mut orphans);
+
+        self.collapse_root();
+        for orphan in orphans {
+            self.insert_leaf(orphan);
+        }
+        let mut removed: HashMap<u64, T> = removed.into_iter().map(|leaf| (leaf.id, leaf.item)).collect();
+        ids.iter().filter_map(|id| removed.remove(&id.0)).collect()
+    }
+
+    /// The leaf holding the item `id` refers to, and the item's index in
+    /// it.
+    fn locate_id(&self, id: ItemId) -> Option<(NodeId, usize)> {
+        let bbox = self.ids.get(&id.0)?;
+        self.nodes.locate(self.root?, &|leaf: &LeafItem<T, N>| leaf.id == id.0, bbox)
+    }
+
+    /// The item `id` refers to, or None if it has been removed.
+    pub fn get(&self, id: ItemId) -> Option<&T> {
+        let bbox = self.ids.get(&id.0)?;
+        self.nodes.find(self.root?, id.0, bbox).map(|leaf| &leaf.item)
+    }
+
+    /// Replace an item equal to `old` with `new`, returning the old item,
+    /// or hand `new` back if there is no such item.  The old item's
+    /// `ItemId` now refers to `new`.
+    ///
+    /// When `new` still fits inside the bounding box of the leaf holding
+    /// `old`, it takes its place directly and only the boxes along that
+    /// path are refit.  Otherwise `old` is removed as by `remove` and `new`
+    /// is inserted from the root.
+    pub fn update(&mut self, old: &T, new: T) -> Result<T, T> where T: PartialEq {
+        let replacement = LeafItem::new(new, 0);
+        let new_bbox = replacement.bbox;
+        match self.remove_where(&|leaf: &LeafItem<T, N>| leaf.item == *old, &old.mbr(), Some(replacement)) {
+            Ok(removed) => {
+                self.ids.insert(removed.id, new_bbox);
+                Ok(removed.item)
+            },
+            Err(replacement) => Err(replacement.expect("replacement is only taken on success").item),
+        }
+    }
+
+    /// Store `item` under `id`: replace the item `id` refers to and return
+    /// it, or, if that item has been removed, insert `item` afresh under
+    /// the same handle and return None.  Ids are never handed out twice,
+    /// so reviving one cannot clash with another item's.
+    ///
+    /// A replacement which still fits inside the bounding box of the old
+    /// item's leaf takes its place in the one traversal that finds it, and
+    /// only the boxes along that path are refit, as with `update`.
+    pub fn insert_or_update(&mut self, id: ItemId, item: T) -> Option<T> {
+        let replacement = LeafItem::new(item, id.0);
+        let new_bbox = replacement.bbox;
+        let old_bbox = match self.ids.insert(id.0, new_bbox) {
+            Some(old_bbox) => old_bbox,
+            None => {
+                self.next_id = self.next_id.max(id.0 + 1);
+                self.insert_leaf(replacement);
+                return None;
+            },
+        };
+        let removed = self.remove_where(&|leaf: &LeafItem<T, N>| leaf.id == id.0, &old_bbox, Some(replacement));
+        Some(removed.ok().expect(MISSING_INDEXED).item)
+    }
+
+    /// Remove every item, leaving the tree empty, and return them in no
+    /// particular order.  Existing `ItemId`s are invalidated.
+    pub fn drain(&mut self) -> IntoIter<T, N> {
+        let drained = RTree {
+            root: self.root.take(),
+            nodes: ::std::mem::replace(&mut self.nodes, Arena::new()),
+            config: self.config,
+            ids: ::std::mem::take(&mut self.ids),
+            next_id: self.next_id,
+        };
+        drained.into_iter()
+    }
+
+    /// Remove every item for which `keep` returns false.  Nodes left
+    /// underfull are dissolved once the whole tree has been filtered, and
+    /// their surviving items reinserted.
+    pub fn retain<F>(&mut self, mut keep: F) where F: FnMut(&T) -> bool {
+        let mut removed = Vec::new();
+        let mut orphans = Vec::new();
+        if let Some(root) = self.root {
+            self.nodes.retain(root, &mut keep, &self.config, &mut removed, &mut orphans);
+        }
+        for id in removed {
+            self.ids.remove(&id);
+        }
+
+        self.collapse_root();
+        for orphan in orphans {
+            self.insert_leaf(orphan);
+        }
+    }
+
+    /// Replace the root by its only child until it has several, and drop it
+    /// entirely once it is empty.
+    fn collapse_root(&mut self) {
+        while let Some(root) = self.root {
+            let next = match self.nodes[root].storage {
+                NodeStorage::Interior(ref children) if children.len() <= 1 => children.first().cloned(),
+                NodeStorage::Leaf(ref items) if items.is_empty() => None,
+                _ => return,
+            };
+            self.nodes.take(root);
+            self.root = next;
+        }
+        self.nodes.clear();
+    }
+
+    /// Apply `f` to every item whose bounding box overlaps `envelope`.
+    ///
+    /// `f` may move or resize the items it is given: once all of them have
+    /// been visited, the bounding boxes of the affected leaves and their
+    /// ancestors are refit in a single pass.  Large moves can leave the tree
+    /// poorly organized, so prefer this for many small, localized edits.
+    /// Returns the number of items visited.
+    pub fn for_each_mut_in_envelope<F>(&mut self, envelope: &Aabb<N>, mut f: F) -> usize
+        where
+            F: FnMut(&mut T) {
+
+        match self.root {
+            Some(root) if self.nodes[root].bbox.overlaps(envelope) => {
+                self.nodes.for_each_mut_in_envelope(root, envelope, &mut self.ids, &mut f)
+            },
+            _ => 0,
+        }
+    }
+
+    /// Shift the whole tree by `offset` without reinserting anything, e.g.
+    /// when re-basing a floating-origin world.
+    ///
+    /// Every node and leaf box is moved directly, so this is linear in the
+    /// size of the tree.  `on_item` is called once for each item and must
+    /// move it by the same offset so that its `mbr()` keeps matching the
+    /// stored box; pass a no-op closure if items are moved elsewhere.
+    pub fn translate<F>(&mut self, offset: Point<N>, mut on_item: F) where F: FnMut(&mut T) {
+        for node in self.nodes.slots_mut() {
+            node.bbox.min = node.bbox.min + offset;
+            node.bbox.max = node.bbox.max + offset;
+            if let NodeStorage::Leaf(ref mut items) = node.storage {
+                for leaf in items.iter_mut() {
+                    leaf.bbox.min = leaf.bbox.min + offset;
+                    leaf.bbox.max = leaf.bbox.max + offset;
+                    on_item(&mut leaf.item);
+                }
+            }
+        }
+        for bbox in self.ids.values_mut() {
+            bbox.min = bbox.min + offset;
+            bbox.max = bbox.max + offset;
+        }
+    }
+
+    /// Iterate over all items whose bounding box does not overlap
+    /// `envelope`.  Subtrees lying entirely inside the envelope are skipped,
+    /// and subtrees entirely outside it are yielded without testing each
+    /// item.
+    pub fn locate_outside_envelope<'a>(&'a self, envelope: &'a Aabb<N>) -> OutsideIter<'a, T, N> {
+        OutsideIter::new(self, envelope)
+    }
+
+    /// Iterate over all items whose bounding box overlaps `envelope`.
+    pub fn locate_in_envelope<'a>(&'a self, envelope: &'a Aabb<N>) -> EnvelopeIter<'a, T, fn(&T, &Aabb<N>) -> bool, N> {
+        EnvelopeIter::new(self, envelope, accept_all)
+    }
+
+    /// Like `locate_in_envelope`, but every candidate whose bounding box
+    /// overlaps `envelope` is passed to `refine` together with the envelope,
+    /// and only yielded if it returns true.  This gives exact range query
+    /// semantics for items whose geometry is smaller than their MBR.
+    pub fn locate_in_envelope_with<'a, F>(&'a self, envelope: &'a Aabb<N>, refine: F) -> EnvelopeIter<'a, T, F, N>
+        where
+            F: FnMut(&T, &Aabb<N>) -> bool {
+
+        EnvelopeIter::new(self, envelope, refine)
+    }
+
+    /// Iterate over all items whose bounding box overlaps `query`.  Unlike
+    /// `locate_in_envelope`, the query box is copied, so the iterator only
+    /// borrows the tree.
+    pub fn iter_bbox<'a>(&'a self, query: &Aabb<N>) -> EnvelopeIter<'a, T, fn(&T, &Aabb<N>) -> bool, N> {
+        EnvelopeIter::new(self, query, accept_all)
+    }
+
+    /// Iterate over all items whose bounding box lies entirely inside
+    /// `query`.
+    pub fn iter_contained_in<'a>(&'a self, query: &Aabb<N>) -> EnvelopeIter<'a, T, fn(&T, &Aabb<N>) -> bool, N> {
+        EnvelopeIter::new(self, query, contained_in)
+    }
+
+    /// The number of items whose bounding box overlaps `query`, as
+    /// `iter_bbox(query).count()` would give.  Subtrees lying entirely
+    /// inside the query are counted whole, without visiting their items.
+    pub fn count_in_bbox(&self, query: &Aabb<N>) -> usize {
+        match self.root {
+            Some(root) if self.nodes[root].bbox.overlaps(query) => self.nodes.count_in_bbox(root, query),
+            _ => 0,
+        }
+    }
+
+    /// Whether any item's bounding box overlaps `query`.  Stops at the
+    /// first one found.
+    pub fn any_in_bbox(&self, query: &Aabb<N>) -> bool {
+        self.visit_bbox(query, |_| ControlFlow::Break(())).is_break()
+    }
+}
+
+impl<T, const N: usize> Default for RTree<T, N> where T: Mbr<N> {
+    fn default() -> RTree<T, N> {
+        RTree::new()
+    }
+}
+
+// Trees print as an outline of their nodes, one per line and indented by
+// depth, with each leaf's items listed below it.
+impl<T, const N: usize> fmt::Debug for RTree<T, N> where T: Mbr<N>+fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RTree {{ len: {}, depth: {}, config: {:?} }}", self.len(), self.depth(), self.config)?;
+        match self.root {
+            Some(root) => self.nodes.fmt_outline(root, f, 1),
+            None => Ok(()),
+        }
+    }
+}
+
+// Ray queries only make sense in three dimensions.
+impl<T> RTree<T> where T: Mbr {
+    pub fn iter_ray<'a>(&'a self, ray: &'a Ray) -> Iter<'a, T> {
+        Iter::new(self, ray)
+    }
+
+    /// Like `iter_ray`, but the traversal stack is borrowed from `context`
+    /// rather than allocated, so reusing one context for many rays saves
+    /// an allocation per ray.
+    pub fn iter_ray_with<'a>(&'a self, context: &'a mut QueryContext, ray: &'a Ray) -> Iter<'a, T> {
+        Iter::with_context(self, ray, context)
+    }
+
+    /// Like `iter_ray`, but items are only yielded if `pred` returns true.
+    /// The predicate is evaluated at the leaves, before the item is handed
+    /// back, so it should be cheap (e.g. comparing a category tag).
+    pub fn iter_ray_where<'a, P>(&'a self, ray: &'a Ray, pred: P) -> FilterIter<'a, T, P>
+        where
+            P: FnMut(&T) -> bool {
+
+        FilterIter {
+            inner: Iter::new(self, ray),
+            pred,
+        }
+    }
+}
+
+/// Scratch memory for ray queries, which `RTree::iter_ray_with` borrows
+/// instead of allocating its own.  Keep one per thread and reuse it for
+/// every ray, with any tree: after the first few queries the traversal
+/// stack has grown as large as it needs to be, and queries stop
+/// allocating.
+#[derive(Clone, Debug, Default)]
+pub struct QueryContext {
+    stack: Vec<(NodeId, f64)>,
+}
+
+impl QueryContext {
+    pub fn new() -> QueryContext {
+        QueryContext::default()
+    }
+}
+
+/// A ray query.  Created by `RTree::iter_ray` and `RTree::iter_ray_with`.
+pub struct Iter<'a, T> where T: Mbr+'a{
+    nodes: &'a Arena<T, 3>,
+    // Nodes still to visit, with the ray parameter at which the ray enters
+    // them.
+    stack: Vec<(NodeId, f64)>,
+    leaf_iter: Option<SliceIter<'a, LeafItem<T>>>,
+    ray: &'a Ray,
+    max_t: f64,
+    // Where the stack was borrowed from, and goes back to when done.
+    context: Option<&'a mut QueryContext>,
+}
+
+impl<'a, T> Iter<'a, T> where T: Mbr+'a {
+    fn new(rtree: &'a RTree<T>, ray: &'a Ray) -> Iter<'a, T> {
+        Iter::with_stack(rtree, ray, Vec::new(), None)
+    }
+
+    fn with_context(rtree: &'a RTree<T>, ray: &'a Ray, context: &'a mut QueryContext) -> Iter<'a, T> {
+        let stack = mem::take(&mut context.stack);
+        Iter::with_stack(rtree, ray, stack, Some(context))
+    }
+
+    fn with_stack(rtree: &'a RTree<T>, ray: &'a Ray, mut stack: Vec<(NodeId, f64)>, context: Option<&'a mut QueryContext>) -> Iter<'a, T> {
+        stack.clear();
+        stack.extend(rtree.root.map(|root| (root, f64::NEG_INFINITY)));
+        Iter {
+            nodes: &rtree.nodes,
+            stack,
+            leaf_iter: None,
+            ray,
+            max_t: ray.max_t,
+            context,
+        }
+    }
+
+    /// Stop looking beyond `t`: nodes and items the ray enters further
+    /// along are skipped from now on, including nodes already queued.  Call
+    /// this with the distance of each closer hit found, to keep the search
+    /// from visiting everything behind it.  Candidates still come in no
+    /// particular order; `iter_ray_ordered` yields them nearest first.
+    pub fn set_max_t(&mut self, t: f64) {
+        self.max_t = self.max_t.min(t);
+    }
+}
+
+/// The ray parameter at which `ray` enters `bbox`, if it does so no later
+/// than `max_t`.
+fn entry_within(bbox: &BBox, ray: &Ray, max_t: f64) -> Option<f64> {
+    bbox.intersect_ray_t(ray).map(|(t, _)| t).filter(|&t| t <= max_t)
+}
+
+impl<'a, T> Iter<'a, T> where T: Mbr+'a {
+    /// Advance to the next item whose bounding box intersects the ray and
+    /// which satisfies `pred`.  The predicate is only consulted for items
+    /// which survived the bounding box test.
+    fn next_matching<P>(&mut self, pred: &mut P) -> Option<&'a T>
+        where
+            P: FnMut(&T) -> bool {
+
+        loop {
+            let (ray, max_t) = (self.ray, self.max_t);
+            if let Some(leaf_iter) = self.leaf_iter.as_mut() {
+                if let Some(val) = leaf_iter.find(|x| entry_within(&x.bbox, ray, max_t).is_some() && pred(&x.item)) {
+                    return Some(&val.item);
+                }
+            }
+
+            // iterator went empty, so we'll pop from the stack and
+            // iterate on the next node's children now,
+            if let Some((node, t)) = self.stack.pop() {
+                if max_t < t {
+                    continue;
+                }
+                let nodes = self.nodes;
+                match nodes[node].storage {
+                    NodeStorage::Interior(ref children) => {
+                        for &child in children.iter() {
+                            if let Some(t) = entry_within(&nodes[child].bbox, ray, max_t) {
+                                self.stack.push((child, t));
+                            }
+                        }
+                    }
+                    NodeStorage::Leaf(ref items) => {
+                        self.leaf_iter = Some(items.iter())
+                    }
+                }
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for Iter<'a, T> where T: Mbr+'a {
+    fn drop(&mut self) {
+        if let Some(context) = self.context.take() {
+            context.stack = mem::take(&mut self.stack);
+        }
+    }
+}
+
+// Queries print what they are looking for, not the nodes left to visit.
+impl<'a, T> fmt::Debug for Iter<'a, T> where T: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Iter").field("ray", self.ray).field("max_t", &self.max_t).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> where T: Mbr+'a {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.next_matching(&mut |_| true)
+    }
+}
+
+fn accept_all<T, const N: usize>(_: &T, _: &Aabb<N>) -> bool {
+    true
+}
+
+fn contained_in<T, const N: usize>(item: &T, envelope: &Aabb<N>) -> bool where T: Mbr<N> {
+    envelope.contains(&item.mbr())
+}
+
+/// An envelope query.  Created by `RTree::locate_in_envelope`,
+/// `RTree::locate_in_envelope_with`, `RTree::iter_bbox` and
+/// `RTree::iter_contained_in`.
+pub struct EnvelopeIter<'a, T, F = fn(&T, &BBox) -> bool, const N: usize = 3> where T: Mbr<N>+'a {
+    nodes: &'a Arena<T, N>,
+    stack: Vec<&'a RTreeNode<T, N>>,
+    leaf_iter: Option<SliceIter<'a, LeafItem<T, N>>>,
+    envelope: Aabb<N>,
+    refine: F,
+}
+
+impl<'a, T, F, const N: usize> EnvelopeIter<'a, T, F, N> where T: Mbr<N>+'a {
+    fn new(rtree: &'a RTree<T, N>, envelope: &Aabb<N>, refine: F) -> EnvelopeIter<'a, T, F, N> {
+        EnvelopeIter {
+            nodes: &rtree.nodes,
+            stack: rtree.root_node().into_iter().collect(),
+            leaf_iter: None,
+            envelope: *envelope,
+            refine,
+        }
+    }
+}
+
+impl<'a, T, F, const N: usize> fmt::Debug for EnvelopeIter<'a, T, F, N> where T: Mbr<N>+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EnvelopeIter").field("envelope", &self.envelope).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T, F, const N: usize> Iterator for EnvelopeIter<'a, T, F, N>
+    where
+        T: Mbr<N>+'a,
+        F: FnMut(&T, &Aabb<N>) -> bool {
+
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            let envelope = &self.envelope;
+            let refine = &mut self.refine;
+            if let Some(leaf_iter) = self.leaf_iter.as_mut() {
+                if let Some(val) = leaf_iter.find(|x| x.bbox.overlaps(envelope) && refine(&x.item, envelope)) {
+                    return Some(&val.item);
+                }
+            }
+
+            if let Some(node) = self.stack.pop() {
+                match node.storage {
+                    NodeStorage::Interior(ref children) => {
+                        for child in self.nodes.children(children) {
+                            if child.bbox.overlaps(envelope) {
+                                self.stack.push(child);
+                            }
+                        }
+                    }
+                    NodeStorage::Leaf(ref items) => {
+                        self.leaf_iter = Some(items.iter())
+                    }
+                }
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+/// Yields items disjoint from a region.  Created by
+/// `RTree::locate_outside_envelope`.
+pub struct OutsideIter<'a, T, const N: usize = 3> where T: Mbr<N>+'a {
+    // Nodes still to visit, paired with whether the node is already known
+    // to be disjoint from the envelope.
+    nodes: &'a Arena<T, N>,
+    stack: Vec<(&'a RTreeNode<T, N>, bool)>,
+    leaf_iter: Option<(SliceIter<'a, LeafItem<T, N>>, bool)>,
+    envelope: &'a Aabb<N>,
+}
+
+impl<'a, T, const N: usize> OutsideIter<'a, T, N> where T: Mbr<N>+'a {
+    fn new(rtree: &'a RTree<T, N>, envelope: &'a Aabb<N>) -> OutsideIter<'a, T, N> {
+        OutsideIter {
+            nodes: &rtree.nodes,
+            stack: rtree.root_node().map(|root| (root, false)).into_iter().collect(),
+            leaf_iter: None,
+            envelope,
+        }
+    }
+}
+
+impl<'a, T, const N: usize> fmt::Debug for OutsideIter<'a, T, N> where T: Mbr<N>+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OutsideIter").field("envelope", self.envelope).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T, const N: usize> Iterator for OutsideIter<'a, T, N> where T: Mbr<N>+'a {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            let envelope = self.envelope;
+            if let Some((ref mut leaf_iter, disjoint)) = self.leaf_iter {
+                if let Some(val) = leaf_iter.find(|x| disjoint || !x.bbox.overlaps(envelope)) {
+                    return Some(&val.item);
+                }
+            }
+
+            let (node, disjoint) = self.stack.pop()?;
+            // Everything below a node inside the envelope overlaps it.
+            if !disjoint && envelope.contains(&node.bbox) {
+                continue;
+            }
+            let disjoint = disjoint || !node.bbox.overlaps(envelope);
+            match node.storage {
+                NodeStorage::Interior(ref children) => {
+                    for child in self.nodes.children(children) {
+                        self.stack.push((child, disjoint));
+                    }
+                }
+                NodeStorage::Leaf(ref items) => {
+                    self.leaf_iter = Some((items.iter(), disjoint))
+                }
+            }
+        }
+    }
+}
+
+/// A ray query which only yields items accepted by a predicate.  Created
+/// by `RTree::iter_ray_where`.
+pub struct FilterIter<'a, T, P> where T: Mbr+'a {
+    inner: Iter<'a, T>,
+    pred: P,
+}
+
+impl<'a, T, P> fmt::Debug for FilterIter<'a, T, P> where T: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FilterIter").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T, P> Iterator for FilterIter<'a, T, P>
+    where
+        T: Mbr+'a,
+        P: FnMut(&T) -> bool {
+
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.inner.next_matching(&mut self.pred)
+    }
+}
+
+mod util {
+    use aabb::Aabb;
+    use std::cmp::{Ord, Ordering};
+    use curve::hilbert_key;
+    use super::{Mbr, RTreeConfig, SplitBox, SplitEvent, SplitStrategy};
+
+    /// Pick the two entries which would waste the most space if they were
+    /// put in the same node, returning their indices.
+    fn pick_seeds<const N: usize>(boxes: &[Aabb<N>]) -> Option<(usize, usize)> {
+        let mut max_d = f64::MIN;
+        let mut seeds = None;
+
+        for (i, e1) in boxes.iter().enumerate() {
+            for (j, e2) in boxes.iter().enumerate().skip(i + 1) {
+                let difference = e1.union(e2).volume() - e1.volume() - e2.volume();
+                if seeds.is_none() || difference > max_d {
+                    max_d = difference;
+                    seeds = Some((i, j));
+                }
+            }
+        }
+
+        seeds
+    }
+
+    /// The union of all boxes yielded by `boxes`, or None if it is empty.
+    pub fn union_all<I, const N: usize>(mut boxes: I) -> Option<Aabb<N>> where I: Iterator<Item=Aabb<N>> {
+        let first = boxes.next()?;
+        Some(boxes.fold(first, |acc, b| acc.union(&b)))
+    }
+
+    fn expansion<const N: usize>(target: &Aabb<N>, adding: &Aabb<N>) -> f64 {
+        target.union(adding).volume() - target.volume()
+    }
+
+    /// The volume shared by `a` and `b`.
+    pub fn overlap_volume<const N: usize>(a: &Aabb<N>, b: &Aabb<N>) -> f64 {
+        a.intersection(b).map_or(0.0, |overlap| overlap.volume())
+    }
+
+    fn axis_bounds<const N: usize>(bbox: &Aabb<N>, axis: usize) -> (f64, f64) {
+        (bbox.min.coords[axis], bbox.max.coords[axis])
+    }
+
+    fn compare(a: f64, b: f64) -> Ordering {
+        a.partial_cmp(&b).unwrap_or_else(|| a.total_cmp(&b))
+    }
+
+    /// Split a full node's entries with the configured strategy.
+    pub fn split<T, const N: usize>(config: &RTreeConfig, items: Vec<T>) -> (Aabb<N>, Vec<T>, Aabb<N>, Vec<T>) where T: Mbr<N> {
+        let observer = config.split_observer.0;
+        let seeds = observer.and_then(|_| {
+            let boxes: Vec<Aabb<N>> = items.iter().map(Mbr::<N>::mbr).collect();
+            let seeds = match config.split {
+                SplitStrategy::Quadratic => pick_seeds(&boxes),
+                SplitStrategy::Linear => linear_seeds(&boxes),
+                _ => None,
+            };
+            seeds.map(|(left, right)| (boxes[left], boxes[right]))
+        });
+        let split = match config.split {
+            SplitStrategy::Quadratic => quad_split(items, config.min_entries),
+            SplitStrategy::Linear => linear_split(items, config.min_entries),
+            SplitStrategy::RStar => rstar_split(items, config.min_entries),
+            SplitStrategy::Hilbert => hilbert_split(items),
+            SplitStrategy::Sah => sah_split(items, config.min_entries),
+        };
+        if let Some(observer) = observer {
+            let (ref lbox, ref lefts, ref rbox, ref rights) = split;
+            observer(&SplitEvent {
+                strategy: config.split,
+                seeds: seeds.as_ref().map(|(left, right)| (SplitBox::new(left), SplitBox::new(right))),
+                left: SplitBox::new(lbox),
+                left_len: lefts.len(),
+                right: SplitBox::new(rbox),
+                right_len: rights.len(),
+                overlap: overlap_volume(lbox, rbox),
+            });
+        }
+        split
+    }
+
+    /// Pick the child of an interior node to insert `target` into, given
+    /// the children's `boxes` and whether they are leaves.
+    pub fn choose_subtree<I, const N: usize>(config: &RTreeConfig, target: Aabb<N>, boxes: I, leaves_below: bool) -> Option<usize>
+        where
+            I: Iterator<Item=Aabb<N>> + Clone {
+
+        match config.split {
+            SplitStrategy::Quadratic | SplitStrategy::Linear => best_fit(target, boxes),
+            SplitStrategy::RStar if leaves_below => least_overlap_enlargement(target, boxes),
+            SplitStrategy::RStar => least_enlargement(target, boxes),
+            // Hilbert trees choose by the items' Hilbert values, which
+            // the boxes alone do not give.
+            SplitStrategy::Hilbert => best_fit(target, boxes),
+            SplitStrategy::Sah => least_area_enlargement(target, boxes),
+        }
+    }
+
+    /// The index of the box whose surface area grows least by adding
+    /// `target`, breaking ties by the smaller surface area.
+    fn least_area_enlargement<I, const N: usize>(target: Aabb<N>, boxes: I) -> Option<usize> where I: Iterator<Item=Aabb<N>> {
+        let growth = |bbox: &Aabb<N>| bbox.union(&target).surface_area() - bbox.surface_area();
+        boxes.enumerate().min_by(|&(_, a), &(_, b)| {
+            compare(growth(&a), growth(&b))
+                .then_with(|| compare(a.surface_area(), b.surface_area()))
+        }).map(|(idx, _)| idx)
+    }
+
+    /// The index of the box which grows least by adding `target`, breaking
+    /// ties by the smaller volume.
+    fn least_enlargement<I, const N: usize>(target: Aabb<N>, boxes: I) -> Option<usize> where I: Iterator<Item=Aabb<N>> {
+        boxes.enumerate().min_by(|&(_, a), &(_, b)| {
+            compare(expansion(&a, &target), expansion(&b, &target))
+                .then_with(|| compare(a.volume(), b.volume()))
+        }).map(|(idx, _)| idx)
+    }
+
+    /// The index of the box whose overlap with its siblings grows least by
+    /// adding `target`, breaking ties by volume enlargement and then volume.
+    fn least_overlap_enlargement<I, const N: usize>(target: Aabb<N>, boxes: I) -> Option<usize> where I: Iterator<Item=Aabb<N>> {
+        let boxes: Vec<Aabb<N>> = boxes.collect();
+        let growth: Vec<f64> = boxes.iter().enumerate().map(|(idx, bbox)| {
+            let enlarged = bbox.union(&target);
+            boxes.iter().enumerate()
+                .filter(|&(other, _)| other != idx)
+                .map(|(_, b)| overlap_volume(&enlarged, b) - overlap_volume(bbox, b))
+                .sum()
+        }).collect();
+
+        (0..boxes.len()).min_by(|&a, &b| {
+            compare(growth[a], growth[b])
+                .then_with(|| compare(expansion(&boxes[a], &target), expansion(&boxes[b], &target)))
+                .then_with(|| compare(boxes[a].volume(), boxes[b].volume()))
+        })
+    }
+
+    /// Sort `items` by how far their centers lie from the center of them
+    /// all, nearest first, so the outermost can be evicted off the end.
+    pub fn sort_outermost_last<T, const N: usize>(items: &mut [T]) where T: Mbr<N> {
+        let center = match union_all(items.iter().map(Mbr::<N>::mbr)) {
+            Some(bbox) => bbox.center(),
+            None => return,
+        };
+        let distance = |item: &T| {
+            let delta = item.mbr().center() - center;
+            delta.dot(&delta)
+        };
+        items.sort_by(|a, b| compare(distance(a), distance(b)));
+    }
+
+    /// The R* split: choose the axis whose candidate distributions have the
+    /// smallest total margin, then the distribution along it with the least
+    /// overlap, breaking ties by total volume.
+    pub fn rstar_split<T, const N: usize>(items: Vec<T>, min_fill: usize) -> (Aabb<N>, Vec<T>, Aabb<N>, Vec<T>)
+        where
+            T: Mbr<N> {
+
+        let boxes: Vec<Aabb<N>> = items.iter().map(Mbr::<N>::mbr).collect();
+        let len = boxes.len();
+        assert!(2 * min_fill <= len, "Unsufficient nodes");
+
+        // For each axis, the entries ordered by lower and by upper bound.
+        let orders: Vec<Vec<usize>> = (0..N).flat_map(|axis| {
+            let by_min = {
+                let mut order: Vec<usize> = (0..len).collect();
+                order.sort_by(|&a, &b| compare(axis_bounds(&boxes[a], axis).0, axis_bounds(&boxes[b], axis).0));
+                order
+            };
+            let by_max = {
+                let mut order: Vec<usize> = (0..len).collect();
+                order.sort_by(|&a, &b| compare(axis_bounds(&boxes[a], axis).1, axis_bounds(&boxes[b], axis).1));
+                order
+            };
+            vec![by_min, by_max]
+        }).collect();
+
+        // The first `k` entries of an order go left, the rest right.  The
+        // union boxes of every prefix and suffix are built incrementally.
+        let distributions = |order: &[usize]| -> Vec<(usize, Aabb<N>, Aabb<N>)> {
+            let mut prefixes = Vec::with_capacity(len);
+            let mut acc = boxes[order[0]];
+            for &idx in order.iter() {
+                acc = acc.union(&boxes[idx]);
+                prefixes.push(acc);
+            }
+            let mut suffixes = vec![boxes[order[len - 1]]; len];
+            let mut acc = boxes[order[len - 1]];
+            for (pos, &idx) in order.iter().enumerate().rev() {
+                acc = acc.union(&boxes[idx]);
+                suffixes[pos] = acc;
+            }
+            (min_fill.max(1)..=len - min_fill.max(1))
+                .map(|k| (k, prefixes[k - 1], suffixes[k]))
+                .collect()
+        };
+
+        let axis = (0..N).min_by(|&a, &b| {
+            let margin_sum = |axis: usize| -> f64 {
+                orders[2 * axis..2 * axis + 2].iter()
+                    .flat_map(|order| distributions(order))
+                    .map(|(_, l, r)| l.margin() + r.margin())
+                    .sum()
+            };
+            compare(margin_sum(a), margin_sum(b))
+        }).unwrap();
+
+        let (order, k, lbox, rbox) = orders[2 * axis..2 * axis + 2].iter()
+            .flat_map(|order| distributions(order).into_iter().map(move |(k, l, r)| (order, k, l, r)))
+            .min_by(|a, b| {
+                compare(overlap_volume(&a.2, &a.3), overlap_volume(&b.2, &b.3))
+                    .then_with(|| compare(a.2.volume() + a.3.volume(), b.2.volume() + b.3.volume()))
+            })
+            .unwrap();
+
+        let mut entries: Vec<Option<T>> = items.into_iter().map(Some).collect();
+        let lefts = order[..k].iter().map(|&idx| entries[idx].take().unwrap()).collect();
+        let rights = order[k..].iter().map(|&idx| entries[idx].take().unwrap()).collect();
+        (lbox, lefts, rbox, rights)
+    }
+
+    /// Split `items` by the Surface Area Heuristic: of every cut of the
+    /// entries sorted by center along some axis, leaving at least
+    /// `min_fill` on each side, take the one minimizing the surface area of
+    /// each half times its number of entries.
+    pub fn sah_split<T, const N: usize>(items: Vec<T>, min_fill: usize) -> (Aabb<N>, Vec<T>, Aabb<N>, Vec<T>)
+        where
+            T: Mbr<N> {
+
+        let boxes: Vec<Aabb<N>> = items.iter().map(Mbr::<N>::mbr).collect();
+        let len = boxes.len();
+        assert!(2 * min_fill <= len, "Unsufficient nodes");
+        let min_fill = min_fill.max(1);
+
+        let sorted = |axis: usize| {
+            let mut order: Vec<usize> = (0..len).collect();
+            order.sort_by(|&a, &b| compare(boxes[a].center().coords[axis], boxes[b].center().coords[axis]));
+            order
+        };
+
+        let mut best: Option<(f64, usize, usize, Aabb<N>, Aabb<N>)> = None;
+        for axis in 0..N {
+            let order = sorted(axis);
+            // The union boxes of every suffix, then of each prefix as the
+            // cut moves right.
+            let mut suffixes = vec![boxes[order[len - 1]]; len];
+            for pos in (0..len - 1).rev() {
+                suffixes[pos] = suffixes[pos + 1].union(&boxes[order[pos]]);
+            }
+            let mut prefix = boxes[order[0]];
+            for k in 1..len {
+                prefix = prefix.union(&boxes[order[k - 1]]);
+                if k < min_fill || len - k < min_fill {
+                    continue;
+                }
+                let cost = prefix.surface_area() * k as f64 + suffixes[k].surface_area() * (len - k) as f64;
+                if best.is_none_or(|b| cost < b.0) {
+                    best = Some((cost, axis, k, prefix, suffixes[k]));
+                }
+            }
+        }
+
+        let (_, axis, k, lbox, rbox) = best.unwrap();
+        let order = sorted(axis);
+        let mut entries: Vec<Option<T>> = items.into_iter().map(Some).collect();
+        let lefts = order[..k].iter().map(|&idx| entries[idx].take().unwrap()).collect();
+        let rights = order[k..].iter().map(|&idx| entries[idx].take().unwrap()).collect();
+        (lbox, lefts, rbox, rights)
+    }
+
+    /// The Hilbert value of the center of `entry`'s bounding box.
+    pub fn hilbert_value<T, const N: usize>(entry: &T) -> u64 where T: Mbr<N> {
+        hilbert_key(&entry.mbr().center())
+    }
+
+    /// Move the entry at `idx` to the end, for popping, keeping the others
+    /// in order if the strategy relies on it.
+    pub fn move_to_end<E>(config: &RTreeConfig, entries: &mut [E], idx: usize) {
+        if config.split == SplitStrategy::Hilbert {
+            entries[idx..].rotate_left(1);
+        } else {
+            let last = entries.len() - 1;
+            entries.swap(idx, last);
+        }
+    }
+
+    /// Reorder `entries` so that those for which `pred` holds come first,
+    /// and return how many there are.
+    pub fn partition<E, F>(entries: &mut [E], pred: F) -> usize where F: Fn(&E) -> bool {
+        let mut split = 0;
+        for idx in 0..entries.len() {
+            if pred(&entries[idx]) {
+                entries.swap(split, idx);
+                split += 1;
+            }
+        }
+        split
+    }
+
+    /// Cut `entries` into `parts` runs of nearly equal length, keeping
+    /// their order.
+    pub fn spread<E>(entries: Vec<E>, parts: usize) -> Vec<Vec<E>> {
+        let total = entries.len();
+        let mut iter = entries.into_iter();
+        (0..parts)
+            .map(|i| iter.by_ref().take((i + 1) * total / parts - i * total / parts).collect())
+            .collect()
+    }
+
+    /// Split `items` into the two halves of their Hilbert order.  They are
+    /// sorted by Hilbert value first; a stable sort, so entries which are
+    /// already in order stay that way.
+    pub fn hilbert_split<T, const N: usize>(mut items: Vec<T>) -> (Aabb<N>, Vec<T>, Aabb<N>, Vec<T>) where T: Mbr<N> {
+        items.sort_by_key(hilbert_value);
+        let rights = items.split_off(items.len() / 2);
+        let lbox = union_all(items.iter().map(Mbr::<N>::mbr)).expect("split nodes must not be empty");
+        let rbox = union_all(rights.iter().map(Mbr::<N>::mbr)).expect("split nodes must not be empty");
+        (lbox, items, rbox, rights)
+    }
+
+    /// Guttman's quadratic split: distribute `items` into two groups of at
+    /// least `min_fill` entries each, trying to minimize the volume of the
+    /// two resulting bounding boxes.
+    pub fn quad_split<T, const N: usize>(items: Vec<T>, min_fill: usize) -> (Aabb<N>, Vec<T>, Aabb<N>, Vec<T>)
+        where
+            T: Mbr<N> {
+
+        let boxes: Vec<Aabb<N>> = items.iter().map(Mbr::<N>::mbr).collect();
+        let (left_seed, right_seed) = pick_seeds(&boxes).expect("Unsufficient nodes");
+        let mut entries: Vec<Option<T>> = items.into_iter().map(Some).collect();
 
         // `items` should be the size of a full node.  Size the other two
         // similarly.
-        let mut lefts = Vec::with_capacity(items.len());
-        let mut rights = Vec::with_capacity(items.len());
+        let mut lefts = Vec::with_capacity(entries.len());
+        let mut rights = Vec::with_capacity(entries.len());
+        let mut lbox = boxes[left_seed];
+        let mut rbox = boxes[right_seed];
+        lefts.extend(entries[left_seed].take());
+        rights.extend(entries[right_seed].take());
+
+        let mut remaining = entries.len() - 2;
+        while remaining > 0 {
+            // If one group needs everything that is left to reach the
+            // minimum fill, hand it over.
+            if lefts.len() + remaining <= min_fill || rights.len() + remaining <= min_fill {
+                let (children, bbox) = if lefts.len() + remaining <= min_fill {
+                    (&mut lefts, &mut lbox)
+                } else {
+                    (&mut rights, &mut rbox)
+                };
+                for (idx, entry) in entries.iter_mut().enumerate() {
+                    if let Some(item) = entry.take() {
+                        *bbox = bbox.union(&boxes[idx]);
+                        children.push(item);
+                    }
+                }
+                break;
+            }
+
+            // Assign the entry with the strongest preference for one group
+            // first.
+            let mut next = None;
+            let mut max_preference = f64::MIN;
+            for (idx, entry) in entries.iter().enumerate() {
+                if entry.is_none() {
+                    continue;
+                }
+                let preference = (expansion(&lbox, &boxes[idx]) - expansion(&rbox, &boxes[idx])).abs();
+                if next.is_none() || preference > max_preference {
+                    max_preference = preference;
+                    next = Some(idx);
+                }
+            }
+            let idx = next.expect("an entry must remain");
+            let ibox = boxes[idx];
+
+            let comparison = PartialOrd::partial_cmp(
+                &expansion(&lbox, &ibox),
+                &expansion(&rbox, &ibox),
+            ).expect("Failed to compare box expansions");
+
+            let comparison = match comparison {
+                Ordering::Equal => PartialOrd::partial_cmp(&lbox.volume(), &rbox.volume())
+                    .expect("Failed to compare box volumes"),
+                other => other,
+            };
+
+            let (children, bbox) = match comparison {
+                Ordering::Less => (&mut lefts, &mut lbox),
+                Ordering::Equal => match Ord::cmp(&lefts.len(), &rights.len()) {
+                    Ordering::Less => (&mut lefts, &mut lbox),
+                    Ordering::Equal => (&mut lefts, &mut lbox),
+                    Ordering::Greater => (&mut rights, &mut rbox),
+                },
+                Ordering::Greater => (&mut rights, &mut rbox),
+            };
+            *bbox = ibox.union(bbox);
+            children.extend(entries[idx].take());
+            remaining -= 1;
+        }
+        (lbox, lefts, rbox, rights)
+    }
+
+    /// Guttman's linear seeds: along each axis, the entry whose lower side
+    /// is highest and the one whose upper side is lowest, taking the pair
+    /// separated furthest relative to the width of all the entries along
+    /// that axis.
+    fn linear_seeds<const N: usize>(boxes: &[Aabb<N>]) -> Option<(usize, usize)> {
+        if boxes.len() < 2 {
+            return None;
+        }
+        let mut seeds = None;
+        let mut max_separation = f64::MIN;
+        for axis in 0..N {
+            let highest_low = (0..boxes.len())
+                .max_by(|&a, &b| compare(axis_bounds(&boxes[a], axis).0, axis_bounds(&boxes[b], axis).0))
+                .unwrap();
+            let lowest_high = (0..boxes.len())
+                .filter(|&idx| idx != highest_low)
+                .min_by(|&a, &b| compare(axis_bounds(&boxes[a], axis).1, axis_bounds(&boxes[b], axis).1))
+                .unwrap();
+            let low = boxes.iter().map(|b| axis_bounds(b, axis).0).fold(f64::INFINITY, f64::min);
+            let high = boxes.iter().map(|b| axis_bounds(b, axis).1).fold(f64::NEG_INFINITY, f64::max);
+            let gap = axis_bounds(&boxes[highest_low], axis).0 - axis_bounds(&boxes[lowest_high], axis).1;
+            let separation = if high > low { gap / (high - low) } else { 0.0 };
+            if seeds.is_none() || separation > max_separation {
+                max_separation = separation;
+                seeds = Some((lowest_high, highest_low));
+            }
+        }
+        seeds
+    }
+
+    /// Guttman's linear split: seed the two groups with `linear_seeds`,
+    /// then hand out the other entries in their original order, each to
+    /// the group whose volume grows least, until one group needs the rest
+    /// to reach `min_fill`.  It takes linear rather than quadratic time in
+    /// the number of entries, at the cost of a looser split.
+    pub fn linear_split<T, const N: usize>(items: Vec<T>, min_fill: usize) -> (Aabb<N>, Vec<T>, Aabb<N>, Vec<T>)
+        where
+            T: Mbr<N> {
+
+        let boxes: Vec<Aabb<N>> = items.iter().map(Mbr::<N>::mbr).collect();
+        let (left_seed, right_seed) = linear_seeds(&boxes).expect("Unsufficient nodes");
+        let len = items.len();
+        let mut lefts = Vec::with_capacity(len);
+        let mut rights = Vec::with_capacity(len);
+        let mut lbox = boxes[left_seed];
+        let mut rbox = boxes[right_seed];
+
+        let mut remaining = len - 2;
+        for (idx, item) in items.into_iter().enumerate() {
+            if idx == left_seed {
+                lefts.push(item);
+                continue;
+            }
+            if idx == right_seed {
+                rights.push(item);
+                continue;
+            }
+            let ibox = boxes[idx];
+            let to_left = if lefts.len() + remaining <= min_fill {
+                true
+            } else if rights.len() + remaining <= min_fill {
+                false
+            } else {
+                compare(expansion(&lbox, &ibox), expansion(&rbox, &ibox))
+                    .then_with(|| compare(lbox.volume(), rbox.volume()))
+                    .then_with(|| lefts.len().cmp(&rights.len()))
+                    != Ordering::Greater
+            };
+            if to_left {
+                lbox = lbox.union(&ibox);
+                lefts.push(item);
+            } else {
+                rbox = rbox.union(&ibox);
+                rights.push(item);
+            }
+            remaining -= 1;
+        }
+        (lbox, lefts, rbox, rights)
+    }
+
+    pub fn best_fit<I, const N: usize>(target: Aabb<N>, boxes: I) -> Option<usize> where I: Iterator<Item=Aabb<N>> + Clone {
+
+        boxes.clone().next()?;
+
+        let mut min_volume = f64::MAX;
+        let mut best_idx = None;
+
+        // Find the smallest node that contains our target.
+        for (idx, bbox) in boxes.clone().enumerate() {
+            let volume = bbox.volume();
+            if volume.is_nan() {
+                panic!("volume must not be NaN");
+            }
+            if bbox.contains(&target) && volume < min_volume {
+                min_volume = volume;
+                best_idx = Some(idx);
+            }
+        }
+
+        if let Some(idx) = best_idx {
+            return Some(idx);
+        }
+
+        // None of the candidates fully contained our target, so search for the
+        // node which would expand the least, if our target was added to it.
+        for (idx, bbox) in boxes.enumerate() {
+            let volume = bbox.union(&target).volume();
+            if volume.is_nan() {
+                panic!("volume must not be NaN");
+            }
+            if volume < min_volume {
+                min_volume = volume;
+                best_idx = Some(idx);
+            }
+        }
+
+        if let Some(idx) = best_idx {
+            return Some(idx);
+        }
+
+        unreachable!("a node must have been selected");
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn debugger() {}
+
+#[cfg(test)]
+mod tests {
+    use ::vec3::Vec3;
+    use ::ray::Ray;
+    use ::bbox::BBox;
+    use arena::{Arena, NodeId};
+    use super::{Aabb, BulkLoadStrategy, Error, Intersectable, Mbr, NodeStorage, Point, QueryContext, RTree, RTreeConfig, SplitStrategy};
+    use super::leaf_heap_capacity;
+    use super::util;
+    use super::test_helpers::{Sphere, Lcg, random_spheres, check_tree};
+
+    #[test]
+    fn test_sphere() {
+        let ray = Ray::new(Vec3::xyz(0.0, 0.0, 0.0), Vec3::xyz(1.0, 0.055, 0.00));
+
+        let mut spheres: RTree<Sphere> = RTree::new();
+        spheres.insert(Sphere::new(Vec3::xyz(100.0, 0.0, 0.0), 5.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(120.0, 0.0, 0.0), 15.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(140.0, 0.0, 0.0), 25.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(160.0, 0.0, 0.0), 35.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(180.0, 0.0, 0.0), 45.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(200.0, 0.0, 0.0), 55.0).unwrap());
+        // The smallest sphere's box sits just below the ray.
+        assert_eq!(spheres.iter_ray(&ray).count(), 5);
+
+        let (t, sphere, hit) = spheres.closest_hit(&ray).unwrap();
+        assert_eq!(sphere.mbr().lerp(0.5, 0.5, 0.5), Vec3::xyz(120.0, 0.0, 0.0));
+        assert_eq!(t, hit.t);
+        assert!((hit.normal.len() - 1.0).abs() < 1e-9);
+        assert!(106.0 < hit.point.x && hit.point.x < 107.0);
+    }
+
+    #[test]
+    fn test_iter_ray_where() {
+        let ray = Ray::new(Vec3::xyz(0.0, 0.0, 0.0), Vec3::xyz(1.0, 0.055, 0.00));
+
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..6 {
+            let x = 100.0 + 20.0 * i as f64;
+            let radius = 5.0 + 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), radius).unwrap());
+        }
+
+        let large = spheres.iter_ray_where(&ray, |s| s.mbr().x_len() > 50.0);
+        assert_eq!(large.count(), 3);
+
+        let none = spheres.iter_ray_where(&ray, |_| false);
+        assert_eq!(none.count(), 0);
+    }
+
+    #[test]
+    fn test_iter_set_max_t() {
+        let mut spheres: RTree<Sphere> = RTree::with_config(RTreeConfig::with_max_entries(8).unwrap());
+        spheres.extend(random_spheres(3000, 27));
+        let origin = Vec3::xyz(-100.0, -100.0, -100.0);
+
+        let mut visited = 0;
+        let mut candidates = 0;
+        for target in random_spheres(20, 27) {
+            let ray = Ray::new(origin, target.mbr().center() - origin);
+            candidates += spheres.iter_ray(&ray).count();
+
+            // Cut the search at each closer hit, as `closest_hit` does.
+            let mut best = f64::INFINITY;
+            let mut iter = spheres.iter_ray(&ray);
+            while let Some(sphere) = iter.next() {
+                visited += 1;
+                if let Some(hit) = sphere.intersect(&ray).filter(|hit| hit.t < best) {
+                    best = hit.t;
+                    iter.set_max_t(best);
+                }
+            }
+            assert_eq!(Some(best), spheres.closest_hit(&ray).map(|(t, _, _)| t));
+
+            // Only candidates entered by then are left.
+            let cut = best * 0.5;
+            let mut iter = spheres.iter_ray(&ray);
+            iter.set_max_t(cut);
+            let expected = spheres.iter_ray(&ray).filter(|s| s.mbr().intersect_ray_t(&ray).unwrap().0 <= cut).count();
+            assert_eq!(iter.count(), expected);
+        }
+        assert!(visited < candidates);
+    }
+
+    #[test]
+    fn test_iter_ray_with() {
+        let mut spheres: RTree<Sphere> = RTree::with_config(RTreeConfig::with_max_entries(8).unwrap());
+        spheres.extend(random_spheres(3000, 28));
+        let mut few: RTree<Sphere> = RTree::new();
+        few.extend(random_spheres(10, 28));
+        let origin = Vec3::xyz(-100.0, -100.0, -100.0);
+        let rays: Vec<Ray> = random_spheres(40, 29).iter().map(|s| Ray::new(origin, s.mbr().center() - origin)).collect();
+
+        let mut context = QueryContext::new();
+        let centers = |found: Vec<&Sphere>| -> Vec<Vec3> { found.into_iter().map(|s| s.mbr().center()).collect() };
+        for (i, ray) in rays.iter().enumerate() {
+            let tree = if i % 4 == 3 { &few } else { &spheres };
+            let expected = centers(tree.iter_ray(ray).collect());
+            assert_eq!(centers(tree.iter_ray_with(&mut context, ray).collect()), expected);
+
+            // The stack goes back to the context even when the query is
+            // cut short.
+            let mut iter = tree.iter_ray_with(&mut context, ray);
+            iter.set_max_t(0.0);
+            assert!(iter.next().is_none_or(|s| s.mbr().intersect_ray_t(ray).unwrap().0 <= 0.0));
+            drop(iter);
+            assert!(context.stack.capacity() > 0);
+        }
+
+        // Once grown, the stack is reused as it is.
+        let capacity = context.stack.capacity();
+        for ray in rays.iter() {
+            assert!(spheres.iter_ray_with(&mut context, ray).count() <= spheres.len());
+        }
+        assert_eq!(context.stack.capacity(), capacity);
+        assert_eq!(RTree::<Sphere>::new().iter_ray_with(&mut context, &rays[0]).count(), 0);
+    }
+
+    #[test]
+    fn test_locate_in_envelope() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..6 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), 2.0).unwrap());
+        }
+
+        let envelope = BBox {
+            min: Vec3::xyz(9.0, -1.0, -1.0),
+            max: Vec3::xyz(29.0, 1.0, 1.0),
+        };
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), 3);
+
+        // Only keep spheres whose centre lies inside the envelope.
+        let exact = spheres.locate_in_envelope_with(&envelope, |s, env| {
+            let mbr = s.mbr();
+            env.inside(&mbr.lerp(0.5, 0.5, 0.5))
+        });
+        assert_eq!(exact.count(), 2);
+    }
+
+    #[test]
+    fn test_iter_bbox() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..6 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), 2.0).unwrap());
+        }
+
+        let (overlapping, contained) = {
+            let query = BBox {
+                min: Vec3::xyz(7.0, -3.0, -3.0),
+                max: Vec3::xyz(29.0, 3.0, 3.0),
+            };
+            (spheres.iter_bbox(&query), spheres.iter_contained_in(&query))
+        };
+        assert_eq!(overlapping.count(), 3);
+
+        let mut xs: Vec<f64> = contained.map(|s| s.mbr().lerp(0.5, 0.5, 0.5).x).collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(xs, vec![10.0, 20.0]);
+    }
+
+    #[test]
+    fn test_count_in_bbox() {
+        let spheres: RTree<Sphere> = RTree::bulk_load(random_spheres(4000, 43));
+        let queries = [
+            BBox { min: Vec3::xyz(100.0, 200.0, 300.0), max: Vec3::xyz(600.0, 700.0, 500.0) },
+            BBox { min: Vec3::xyz(-10.0, -10.0, -10.0), max: Vec3::xyz(1010.0, 1010.0, 1010.0) },
+            BBox { min: Vec3::xyz(500.0, 500.0, 500.0), max: Vec3::xyz(500.0, 500.0, 500.0) },
+            BBox { min: Vec3::xyz(2000.0, 0.0, 0.0), max: Vec3::xyz(3000.0, 1.0, 1.0) },
+        ];
+        for query in queries.iter() {
+            let expected = spheres.iter_bbox(query).count();
+            assert_eq!(spheres.count_in_bbox(query), expected);
+            assert_eq!(spheres.any_in_bbox(query), expected > 0);
+        }
+        assert_eq!(spheres.count_in_bbox(&queries[1]), 4000);
+        assert!(!RTree::<Sphere>::new().any_in_bbox(&queries[1]));
+    }
+
+    #[test]
+    fn test_for_each_mut_in_envelope() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..6 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), 2.0).unwrap());
+        }
+
+        let envelope = BBox {
+            min: Vec3::xyz(35.0, -1.0, -1.0),
+            max: Vec3::xyz(60.0, 1.0, 1.0),
+        };
+        let visited = spheres.for_each_mut_in_envelope(&envelope, |s| {
+            s.translate(Vec3::xyz(0.0, 100.0, 0.0));
+        });
+        assert_eq!(visited, 2);
+
+        // The moved spheres are found at their new location only.
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), 0);
+        let moved = BBox {
+            min: Vec3::xyz(35.0, 99.0, -1.0),
+            max: Vec3::xyz(60.0, 101.0, 1.0),
+        };
+        assert_eq!(spheres.locate_in_envelope(&moved).count(), 2);
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..4 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), 1.0).unwrap());
+        }
 
-        // TODO: We need to select the best child first, instead of doing them
-        // sequentially.
-        for item in items.into_iter() {
-            let ibox = item.mbr();
+        let offset = Vec3::xyz(0.0, 0.0, -500.0);
+        let mut notified = 0;
+        spheres.translate(offset, |s| {
+            s.translate(offset);
+            notified += 1;
+        });
+        assert_eq!(notified, 4);
 
-            let comparison = PartialOrd::partial_cmp(
-                &expansion(&lbox, &ibox),
-                &expansion(&rbox, &ibox),
-            ).expect("Failed to compare box expansions");
+        let ray = Ray::new(Vec3::xyz(-10.0, 0.0, -500.0), Vec3::xyz(1.0, 0.0, 0.0));
+        assert_eq!(spheres.iter_ray(&ray).count(), 4);
 
-            let (children, bbox) = match comparison {
-                Ordering::Less => (&mut lefts, &mut lbox),
-                Ordering::Equal => match Ord::cmp(&lefts.len(), &rights.len()) {
-                    Ordering::Less => (&mut lefts, &mut lbox),
-                    Ordering::Equal => (&mut lefts, &mut lbox),
-                    Ordering::Greater => (&mut rights, &mut rbox),
-                },
-                Ordering::Greater => (&mut rights, &mut rbox),
-            };
-            *bbox = item.mbr().union(bbox);
-            children.push(item);
+        let old = Ray::new(Vec3::xyz(-10.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0));
+        assert_eq!(spheres.iter_ray(&old).count(), 0);
+    }
+
+    #[test]
+    fn test_locate_outside_envelope() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let envelope = BBox {
+            min: Vec3::xyz(9.0, -1.0, -1.0),
+            max: Vec3::xyz(29.0, 1.0, 1.0),
+        };
+        assert_eq!(spheres.locate_outside_envelope(&envelope).count(), 0);
+
+        for i in 0..6 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), 2.0).unwrap());
         }
-        (lbox, lefts, rbox, rights)
+
+        let inside = spheres.locate_in_envelope(&envelope).count();
+        let outside = spheres.locate_outside_envelope(&envelope).count();
+        assert_eq!(inside, 3);
+        assert_eq!(outside, 3);
+
+        let everything = BBox {
+            min: Vec3::xyz(-100.0, -100.0, -100.0),
+            max: Vec3::xyz(100.0, 100.0, 100.0),
+        };
+        assert_eq!(spheres.locate_outside_envelope(&everything).count(), 0);
+
+        let far_away = BBox {
+            min: Vec3::xyz(500.0, 500.0, 500.0),
+            max: Vec3::xyz(600.0, 600.0, 600.0),
+        };
+        assert_eq!(spheres.locate_outside_envelope(&far_away).count(), 6);
     }
 
-    pub fn best_fit<T>(target: BBox, children: &[T]) -> Option<usize> where T: Mbr {
+    #[test]
+    fn test_insert_splits() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for sphere in random_spheres(2000, 1) {
+            spheres.insert(sphere);
+        }
+
+        assert_eq!(spheres.root_node().unwrap().deep_len(), 2000);
+        assert!(check_tree(&spheres) >= 1);
 
-        if children.len() == 0 {
-            return None;
+        let expected = random_spheres(2000, 1);
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(400.0, 500.0, 600.0),
+        };
+        let brute_force = expected.iter().filter(|s| s.mbr().overlaps(&envelope)).count();
+        assert!(brute_force > 0);
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), brute_force);
+
+        let target = expected[0].mbr().lerp(0.5, 0.5, 0.5);
+        let ray = Ray::new(Vec3::xyz(0.0, 0.0, 0.0), target);
+        let brute_force = expected.iter().filter(|s| s.mbr().intersects(&ray)).count();
+        assert!(brute_force > 0);
+        assert_eq!(spheres.iter_ray(&ray).count(), brute_force);
+    }
+
+    #[test]
+    fn test_insert_expands_bounds() {
+        // Each sphere lies outside everything inserted before it, so every
+        // insertion expands the bounds all the way up to the root.
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..1000 {
+            let x = 3.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, -x), 1.0).unwrap());
         }
 
-        let mut min_volume = f64::MAX;
-        let mut best_idx = None;
+        assert!(check_tree(&spheres) >= 1);
+        let root = spheres.root_node().unwrap();
+        assert_eq!(root.deep_len(), 1000);
+        assert_eq!(root.bbox.max, Vec3::xyz(2998.0, 1.0, 1.0));
+        assert_eq!(root.bbox.min, Vec3::xyz(-1.0, -1.0, -2998.0));
+    }
 
-        // Find the smallest node that contains our target.
-        for (idx, child) in children.iter().enumerate() {
-            let bbox = child.mbr();
-            let volume = bbox.volume();
-            if volume.is_nan() {
-                panic!("volume must not be NaN");
+    #[test]
+    fn test_remove() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let missing = Sphere::new(Vec3::xyz(-50.0, 0.0, 0.0), 1.0).unwrap();
+        assert!(spheres.remove(&missing).is_none());
+
+        for sphere in random_spheres(2000, 2) {
+            spheres.insert(sphere);
+        }
+        assert!(spheres.remove(&missing).is_none());
+
+        let expected = random_spheres(2000, 2);
+        for sphere in expected.iter().step_by(2) {
+            assert_eq!(spheres.remove(sphere).as_ref(), Some(sphere));
+            assert!(spheres.remove(sphere).is_none());
+        }
+
+        assert_eq!(spheres.root_node().unwrap().deep_len(), 1000);
+        check_tree(&spheres);
+
+        let everything = BBox {
+            min: Vec3::xyz(-100.0, -100.0, -100.0),
+            max: Vec3::xyz(1100.0, 1100.0, 1100.0),
+        };
+        assert_eq!(spheres.locate_in_envelope(&everything).count(), 1000);
+
+        for sphere in expected.iter().skip(1).step_by(2) {
+            assert_eq!(spheres.remove(sphere).as_ref(), Some(sphere));
+            check_tree(&spheres);
+        }
+        assert!(spheres.root.is_none());
+        assert_eq!(spheres.locate_in_envelope(&everything).count(), 0);
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(RTreeConfig::new(3, 1).is_err());
+        assert!(RTreeConfig::new(8, 0).is_err());
+        assert!(RTreeConfig::new(8, 5).is_err());
+        assert_eq!(RTreeConfig::with_max_entries(8).unwrap(), RTreeConfig::new(8, 3).unwrap());
+
+        let config = RTreeConfig::new(6, 2).unwrap();
+        let mut spheres: RTree<Sphere> = RTree::with_config(config);
+        for sphere in random_spheres(500, 4) {
+            spheres.insert(sphere);
+        }
+        // 500 items in nodes of at most 6 need at least four levels.
+        assert!(check_tree(&spheres) >= 3);
+
+        for sphere in random_spheres(500, 4).iter().step_by(3) {
+            assert_eq!(spheres.remove(sphere).as_ref(), Some(sphere));
+        }
+        check_tree(&spheres);
+
+        let packed = RTree::bulk_load_with_config(random_spheres(500, 4), config);
+        assert_eq!(packed.config(), &config);
+        check_tree(&packed);
+    }
+
+    #[test]
+    fn test_rstar() {
+        // Tight clusters of spheres scattered through the cube.
+        let mut rng = Lcg::new(9);
+        let mut clustered = Vec::new();
+        for _ in 0..40 {
+            let center = Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()).scale(1000.0);
+            for _ in 0..100 {
+                let offset = Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()).scale(30.0);
+                clustered.push((center + offset, 0.5 + rng.next_f64()));
             }
-            if bbox.contains(&target) && volume < min_volume {
-                min_volume = volume;
-                best_idx = Some(idx);
+        }
+        let spheres = || clustered.iter().map(|&(origin, radius)| Sphere::new(origin, radius).unwrap());
+
+        let mut quadratic: RTree<Sphere> = RTree::new();
+        let config = RTreeConfig::default().with_split_strategy(SplitStrategy::RStar);
+        let mut rstar: RTree<Sphere> = RTree::with_config(config);
+        for (a, b) in spheres().zip(spheres()) {
+            quadratic.insert(a);
+            rstar.insert(b);
+        }
+        check_tree(&rstar);
+        assert_eq!(rstar.root_node().unwrap().deep_len(), 4000);
+        assert!(rstar.quality().overlap_factor < quadratic.quality().overlap_factor);
+
+        let expected: Vec<Sphere> = spheres().collect();
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(600.0, 700.0, 800.0),
+        };
+        let brute_force = expected.iter().filter(|s| s.mbr().overlaps(&envelope)).count();
+        assert_eq!(rstar.locate_in_envelope(&envelope).count(), brute_force);
+
+        for sphere in expected.iter().step_by(2) {
+            assert_eq!(rstar.remove(sphere).as_ref(), Some(sphere));
+        }
+        check_tree(&rstar);
+        assert_eq!(rstar.root_node().unwrap().deep_len(), 2000);
+    }
+
+    /// The summed surface areas of every node's box, which the Surface
+    /// Area Heuristic takes as the cost of tracing a ray through the tree.
+    fn node_surface_area<T: Mbr>(tree: &RTree<T>) -> f64 {
+        let mut stack: Vec<NodeId> = tree.root.into_iter().collect();
+        let mut total = 0.0;
+        while let Some(node) = stack.pop() {
+            total += tree.nodes[node].bbox.surface_area();
+            stack.extend(tree.nodes.child_ids(node));
+        }
+        total
+    }
+
+    #[test]
+    fn test_sah() {
+        let config = RTreeConfig::new(8, 3).unwrap();
+        let mut quadratic: RTree<Sphere> = RTree::with_config(config);
+        let mut sah: RTree<Sphere> = RTree::with_config(config.with_split_strategy(SplitStrategy::Sah));
+        quadratic.extend(random_spheres(3000, 83));
+        sah.extend(random_spheres(3000, 83));
+        check_tree(&sah);
+        assert_eq!(sah.validate(), Ok(()));
+        assert!(node_surface_area(&sah) < node_surface_area(&quadratic));
+
+        let expected = random_spheres(3000, 83);
+        let ray = Ray::new(Vec3::xyz(-10.0, 500.0, 500.0), Vec3::xyz(1.0, 0.01, -0.02));
+        let hits = |tree: &RTree<Sphere>| {
+            let mut hits: Vec<f64> = tree.iter_ray(&ray).filter_map(|s| s.intersect(&ray)).map(|i| i.t).collect();
+            hits.sort_by(f64::total_cmp);
+            hits
+        };
+        assert_eq!(hits(&sah), hits(&quadratic));
+        for sphere in expected.iter().step_by(2) {
+            assert!(sah.remove(sphere).is_some());
+        }
+        check_tree(&sah);
+        assert_eq!(sah.validate(), Ok(()));
+
+        let sah_config = config.with_bulk_load_strategy(BulkLoadStrategy::Sah);
+        let packed: RTree<Sphere> = RTree::bulk_load_with_config(random_spheres(3000, 83), sah_config);
+        let tiled: RTree<Sphere> = RTree::bulk_load_with_config(random_spheres(3000, 83), config);
+        check_tree(&packed);
+        assert_eq!(packed.validate(), Ok(()));
+        assert!(node_surface_area(&packed) < node_surface_area(&tiled));
+        assert_eq!(hits(&packed), hits(&quadratic));
+    }
+
+    #[test]
+    fn test_linear() {
+        // Two far apart rows of boxes split along the gap between them,
+        // whatever order they come in.
+        let mut boxes: Vec<BBox> = (0..5).flat_map(|i| {
+            let low = Vec3::xyz(i as f64, 0.0, 0.0);
+            let high = Vec3::xyz(i as f64, 100.0, 0.0);
+            vec![BBox { min: low, max: low + 0.5 }, BBox { min: high, max: high + 0.5 }]
+        }).collect();
+        boxes.rotate_left(3);
+        let (lbox, lefts, rbox, rights) = util::linear_split(boxes, 3);
+        assert_eq!((lefts.len(), rights.len()), (5, 5));
+        assert!(!lbox.overlaps(&rbox));
+        assert!(lefts.iter().all(|b| lbox.contains(b)) && rights.iter().all(|b| rbox.contains(b)));
+
+        let config = RTreeConfig::new(8, 3).unwrap().with_split_strategy(SplitStrategy::Linear);
+        let mut spheres: RTree<Sphere> = RTree::with_config(config);
+        spheres.extend(random_spheres(3000, 91));
+        check_tree(&spheres);
+        assert_eq!(spheres.validate(), Ok(()));
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(400.0, 500.0, 600.0),
+        };
+        let brute_force = random_spheres(3000, 91).iter().filter(|s| s.mbr().overlaps(&envelope)).count();
+        assert_eq!(spheres.locate_in_envelope(&envelope).count(), brute_force);
+        for sphere in random_spheres(3000, 91).iter().step_by(2) {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        check_tree(&spheres);
+        assert_eq!(spheres.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_update() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for sphere in random_spheres(2000, 5) {
+            spheres.insert(sphere);
+        }
+        let missing = Sphere::new(Vec3::xyz(-50.0, 0.0, 0.0), 1.0).unwrap();
+        let also_missing = Sphere::new(Vec3::xyz(-50.0, 0.0, 0.0), 1.0).unwrap();
+        assert_eq!(spheres.update(&missing, also_missing), Err(missing));
+
+        // Nudge some spheres by a hair, and send others across the cube.
+        let mut moved = random_spheres(2000, 5);
+        for (idx, sphere) in moved.iter_mut().enumerate() {
+            let offset = if idx % 2 == 0 { 0.001 } else { 500.0 };
+            sphere.translate(Vec3::xyz(offset, -offset, offset));
+        }
+        let expected: Vec<BBox> = moved.iter().map(Mbr::mbr).collect();
+        for (old, new) in random_spheres(2000, 5).iter().zip(moved) {
+            assert_eq!(spheres.update(old, new).as_ref(), Ok(old));
+        }
+
+        assert_eq!(spheres.root_node().unwrap().deep_len(), 2000);
+        check_tree(&spheres);
+        let envelope = BBox {
+            min: Vec3::xyz(400.0, -200.0, 600.0),
+            max: Vec3::xyz(900.0, 300.0, 1100.0),
+        };
+        let brute_force = expected.iter().filter(|b| b.overlaps(&envelope)).count();
+        assert!(brute_force > 0);
+        assert_eq!(spheres.iter_bbox(&envelope).count(), brute_force);
+    }
+
+    #[test]
+    fn test_item_ids() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let ids: Vec<_> = random_spheres(2000, 6).into_iter().map(|s| spheres.insert(s)).collect();
+        let expected = random_spheres(2000, 6);
+
+        // Handles survive the splits caused by later insertions.
+        for (id, sphere) in ids.iter().zip(expected.iter()) {
+            assert_eq!(spheres.get(*id), Some(sphere));
+        }
+
+        for (id, sphere) in ids.iter().zip(expected.iter()).step_by(2) {
+            assert_eq!(spheres.remove_by_id(*id).as_ref(), Some(sphere));
+            assert!(spheres.get(*id).is_none());
+            assert!(spheres.remove_by_id(*id).is_none());
+        }
+        check_tree(&spheres);
+
+        // A handle follows its item through an update.
+        let moved = || Sphere::new(Vec3::xyz(-500.0, 0.0, 0.0), 1.0).unwrap();
+        assert!(spheres.update(&expected[1], moved()).is_ok());
+        assert_eq!(spheres.get(ids[1]), Some(&moved()));
+        assert_eq!(spheres.remove_by_id(ids[1]), Some(moved()));
+        check_tree(&spheres);
+    }
+
+    #[test]
+    fn test_insert_or_update() {
+        let mut spheres: RTree<Sphere> = RTree::with_config(RTreeConfig::new(8, 3).unwrap());
+        let ids: Vec<_> = random_spheres(1000, 7).into_iter().map(|s| spheres.insert(s)).collect();
+        let expected = random_spheres(1000, 7);
+
+        // Live handles are updated in place, whether the item moves by a
+        // hair or across the cube.
+        let mut moved = random_spheres(1000, 7);
+        for (idx, sphere) in moved.iter_mut().enumerate() {
+            let offset = if idx % 2 == 0 { 0.001 } else { 500.0 };
+            sphere.translate(Vec3::xyz(offset, -offset, offset));
+        }
+        for ((&id, old), new) in ids.iter().zip(expected.iter()).zip(moved) {
+            assert_eq!(spheres.insert_or_update(id, new).as_ref(), Some(old));
+        }
+        assert_eq!(spheres.len(), 1000);
+        check_tree(&spheres);
+
+        // Removed handles come back to life, and new ones never clash.
+        let revived = || Sphere::new(Vec3::xyz(-50.0, 0.0, 0.0), 1.0).unwrap();
+        assert!(spheres.remove_by_id(ids[3]).is_some());
+        assert!(spheres.insert_or_update(ids[3], revived()).is_none());
+        assert_eq!(spheres.get(ids[3]), Some(&revived()));
+        let fresh = spheres.insert(revived());
+        assert!(!ids.contains(&fresh));
+        assert_eq!(spheres.len(), 1001);
+        check_tree(&spheres);
+    }
+
+    #[test]
+    fn test_remove_many() {
+        for &split in [SplitStrategy::Quadratic, SplitStrategy::Hilbert].iter() {
+            let config = RTreeConfig::new(8, 3).unwrap().with_split_strategy(split);
+            let mut batched: RTree<Sphere> = RTree::with_config(config);
+            let mut single: RTree<Sphere> = RTree::with_config(config);
+            let ids: Vec<_> = random_spheres(2000, 16).into_iter().map(|s| batched.insert(s)).collect();
+            for sphere in random_spheres(2000, 16) {
+                single.insert(sphere);
+            }
+            let expected = random_spheres(2000, 16);
+
+            // Every third item, plus a whole corner of the cube, so that
+            // some nodes are emptied entirely.
+            let doomed: Vec<_> = (0..2000)
+                .filter(|&idx| idx % 3 == 0 || expected[idx].mbr().max.x < 300.0)
+                .map(|idx| ids[idx])
+                .collect();
+            let mut request = doomed.clone();
+            request.push(doomed[0]);
+            assert!(batched.remove_by_id(doomed[1]).is_some());
+            let removed = batched.remove_many(&request);
+            assert_eq!(removed.len(), doomed.len() - 1);
+            for (id, sphere) in doomed.iter().filter(|&&id| id != doomed[1]).zip(removed.iter()) {
+                assert_eq!(*sphere, expected[id.0 as usize]);
+            }
+            for &id in doomed.iter() {
+                single.remove_by_id(id);
+            }
+            check_tree(&batched);
+            check_tree(&single);
+
+            // Both ways leave the same items behind.
+            assert_eq!(batched.len(), single.len());
+            for &id in ids.iter() {
+                assert_eq!(batched.get(id), single.get(id));
+            }
+            assert!(batched.remove_many(&doomed).is_empty());
+            assert_eq!(batched.remove_many(&ids).len(), single.len());
+            assert!(batched.is_empty());
+            check_tree(&batched);
+        }
+    }
+
+    #[test]
+    fn test_len_and_depth() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        assert!(spheres.is_empty());
+        assert_eq!((spheres.len(), spheres.depth()), (0, 0));
+
+        spheres.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        assert!(!spheres.is_empty());
+        assert_eq!((spheres.len(), spheres.depth()), (1, 1));
+
+        for sphere in random_spheres(2000, 8) {
+            spheres.insert(sphere);
+        }
+        assert_eq!(spheres.len(), 2001);
+        assert_eq!(spheres.depth(), check_tree(&spheres) + 1);
+
+        for sphere in random_spheres(2000, 8).iter().step_by(4) {
+            spheres.remove(sphere);
+        }
+        assert_eq!(spheres.len(), 1501);
+    }
+
+    #[test]
+    fn test_retain_and_drain() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for sphere in random_spheres(2000, 12) {
+            spheres.insert(sphere);
+        }
+
+        // Unload everything in the lower half of the cube.
+        spheres.retain(|s| s.mbr().min.x >= 500.0);
+        check_tree(&spheres);
+        let expected = random_spheres(2000, 12).into_iter().filter(|s| s.mbr().min.x >= 500.0).count();
+        assert_eq!(spheres.len(), expected);
+        assert!(spheres.iter().all(|s| s.mbr().min.x >= 500.0));
+
+        spheres.retain(|_| false);
+        assert!(spheres.is_empty());
+        check_tree(&spheres);
+
+        for sphere in random_spheres(300, 13) {
+            spheres.insert(sphere);
+        }
+        assert_eq!(spheres.drain().count(), 300);
+        assert!(spheres.is_empty());
+        assert_eq!(spheres.len(), 0);
+
+        let id = spheres.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        assert!(spheres.get(id).is_some());
+    }
+
+    #[test]
+    fn test_concurrent_readers() {
+        let spheres: RTree<Sphere> = RTree::bulk_load(random_spheres(5000, 14));
+        let queries: Vec<(Ray, BBox)> = {
+            let mut rng = Lcg::new(15);
+            let mut point = || Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 1000.0;
+            (0..200).map(|_| {
+                let ray = Ray::new(point(), point());
+                let (a, b) = (point(), point());
+                (ray, ::bbox::union_points(&a, &b))
+            }).collect()
+        };
+        let run = |tree: &RTree<Sphere>| -> Vec<(usize, usize)> {
+            queries.iter()
+                .map(|(ray, envelope)| (tree.iter_ray(ray).count(), tree.iter_bbox(envelope).count()))
+                .collect()
+        };
+        let expected = run(&spheres);
+
+        ::std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..16).map(|_| scope.spawn(|| run(&spheres))).collect();
+            for reader in readers {
+                assert_eq!(reader.join().unwrap(), expected);
             }
+        });
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Rect(Point<2>, Point<2>);
+
+    impl Mbr<2> for Rect {
+        fn mbr(&self) -> Aabb<2> {
+            Aabb { min: self.0, max: self.1 }
         }
+    }
 
-        if let Some(idx) = best_idx {
-            return Some(idx);
+    fn random_rects(count: usize, seed: u64) -> Vec<Rect> {
+        let mut rng = Lcg::new(seed);
+        (0..count).map(|_| {
+            let min = Point::xy(rng.next_f64(), rng.next_f64()).scale(1000.0);
+            let size = Point::xy(rng.next_f64(), rng.next_f64()).scale(10.0);
+            Rect(min, min + size)
+        }).collect()
+    }
+
+    #[test]
+    fn test_two_dimensions() {
+        let query = Aabb { min: Point::xy(100.0, 200.0), max: Point::xy(400.0, 500.0) };
+        let expected = random_rects(3000, 51);
+        let brute_force = expected.iter().filter(|r| r.mbr().overlaps(&query)).count();
+        assert!(brute_force > 0);
+
+        let config = RTreeConfig::with_max_entries(16).unwrap();
+        for &split in [SplitStrategy::Quadratic, SplitStrategy::Linear, SplitStrategy::RStar, SplitStrategy::Hilbert, SplitStrategy::Sah].iter() {
+            let mut rects: RTree<Rect, 2> = RTree::with_config(config.with_split_strategy(split));
+            for rect in random_rects(3000, 51) {
+                rects.insert(rect);
+            }
+            check_tree(&rects);
+            assert_eq!(rects.validate(), Ok(()));
+            assert_eq!(rects.iter_bbox(&query).count(), brute_force);
+            assert_eq!(rects.count_in_bbox(&query), brute_force);
+
+            for rect in expected.iter().step_by(2) {
+                assert_eq!(rects.remove(rect).as_ref(), Some(rect));
+            }
+            check_tree(&rects);
+            assert_eq!(rects.len(), 1500);
         }
 
-        // None of the candidates fully contained our target, so search for the
-        // node which would expand the least, if our target was added to it.
-        for (idx, child) in children.iter().enumerate() {
-            let volume = child.mbr().union(&target).volume();
-            if volume.is_nan() {
-                panic!("volume must not be NaN");
+        let packed: RTree<Rect, 2> = RTree::bulk_load_with_config(random_rects(3000, 51), config);
+        check_tree(&packed);
+        assert_eq!(packed.locate_in_envelope(&query).count(), brute_force);
+        let point = Point::xy(500.0, 500.0);
+        let mut distances: Vec<f64> = expected.iter().map(|r| r.mbr().distance_squared_to_point(&point)).collect();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let nearest: Vec<f64> = packed.nearest_n(point, 10).iter().map(|r| r.mbr().distance_squared_to_point(&point)).collect();
+        assert_eq!(nearest, &distances[..10]);
+    }
+
+    #[test]
+    fn test_many_dimensions() {
+        struct Feature(Point<5>);
+
+        impl Mbr<5> for Feature {
+            fn mbr(&self) -> Aabb<5> {
+                Aabb::from_point(self.0)
             }
-            if volume < min_volume {
-                min_volume = volume;
-                best_idx = Some(idx);
+        }
+
+        let mut rng = Lcg::new(52);
+        let features: RTree<Feature, 5> = (0..2000).map(|_| Feature(Point::from_fn(|_| rng.next_f64()))).collect();
+        check_tree(&features);
+        let center = Point::splat(0.5);
+        let within = features.iter().filter(|f| (f.0 - center).len() <= 0.3).count();
+        assert!(0 < within && within < 2000);
+        assert_eq!(features.iter_within_distance(center, 0.3).count(), within);
+    }
+
+    #[test]
+    fn test_provided_mbrs() {
+        let unit = BBox { min: Vec3::zero(), max: Vec3::one() };
+        let mut labelled: RTree<(BBox, &str)> = RTree::new();
+        labelled.insert((unit, "unit"));
+        labelled.insert((unit.expand(5.0), "padded"));
+        let far = BBox::from_point(Vec3::xyz(3.0, 3.0, 3.0));
+        let found: Vec<&str> = labelled.iter_bbox(&far).map(|&(_, label)| label).collect();
+        assert_eq!(found, vec!["padded"]);
+
+        let points: RTree<Vec3> = vec![Vec3::zero(), Vec3::one(), Vec3::xyz(2.0, 2.0, 2.0)].into_iter().collect();
+        assert_eq!(points.nearest_n(Vec3::xyz(1.9, 1.9, 1.9), 1), vec![&Vec3::xyz(2.0, 2.0, 2.0)]);
+        let arrays: RTree<[f64; 2], 2> = vec![[0.0, 0.0], [5.0, 5.0]].into_iter().collect();
+        assert_eq!(arrays.count_in_bbox(&Aabb { min: Point::xy(4.0, 4.0), max: Point::xy(6.0, 6.0) }), 1);
+        let boxes: RTree<BBox> = vec![unit, far].into_iter().collect();
+        assert!(boxes.any_in_bbox(&far));
+
+        // Items can be shared with, or borrowed from, other structures.
+        let spheres = random_spheres(100, 53);
+        let borrowed: RTree<&Sphere> = spheres.iter().collect();
+        let shared: RTree<::std::rc::Rc<Sphere>> = random_spheres(100, 53).into_iter().map(::std::rc::Rc::new).collect();
+        let boxed: RTree<Box<Sphere>> = random_spheres(100, 53).into_iter().map(Box::new).collect();
+        let query = BBox { min: Vec3::xyz(0.0, 0.0, 0.0), max: Vec3::xyz(500.0, 500.0, 500.0) };
+        let expected = spheres.iter().filter(|s| s.mbr().overlaps(&query)).count();
+        assert_eq!(borrowed.iter_bbox(&query).count(), expected);
+        assert_eq!(shared.iter_bbox(&query).count(), expected);
+        assert_eq!(boxed.iter_bbox(&query).count(), expected);
+    }
+
+    /// The Hilbert values of the items in tree order.
+    fn hilbert_values<T: Mbr>(tree: &RTree<T>) -> Vec<u64> {
+        fn walk<T: Mbr>(nodes: &Arena<T, 3>, node: NodeId, out: &mut Vec<u64>) {
+            match nodes[node].storage {
+                NodeStorage::Interior(ref children) => children.iter().for_each(|&c| walk(nodes, c, out)),
+                NodeStorage::Leaf(ref items) => out.extend(items.iter().map(util::hilbert_value)),
             }
         }
+        let mut out = Vec::new();
+        if let Some(root) = tree.root {
+            walk(&tree.nodes, root, &mut out);
+        }
+        out
+    }
 
-        if let Some(idx) = best_idx {
-            return Some(idx);
+    #[test]
+    fn test_hilbert() {
+        let config = RTreeConfig::new(8, 3).unwrap();
+        let hilbert_config = config.with_split_strategy(SplitStrategy::Hilbert);
+        let mut hilbert: RTree<Sphere> = RTree::with_config(hilbert_config);
+        let mut quadratic: RTree<Sphere> = RTree::with_config(config);
+        hilbert.extend(random_spheres(3000, 79));
+        quadratic.extend(random_spheres(3000, 79));
+        check_tree(&hilbert);
+        assert_eq!(hilbert.validate(), Ok(()));
+
+        // Items stay in Hilbert order, and sharing entries with siblings
+        // keeps the nodes fuller.
+        let values = hilbert_values(&hilbert);
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(hilbert.quality().average_leaf_fill > quadratic.quality().average_leaf_fill);
+
+        let query = BBox { min: Vec3::xyz(100.0, 200.0, 300.0), max: Vec3::xyz(400.0, 500.0, 600.0) };
+        let brute_force = random_spheres(3000, 79).iter().filter(|s| s.mbr().overlaps(&query)).count();
+        assert_eq!(hilbert.iter_bbox(&query).count(), brute_force);
+        for sphere in random_spheres(3000, 79).iter().step_by(3) {
+            assert!(hilbert.remove(sphere).is_some());
         }
+        check_tree(&hilbert);
+        assert_eq!(hilbert.len(), 2000);
+        let values = hilbert_values(&hilbert);
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
 
-        unreachable!("a node must have been selected");
+        let packed: RTree<Sphere> = RTree::bulk_load_with_config(random_spheres(3000, 79), hilbert_config);
+        check_tree(&packed);
+        assert_eq!(packed.iter_bbox(&query).count(), brute_force);
+        let values = hilbert_values(&packed);
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(packed.quality().leaf_count, 3000usize.div_ceil(8));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use ::vec3::Vec3;
-    use ::ray::Ray;
-    use super::RTree;
-    use super::test_helpers::Sphere;
+    #[test]
+    fn test_try_insert() {
+        for &split in [SplitStrategy::Quadratic, SplitStrategy::Linear, SplitStrategy::RStar].iter() {
+            let config = RTreeConfig::default().with_split_strategy(split);
+            let mut boxes: RTree<BBox> = RTree::with_config(config);
+            for i in 0..200 {
+                let min = Vec3::xyz(i as f64, (i % 13) as f64, 0.0);
+                assert!(boxes.try_insert(BBox { min, max: min + 1.0 }).is_ok());
+            }
+
+            let nan = BBox::from_point(Vec3::xyz(f64::NAN, 0.0, 0.0));
+            assert_eq!(boxes.try_insert(nan), Err(Error::NanBounds));
+            let infinite = BBox { min: Vec3::zero(), max: Vec3::xyz(f64::INFINITY, 1.0, 1.0) };
+            assert_eq!(boxes.try_insert(infinite), Err(Error::UnboundedVolume));
+            let huge = BBox { min: Vec3::splat(-1e200), max: Vec3::splat(1e200) };
+            assert_eq!(boxes.try_insert(huge), Err(Error::UnboundedVolume));
+
+            assert_eq!(boxes.len(), 200);
+            assert_eq!(boxes.validate(), Ok(()));
+            check_tree(&boxes);
+        }
+
+        // An infinite point has an undefined extent even in an empty tree.
+        let mut points: RTree<Vec3> = RTree::new();
+        assert_eq!(points.try_insert(Vec3::xyz(0.0, f64::NEG_INFINITY, 0.0)), Err(Error::UnboundedVolume));
+        assert!(points.is_empty());
+    }
 
     #[test]
-    fn test_sphere() {
-        let ray = Ray::new(Vec3::xyz(0.0, 0.0, 0.0), Vec3::xyz(1.0, 0.055, 0.00));
+    fn test_debug_clone_and_default() {
+        let mut points: RTree<Vec3> = RTree::with_config(RTreeConfig::new(4, 2).unwrap());
+        for i in 0..5 {
+            points.insert(Vec3::xyz(i as f64, 0.0, 0.0));
+        }
+        let copy = points.clone();
+        points.remove(&Vec3::zero());
+        assert_eq!((points.len(), copy.len()), (4, 5));
+        check_tree(&copy);
 
-        let mut spheres: RTree<Sphere> = RTree::new();
-        spheres.insert(Sphere::new(Vec3::xyz(100.0, 0.0, 0.0), 5.0).unwrap());
-        spheres.insert(Sphere::new(Vec3::xyz(120.0, 0.0, 0.0), 15.0).unwrap());
-        spheres.insert(Sphere::new(Vec3::xyz(140.0, 0.0, 0.0), 25.0).unwrap());
-        spheres.insert(Sphere::new(Vec3::xyz(160.0, 0.0, 0.0), 35.0).unwrap());
-        spheres.insert(Sphere::new(Vec3::xyz(180.0, 0.0, 0.0), 45.0).unwrap());
-        spheres.insert(Sphere::new(Vec3::xyz(200.0, 0.0, 0.0), 55.0).unwrap());
-        let intersect_res = spheres.intersects(&ray);
-        println!("intersect = {:?}", intersect_res);
-        panic!();
+        let outline = format!("{:?}", copy);
+        let lines: Vec<&str> = outline.lines().collect();
+        assert!(lines[0].starts_with("RTree { len: 5, depth: 2, config: RTreeConfig {"));
+        assert_eq!(lines[1], "  interior (0, 0, 0)..(4, 0, 0), 5 items");
+        assert_eq!(lines.iter().filter(|line| line.starts_with("    leaf ")).count(), 2);
+        assert!(lines.contains(&"      #4 (4, 0, 0)..(4, 0, 0): (4, 0, 0)"));
+        assert_eq!(lines.len(), 1 + 1 + 2 + 5);
+
+        let empty: RTree<Vec3> = Default::default();
+        assert!(format!("{:?}", empty).starts_with("RTree { len: 0, depth: 0, "));
+        assert!(!format!("{:?}", empty).contains('\n'));
+
+        let ray = Ray::new(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0));
+        assert_eq!(ray, Ray::new(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0)));
+        assert_ne!(ray, Ray::new(Vec3::one(), Vec3::xyz(1.0, 0.0, 0.0)));
+        assert_eq!(BBox::from_point(Vec3::one()), BBox { min: Vec3::one(), max: Vec3::one() });
+
+        // Queries print what they look for rather than where they are.
+        let query = format!("{:?}", copy.iter_ray(&ray));
+        assert!(query.starts_with("Iter { ray: Ray {") && query.ends_with(", .. }"));
+        assert_eq!(format!("{:?}", copy.iter()), "Items { remaining: 5, .. }");
+        assert!(format!("{:?}", copy.into_packed()).starts_with("PackedRTree { len: 5, "));
     }
-}
 
-#[no_mangle]
-pub extern "C" fn debugger() {}
\ No newline at end of file
+    #[test]
+    fn test_arena_reuses_slots() {
+        let mut spheres: RTree<Sphere> = RTree::with_config(RTreeConfig::new(8, 3).unwrap());
+        let items = random_spheres(2000, 61);
+        spheres.extend(random_spheres(2000, 61));
+        let slots = spheres.nodes.slots().len();
+        assert_eq!(spheres.nodes.len(), slots);
+
+        // Dissolved nodes leave free slots behind, which later splits take
+        // before the arena grows.
+        for sphere in items[..1000].iter() {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        check_tree(&spheres);
+        assert!(spheres.nodes.len() < slots);
+        spheres.extend(random_spheres(2000, 61).into_iter().take(1000));
+        check_tree(&spheres);
+        assert!(spheres.nodes.slots().len() <= slots + slots / 10);
+
+        for sphere in items.iter() {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        assert!(spheres.nodes.slots().is_empty());
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let config = RTreeConfig::new(8, 3).unwrap();
+        let mut spheres: RTree<Sphere> = RTree::with_config(config);
+        spheres.reserve(2000);
+        assert!(spheres.ids.capacity() >= 2000);
+        let reserved = spheres.nodes.capacity();
+        assert!(reserved >= config.node_estimate(2000));
+
+        spheres.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        match spheres.root_node().unwrap().storage {
+            NodeStorage::Leaf(ref items) => assert!(items.capacity() >= 8),
+            NodeStorage::Interior(_) => unreachable!(),
+        }
+        spheres.extend(random_spheres(1500, 67));
+        check_tree(&spheres);
+        assert_eq!(spheres.nodes.capacity(), reserved);
+
+        // Reserving again only asks for the nodes that are still missing.
+        spheres.reserve(499);
+        assert_eq!(spheres.nodes.capacity(), reserved);
+
+        let points: RTree<Vec3> = RTree::with_capacity(100);
+        assert!(points.is_empty());
+        assert!(points.ids.capacity() >= 100);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut spheres: RTree<Sphere> = RTree::with_config(RTreeConfig::new(8, 3).unwrap());
+        spheres.reserve(5000);
+        spheres.extend(random_spheres(3000, 71));
+        for sphere in random_spheres(3000, 71).iter().step_by(3) {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        let query = BBox { min: Vec3::xyz(100.0, 200.0, 300.0), max: Vec3::xyz(400.0, 500.0, 600.0) };
+        let found = spheres.iter_bbox(&query).count();
+        let before = spheres.heap_size_bytes();
+        assert!(spheres.nodes.len() < spheres.nodes.slots().len());
+
+        spheres.shrink_to_fit();
+        check_tree(&spheres);
+        assert!(spheres.heap_size_bytes() < before);
+        assert_eq!(spheres.nodes.slots().len(), spheres.nodes.len());
+        assert_eq!(spheres.nodes.capacity(), spheres.nodes.len());
+        for node in spheres.nodes.slots() {
+            if let NodeStorage::Leaf(ref items) = node.storage {
+                assert!(leaf_heap_capacity(items) <= items.len());
+            }
+        }
+        assert_eq!(spheres.len(), 2000);
+        assert_eq!(spheres.iter_bbox(&query).count(), found);
+
+        // The tree keeps working on the compacted arena.
+        spheres.extend(random_spheres(500, 73));
+        assert!(spheres.remove(&random_spheres(1, 71).pop().unwrap()).is_none());
+        check_tree(&spheres);
+
+        spheres.retain(|_| false);
+        spheres.shrink_to_fit();
+        assert_eq!(spheres.nodes.capacity(), 0);
+        assert_eq!(RTree::<Sphere>::new().heap_size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_small_leaves() {
+        // Small nodes keep every leaf within the inline capacity, and
+        // splits, reinsertion and removal must move entries in and out of
+        // it correctly.
+        let config = RTreeConfig::new(4, 2).unwrap().with_split_strategy(SplitStrategy::RStar);
+        let mut spheres: RTree<Sphere> = RTree::with_config(config);
+        spheres.extend(random_spheres(1000, 79));
+        check_tree(&spheres);
+        for sphere in random_spheres(1000, 79).iter().step_by(2) {
+            assert!(spheres.remove(sphere).is_some());
+        }
+        check_tree(&spheres);
+        assert_eq!(spheres.len(), 500);
+
+        let mut hilbert: RTree<Sphere> = RTree::with_config(config.with_split_strategy(SplitStrategy::Hilbert));
+        hilbert.extend(random_spheres(500, 83));
+        for sphere in random_spheres(500, 83).iter().skip(1).step_by(3) {
+            assert!(hilbert.remove(sphere).is_some());
+        }
+        check_tree(&hilbert);
+
+        // Once trimmed, leaves that fit inline need no heap memory at all.
+        spheres.shrink_to_fit();
+        hilbert.shrink_to_fit();
+        for node in spheres.nodes.slots().iter().chain(hilbert.nodes.slots()) {
+            if let NodeStorage::Leaf(ref items) = node.storage {
+                assert!(items.len() <= 4);
+                if cfg!(feature = "inline") {
+                    assert_eq!(leaf_heap_capacity(items), 0);
+                }
+            }
+        }
+    }
+}
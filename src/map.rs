@@ -0,0 +1,463 @@
+//! A map from geometry to values, stored in an `RTree` and searched by the
+//! keys' bounding boxes:
+//!
+//! ```
+//! use rtree::prelude::*;
+//! use rtree::map::RTreeMap;
+//!
+//! let mut labels = RTreeMap::new();
+//! labels.insert(Vec3::xyz(1.0, 2.0, 3.0), "a");
+//! labels.insert(Vec3::xyz(8.0, 8.0, 8.0), "b");
+//! *labels.entry(Vec3::xyz(1.0, 2.0, 3.0)).or_insert("c") = "d";
+//!
+//! let near = BBox { min: Vec3::zero(), max: Vec3::xyz(5.0, 5.0, 5.0) };
+//! let found: Vec<_> = labels.iter_bbox(&near).map(|(_, label)| *label).collect();
+//! assert_eq!(found, ["d"]);
+//! ```
+
+use std::fmt;
+use std::mem;
+
+use aabb::Aabb;
+use arena::NodeId;
+use iter_mut::IterMut;
+use items::Items;
+use super::{EnvelopeIter, ItemId, LeafItem, Mbr, RTree, RTreeConfig};
+
+const MISSING_VALUE: &str = "occupied entry has no value";
+
+/// A key and its value, as stored in the tree.
+#[derive(Debug)]
+struct Pair<G, V> {
+    key: G,
+    value: V,
+}
+
+impl<G, V, const N: usize> Mbr<N> for Pair<G, V> where G: Mbr<N> {
+    fn mbr(&self) -> Aabb<N> {
+        self.key.mbr()
+    }
+}
+
+/// An `RTree` of keys, each with a value.  The keys provide the bounding
+/// boxes and are compared with `PartialEq`, so every key is stored at most
+/// once.  Keys live in three dimensions unless `N` says otherwise, e.g.
+/// `RTreeMap<Point<2>, V, 2>` for map data.
+#[derive(Debug)]
+pub struct RTreeMap<G, V, const N: usize = 3> where G: Mbr<N> {
+    tree: RTree<Pair<G, V>, N>,
+}
+
+impl<G, V, const N: usize> RTreeMap<G, V, N> where G: Mbr<N> {
+    pub fn new() -> RTreeMap<G, V, N> {
+        RTreeMap::with_config(RTreeConfig::default())
+    }
+
+    pub fn with_config(config: RTreeConfig) -> RTreeMap<G, V, N> {
+        RTreeMap { tree: RTree::with_config(config) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Store `value` under `key`, returning the value it replaces if `key`
+    /// was already present.
+    pub fn insert(&mut self, key: G, value: V) -> Option<V> where G: PartialEq {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            },
+        }
+    }
+
+    pub fn get(&self, key: &G) -> Option<&V> where G: PartialEq {
+        self.tree.iter_bbox(&key.mbr())
+            .find(|pair| pair.key == *key)
+            .map(|pair| &pair.value)
+    }
+
+    pub fn get_mut(&mut self, key: &G) -> Option<&mut V> where G: PartialEq {
+        self.tree.iter_bbox_mut(&key.mbr())
+            .find(|pair| pair.key == *key)
+            .map(|pair| &mut pair.value)
+    }
+
+    pub fn contains_key(&self, key: &G) -> bool where G: PartialEq {
+        self.get(key).is_some()
+    }
+
+    /// Take `key` and its value out of the map.
+    pub fn remove(&mut self, key: &G) -> Option<V> where G: PartialEq {
+        let is_target = |leaf: &LeafItem<Pair<G, V>, N>| leaf.item.key == *key;
+        let removed = self.tree.remove_where(&is_target, &key.mbr(), None).ok()?;
+        self.tree.ids.remove(&removed.id);
+        Some(removed.item.value)
+    }
+
+    /// The entry for `key`, for updating its value in place or inserting
+    /// one if it is missing.  The tree is searched once, here: an occupied
+    /// entry remembers where its pair is stored.
+    pub fn entry(&mut self, key: G) -> Entry<'_, G, V, N> where G: PartialEq {
+        let is_target = |leaf: &LeafItem<Pair<G, V>, N>| leaf.item.key == key;
+        let found = match self.tree.root {
+            Some(root) if self.tree.nodes[root].bbox.contains(&key.mbr()) => self.tree.nodes.locate(root, &is_target, &key.mbr()),
+            _ => None,
+        };
+        match found {
+            Some((leaf, index)) => Entry::Occupied(OccupiedEntry { map: self, key, leaf, index }),
+            None => Entry::Vacant(VacantEntry { map: self, key }),
+        }
+    }
+
+    /// Iterate over every key and value.
+    pub fn iter(&self) -> MapIter<'_, G, V, N> {
+        MapIter { source: Source::All(self.tree.iter()) }
+    }
+
+    /// Iterate over the keys whose bounding box overlaps `query`, and their
+    /// values.
+    pub fn iter_bbox(&self, query: &Aabb<N>) -> MapIter<'_, G, V, N> {
+        MapIter { source: Source::Envelope(self.tree.iter_bbox(query)) }
+    }
+
+    /// Iterate over every key, with mutable access to the values.
+    pub fn iter_mut(&mut self) -> MapIterMut<'_, G, V, N> {
+        MapIterMut { inner: self.tree.iter_mut() }
+    }
+
+    /// Iterate over the keys whose bounding box overlaps `query`, with
+    /// mutable access to their values.
+    pub fn iter_bbox_mut(&mut self, query: &Aabb<N>) -> MapIterMut<'_, G, V, N> {
+        MapIterMut { inner: self.tree.iter_bbox_mut(query) }
+    }
+}
+
+impl<G, V, const N: usize> Default for RTreeMap<G, V, N> where G: Mbr<N> {
+    fn default() -> RTreeMap<G, V, N> {
+        RTreeMap::new()
+    }
+}
+
+/// A key of an `RTreeMap`, which may or may not have a value.  Created by
+/// `RTreeMap::entry`.
+#[derive(Debug)]
+pub enum Entry<'a, G, V, const N: usize = 3> where G: Mbr<N>+'a, V: 'a {
+    Occupied(OccupiedEntry<'a, G, V, N>),
+    Vacant(VacantEntry<'a, G, V, N>),
+}
+
+impl<'a, G, V, const N: usize> Entry<'a, G, V, N> where G: Mbr<N>+PartialEq+'a, V: 'a {
+    pub fn key(&self) -> &G {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    /// The value, after inserting `default` if there was none.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// The value, after inserting the result of `default` if there was none.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut V where F: FnOnce() -> V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V where V: Default {
+        self.or_insert_with(V::default)
+    }
+
+    /// Apply `f` to the value if there is one.
+    pub fn and_modify<F>(self, f: F) -> Entry<'a, G, V, N> where F: FnOnce(&mut V) {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            },
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+/// A key which has a value.
+pub struct OccupiedEntry<'a, G, V, const N: usize = 3> where G: Mbr<N>+'a, V: 'a {
+    map: &'a mut RTreeMap<G, V, N>,
+    key: G,
+    // Where the pair is stored, so that it need not be searched for again.
+    leaf: NodeId,
+    index: usize,
+}
+
+impl<'a, G, V, const N: usize> OccupiedEntry<'a, G, V, N> where G: Mbr<N>+PartialEq+'a, V: 'a {
+    pub fn key(&self) -> &G {
+        &self.key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.map.tree.nodes.leaf_entry(self.leaf, self.index).item.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.tree.nodes.leaf_entry_mut(self.leaf, self.index).item.value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.tree.nodes.leaf_entry_mut(self.leaf, self.index).item.value
+    }
+
+    /// Replace the value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    /// Take the key and its value out of the map.
+    pub fn remove(self) -> V {
+        let id = self.map.tree.nodes.leaf_entry(self.leaf, self.index).id;
+        self.map.tree.remove_by_id(ItemId(id)).expect(MISSING_VALUE).value
+    }
+}
+
+impl<'a, G, V, const N: usize> fmt::Debug for OccupiedEntry<'a, G, V, N> where G: Mbr<N>+fmt::Debug+'a, V: fmt::Debug+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pair = &self.map.tree.nodes.leaf_entry(self.leaf, self.index).item;
+        f.debug_struct("OccupiedEntry").field("key", &self.key).field("value", &pair.value).finish()
+    }
+}
+
+/// A key which has no value.
+pub struct VacantEntry<'a, G, V, const N: usize = 3> where G: Mbr<N>+'a, V: 'a {
+    map: &'a mut RTreeMap<G, V, N>,
+    key: G,
+}
+
+impl<'a, G, V, const N: usize> VacantEntry<'a, G, V, N> where G: Mbr<N>+PartialEq+'a, V: 'a {
+    pub fn key(&self) -> &G {
+        &self.key
+    }
+
+    pub fn into_key(self) -> G {
+        self.key
+    }
+
+    /// Store `value` under the key, returning a reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let VacantEntry { map, key } = self;
+        &mut map.tree.insert_mut(Pair { key, value }).1.value
+    }
+}
+
+impl<'a, G, V, const N: usize> fmt::Debug for VacantEntry<'a, G, V, N> where G: Mbr<N>+fmt::Debug+'a, V: 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(&self.key).finish()
+    }
+}
+
+/// The filter of a plain box query over the map's pairs.
+type AcceptPair<G, V, const N: usize> = fn(&Pair<G, V>, &Aabb<N>) -> bool;
+
+enum Source<'a, G, V, const N: usize> where G: Mbr<N>+'a, V: 'a {
+    All(Items<'a, Pair<G, V>, N>),
+    Envelope(EnvelopeIter<'a, Pair<G, V>, AcceptPair<G, V, N>, N>),
+}
+
+/// Keys and values of an `RTreeMap`.  Created by `RTreeMap::iter` and
+/// `RTreeMap::iter_bbox`.
+pub struct MapIter<'a, G, V, const N: usize = 3> where G: Mbr<N>+'a, V: 'a {
+    source: Source<'a, G, V, N>,
+}
+
+impl<'a, G, V, const N: usize> Iterator for MapIter<'a, G, V, N> where G: Mbr<N>+'a, V: 'a {
+    type Item = (&'a G, &'a V);
+
+    fn next(&mut self) -> Option<(&'a G, &'a V)> {
+        let pair = match self.source {
+            Source::All(ref mut items) => items.next(),
+            Source::Envelope(ref mut items) => items.next(),
+        };
+        pair.map(|pair| (&pair.key, &pair.value))
+    }
+}
+
+impl<'a, G, V, const N: usize> fmt::Debug for MapIter<'a, G, V, N> where G: Mbr<N>+'a, V: 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut iter = f.debug_struct("MapIter");
+        match self.source {
+            Source::All(ref items) => iter.field("items", items),
+            Source::Envelope(ref items) => iter.field("items", items),
+        };
+        iter.finish()
+    }
+}
+
+/// Keys of an `RTreeMap` with mutable access to their values.  Created by
+/// `RTreeMap::iter_mut` and `RTreeMap::iter_bbox_mut`.
+pub struct MapIterMut<'a, G, V, const N: usize = 3> where G: Mbr<N>+'a, V: 'a {
+    inner: IterMut<'a, Pair<G, V>, N>,
+}
+
+impl<'a, G, V, const N: usize> Iterator for MapIterMut<'a, G, V, N> where G: Mbr<N>+'a, V: 'a {
+    type Item = (&'a G, &'a mut V);
+
+    fn next(&mut self) -> Option<(&'a G, &'a mut V)> {
+        self.inner.next().map(|pair| (&pair.key, &mut pair.value))
+    }
+}
+
+impl<'a, G, V, const N: usize> fmt::Debug for MapIterMut<'a, G, V, N> where G: Mbr<N>+'a, V: 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MapIterMut").field("items", &self.inner).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::aabb::Aabb;
+    use ::bbox::BBox;
+    use ::point::Point;
+    use ::vec3::Vec3;
+    use super::{Entry, RTreeMap};
+    use super::super::{LeafCapacity, RTree, RTreeConfig, RebalancePolicy};
+    use super::super::test_helpers::Lcg;
+
+    #[test]
+    fn test_map() {
+        let mut map = RTreeMap::new();
+        for i in 0..100 {
+            let x = i as f64;
+            assert_eq!(map.insert(Vec3::xyz(x, x % 7.0, 0.0), i), None);
+        }
+        assert_eq!(map.insert(Vec3::xyz(3.0, 3.0, 0.0), 1000), Some(3));
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&Vec3::xyz(3.0, 3.0, 0.0)), Some(&1000));
+        assert_eq!(map.get(&Vec3::xyz(3.0, 4.0, 0.0)), None);
+
+        *map.get_mut(&Vec3::xyz(4.0, 4.0, 0.0)).unwrap() += 1;
+        assert_eq!(map.remove(&Vec3::xyz(4.0, 4.0, 0.0)), Some(5));
+        assert_eq!(map.remove(&Vec3::xyz(4.0, 4.0, 0.0)), None);
+        assert!(!map.contains_key(&Vec3::xyz(4.0, 4.0, 0.0)));
+        assert_eq!(map.len(), 99);
+
+        let query = BBox { min: Vec3::zero(), max: Vec3::xyz(9.5, 9.5, 0.0) };
+        for (_, value) in map.iter_bbox_mut(&query) {
+            *value = -*value;
+        }
+        let mut found: Vec<i32> = map.iter_bbox(&query).map(|(_, &v)| v).collect();
+        found.sort();
+        assert_eq!(found, [-1000, -9, -8, -7, -6, -5, -2, -1, 0]);
+        assert_eq!(map.iter().count(), 99);
+        assert_eq!(map.iter().map(|(key, &v)| (key.x as i32 == v.abs()) as usize).sum::<usize>(), 98);
+        assert_eq!(map.iter_mut().count(), 99);
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut counts = RTreeMap::new();
+        for i in 0..50 {
+            let cell = Vec3::xyz((i % 5) as f64, 0.0, 0.0);
+            counts.entry(cell).and_modify(|c| *c += 1).or_insert(1);
+        }
+        assert_eq!(counts.len(), 5);
+        assert!(counts.iter().all(|(_, &count)| count == 10));
+
+        *counts.entry(Vec3::xyz(9.0, 0.0, 0.0)).or_default() += 3;
+        assert_eq!(counts.get(&Vec3::xyz(9.0, 0.0, 0.0)), Some(&3));
+
+        match counts.entry(Vec3::zero()) {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.insert(20), 10);
+                assert_eq!(*entry.get(), 20);
+                assert_eq!(entry.remove(), 20);
+            },
+            Entry::Vacant(_) => panic!("the origin should have a count"),
+        }
+        match counts.entry(Vec3::zero()) {
+            Entry::Occupied(_) => panic!("the origin should have been removed"),
+            Entry::Vacant(entry) => assert_eq!(*entry.insert(7), 7),
+        }
+        assert_eq!(counts.len(), 6);
+    }
+
+    #[test]
+    fn test_map_2d() {
+        let mut towns: RTreeMap<Point<2>, &str, 2> = RTreeMap::new();
+        towns.insert(Point::xy(1.0, 1.0), "a");
+        towns.insert(Point::xy(4.0, 2.0), "b");
+        *towns.entry(Point::xy(9.0, 9.0)).or_default() = "c";
+        assert_eq!(towns.get(&Point::xy(4.0, 2.0)), Some(&"b"));
+
+        let west = Aabb { min: Point::xy(0.0, 0.0), max: Point::xy(5.0, 5.0) };
+        for (_, name) in towns.iter_bbox_mut(&west) {
+            *name = "west";
+        }
+        let mut found: Vec<_> = towns.iter_bbox(&west).map(|(key, &name)| (key.x, name)).collect();
+        found.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(found, [(1.0, "west"), (4.0, "west")]);
+        assert_eq!(towns.remove(&Point::xy(9.0, 9.0)), Some("c"));
+        assert_eq!(towns.len(), 2);
+    }
+
+    #[test]
+    fn test_leaf_capacity() {
+        // A dense cluster in a corner of a sparse map.
+        let mut rng = Lcg::new(23);
+        let points: Vec<Vec3> = (0..4000)
+            .map(|idx| {
+                let scale = if idx % 2 == 0 { 10.0 } else { 1000.0 };
+                Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * scale
+            })
+            .collect();
+        let config = RTreeConfig::new(32, 2).unwrap().with_leaf_capacity(LeafCapacity::Adaptive(4));
+        let mut map = RTreeMap::with_config(config);
+        let mut plain: RTree<Vec3> = RTree::with_config(config);
+        for (idx, point) in points.iter().enumerate() {
+            assert_eq!(map.insert(*point, idx), None);
+            plain.insert(*point);
+        }
+
+        // Keys go where a plain tree puts its items, so leaves in the
+        // cluster stay as small.
+        let largest = map.tree.nodes.slots().iter()
+            .filter(|node| node.is_leaf() && node.bbox.max.x.max(node.bbox.max.y).max(node.bbox.max.z) <= 50.0)
+            .map(|node| node.shallow_len())
+            .max();
+        assert!(largest.is_some_and(|largest| largest < 16), "{:?}", largest);
+        assert_eq!(map.tree.quality(), plain.quality());
+        assert_eq!(map.tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_rebalance_policy() {
+        // Cells of a grid, entered a row at a time.
+        let config = RTreeConfig::new(16, 4).unwrap().with_rebalance_policy(RebalancePolicy::BelowFill(70));
+        let mut map = RTreeMap::with_config(config);
+        let mut plain: RTree<Vec3> = RTree::with_config(config);
+        let mut unbalanced = RTreeMap::with_config(RTreeConfig::new(16, 4).unwrap());
+        for y in 0..60 {
+            for x in 0..60 {
+                let cell = Vec3::xyz(x as f64, y as f64, 0.0);
+                *map.entry(cell).or_insert(0) += 1;
+                plain.insert(cell);
+                unbalanced.insert(cell, 1);
+            }
+        }
+
+        // Entries are rebalanced as a plain tree's items are.
+        assert_eq!(map.tree.validate(), Ok(()));
+        let quality = map.tree.quality();
+        assert_eq!(quality, plain.quality());
+        assert!(quality.leaf_count < unbalanced.tree.quality().leaf_count);
+        assert!(quality.average_leaf_fill > 0.6);
+        assert!(map.iter().all(|(_, &count)| count == 1));
+    }
+}
@@ -0,0 +1,402 @@
+//! Loading triangle meshes from Wavefront OBJ and Stanford PLY files.
+//! Only available with the `mesh` feature.
+//!
+//! Polygons are split into fans of triangles around their first vertex, and
+//! everything but vertex positions and faces (normals, texture coordinates,
+//! materials, colours and other elements) is ignored.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use shapes::Triangle;
+use vec3::Vec3;
+use super::RTree;
+
+const BAD_NUMBER: &str = "malformed number";
+const BAD_VERTEX: &str = "vertex needs three coordinates";
+const BAD_INDEX: &str = "face refers to a missing vertex";
+const BAD_FACE: &str = "face needs at least three vertices";
+const NOT_PLY: &str = "not a ply file";
+const BAD_FORMAT: &str = "unsupported ply format";
+const BAD_HEADER: &str = "malformed ply header";
+const BAD_TYPE: &str = "unknown ply property type";
+const NO_POSITIONS: &str = "ply vertices need x, y and z properties";
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_f64(token: &str) -> io::Result<f64> {
+    token.parse().map_err(|_| invalid(BAD_NUMBER))
+}
+
+/// Split the polygon `indices` into triangles, appending them to `out`.
+fn triangulate(vertices: &[Vec3], indices: &[usize], out: &mut Vec<Triangle>) -> io::Result<()> {
+    if indices.len() < 3 {
+        return Err(invalid(BAD_FACE));
+    }
+    let vertex = |i: usize| vertices.get(indices[i]).cloned().ok_or_else(|| invalid(BAD_INDEX));
+    let first = vertex(0)?;
+    for i in 1..indices.len() - 1 {
+        out.push(Triangle::new(first, vertex(i)?, vertex(i + 1)?));
+    }
+    Ok(())
+}
+
+/// Resolve an OBJ face vertex such as `7`, `7/2`, `7//3` or `-1` to a
+/// zero-based position index.  Negative indices count back from the latest
+/// vertex.
+fn obj_index(token: &str, vertex_count: usize) -> io::Result<usize> {
+    let position = token.split('/').next().unwrap_or("");
+    let index: i64 = position.parse().map_err(|_| invalid(BAD_NUMBER))?;
+    let resolved = if index < 0 { vertex_count as i64 + index } else { index - 1 };
+    if resolved < 0 {
+        return Err(invalid(BAD_INDEX));
+    }
+    Ok(resolved as usize)
+}
+
+/// Read the faces of a Wavefront OBJ file as triangles.
+pub fn read_obj<R>(r: R) -> io::Result<Vec<Triangle>> where R: BufRead {
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut indices = Vec::new();
+    for line in r.lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut coord = || tokens.next().ok_or_else(|| invalid(BAD_VERTEX)).and_then(parse_f64);
+                vertices.push(Vec3::xyz(coord()?, coord()?, coord()?));
+            },
+            Some("f") => {
+                indices.clear();
+                for token in tokens {
+                    indices.push(obj_index(token, vertices.len())?);
+                }
+                triangulate(&vertices, &indices, &mut triangles)?;
+            },
+            _ => (),
+        }
+    }
+    Ok(triangles)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Scalar> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(invalid(BAD_TYPE)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    // The type of the length, then of the items.
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match *self {
+            Property::Scalar(ref name, _) | Property::List(ref name, _, _) => name,
+        }
+    }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// The body of a PLY file, read value by value.
+struct PlyBody<R> {
+    r: R,
+    format: PlyFormat,
+    line: Vec<String>,
+}
+
+impl<R> PlyBody<R> where R: BufRead {
+    fn read(&mut self, scalar: Scalar) -> io::Result<f64> {
+        if self.format == PlyFormat::Ascii {
+            while self.line.is_empty() {
+                let mut line = String::new();
+                if self.r.read_line(&mut line)? == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+                self.line = line.split_whitespace().rev().map(String::from).collect();
+            }
+            return parse_f64(&self.line.pop().unwrap());
+        }
+
+        let mut buf = [0; 8];
+        let bytes = &mut buf[..scalar.size()];
+        self.r.read_exact(bytes)?;
+        if self.format == PlyFormat::BinaryBigEndian {
+            bytes.reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => buf[0] as i8 as f64,
+            Scalar::U8 => buf[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+fn read_ply_header<R>(r: &mut R) -> io::Result<(PlyFormat, Vec<Element>)> where R: BufRead {
+    let mut line = String::new();
+    r.read_line(&mut line)?;
+    if line.trim_end() != "ply" {
+        return Err(invalid(NOT_PLY));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Err(invalid(BAD_HEADER));
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            ["format", kind, _] => format = Some(match *kind {
+                "ascii" => PlyFormat::Ascii,
+                "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                _ => return Err(invalid(BAD_FORMAT)),
+            }),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid(BAD_HEADER))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements.last_mut()
+                .ok_or_else(|| invalid(BAD_HEADER))?
+                .properties.push(Property::List(name.to_string(), Scalar::parse(count)?, Scalar::parse(item)?)),
+            ["property", ty, name] => elements.last_mut()
+                .ok_or_else(|| invalid(BAD_HEADER))?
+                .properties.push(Property::Scalar(name.to_string(), Scalar::parse(ty)?)),
+            _ => return Err(invalid(BAD_HEADER)),
+        }
+    }
+    Ok((format.ok_or_else(|| invalid(BAD_HEADER))?, elements))
+}
+
+/// Read the faces of a Stanford PLY file, in ASCII or either binary
+/// format, as triangles.
+pub fn read_ply<R>(mut r: R) -> io::Result<Vec<Triangle>> where R: BufRead {
+    let (format, elements) = read_ply_header(&mut r)?;
+    let mut body = PlyBody { r, format, line: Vec::new() };
+
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut indices = Vec::new();
+    let mut values = Vec::new();
+    for element in elements.iter() {
+        let position = |axis: &str| element.properties.iter().position(|p| p.name() == axis);
+        let xyz = match element.name.as_str() {
+            "vertex" => match (position("x"), position("y"), position("z")) {
+                (Some(x), Some(y), Some(z)) => Some((x, y, z)),
+                _ => return Err(invalid(NO_POSITIONS)),
+            },
+            _ => None,
+        };
+        let is_face = element.name == "face";
+
+        for _ in 0..element.count {
+            values.clear();
+            for property in element.properties.iter() {
+                match *property {
+                    Property::Scalar(_, scalar) => values.push(body.read(scalar)?),
+                    Property::List(ref name, count, item) => {
+                        let count = body.read(count)? as usize;
+                        let is_indices = is_face && (name == "vertex_indices" || name == "vertex_index");
+                        if is_indices {
+                            indices.clear();
+                        }
+                        for _ in 0..count {
+                            let value = body.read(item)?;
+                            if is_indices {
+                                indices.push(value as usize);
+                            }
+                        }
+                        if is_indices {
+                            triangulate(&vertices, &indices, &mut triangles)?;
+                        }
+                        values.push(0.0);
+                    },
+                }
+            }
+            if let Some((x, y, z)) = xyz {
+                vertices.push(Vec3::xyz(values[x], values[y], values[z]));
+            }
+        }
+    }
+    Ok(triangles)
+}
+
+fn open<P>(path: P) -> io::Result<BufReader<File>> where P: AsRef<Path> {
+    File::open(path).map(BufReader::new)
+}
+
+impl RTree<Triangle> {
+    /// Load the Wavefront OBJ file at `path` and bulk-load its triangles.
+    pub fn from_obj<P>(path: P) -> io::Result<RTree<Triangle>> where P: AsRef<Path> {
+        read_obj(open(path)?).map(RTree::bulk_load)
+    }
+
+    /// Load the Stanford PLY file at `path` and bulk-load its triangles.
+    pub fn from_ply<P>(path: P) -> io::Result<RTree<Triangle>> where P: AsRef<Path> {
+        read_ply(open(path)?).map(RTree::bulk_load)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::io::{self, Cursor};
+
+    use ::vec3::Vec3;
+    use super::super::RTree;
+    use super::{read_obj, read_ply};
+
+    const QUAD_OBJ: &str = "\
+# A unit square and a triangle
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vn 0 0 1
+f 1/1/1 2/1/1 3/1/1 4/1/1
+v 0 0 5
+f -1 1//1 2
+";
+
+    #[test]
+    fn test_read_obj() {
+        let triangles = read_obj(Cursor::new(QUAD_OBJ)).unwrap();
+        assert_eq!(triangles.len(), 3);
+        assert_eq!(triangles[0].a, Vec3::zero());
+        assert_eq!(triangles[1].b, Vec3::xyz(1.0, 1.0, 0.0));
+        assert_eq!(triangles[1].c, Vec3::xyz(0.0, 1.0, 0.0));
+        assert_eq!(triangles[2].a, Vec3::xyz(0.0, 0.0, 5.0));
+
+        let path = env::temp_dir().join(format!("rtree-mesh-{}.obj", process::id()));
+        fs::write(&path, QUAD_OBJ).unwrap();
+        let tree = RTree::from_obj(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tree.unwrap().len(), 3);
+
+        for bad in ["f 1 2 3\n", "v 0 0 0\nf 1 1\n", "v 0 x 0\n", "v 0 0\n"].iter() {
+            let err = read_obj(Cursor::new(*bad)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_read_ply_ascii() {
+        let ply = "\
+ply
+format ascii 1.0
+comment a unit square
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255
+1 0 0 255
+1 1 0 255
+0 1 0 255
+4 0 1 2 3
+";
+        let triangles = read_ply(Cursor::new(ply)).unwrap();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(triangles[1].c, Vec3::xyz(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_read_ply_binary() {
+        let header = "\
+ply
+format binary_big_endian 1.0
+element vertex 3
+property double x
+property double y
+property double z
+element face 1
+property list uchar uint vertex_indices
+property float quality
+end_header
+";
+        let mut bytes = header.as_bytes().to_vec();
+        for v in [[0.0, 0.0, 1.0], [2.0, 0.0, 1.0], [0.0, 3.0, 1.0]].iter() {
+            for coord in v.iter() {
+                bytes.extend_from_slice(&f64::to_be_bytes(*coord));
+            }
+        }
+        bytes.push(3);
+        for i in 0..3u32 {
+            bytes.extend_from_slice(&i.to_be_bytes());
+        }
+        bytes.extend_from_slice(&1.5f32.to_be_bytes());
+
+        let triangles = read_ply(Cursor::new(bytes)).unwrap();
+        assert_eq!(triangles.len(), 1);
+        assert_eq!(triangles[0].b, Vec3::xyz(2.0, 0.0, 1.0));
+        assert_eq!(triangles[0].c, Vec3::xyz(0.0, 3.0, 1.0));
+
+        assert!(read_ply(Cursor::new("obj\n")).is_err());
+        let truncated = read_ply(Cursor::new(header)).unwrap_err();
+        assert_eq!(truncated.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
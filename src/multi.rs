@@ -0,0 +1,104 @@
+use bbox::BBox;
+use ray::Ray;
+use vec3::Vec3;
+use super::{ClosestPoint, Mbr, RTree};
+
+/// A read-only view over several trees holding the same kind of item, e.g.
+/// one tree per chunk or per layer.  Queries run against every tree and
+/// their results are merged.
+#[derive(Debug)]
+pub struct MultiTree<'a, T> where T: Mbr+'a {
+    trees: Vec<&'a RTree<T>>,
+}
+
+impl<'a, T> MultiTree<'a, T> where T: Mbr+'a {
+    pub fn new(trees: Vec<&'a RTree<T>>) -> MultiTree<'a, T> {
+        MultiTree { trees }
+    }
+
+    /// Add another tree to the view.
+    pub fn push(&mut self, tree: &'a RTree<T>) {
+        self.trees.push(tree);
+    }
+
+    pub fn trees(&self) -> &[&'a RTree<T>] {
+        &self.trees
+    }
+
+    /// Ray candidates from all trees, one tree after another.
+    pub fn iter_ray(&self, ray: &'a Ray) -> impl Iterator<Item=&'a T> + '_ {
+        self.trees.iter().flat_map(move |tree| tree.iter_ray(ray))
+    }
+
+    /// Items overlapping `envelope` from all trees, one tree after another.
+    pub fn locate_in_envelope(&self, envelope: &'a BBox) -> impl Iterator<Item=&'a T> + '_ {
+        self.trees.iter().flat_map(move |tree| tree.locate_in_envelope(envelope))
+    }
+
+    /// The sum of the per-tree estimates.
+    pub fn estimate_matches(&self, query: &BBox) -> f64 {
+        self.trees.iter().map(|tree| tree.estimate_matches(query)).sum()
+    }
+}
+
+impl<'a, T> MultiTree<'a, T> where T: Mbr + ClosestPoint + 'a {
+    /// The item with the closest surface across all trees, along with the
+    /// closest point on that surface.
+    pub fn nearest_surface_point(&self, point: &Vec3) -> Option<(&'a T, Vec3)> {
+        let mut best: Option<(&'a T, Vec3, f64)> = None;
+        for tree in self.trees.iter() {
+            if let Some((item, surface)) = tree.nearest_surface_point(point) {
+                let delta = surface - *point;
+                let distance_squared = delta.dot(&delta);
+                match best {
+                    Some((_, _, best_distance)) if best_distance <= distance_squared => (),
+                    _ => best = Some((item, surface, distance_squared)),
+                }
+            }
+        }
+        best.map(|(item, surface, _)| (item, surface))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::super::RTree;
+    use super::super::test_helpers::Sphere;
+    use super::MultiTree;
+
+    fn row(y: f64) -> RTree<Sphere> {
+        let mut spheres = RTree::new();
+        for i in 0..4 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, y, 0.0), 1.0).unwrap());
+        }
+        spheres
+    }
+
+    #[test]
+    fn test_multi_tree_queries() {
+        let near = row(0.0);
+        let far = row(50.0);
+        let multi = MultiTree::new(vec![&near, &far]);
+
+        let ray = Ray::new(Vec3::xyz(-10.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0));
+        assert_eq!(multi.iter_ray(&ray).count(), 4);
+
+        let envelope = BBox {
+            min: Vec3::xyz(-5.0, -5.0, -5.0),
+            max: Vec3::xyz(5.0, 55.0, 5.0),
+        };
+        assert_eq!(multi.locate_in_envelope(&envelope).count(), 2);
+        assert_eq!(multi.estimate_matches(&envelope), 2.0 * near.estimate_matches(&envelope));
+
+        let (_, surface) = multi.nearest_surface_point(&Vec3::xyz(30.0, 45.0, 0.0)).unwrap();
+        assert_eq!(surface, Vec3::xyz(30.0, 49.0, 0.0));
+
+        assert!(MultiTree::<Sphere>::new(Vec::new())
+            .nearest_surface_point(&Vec3::zero())
+            .is_none());
+    }
+}
@@ -0,0 +1,330 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::slice::Iter as SliceIter;
+
+use aabb::Aabb;
+use point::Point;
+use arena::Arena;
+use vec3::Vec3;
+use super::{Mbr, RTree, RTreeNode, NodeStorage, LeafItem};
+
+/// Geometry which can report the point on its surface closest to a query
+/// point.  The returned point must lie within the item's `mbr()`.
+pub trait ClosestPoint<const N: usize = 3> {
+    fn closest_point(&self, point: &Point<N>) -> Point<N>;
+}
+
+enum Candidate<'a, T, const N: usize> where T: Mbr<N>+'a {
+    Node(&'a RTreeNode<T, N>),
+    Item(&'a T, Point<N>),
+    // An item ranked by the distance to its bounding box.
+    Boxed(&'a T),
+}
+
+struct Entry<'a, T, const N: usize> where T: Mbr<N>+'a {
+    distance_squared: f64,
+    candidate: Candidate<'a, T, N>,
+}
+
+impl<'a, T, const N: usize> PartialEq for Entry<'a, T, N> where T: Mbr<N>+'a {
+    fn eq(&self, other: &Entry<'a, T, N>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a, T, const N: usize> Eq for Entry<'a, T, N> where T: Mbr<N>+'a {}
+
+impl<'a, T, const N: usize> PartialOrd for Entry<'a, T, N> where T: Mbr<N>+'a {
+    fn partial_cmp(&self, other: &Entry<'a, T, N>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T, const N: usize> Ord for Entry<'a, T, N> where T: Mbr<N>+'a {
+    // Reversed, so that the BinaryHeap pops the closest entry first.
+    fn cmp(&self, other: &Entry<'a, T, N>) -> Ordering {
+        other.distance_squared.partial_cmp(&self.distance_squared)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> + ClosestPoint<N> {
+    /// Find the item whose surface is closest to `point`, returning it along
+    /// with the closest point on its surface.
+    ///
+    /// Nodes are visited best-first by their bounding box distance, so only
+    /// items whose boxes could beat the best surface found so far are
+    /// evaluated.
+    pub fn nearest_surface_point(&self, point: &Point<N>) -> Option<(&T, Point<N>)> {
+        let mut heap = BinaryHeap::new();
+        if let Some(root) = self.root_node() {
+            heap.push(Entry {
+                distance_squared: root.bbox.distance_squared_to_point(point),
+                candidate: Candidate::Node(root),
+            });
+        }
+
+        while let Some(entry) = heap.pop() {
+            match entry.candidate {
+                Candidate::Item(item, surface) => return Some((item, surface)),
+                Candidate::Boxed(_) => unreachable!(),
+                Candidate::Node(node) => match node.storage {
+                    NodeStorage::Interior(ref children) => {
+                        for child in self.nodes.children(children) {
+                            heap.push(Entry {
+                                distance_squared: child.bbox.distance_squared_to_point(point),
+                                candidate: Candidate::Node(child),
+                            });
+                        }
+                    },
+                    NodeStorage::Leaf(ref items) => {
+                        for leaf in items.iter() {
+                            let surface = leaf.item.closest_point(point);
+                            let delta = surface - *point;
+                            heap.push(Entry {
+                                distance_squared: delta.dot(&delta),
+                                candidate: Candidate::Item(&leaf.item, surface),
+                            });
+                        }
+                    },
+                },
+            }
+        }
+
+        None
+    }
+}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    /// The `k` items whose bounding boxes are closest to `point`, nearest
+    /// first.  Items containing the point are at distance zero.
+    ///
+    /// Nodes and items share one priority queue ordered by bounding box
+    /// distance, so the search stops as soon as `k` items have been popped.
+    pub fn nearest_n(&self, point: Point<N>, k: usize) -> Vec<&T> {
+        let mut found = Vec::with_capacity(k);
+        if 0 < k {
+            let _ = self.visit_nearest(point, |item| {
+                found.push(item);
+                if found.len() == k { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+            });
+        }
+        found
+    }
+
+    /// Call `f` on items in order of their bounding box distance from
+    /// `point`, nearest first, until it returns `ControlFlow::Break`.
+    /// Returns `Break` if `f` did, or `Continue` once every item has been
+    /// visited.
+    pub fn visit_nearest<'a, F>(&'a self, point: Point<N>, mut f: F) -> ControlFlow<()>
+        where
+            F: FnMut(&'a T) -> ControlFlow<()> {
+
+        let mut heap = BinaryHeap::new();
+        if let Some(root) = self.root_node() {
+            heap.push(Entry {
+                distance_squared: root.bbox.distance_squared_to_point(&point),
+                candidate: Candidate::Node(root),
+            });
+        }
+
+        while let Some(entry) = heap.pop() {
+            match entry.candidate {
+                Candidate::Boxed(item) => f(item)?,
+                Candidate::Item(..) => unreachable!(),
+                Candidate::Node(node) => match node.storage {
+                    NodeStorage::Interior(ref children) => {
+                        for child in self.nodes.children(children) {
+                            heap.push(Entry {
+                                distance_squared: child.bbox.distance_squared_to_point(&point),
+                                candidate: Candidate::Node(child),
+                            });
+                        }
+                    },
+                    NodeStorage::Leaf(ref items) => {
+                        for leaf in items.iter() {
+                            heap.push(Entry {
+                                distance_squared: leaf.bbox.distance_squared_to_point(&point),
+                                candidate: Candidate::Boxed(&leaf.item),
+                            });
+                        }
+                    },
+                },
+            }
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Iterate over all items whose bounding box comes within `radius` of
+    /// `center`, in no particular order.  A negative radius finds nothing.
+    pub fn iter_within_distance(&self, center: Point<N>, radius: f64) -> WithinDistanceIter<'_, T, fn(&T, &Point<N>) -> f64, N> {
+        WithinDistanceIter::new(self, center, radius, zero_distance)
+    }
+
+    /// Like `iter_within_distance`, but every candidate is passed to
+    /// `distance` together with `center`, and only yielded if the distance
+    /// it returns is at most `radius`.  This gives exact results for items
+    /// whose geometry is smaller than their MBR.
+    pub fn iter_within_distance_with<F>(&self, center: Point<N>, radius: f64, distance: F) -> WithinDistanceIter<'_, T, F, N>
+        where
+            F: FnMut(&T, &Point<N>) -> f64 {
+
+        WithinDistanceIter::new(self, center, radius, distance)
+    }
+}
+
+// Leaves the bounding box test to decide.
+fn zero_distance<T, const N: usize>(_: &T, _: &Point<N>) -> f64 {
+    0.0
+}
+
+/// A distance query.  Created by `RTree::iter_within_distance` and
+/// `RTree::iter_within_distance_with`.
+pub struct WithinDistanceIter<'a, T, F = fn(&T, &Vec3) -> f64, const N: usize = 3> where T: Mbr<N>+'a {
+    nodes: &'a Arena<T, N>,
+    stack: Vec<&'a RTreeNode<T, N>>,
+    leaf_iter: Option<SliceIter<'a, LeafItem<T, N>>>,
+    center: Point<N>,
+    radius: f64,
+    distance: F,
+}
+
+impl<'a, T, F, const N: usize> WithinDistanceIter<'a, T, F, N> where T: Mbr<N>+'a {
+    fn new(rtree: &'a RTree<T, N>, center: Point<N>, radius: f64, distance: F) -> WithinDistanceIter<'a, T, F, N> {
+        let mut stack = Vec::new();
+        if let Some(root) = rtree.root_node() {
+            if 0.0 <= radius {
+                stack.push(root);
+            }
+        }
+        WithinDistanceIter {
+            nodes: &rtree.nodes,
+            stack,
+            leaf_iter: None,
+            center,
+            radius,
+            distance,
+        }
+    }
+}
+
+impl<'a, T, F, const N: usize> fmt::Debug for WithinDistanceIter<'a, T, F, N> where T: Mbr<N>+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WithinDistanceIter").field("center", &self.center).field("radius", &self.radius).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T, F, const N: usize> Iterator for WithinDistanceIter<'a, T, F, N>
+    where
+        T: Mbr<N>+'a,
+        F: FnMut(&T, &Point<N>) -> f64 {
+
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let (center, radius) = (self.center, self.radius);
+        let in_reach = |bbox: &Aabb<N>| bbox.distance_squared_to_point(&center) <= radius * radius;
+        loop {
+            let distance = &mut self.distance;
+            if let Some(leaf_iter) = self.leaf_iter.as_mut() {
+                if let Some(val) = leaf_iter.find(|x| in_reach(&x.bbox) && distance(&x.item, &center) <= radius) {
+                    return Some(&val.item);
+                }
+            }
+
+            match self.stack.pop()?.storage {
+                NodeStorage::Interior(ref children) => {
+                    for child in self.nodes.children(children) {
+                        if in_reach(&child.bbox) {
+                            self.stack.push(child);
+                        }
+                    }
+                }
+                NodeStorage::Leaf(ref items) => {
+                    self.leaf_iter = Some(items.iter())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::vec3::Vec3;
+    use super::ClosestPoint;
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_nearest_surface_point() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        assert!(spheres.nearest_surface_point(&Vec3::zero()).is_none());
+
+        spheres.insert(Sphere::new(Vec3::xyz(10.0, 0.0, 0.0), 1.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(0.0, 20.0, 0.0), 15.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(-30.0, 0.0, 0.0), 2.0).unwrap());
+
+        // The large sphere's surface is closer than the small one's, even
+        // though its centre is further away.
+        let (_, surface) = spheres.nearest_surface_point(&Vec3::zero()).unwrap();
+        assert_eq!(surface, Vec3::xyz(0.0, 5.0, 0.0));
+
+        let (_, surface) = spheres.nearest_surface_point(&Vec3::xyz(13.0, 0.0, 0.0)).unwrap();
+        assert_eq!(surface, Vec3::xyz(11.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_nearest_n() {
+        let spheres = random_spheres(2000, 7);
+        let point = Vec3::xyz(500.0, 500.0, 500.0);
+
+        let mut expected: Vec<f64> = spheres.iter()
+            .map(|s| s.mbr().distance_squared_to_point(&point))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let mut tree = RTree::new();
+        for sphere in spheres {
+            tree.insert(sphere);
+        }
+
+        assert!(tree.nearest_n(point, 0).is_empty());
+        let found: Vec<f64> = tree.nearest_n(point, 10).iter()
+            .map(|s| s.mbr().distance_squared_to_point(&point))
+            .collect();
+        assert_eq!(found, &expected[..10]);
+        assert_eq!(tree.nearest_n(point, 5000).len(), 2000);
+    }
+
+    #[test]
+    fn test_iter_within_distance() {
+        let spheres = random_spheres(3000, 23);
+        let (center, radius) = (Vec3::xyz(400.0, 600.0, 500.0), 120.0);
+        let surface_distance = |s: &Sphere, p: &Vec3| (s.closest_point(p) - *p).len();
+        let boxed = spheres.iter()
+            .filter(|s| s.mbr().distance_squared_to_point(&center) <= radius * radius)
+            .count();
+        let exact = spheres.iter()
+            .filter(|s| surface_distance(s, &center) <= radius)
+            .count();
+        assert!(0 < exact && exact <= boxed);
+
+        let tree: RTree<Sphere> = RTree::bulk_load(spheres);
+        assert_eq!(tree.iter_within_distance(center, radius).count(), boxed);
+        assert_eq!(tree.iter_within_distance_with(center, radius, surface_distance).count(), exact);
+        for sphere in tree.iter_within_distance_with(center, radius, surface_distance) {
+            assert!(surface_distance(sphere, &center) <= radius);
+        }
+        assert_eq!(tree.iter_within_distance(center, -1.0).count(), 0);
+        assert_eq!(RTree::<Sphere>::new().iter_within_distance(center, radius).count(), 0);
+
+        // The box's corner is in reach, but the sphere itself is not.
+        let mut corner = RTree::new();
+        corner.insert(Sphere::new(Vec3::xyz(10.0, 10.0, 10.0), 5.0).unwrap());
+        assert_eq!(corner.iter_within_distance(Vec3::zero(), 10.0).count(), 1);
+        assert_eq!(corner.iter_within_distance_with(Vec3::zero(), 10.0, surface_distance).count(), 0);
+    }
+}
@@ -0,0 +1,180 @@
+use arena::Arena;
+use ray::Ray;
+use super::{Mbr, RTree, RTreeNode, NodeStorage, util};
+
+/// A fixed-length vector of bits, one per ray in a batch query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitVec {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitVec {
+    /// A vector of `len` cleared bits.
+    pub fn new(len: usize) -> BitVec {
+        BitVec {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, idx: usize) -> bool {
+        assert!(idx < self.len, "bit index out of range");
+        self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    pub fn set(&mut self, idx: usize) {
+        assert!(idx < self.len, "bit index out of range");
+        self.words[idx / 64] |= 1 << (idx % 64);
+    }
+
+    /// The number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// The underlying words, least significant bit first.
+    pub fn as_words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=bool> + '_ {
+        (0..self.len).map(move |idx| self.get(idx))
+    }
+}
+
+/// Test the rays whose indices are in `active` against the items below
+/// `node`.  Rather than copying the rays which reach each node, `active` is
+/// reordered in place so that they come first, and children are given that
+/// front part, so a whole traversal shares the caller's one buffer.
+fn occlude_node<T, F>(nodes: &Arena<T, 3>, node: &RTreeNode<T>, active: &mut [usize], rays: &[Ray], occluded: &mut BitVec, occludes: &mut F)
+    where
+        T: Mbr,
+        F: FnMut(&T, &Ray) -> bool {
+
+    // Drop rays which already found an occluder, or which miss this node.
+    let live = util::partition(active, |&r| !occluded.get(r) && node.bbox.intersects(&rays[r]));
+    if live == 0 {
+        return;
+    }
+    let active = &mut active[..live];
+
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            for child in nodes.children(children) {
+                occlude_node(nodes, child, active, rays, occluded, occludes);
+            }
+        },
+        NodeStorage::Leaf(ref items) => {
+            for &r in active.iter() {
+                let ray = &rays[r];
+                if items.iter().any(|i| i.bbox.intersects(ray) && occludes(&i.item, ray)) {
+                    occluded.set(r);
+                }
+            }
+        },
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Answer a batch of shadow or visibility rays, returning a bit per ray
+    /// which is set if any item occludes it.
+    ///
+    /// `occludes` is the exact test, and is only called for items whose
+    /// bounding box the ray hits; it is responsible for any distance limit,
+    /// e.g. ignoring hits beyond the light.  The rays are traversed as a
+    /// packet, and each ray drops out of the traversal as soon as an
+    /// occluder is found.
+    pub fn occlusion_batch<F>(&self, rays: &[Ray], mut occludes: F) -> BitVec
+        where
+            F: FnMut(&T, &Ray) -> bool {
+
+        let mut occluded = BitVec::new(rays.len());
+        if let Some(root) = self.root_node() {
+            let mut active: Vec<usize> = (0..rays.len()).collect();
+            occlude_node(&self.nodes, root, &mut active, rays, &mut occluded, &mut occludes);
+        }
+        occluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::hit::Intersectable;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+    use super::BitVec;
+
+    #[test]
+    fn test_bitvec() {
+        let mut bits = BitVec::new(70);
+        assert_eq!(bits.len(), 70);
+        assert_eq!(bits.as_words().len(), 2);
+        bits.set(3);
+        bits.set(65);
+        assert!(bits.get(3) && bits.get(65) && !bits.get(4));
+        assert_eq!(bits.count_ones(), 2);
+        assert_eq!(bits.iter().filter(|&b| b).count(), 2);
+        assert!(BitVec::new(0).is_empty());
+    }
+
+    #[test]
+    fn test_occlusion_batch() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        spheres.insert(Sphere::new(Vec3::xyz(10.0, 0.0, 0.0), 1.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(20.0, 0.0, 0.0), 1.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(10.0, 10.0, 0.0), 1.0).unwrap());
+
+        let rays = [
+            Ray::new(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0)),
+            Ray::new(Vec3::zero(), Vec3::xyz(-1.0, 0.0, 0.0)),
+            Ray::new(Vec3::zero(), Vec3::xyz(1.0, 0.5, 0.0)),
+            Ray::new(Vec3::xyz(0.0, 10.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0)),
+        ];
+
+        let mut tests = 0;
+        let occluded = spheres.occlusion_batch(&rays, |_, _| {
+            tests += 1;
+            true
+        });
+        assert_eq!(occluded.iter().collect::<Vec<_>>(), vec![true, false, false, true]);
+        // Each occluded ray stops after its first occluder.
+        assert_eq!(tests, 2);
+
+        let occluded = spheres.occlusion_batch(&rays, |_, _| false);
+        assert_eq!(occluded.count_ones(), 0);
+    }
+
+    #[test]
+    fn test_occlusion_batch_matches_brute_force() {
+        let spheres = random_spheres(2000, 61);
+        let mut tree: RTree<Sphere> = RTree::new();
+        tree.extend(random_spheres(2000, 61));
+
+        // Shadow rays from points in the cube towards a light, cut off at
+        // the light, so some are blocked and some are not.
+        let light = Vec3::xyz(500.0, 500.0, 2000.0);
+        let rays: Vec<Ray> = random_spheres(300, 62).iter()
+            .map(|s| {
+                let origin = s.mbr().center();
+                Ray::with_max_t(origin, light - origin, 1.0)
+            })
+            .collect();
+        let blocks = |sphere: &Sphere, ray: &Ray| sphere.intersect(ray).is_some_and(|hit| ray.in_range(hit.t));
+        let occluded = tree.occlusion_batch(&rays, blocks);
+        for (idx, ray) in rays.iter().enumerate() {
+            assert_eq!(occluded.get(idx), spheres.iter().any(|s| blocks(s, ray)), "ray {}", idx);
+        }
+        assert!(occluded.count_ones() > 0 && occluded.count_ones() < rays.len());
+    }
+}
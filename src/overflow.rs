@@ -0,0 +1,252 @@
+use bbox::BBox;
+use hit::{Intersectable, Intersection};
+use ray::Ray;
+use super::{ItemId, LeafItem, Mbr, RTree, RTreeConfig};
+
+const FRACTION_OUT_OF_RANGE: &str = "oversized fraction must be positive";
+
+/// A tree which keeps items too large for it out of its nodes, such as an
+/// infinite ground plane.  Inserted into an `RTree`, one such item would
+/// stretch the box of every node on its path to cover the whole scene, and
+/// no query could prune those nodes again.  Here an item whose bounding
+/// box has more than a set fraction of the surface area of the root's, or
+/// an infinite one, is kept in a list beside the tree instead, which every
+/// query scans in full.  This is simpler than the cutting `ClippedRTree`
+/// does, and as good while there are only a few such items.
+///
+/// Items are measured against the tree as it is when they are inserted,
+/// so insert the small items first.  While the tree still fits in a single
+/// leaf there is too little of it to measure against, and only items with
+/// infinite bounds are kept aside.
+#[derive(Debug)]
+pub struct OverflowRTree<T> where T: Mbr {
+    tree: RTree<T>,
+    oversized: Vec<LeafItem<T>>,
+    fraction: f64,
+}
+
+impl<T> OverflowRTree<T> where T: Mbr {
+    /// An empty tree keeping aside items with more than `fraction` times
+    /// the surface area of the root's bounding box.
+    pub fn new(fraction: f64) -> Result<OverflowRTree<T>, &'static str> {
+        OverflowRTree::with_config(RTreeConfig::default(), fraction)
+    }
+
+    /// Like `new`, with nodes following `config`.
+    pub fn with_config(config: RTreeConfig, fraction: f64) -> Result<OverflowRTree<T>, &'static str> {
+        if fraction.is_nan() || fraction <= 0.0 {
+            return Err(FRACTION_OUT_OF_RANGE);
+        }
+        Ok(OverflowRTree {
+            tree: RTree::with_config(config),
+            oversized: Vec::new(),
+            fraction,
+        })
+    }
+
+    /// The tree of all items but the oversized ones, for the queries this
+    /// type doesn't offer itself.
+    pub fn tree(&self) -> &RTree<T> {
+        &self.tree
+    }
+
+    /// The items kept beside the tree.
+    pub fn oversized(&self) -> impl Iterator<Item=&T> + '_ {
+        self.oversized.iter().map(|leaf| &leaf.item)
+    }
+
+    /// The number of items, oversized ones included.
+    pub fn len(&self) -> usize {
+        self.tree.len() + self.oversized.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty() && self.oversized.is_empty()
+    }
+
+    /// Add `item` to the tree, or beside it if it is oversized, returning
+    /// a handle to it.
+    pub fn insert(&mut self, item: T) -> ItemId {
+        let area = item.mbr().surface_area();
+        let oversized = !area.is_finite() || self.tree.root_node().is_some_and(|root| {
+            !root.is_leaf() && self.fraction * root.bbox.surface_area() < area
+        });
+        if !oversized {
+            return self.tree.insert(item);
+        }
+        // Ids come from the tree, so they never clash.
+        let leaf = LeafItem::new(item, self.tree.next_id);
+        self.tree.next_id += 1;
+        self.oversized.push(leaf);
+        ItemId(self.tree.next_id - 1)
+    }
+
+    /// Remove the item `id` refers to.
+    pub fn remove(&mut self, id: ItemId) -> Option<T> {
+        match self.oversized.iter().position(|leaf| leaf.id == id.0) {
+            Some(at) => Some(self.oversized.swap_remove(at).item),
+            None => self.tree.remove_by_id(id),
+        }
+    }
+
+    /// The item `id` refers to, or None if it has been removed.
+    pub fn get(&self, id: ItemId) -> Option<&T> {
+        match self.oversized.iter().find(|leaf| leaf.id == id.0) {
+            Some(leaf) => Some(&leaf.item),
+            None => self.tree.get(id),
+        }
+    }
+
+    /// Every item: the oversized ones, then those in the tree.
+    pub fn iter(&self) -> impl Iterator<Item=&T> + '_ {
+        self.oversized().chain(self.tree.iter())
+    }
+
+    /// The items whose bounding box overlaps `query`.
+    pub fn iter_bbox(&self, query: &BBox) -> impl Iterator<Item=&T> + '_ {
+        let query = *query;
+        self.oversized.iter()
+            .filter(move |leaf| leaf.bbox.overlaps(&query))
+            .map(|leaf| &leaf.item)
+            .chain(self.tree.iter_bbox(&query))
+    }
+
+    /// The items whose bounding box intersects `ray`.
+    pub fn iter_ray<'a>(&'a self, ray: &'a Ray) -> impl Iterator<Item=&'a T> + 'a {
+        self.oversized.iter()
+            .filter(move |leaf| leaf.bbox.intersects(ray))
+            .map(|leaf| &leaf.item)
+            .chain(self.tree.iter_ray(ray))
+    }
+
+    /// Like `RTree::closest_hit_with`.  The oversized items are tested
+    /// first, so that a hit among them cuts the traversal of the tree
+    /// short.
+    pub fn closest_hit_with<F>(&self, ray: &Ray, intersect: F) -> Option<(f64, &T)>
+        where
+            F: Fn(&T, &Ray) -> Option<f64> {
+
+        self.closest_by(ray, |item, ray| intersect(item, ray).map(|t| (t, ())), |ray| {
+            self.tree.closest_hit_with(ray, &intersect).map(|(t, item)| (t, item, ()))
+        }).map(|(t, item, ())| (t, item))
+    }
+
+    /// The nearest hit among the oversized items according to `hit`, or
+    /// the one `in_tree` finds along the ray cut off at it, if nearer.
+    fn closest_by<'a, F, G, H>(&'a self, ray: &Ray, hit: F, in_tree: G) -> Option<(f64, &'a T, H)>
+        where
+            F: Fn(&T, &Ray) -> Option<(f64, H)>,
+            G: FnOnce(&Ray) -> Option<(f64, &'a T, H)> {
+
+        let mut best: Option<(f64, &T, H)> = None;
+        for leaf in self.oversized.iter().filter(|leaf| leaf.bbox.intersects(ray)) {
+            if let Some((t, found)) = hit(&leaf.item, ray).filter(|&(t, _)| ray.in_range(t)) {
+                if best.as_ref().is_none_or(|&(best_t, _, _)| t < best_t) {
+                    best = Some((t, &leaf.item, found));
+                }
+            }
+        }
+        let cut = Ray { max_t: best.as_ref().map_or(ray.max_t, |&(t, _, _)| t), ..*ray };
+        in_tree(&cut).or(best)
+    }
+}
+
+impl<T> OverflowRTree<T> where T: Mbr + Intersectable {
+    /// Like `RTree::closest_hit`, oversized items included.
+    pub fn closest_hit(&self, ray: &Ray) -> Option<(f64, &T, Intersection)> {
+        self.closest_by(ray, |item, ray| item.intersect(ray).map(|hit| (hit.t, hit)), |ray| self.tree.closest_hit(ray))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::OverflowRTree;
+    use super::super::{Intersectable, Mbr};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_overflow() {
+        assert!(OverflowRTree::<BBox>::new(0.0).is_err());
+        assert!(OverflowRTree::<BBox>::new(f64::NAN).is_err());
+
+        // An infinite ground plane is kept aside even in an empty tree, a
+        // box around the whole scene only once there is a tree to compare.
+        let ground = BBox {
+            min: Vec3::xyz(f64::NEG_INFINITY, -1.0, f64::NEG_INFINITY),
+            max: Vec3::xyz(f64::INFINITY, 0.0, f64::INFINITY),
+        };
+        let scene = BBox { min: Vec3::zero(), max: Vec3::xyz(1000.0, 1000.0, 1000.0) };
+        let mut boxes = OverflowRTree::new(0.5).unwrap();
+        let ground_id = boxes.insert(ground);
+        let mut all = vec![ground];
+        for sphere in random_spheres(1000, 23) {
+            boxes.insert(sphere.mbr());
+            all.push(sphere.mbr());
+        }
+        let scene_id = boxes.insert(scene);
+        all.push(scene);
+        assert_eq!(boxes.len(), all.len());
+        assert_eq!(boxes.oversized().collect::<Vec<_>>(), [&ground, &scene]);
+        assert_eq!(boxes.tree().len(), 1000);
+        assert!(boxes.tree().root_node().unwrap().bbox.surface_area().is_finite());
+
+        // Queries see the oversized items alongside the tree's.
+        let query = BBox { min: Vec3::xyz(100.0, -5.0, 300.0), max: Vec3::xyz(300.0, 200.0, 500.0) };
+        let expected = all.iter().filter(|b| b.overlaps(&query)).count();
+        assert_eq!(boxes.iter_bbox(&query).count(), expected);
+        assert!(boxes.iter_bbox(&query).any(|b| *b == ground));
+        let ray = Ray::new(Vec3::xyz(-10.0, 500.0, 500.0), Vec3::xyz(1.0, 0.1, 0.05));
+        assert_eq!(boxes.iter_ray(&ray).count(), all.iter().filter(|b| b.intersects(&ray)).count());
+        assert_eq!(boxes.iter().count(), all.len());
+
+        // Rays looking down hit the nearest box, or the ground.
+        let entry = |bbox: &BBox, ray: &Ray| bbox.intersect_ray_t(ray).map(|(t, _)| t).filter(|&t| t > 0.0);
+        for sphere in random_spheres(30, 24) {
+            let origin = Vec3::xyz(500.0, 2000.0, 500.0);
+            let ray = Ray::new(origin, sphere.mbr().center() - origin);
+            let nearest = all.iter().filter(|&&b| b != scene).filter_map(|b| entry(b, &ray)).fold(f64::INFINITY, f64::min);
+            let without_scene = |bbox: &BBox, ray: &Ray| entry(bbox, ray).filter(|_| *bbox != scene);
+            assert_eq!(boxes.closest_hit_with(&ray, without_scene).map(|(t, _)| t), Some(nearest));
+        }
+
+        assert_eq!(boxes.get(scene_id), Some(&scene));
+        assert_eq!(boxes.remove(ground_id), Some(ground));
+        assert!(boxes.get(ground_id).is_none());
+        assert!(boxes.remove(ground_id).is_none());
+        assert_eq!(boxes.len(), all.len() - 1);
+    }
+
+    #[test]
+    fn test_overflow_closest_hit() {
+        // A sphere under the whole scene, hit wherever nothing else is.
+        let floor = Sphere::new(Vec3::xyz(500.0, -1e6, 500.0), 1e6).unwrap();
+        let mut spheres = OverflowRTree::new(0.5).unwrap();
+        for sphere in random_spheres(1000, 25) {
+            spheres.insert(sphere);
+        }
+        spheres.insert(Sphere::new(Vec3::xyz(500.0, -1e6, 500.0), 1e6).unwrap());
+        assert_eq!(spheres.oversized().count(), 1);
+
+        let origin = Vec3::xyz(500.0, 2000.0, 500.0);
+        let mut floor_hits = 0;
+        // Half the rays aim at spheres, half at random points.
+        let targets = random_spheres(20, 25).into_iter().chain(random_spheres(20, 26));
+        for target in targets {
+            let ray = Ray::new(origin, target.mbr().center() - origin);
+            let expected = random_spheres(1000, 25).iter()
+                .chain(Some(&floor))
+                .filter_map(|s| s.intersect(&ray))
+                .map(|hit| hit.t)
+                .fold(None, |best: Option<f64>, t| Some(best.map_or(t, |b| b.min(t))));
+            let found = spheres.closest_hit(&ray);
+            assert_eq!(found.map(|(t, _, _)| t), expected);
+            floor_hits += found.is_some_and(|(_, sphere, _)| *sphere == floor) as usize;
+        }
+        assert!(floor_hits > 0 && floor_hits < 40);
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::slice::Iter as SliceIter;
+
+use bbox::BBox;
+use hit::{Intersectable, Intersection};
+use iter_mut::Query;
+use ray::Ray;
+use slab::{BoxGroup, CompactBoxGroup, LANES};
+use super::{Mbr, RTree, NodeStorage};
+
+/// A node of a packed tree.  Its children are `nodes[start..end]`, or for
+/// leaves `items[start..end]`, and their boxes are also packed four to a
+/// group from `group` on, in `leaf_groups` for leaves and in `node_groups`
+/// otherwise.
+#[derive(Clone, Copy)]
+struct PackedNode {
+    start: usize,
+    end: usize,
+    group: usize,
+    leaf: bool,
+}
+
+/// The boxes of the children of interior nodes, at full precision or, in
+/// a tree made by `RTree::into_packed_compact`, rounded outward to `f32`.
+enum NodeGroups {
+    Full(Vec<BoxGroup>),
+    Compact(Vec<CompactBoxGroup>),
+}
+
+impl NodeGroups {
+    fn len(&self) -> usize {
+        match *self {
+            NodeGroups::Full(ref groups) => groups.len(),
+            NodeGroups::Compact(ref groups) => groups.len(),
+        }
+    }
+
+    fn pack(&mut self, boxes: &[BBox]) {
+        match *self {
+            NodeGroups::Full(ref mut groups) => BoxGroup::pack(boxes, groups),
+            NodeGroups::Compact(ref mut groups) => CompactBoxGroup::pack(boxes, groups),
+        }
+    }
+
+    fn intersect_ray(&self, group: usize, ray: &Ray) -> [f64; LANES] {
+        match *self {
+            NodeGroups::Full(ref groups) => groups[group].intersect_ray(ray),
+            NodeGroups::Compact(ref groups) => groups[group].intersect_ray(ray),
+        }
+    }
+
+    /// The boxes of the `group`th group at full precision.
+    fn widen(&self, group: usize) -> BoxGroup {
+        match *self {
+            NodeGroups::Full(ref groups) => groups[group],
+            NodeGroups::Compact(ref groups) => groups[group].widen(),
+        }
+    }
+}
+
+/// A read-only copy of a built tree with every node in one contiguous
+/// array, in breadth-first order, and every item in another.  Traversal
+/// follows index ranges instead of nested vectors, which avoids chasing a
+/// pointer and touching a separate allocation for every node.  Created by
+/// `RTree::into_packed`.
+///
+/// Made by `RTree::into_packed_compact` instead, the bounds of interior
+/// nodes are kept as `f32`, rounded outward, which halves what traversal
+/// reads on its way down.  The rounded boxes are only ever larger, so no
+/// match is missed; leaves keep their items' boxes at full precision, so
+/// none is made up either, and queries return exactly what they would
+/// otherwise.
+pub struct PackedRTree<T> where T: Mbr {
+    bounds: Option<BBox>,
+    nodes: Vec<PackedNode>,
+    node_groups: NodeGroups,
+    leaf_groups: Vec<BoxGroup>,
+    boxes: Vec<BBox>,
+    items: Vec<T>,
+}
+
+impl<T> PackedRTree<T> where T: Mbr {
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Every item, in leaf order.
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Whether interior bounds are rounded to `f32`, as made by
+    /// `RTree::into_packed_compact`.
+    pub fn is_compact(&self) -> bool {
+        match self.node_groups {
+            NodeGroups::Full(_) => false,
+            NodeGroups::Compact(_) => true,
+        }
+    }
+
+    /// For each lane of the `g`th group of `node`'s children, where `ray`
+    /// enters that child's box.
+    fn intersect_group(&self, node: &PackedNode, g: usize, ray: &Ray) -> [f64; LANES] {
+        if node.leaf {
+            self.leaf_groups[node.group + g].intersect_ray(ray)
+        } else {
+            self.node_groups.intersect_ray(node.group + g, ray)
+        }
+    }
+
+    /// Items whose bounding box `ray` passes through.
+    pub fn iter_ray(&self, ray: &Ray) -> PackedIter<'_, T> {
+        PackedIter::new(self, Query::Ray(*ray))
+    }
+
+    /// Items whose bounding box overlaps `query`.
+    pub fn iter_bbox(&self, query: &BBox) -> PackedIter<'_, T> {
+        PackedIter::new(self, Query::Envelope(*query))
+    }
+}
+
+impl<T> fmt::Debug for PackedRTree<T> where T: Mbr+fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PackedRTree")
+            .field("len", &self.len())
+            .field("nodes", &self.nodes.len())
+            .field("compact", &self.is_compact())
+            .field("bounds", &self.bounds)
+            .field("items", &self.items)
+            .finish()
+    }
+}
+
+impl<T> PackedRTree<T> where T: Mbr + Intersectable {
+    /// The first item hit by `ray`, like `RTree::closest_hit`.  Children are
+    /// visited nearest first and anything the ray enters beyond the best
+    /// hit so far is skipped.
+    ///
+    /// Each node's children are tested against the ray four at a time,
+    /// using AVX when the `simd` feature is enabled and the CPU has it.
+    pub fn closest_hit(&self, ray: &Ray) -> Option<(f64, &T, Intersection)> {
+        let mut best: Option<(f64, &T, Intersection)> = None;
+        let mut stack = Vec::new();
+        if let Some(ref bounds) = self.bounds {
+            if let Some((t, _)) = bounds.intersect_ray_t(ray) {
+                stack.push((0, t));
+            }
+        }
+        let mut entries = Vec::new();
+        while let Some((index, t)) = stack.pop() {
+            if best.is_some_and(|(best_t, _, _)| best_t < t) {
+                continue;
+            }
+            let node = self.nodes[index];
+            entries.clear();
+            for (g, first) in (node.start..node.end).step_by(LANES).enumerate() {
+                let hits = self.intersect_group(&node, g, ray);
+                for (lane, &t) in hits.iter().enumerate().take(node.end - first) {
+                    if t < f64::INFINITY {
+                        entries.push((first + lane, t));
+                    }
+                }
+            }
+
+            if node.leaf {
+                for &(i, t) in entries.iter() {
+                    if best.is_some_and(|(best_t, _, _)| best_t < t) {
+                        continue;
+                    }
+                    let item = &self.items[i];
+                    if let Some(hit) = item.intersect(ray).filter(|hit| ray.in_range(hit.t)) {
+                        if best.is_none_or(|(best_t, _, _)| hit.t < best_t) {
+                            best = Some((hit.t, item, hit));
+                        }
+                    }
+                }
+            } else {
+                // Farthest first, so the nearest child is popped next.
+                entries.sort_by(|a, b| b.1.total_cmp(&a.1));
+                stack.extend(entries.iter().cloned());
+            }
+        }
+        best
+    }
+}
+
+/// Items of a packed tree matching a query.  Created by
+/// `PackedRTree::iter_ray` and `PackedRTree::iter_bbox`.
+pub struct PackedIter<'a, T> where T: Mbr+'a {
+    tree: &'a PackedRTree<T>,
+    stack: Vec<usize>,
+    leaf_iter: Option<SliceIter<'a, BBox>>,
+    leaf_index: usize,
+    query: Query,
+}
+
+impl<'a, T> PackedIter<'a, T> where T: Mbr+'a {
+    fn new(tree: &'a PackedRTree<T>, query: Query) -> PackedIter<'a, T> {
+        let mut stack = Vec::new();
+        if tree.bounds.as_ref().is_some_and(|bounds| query.matches(bounds)) {
+            stack.push(0);
+        }
+        PackedIter {
+            tree,
+            stack,
+            leaf_iter: None,
+            leaf_index: 0,
+            query,
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for PackedIter<'a, T> where T: Mbr+'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PackedIter").field("query", &self.query).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> Iterator for PackedIter<'a, T> where T: Mbr+'a {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(leaf_iter) = self.leaf_iter.as_mut() {
+                for bbox in leaf_iter {
+                    let index = self.leaf_index;
+                    self.leaf_index += 1;
+                    if self.query.matches(bbox) {
+                        return Some(&self.tree.items[index]);
+                    }
+                }
+            }
+
+            let node = self.tree.nodes[self.stack.pop()?];
+            if node.leaf {
+                self.leaf_iter = Some(self.tree.boxes[node.start..node.end].iter());
+                self.leaf_index = node.start;
+            } else {
+                // Each group is widened once for all of its lanes.
+                for (g, first) in (node.start..node.end).step_by(LANES).enumerate() {
+                    let group = self.tree.node_groups.widen(node.group + g);
+                    for (lane, i) in (first..node.end.min(first + LANES)).enumerate() {
+                        if self.query.matches(&group.bbox(lane)) {
+                            self.stack.push(i);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Flatten the tree into a read-only `PackedRTree` for fast traversal.
+    /// The node hierarchy is kept as is; item ids are dropped.
+    pub fn into_packed(self) -> PackedRTree<T> {
+        self.flatten(NodeGroups::Full(Vec::new()))
+    }
+
+    /// Like `into_packed`, but keeping the bounds of interior nodes as
+    /// `f32`, rounded outward, for traversals which are bound by memory
+    /// rather than arithmetic.  Queries return exactly the same items.
+    pub fn into_packed_compact(self) -> PackedRTree<T> {
+        self.flatten(NodeGroups::Compact(Vec::new()))
+    }
+
+    fn flatten(self, node_groups: NodeGroups) -> PackedRTree<T> {
+        let mut packed = PackedRTree {
+            bounds: None,
+            nodes: Vec::new(),
+            node_groups,
+            leaf_groups: Vec::new(),
+            boxes: Vec::with_capacity(self.len()),
+            items: Vec::with_capacity(self.len()),
+        };
+        let mut nodes = self.nodes;
+        let mut queue = VecDeque::new();
+        if let Some(root) = self.root {
+            packed.bounds = Some(nodes[root].bbox);
+            packed.nodes.push(PackedNode { start: 0, end: 0, group: 0, leaf: nodes[root].is_leaf() });
+            queue.push_back(root);
+        }
+
+        // Nodes are numbered in the order they leave the queue, so each
+        // node's children are appended as one contiguous run.
+        let mut index = 0;
+        while let Some(node) = queue.pop_front() {
+            let (start, group) = match nodes.take(node).storage {
+                NodeStorage::Interior(children) => {
+                    let start = packed.nodes.len();
+                    let group = packed.node_groups.len();
+                    let boxes: Vec<BBox> = children.iter().map(|&child| nodes[child].bbox).collect();
+                    packed.node_groups.pack(&boxes);
+                    for child in children {
+                        packed.nodes.push(PackedNode { start: 0, end: 0, group: 0, leaf: nodes[child].is_leaf() });
+                        queue.push_back(child);
+                    }
+                    (start, group)
+                },
+                NodeStorage::Leaf(items) => {
+                    let start = packed.items.len();
+                    let group = packed.leaf_groups.len();
+                    for leaf in items {
+                        packed.boxes.push(leaf.bbox);
+                        packed.items.push(leaf.item);
+                    }
+                    BoxGroup::pack(&packed.boxes[start..], &mut packed.leaf_groups);
+                    (start, group)
+                },
+            };
+            let end = if packed.nodes[index].leaf { packed.items.len() } else { packed.nodes.len() };
+            packed.nodes[index].start = start;
+            packed.nodes[index].end = end;
+            packed.nodes[index].group = group;
+            index += 1;
+        }
+        packed
+    }
+}
+
+impl<T> From<RTree<T>> for PackedRTree<T> where T: Mbr {
+    fn from(tree: RTree<T>) -> PackedRTree<T> {
+        tree.into_packed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::PackedRTree;
+    use super::super::RTree;
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_packed_queries() {
+        let tree: RTree<Sphere> = random_spheres(4000, 41).into_iter().collect();
+        let packed: PackedRTree<Sphere> = random_spheres(4000, 41).into_iter().collect::<RTree<_>>().into();
+        assert_eq!(packed.len(), 4000);
+        assert!(PackedRTree::from(RTree::<Sphere>::new()).is_empty());
+
+        let envelope = BBox {
+            min: Vec3::xyz(200.0, 100.0, 300.0),
+            max: Vec3::xyz(450.0, 500.0, 600.0),
+        };
+        let expected = tree.iter_bbox(&envelope).count();
+        assert!(expected > 0);
+        assert_eq!(packed.iter_bbox(&envelope).count(), expected);
+
+        let mut hits = 0;
+        for i in 0..40 {
+            let f = i as f64 / 40.0;
+            let ray = Ray::new(Vec3::xyz(-10.0, 1000.0 * f, 500.0), Vec3::xyz(1.0, 0.2 - f * 0.4, 0.05));
+            assert_eq!(packed.iter_ray(&ray).count(), tree.iter_ray(&ray).count());
+            let expected = tree.closest_hit(&ray).map(|(t, _, hit)| (t, hit));
+            hits += expected.iter().count();
+            assert_eq!(packed.closest_hit(&ray).map(|(t, _, hit)| (t, hit)), expected);
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn test_compact_bounds() {
+        let full: PackedRTree<Sphere> = random_spheres(4000, 42).into_iter().collect::<RTree<_>>().into_packed();
+        let compact = random_spheres(4000, 42).into_iter().collect::<RTree<_>>().into_packed_compact();
+        assert!(compact.is_compact() && !full.is_compact());
+        assert_eq!(compact.len(), 4000);
+        assert!(RTree::<Sphere>::new().into_packed_compact().iter_bbox(&BBox::from_point(Vec3::zero())).next().is_none());
+
+        // Both trees hold the items in the same order, so matches can be
+        // compared by position.
+        let positions = |tree: &PackedRTree<Sphere>, found: Vec<&Sphere>| -> Vec<usize> {
+            found.into_iter().map(|s| (s as *const Sphere as usize - tree.items().as_ptr() as usize) / ::std::mem::size_of::<Sphere>()).collect()
+        };
+        for i in 0..20 {
+            let f = i as f64 / 20.0;
+            // Edges which f32 cannot hold, just inside some spheres.
+            let envelope = BBox {
+                min: Vec3::xyz(1000.0 * f + 0.1, 300.0, 200.0 / 3.0),
+                max: Vec3::xyz(1000.0 * f + 150.3, 700.0 / 3.0 + 400.0, 800.0),
+            };
+            let expected = positions(&full, full.iter_bbox(&envelope).collect());
+            assert_eq!(positions(&compact, compact.iter_bbox(&envelope).collect()), expected);
+
+            let ray = Ray::new(Vec3::xyz(-10.0, 1000.0 * f, 500.0 + f / 3.0), Vec3::xyz(1.0, 0.2 - f * 0.4, 0.05));
+            let expected = positions(&full, full.iter_ray(&ray).collect());
+            assert_eq!(positions(&compact, compact.iter_ray(&ray).collect()), expected);
+            let expected = full.closest_hit(&ray).map(|(t, _, hit)| (t, hit));
+            assert_eq!(compact.closest_hit(&ray).map(|(t, _, hit)| (t, hit)), expected);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use rayon::prelude::*;
+
+use arena::Arena;
+use bbox::BBox;
+use vec3::Vec3;
+use super::{Mbr, RTree, RTreeNode, NodeStorage, LeafItem};
+
+/// Collect the item slices of every leaf whose box overlaps `query`, or of
+/// every leaf when there is no query.  Only interior nodes are visited
+/// here; the items themselves are left for the thread pool.
+fn collect_leaves<'a, T>(nodes: &'a Arena<T, 3>, node: &'a RTreeNode<T>, query: Option<&BBox>, leaves: &mut Vec<&'a [LeafItem<T>]>)
+    where T: Mbr {
+
+    if query.is_some_and(|q| !node.bbox.overlaps(q)) {
+        return;
+    }
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            for child in nodes.children(children) {
+                collect_leaves(nodes, child, query, leaves);
+            }
+        },
+        NodeStorage::Leaf(ref items) => leaves.push(items),
+    }
+}
+
+impl<T> RTree<T> where T: Mbr + Sync {
+    fn leaves(&self, query: Option<&BBox>) -> Vec<&[LeafItem<T>]> {
+        let mut leaves = Vec::new();
+        if let Some(root) = self.root_node() {
+            collect_leaves(&self.nodes, root, query, &mut leaves);
+        }
+        leaves
+    }
+
+    /// A parallel iterator over every item in the tree, in no particular
+    /// order.  Only available with the `parallel` feature.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item=&T> + '_ {
+        self.leaves(None)
+            .into_par_iter()
+            .flat_map_iter(|items| items.iter().map(|leaf| &leaf.item))
+    }
+
+    /// Like `iter_bbox`, but a parallel iterator.  Leaves overlapping
+    /// `query` are found up front, and their items are then filtered on the
+    /// rayon thread pool.
+    pub fn par_iter_bbox(&self, query: &BBox) -> impl ParallelIterator<Item=&T> + '_ {
+        let query = *query;
+        self.leaves(Some(&query))
+            .into_par_iter()
+            .flat_map_iter(move |items| {
+                items.iter()
+                    .filter(move |leaf| leaf.bbox.overlaps(&query))
+                    .map(|leaf| &leaf.item)
+            })
+    }
+
+    /// `nearest_n` for each of `points`, run on the rayon thread pool.  The
+    /// results are in the same order as `points`.
+    pub fn par_nearest_batch(&self, points: &[Vec3], k: usize) -> Vec<Vec<&T>> {
+        points.par_iter().map(|point| self.nearest_n(*point, k)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_par_iter() {
+        let spheres = RTree::bulk_load(random_spheres(3000, 7));
+        assert_eq!(spheres.par_iter().count(), 3000);
+
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(600.0, 700.0, 800.0),
+        };
+        let mut serial: Vec<&Sphere> = spheres.iter_bbox(&envelope).collect();
+        let mut parallel: Vec<&Sphere> = spheres.par_iter_bbox(&envelope).collect();
+        assert!(!serial.is_empty());
+        serial.sort_by(|a, b| a.mbr().min.x.total_cmp(&b.mbr().min.x));
+        parallel.sort_by(|a, b| a.mbr().min.x.total_cmp(&b.mbr().min.x));
+        assert_eq!(serial, parallel);
+
+        let empty: RTree<Sphere> = RTree::new();
+        assert_eq!(empty.par_iter().count(), 0);
+        assert_eq!(empty.par_iter_bbox(&envelope).count(), 0);
+    }
+
+    #[test]
+    fn test_par_nearest_batch() {
+        let spheres = RTree::bulk_load(random_spheres(2000, 8));
+        let points: Vec<Vec3> = (0..50).map(|i| Vec3::xyz(i as f64 * 20.0, 500.0, 1000.0 - i as f64 * 20.0)).collect();
+        let batch = spheres.par_nearest_batch(&points, 5);
+        assert_eq!(batch.len(), points.len());
+        for (point, found) in points.iter().zip(batch.iter()) {
+            assert_eq!(*found, spheres.nearest_n(*point, 5));
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::array;
+use std::cmp;
+use std::fmt;
+use std::ops::{Add, Mul, Div, Neg, Sub, Deref, DerefMut};
+
+/// A point or vector in `N` dimensions.  `Vec3` is `Point<3>`; two and
+/// three dimensional points also have named `x`, `y` (and `z`) fields,
+/// reached through `Xy` and `Xyz`.
+///
+/// This is a breaking change from when `Vec3` was a struct of its own: the
+/// fields can be read and assigned, but struct literals and patterns such
+/// as `Vec3 { x, y, z }` no longer compile.  Build points with `Vec3::xyz`,
+/// `Point::xy` or `Point::new` instead, and destructure `coords`, e.g.
+/// `let [x, y, z] = v.coords;`.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Point<const N: usize> {
+    pub coords: [f64; N],
+}
+
+/// The named coordinates of a `Point<2>`.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Xy {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// The named coordinates of a `Point<3>`.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Xyz {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+// `Xy` and `Xyz` are laid out exactly like `[f64; 2]` and `[f64; 3]`, so
+// the coordinates can be viewed through them in place.
+impl Deref for Point<2> {
+    type Target = Xy;
+
+    fn deref(&self) -> &Xy {
+        unsafe { &*(&self.coords as *const [f64; 2]).cast::<Xy>() }
+    }
+}
+
+impl DerefMut for Point<2> {
+    fn deref_mut(&mut self) -> &mut Xy {
+        unsafe { &mut *(&mut self.coords as *mut [f64; 2]).cast::<Xy>() }
+    }
+}
+
+impl Deref for Point<3> {
+    type Target = Xyz;
+
+    fn deref(&self) -> &Xyz {
+        unsafe { &*(&self.coords as *const [f64; 3]).cast::<Xyz>() }
+    }
+}
+
+impl DerefMut for Point<3> {
+    fn deref_mut(&mut self) -> &mut Xyz {
+        unsafe { &mut *(&mut self.coords as *mut [f64; 3]).cast::<Xyz>() }
+    }
+}
+
+impl Point<2> {
+    pub fn xy(x: f64, y: f64) -> Point<2> {
+        Point { coords: [x, y] }
+    }
+}
+
+impl<const N: usize> Point<N> {
+    pub fn new(coords: [f64; N]) -> Point<N> {
+        Point { coords }
+    }
+
+    /// The point with every coordinate set to `value`.
+    pub fn splat(value: f64) -> Point<N> {
+        Point { coords: [value; N] }
+    }
+
+    pub fn zero() -> Point<N> {
+        Point::splat(0.0)
+    }
+
+    pub fn one() -> Point<N> {
+        Point::splat(1.0)
+    }
+
+    /// The point whose coordinates are `f` of each axis index.
+    pub fn from_fn<F>(f: F) -> Point<N> where F: FnMut(usize) -> f64 {
+        Point { coords: array::from_fn(f) }
+    }
+
+    /// Apply `f` to each coordinate.
+    pub fn map<F>(&self, f: F) -> Point<N> where F: Fn(f64) -> f64 {
+        Point { coords: self.coords.map(f) }
+    }
+
+    /// Combine the coordinates of both points pairwise with `f`.
+    pub fn zip_map<F>(&self, other: &Point<N>, f: F) -> Point<N> where F: Fn(f64, f64) -> f64 {
+        Point::from_fn(|i| f(self.coords[i], other.coords[i]))
+    }
+
+    pub fn len(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn dot(&self, other: &Point<N>) -> f64 {
+        self.coords.iter().zip(other.coords.iter()).map(|(a, b)| a * b).sum()
+    }
+
+    pub fn unit(&self) -> Point<N> {
+        let len = self.len();
+        self.map(|c| c / len)
+    }
+
+    pub fn scale(&self, scalar: f64) -> Point<N> {
+        self.map(|c| c * scalar)
+    }
+
+    pub fn lerp(v1: &Point<N>, v2: &Point<N>, alpha: f64) -> Point<N> {
+        v1.zip_map(v2, |a, b| a + (b - a) * alpha)
+    }
+
+    pub fn clamp(&self, min: f64, max: f64) -> Point<N> {
+        self.map(|c| c.max(min).min(max))
+    }
+}
+
+impl<const N: usize> Add for Point<N> {
+    type Output = Point<N>;
+
+    fn add(self, other: Point<N>) -> Point<N> {
+        self.zip_map(&other, |a, b| a + b)
+    }
+}
+
+impl<const N: usize> Add<f64> for Point<N> {
+    type Output = Point<N>;
+
+    fn add(self, other: f64) -> Point<N> {
+        self.map(|c| c + other)
+    }
+}
+
+impl<const N: usize> Sub for Point<N> {
+    type Output = Point<N>;
+
+    fn sub(self, other: Point<N>) -> Point<N> {
+        self.zip_map(&other, |a, b| a - b)
+    }
+}
+
+impl<const N: usize> Sub<f64> for Point<N> {
+    type Output = Point<N>;
+
+    fn sub(self, other: f64) -> Point<N> {
+        self.map(|c| c - other)
+    }
+}
+
+impl<const N: usize> Mul for Point<N> {
+    type Output = Point<N>;
+
+    fn mul(self, other: Point<N>) -> Point<N> {
+        self.zip_map(&other, |a, b| a * b)
+    }
+}
+
+impl<const N: usize> Mul<f64> for Point<N> {
+    type Output = Point<N>;
+
+    fn mul(self, other: f64) -> Point<N> {
+        self.map(|c| c * other)
+    }
+}
+
+impl<const N: usize> Div for Point<N> {
+    type Output = Point<N>;
+
+    fn div(self, other: Point<N>) -> Point<N> {
+        self.zip_map(&other, |a, b| a / b)
+    }
+}
+
+impl<const N: usize> Div<f64> for Point<N> {
+    type Output = Point<N>;
+
+    fn div(self, other: f64) -> Point<N> {
+        self.map(|c| c / other)
+    }
+}
+
+impl<const N: usize> Neg for Point<N> {
+    type Output = Point<N>;
+
+    fn neg(self) -> Point<N> {
+        self.map(|c| -c)
+    }
+}
+
+impl<const N: usize> cmp::PartialEq for Point<N> {
+    fn eq(&self, other: &Point<N>) -> bool {
+        self.coords == other.coords
+    }
+}
+
+impl<const N: usize> fmt::Debug for Point<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, c) in self.coords.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        write!(f, ")")
+    }
+}
+
+// Points are written as a tuple of their coordinates, which serde cannot
+// derive for arrays of any length.
+#[cfg(feature = "serde")]
+impl<const N: usize> ::serde::Serialize for Point<N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: ::serde::Serializer {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for c in self.coords.iter() {
+            tuple.serialize_element(c)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> ::serde::Deserialize<'de> for Point<N> {
+    fn deserialize<D>(deserializer: D) -> Result<Point<N>, D::Error> where D: ::serde::Deserializer<'de> {
+        use serde::de::{Error, SeqAccess, Visitor};
+
+        struct CoordsVisitor<const N: usize>;
+
+        impl<'de, const N: usize> Visitor<'de> for CoordsVisitor<N> {
+            type Value = Point<N>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a tuple of {} coordinates", N)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Point<N>, A::Error> where A: SeqAccess<'de> {
+                let mut coords = [0.0; N];
+                for (i, c) in coords.iter_mut().enumerate() {
+                    *c = seq.next_element()?.ok_or_else(|| A::Error::invalid_length(i, &self))?;
+                }
+                Ok(Point { coords })
+            }
+        }
+
+        deserializer.deserialize_tuple(N, CoordsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{Xyz, Vec3};
+    use super::Point;
+
+    #[test]
+    fn test_named_coordinates() {
+        let mut p = Point::xy(1.0, 2.0);
+        p.y += 1.0;
+        assert_eq!((p.x, p.y), (1.0, 3.0));
+        assert_eq!(p.coords, [1.0, 3.0]);
+
+        let q = Point::new([3.0, 4.0, 0.0, 12.0]);
+        assert_eq!(q.len(), 13.0);
+        assert_eq!(q - Point::one(), Point::new([2.0, 3.0, -1.0, 11.0]));
+        assert_eq!(format!("{:?}", Point::xy(0.5, -1.0)), "(0.5, -1)");
+
+        // The named fields can be borrowed as a whole, from outside too.
+        let mut v = Vec3::xyz(1.0, 2.0, 3.0);
+        let Xyz { x, y, z } = *v;
+        assert_eq!([x, y, z], v.coords);
+        let fields: &mut Xyz = &mut v;
+        fields.z = -3.0;
+        assert_eq!(v, Vec3::xyz(1.0, 2.0, -3.0));
+    }
+}
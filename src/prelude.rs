@@ -0,0 +1,36 @@
+//! The commonly used types and traits, for glob importing:
+//!
+//! ```
+//! use rtree::prelude::*;
+//! ```
+
+pub use super::{RTree, RTreeBuilder, RTreeConfig, SplitStrategy, ReinsertPolicy, ReinsertOrder, BulkLoadStrategy, RebalancePolicy, LeafCapacity, SplitEvent, SplitObserver, ItemId, Mbr, ClosestPoint, Intersectable, Intersection, Hit, HitBuffers, Mailbox};
+pub use super::{Aabb, Point, BBox, BoundingBox, Vec3, Ray, Transform};
+pub use super::{Iter, IterMut, Items, IntoIter, SortedIter, ClippedRTree, FragmentedRTree, OverflowRTree, FilterIter, EnvelopeIter, OutsideIter, OrderedIter, QueryContext, JoinIter, SelfJoinIter, ConvexIter, WithinDistanceIter};
+pub use super::{Dedup, MultiTree, Codec, PackedRTree, PackedIter, CowRTree, CowIter, RebuildingIndex, Instance, Frustum, RTreeMap, KeyedRTreeMap};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Point(Vec3);
+
+    impl Mbr for Point {
+        fn mbr(&self) -> BBox {
+            BBox { min: self.0, max: self.0 }
+        }
+    }
+
+    #[test]
+    fn test_prelude_is_sufficient() {
+        let mut points: RTree<Point> = RTree::new();
+        points.insert(Point(Vec3::xyz(1.0, 2.0, 3.0)));
+
+        let envelope = BBox {
+            min: Vec3::zero(),
+            max: Vec3::xyz(5.0, 5.0, 5.0),
+        };
+        let found: EnvelopeIter<Point> = points.locate_in_envelope(&envelope);
+        assert_eq!(Dedup::new(found).count(), 1);
+    }
+}
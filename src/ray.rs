@@ -1,33 +1,154 @@
+use std::f64;
+
+use ::transform::Transform;
 use ::vec3::Vec3;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
 
     pub inverse_dir: Vec3, // This is used to optimise ray-bbox intersection checks
-    pub signs: [bool; 3] // Handle degenerate case in bbox intersection
+    pub signs: [bool; 3], // Handle degenerate case in bbox intersection
+
+    // Only hits with `min_t < t <= max_t` count.  A ray is unbounded by
+    // default; a finite `max_t` makes it a segment.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_t: f64,
+    #[cfg_attr(feature = "serde", serde(default = "unbounded"))]
+    pub max_t: f64,
 }
 
+#[cfg(feature = "serde")]
+fn unbounded() -> f64 {
+    f64::INFINITY
+}
+
+const RAY_DIRECTION_INVALID: &str = "ray direction must be finite and non-zero";
 
 impl Ray {
+    /// Like `Ray::new`, but refusing a direction which is zero, infinite or
+    /// NaN, for which no ray parameter means anything.
+    pub fn try_new(origin: Vec3, direction: Vec3) -> Result<Ray, &'static str> {
+        let length_squared = direction.dot(&direction);
+        if !(length_squared > 0.0 && length_squared.is_finite()) {
+            return Err(RAY_DIRECTION_INVALID);
+        }
+        Ok(Ray::new(origin, direction))
+    }
+
+    /// Whether any component of the origin or direction is NaN.  Such a
+    /// ray hits nothing.
+    pub fn has_nan(&self) -> bool {
+        let Ray { origin: o, direction: d, .. } = *self;
+        o.x.is_nan() || o.y.is_nan() || o.z.is_nan() || d.x.is_nan() || d.y.is_nan() || d.z.is_nan()
+    }
+
+    /// A ray from `origin` along `direction`.  Zero direction components
+    /// are fine: their inverse is infinite, with the sign of the zero.
 	pub fn new(origin: Vec3, direction: Vec3) -> Ray {
         let inv_x = 1.0 / direction.x;
         let inv_y = 1.0 / direction.y;
         let inv_z = 1.0 / direction.z;
 
         Ray {
-            origin: origin,
-            direction: direction,
-            inverse_dir: Vec3 {
-                x: inv_x,
-                y: inv_y,
-                z: inv_z
-            },
+            origin,
+            direction,
+            inverse_dir: Vec3::xyz(inv_x, inv_y, inv_z),
             signs: [
                 inv_x > 0.0,
                 inv_y > 0.0,
                 inv_z > 0.0
-            ]
+            ],
+            min_t: 0.0,
+            max_t: f64::INFINITY
         }
     }
-}
\ No newline at end of file
+
+    /// A ray which ends at `origin + direction * max_t`, for picking, shadow
+    /// rays and line of sight checks that must not see past their target.
+    pub fn with_max_t(origin: Vec3, direction: Vec3, max_t: f64) -> Ray {
+        Ray {
+            max_t,
+            ..Ray::new(origin, direction)
+        }
+    }
+
+    /// The segment from `from` to `to`, with a unit direction so that `t`
+    /// is the distance travelled.  If the points coincide the direction is
+    /// NaN and the ray hits nothing.
+    pub fn between(from: Vec3, to: Vec3) -> Ray {
+        let offset = to - from;
+        let distance = offset.len();
+        Ray::with_max_t(from, offset.unit(), distance)
+    }
+
+    /// The point at parameter `t` along the ray.
+    pub fn point_at(&self, t: f64) -> Vec3 {
+        self.origin + self.direction.scale(t)
+    }
+
+    /// Whether a hit at `t` lies within the ray's interval.
+    pub fn in_range(&self, t: f64) -> bool {
+        self.min_t < t && t <= self.max_t
+    }
+
+    /// This ray moved by `transform`, with `inverse_dir` and `signs`
+    /// rebuilt for the new direction.  The direction is not normalised, so a
+    /// ray parameter names the same point on both rays, and the interval is
+    /// kept as is.
+    pub fn transformed(&self, transform: &Transform) -> Ray {
+        Ray {
+            min_t: self.min_t,
+            max_t: self.max_t,
+            ..Ray::new(transform.transform_point(&self.origin), transform.transform_vector(&self.direction))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::transform::Transform;
+    use ::vec3::Vec3;
+    use super::Ray;
+
+    #[test]
+    fn test_transformed() {
+        let ray = Ray::new(Vec3::xyz(1.0, 2.0, 3.0), Vec3::xyz(1.0, 0.0, -1.0));
+        let flip = Transform::translation(Vec3::xyz(0.0, 10.0, 0.0)) * Transform::scale(Vec3::xyz(-2.0, 1.0, 1.0));
+        let moved = ray.transformed(&flip);
+        assert_eq!(moved.origin, Vec3::xyz(-2.0, 12.0, 3.0));
+        assert_eq!(moved.direction, Vec3::xyz(-2.0, 0.0, -1.0));
+        assert_eq!(moved.inverse_dir.x, -0.5);
+        assert_eq!(moved.signs, [false, true, false]);
+
+        assert_eq!(moved.point_at(3.0), flip.transform_point(&ray.point_at(3.0)));
+
+        let segment = Ray::with_max_t(ray.origin, ray.direction, 2.5).transformed(&flip);
+        assert_eq!((segment.min_t, segment.max_t), (0.0, 2.5));
+    }
+
+    #[test]
+    fn test_between() {
+        let (from, to) = (Vec3::xyz(1.0, 1.0, 1.0), Vec3::xyz(4.0, 5.0, 1.0));
+        let ray = Ray::between(from, to);
+        assert_eq!(ray.direction, Vec3::xyz(0.6, 0.8, 0.0));
+        assert_eq!((ray.min_t, ray.max_t), (0.0, 5.0));
+        assert_eq!(ray.point_at(0.0), from);
+        assert_eq!(ray.point_at(ray.max_t), to);
+        assert!(Ray::between(from, from).has_nan());
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(Ray::try_new(Vec3::zero(), Vec3::zero()).is_err());
+        assert!(Ray::try_new(Vec3::zero(), Vec3::xyz(0.0, f64::NAN, 0.0)).is_err());
+        assert!(Ray::try_new(Vec3::zero(), Vec3::xyz(f64::INFINITY, 0.0, 0.0)).is_err());
+
+        let ray = Ray::try_new(Vec3::zero(), Vec3::xyz(0.0, -0.0, 2.0)).unwrap();
+        assert_eq!(ray.inverse_dir.x, f64::INFINITY);
+        assert_eq!(ray.inverse_dir.y, f64::NEG_INFINITY);
+        assert_eq!(ray.signs, [true, false, true]);
+    }
+}
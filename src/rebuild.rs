@@ -0,0 +1,360 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use bbox::BBox;
+use packed::PackedRTree;
+use ray::Ray;
+use super::{ItemId, Mbr, RTree, RTreeConfig};
+
+const REBUILD_PANICKED: &str = "background rebuild panicked";
+
+/// An item and its id, as stored in the trees of a `RebuildingIndex`.
+#[derive(Clone)]
+struct Keyed<T> {
+    id: u64,
+    item: T,
+}
+
+impl<T> Mbr for Keyed<T> where T: Mbr {
+    fn mbr(&self) -> BBox {
+        self.item.mbr()
+    }
+}
+
+/// A change made while a rebuild is running, to be replayed on top of the
+/// tree it builds.
+enum Change<T> {
+    Insert(u64, T),
+    Remove(u64),
+}
+
+/// A rebuild running in the background, and the changes made since it
+/// took its snapshot.
+struct Rebuild<T> where T: Mbr {
+    handle: JoinHandle<(PackedRTree<Keyed<T>>, HashSet<u64>)>,
+    log: Vec<Change<T>>,
+    // The items inserted before the rebuild started, which it folds into
+    // the packed tree, and their ids.  Until then they are shared with it
+    // and removed like the items of the packed tree.
+    frozen: Arc<RTree<Keyed<T>>>,
+    frozen_ids: HashMap<u64, ItemId>,
+}
+
+/// An index for long-running services which keeps answering queries while
+/// it re-optimizes itself.
+///
+/// Queries are answered from a `PackedRTree`, plus the items inserted
+/// since it was built, which are kept in a small `RTree`, minus the ones
+/// removed since.  As changes pile up the packed tree serves a smaller
+/// share of each query; `start_rebuild` bulk loads a fresh packed tree
+/// from a snapshot of every item on a background thread.  Changes made
+/// meanwhile are applied as usual, and also logged.  Once the new tree is
+/// ready, `poll` or `finish_rebuild` swaps it in and replays the log on
+/// top of it, all at once, so no query ever sees a half-built tree.
+///
+/// Taking the snapshot shares the packed tree and the tree of items
+/// inserted since, and copies the ids of the items removed since; the items
+/// themselves are cloned on the background thread.
+pub struct RebuildingIndex<T> where T: Mbr {
+    packed: Arc<PackedRTree<Keyed<T>>>,
+    // The ids of the items in `packed`, including those since removed.
+    packed_ids: HashSet<u64>,
+    added: RTree<Keyed<T>>,
+    added_ids: HashMap<u64, ItemId>,
+    removed: HashSet<u64>,
+    rebuild: Option<Rebuild<T>>,
+    config: RTreeConfig,
+    next_id: u64,
+}
+
+impl<T> RebuildingIndex<T> where T: Mbr + Clone + Send + Sync + 'static {
+    pub fn new() -> RebuildingIndex<T> {
+        RebuildingIndex::with_config(RTreeConfig::default())
+    }
+
+    /// An empty index whose trees are built following `config`.
+    pub fn with_config(config: RTreeConfig) -> RebuildingIndex<T> {
+        RebuildingIndex::bulk_load_with_config(Vec::new(), config)
+    }
+
+    /// An index of `items`, packed by `RTree::bulk_load`.  Their ids
+    /// count up from 0 in the order given.
+    pub fn bulk_load(items: Vec<T>) -> RebuildingIndex<T> {
+        RebuildingIndex::bulk_load_with_config(items, RTreeConfig::default())
+    }
+
+    pub fn bulk_load_with_config(items: Vec<T>, config: RTreeConfig) -> RebuildingIndex<T> {
+        let next_id = items.len() as u64;
+        let items = items.into_iter().enumerate().map(|(id, item)| Keyed { id: id as u64, item }).collect();
+        let (packed, packed_ids) = pack(items, config);
+        RebuildingIndex {
+            packed: Arc::new(packed),
+            packed_ids,
+            added: RTree::with_config(config),
+            added_ids: HashMap::new(),
+            removed: HashSet::new(),
+            rebuild: None,
+            config,
+            next_id,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.packed_ids.len() + self.frozen_len() - self.removed.len() + self.added_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of changes since the packed tree was built, which a
+    /// rebuild would fold into it.
+    pub fn pending_changes(&self) -> usize {
+        self.added_ids.len() + self.frozen_len() + self.removed.len()
+    }
+
+    /// The items inserted before the running rebuild started, if any.
+    fn frozen(&self) -> Option<&RTree<Keyed<T>>> {
+        self.rebuild.as_ref().map(|rebuild| &*rebuild.frozen)
+    }
+
+    /// Whether `id` is among the items the running rebuild folds in.
+    fn is_frozen(&self, id: u64) -> bool {
+        self.rebuild.as_ref().is_some_and(|rebuild| rebuild.frozen_ids.contains_key(&id))
+    }
+
+    fn frozen_len(&self) -> usize {
+        self.rebuild.as_ref().map_or(0, |rebuild| rebuild.frozen_ids.len())
+    }
+
+    /// Add `item` to the index, returning a handle to it.
+    pub fn insert(&mut self, item: T) -> ItemId {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(ref mut rebuild) = self.rebuild {
+            rebuild.log.push(Change::Insert(id, item.clone()));
+        }
+        self.added_ids.insert(id, self.added.insert(Keyed { id, item }));
+        ItemId(id)
+    }
+
+    /// Remove the item `id` refers to.  Returns false if it has already
+    /// been removed.
+    pub fn remove(&mut self, id: ItemId) -> bool {
+        let removed = match self.added_ids.remove(&id.0) {
+            Some(added) => self.added.remove_by_id(added).is_some(),
+            None => (self.packed_ids.contains(&id.0) || self.is_frozen(id.0)) && self.removed.insert(id.0),
+        };
+        if let Some(ref mut rebuild) = self.rebuild {
+            if removed {
+                rebuild.log.push(Change::Remove(id.0));
+            }
+        }
+        removed
+    }
+
+    /// Every item, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item=&T> + '_ {
+        self.packed.items().iter()
+            .chain(self.frozen().into_iter().flat_map(|frozen| frozen.iter()))
+            .filter(move |keyed| !self.removed.contains(&keyed.id))
+            .chain(self.added.iter())
+            .map(|keyed| &keyed.item)
+    }
+
+    /// Items whose bounding box overlaps `query`.
+    pub fn iter_bbox(&self, query: &BBox) -> impl Iterator<Item=&T> + '_ {
+        let envelope = *query;
+        self.packed.iter_bbox(query)
+            .chain(self.frozen().into_iter().flat_map(move |frozen| frozen.iter_bbox(&envelope)))
+            .filter(move |keyed| !self.removed.contains(&keyed.id))
+            .chain(self.added.iter_bbox(query))
+            .map(|keyed| &keyed.item)
+    }
+
+    /// Items whose bounding box `ray` passes through.
+    pub fn iter_ray<'a>(&'a self, ray: &'a Ray) -> impl Iterator<Item=&'a T> + 'a {
+        self.packed.iter_ray(ray)
+            .chain(self.frozen().into_iter().flat_map(move |frozen| frozen.iter_ray(ray)))
+            .filter(move |keyed| !self.removed.contains(&keyed.id))
+            .chain(self.added.iter_ray(ray))
+            .map(|keyed| &keyed.item)
+    }
+
+    pub fn is_rebuilding(&self) -> bool {
+        self.rebuild.is_some()
+    }
+
+    /// Start building a fresh packed tree of every item on a background
+    /// thread.  Returns false if a rebuild is already running.
+    pub fn start_rebuild(&mut self) -> bool {
+        if self.rebuild.is_some() {
+            return false;
+        }
+        let config = self.config;
+        let frozen = Arc::new(mem::replace(&mut self.added, RTree::with_config(config)));
+        let frozen_ids = mem::take(&mut self.added_ids);
+        let (packed, added) = (self.packed.clone(), frozen.clone());
+        let removed = self.removed.clone();
+        let handle = thread::spawn(move || {
+            let items = packed.items().iter()
+                .filter(|keyed| !removed.contains(&keyed.id))
+                .chain(added.iter())
+                .cloned()
+                .collect();
+            pack(items, config)
+        });
+        self.rebuild = Some(Rebuild { handle, log: Vec::new(), frozen, frozen_ids });
+        true
+    }
+
+    /// Swap in the rebuilt tree if it is ready.  Returns whether it was.
+    pub fn poll(&mut self) -> bool {
+        if !self.rebuild.as_ref().is_some_and(|rebuild| rebuild.handle.is_finished()) {
+            return false;
+        }
+        self.finish_rebuild();
+        true
+    }
+
+    /// Wait for the running rebuild, if any, and swap in its tree.
+    pub fn finish_rebuild(&mut self) {
+        let rebuild = match self.rebuild.take() {
+            Some(rebuild) => rebuild,
+            None => return,
+        };
+        let (packed, packed_ids) = rebuild.handle.join().expect(REBUILD_PANICKED);
+        self.packed = Arc::new(packed);
+        self.packed_ids = packed_ids;
+        self.added = RTree::with_config(self.config);
+        self.added_ids.clear();
+        self.removed.clear();
+        for change in rebuild.log {
+            match change {
+                Change::Insert(id, item) => {
+                    self.added_ids.insert(id, self.added.insert(Keyed { id, item }));
+                },
+                Change::Remove(id) => {
+                    match self.added_ids.remove(&id) {
+                        Some(added) => {
+                            self.added.remove_by_id(added);
+                        },
+                        None => {
+                            self.removed.insert(id);
+                        },
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<T> Default for RebuildingIndex<T> where T: Mbr + Clone + Send + Sync + 'static {
+    fn default() -> RebuildingIndex<T> {
+        RebuildingIndex::new()
+    }
+}
+
+// Only counts are printed: the items are spread over the packed tree, the
+// tree of later insertions and the log of a running rebuild.
+impl<T> fmt::Debug for RebuildingIndex<T> where T: Mbr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RebuildingIndex")
+            .field("packed", &self.packed.len())
+            .field("added", &self.added_ids.len())
+            .field("removed", &self.removed.len())
+            .field("rebuilding", &self.rebuild.is_some())
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A packed tree of `items`, and their ids.
+fn pack<T>(items: Vec<Keyed<T>>, config: RTreeConfig) -> (PackedRTree<Keyed<T>>, HashSet<u64>) where T: Mbr {
+    let ids = items.iter().map(|keyed| keyed.id).collect();
+    (RTree::bulk_load_with_config(items, config).into_packed(), ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::RebuildingIndex;
+    use super::super::{ItemId, Mbr};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    /// The centers of the spheres `index` finds in `query`, in a set order.
+    fn found(index: &RebuildingIndex<Arc<Sphere>>, query: &BBox) -> Vec<(u64, u64, u64)> {
+        let mut found: Vec<_> = index.iter_bbox(query)
+            .map(|s| {
+                let center = s.mbr().center();
+                (center.x.to_bits(), center.y.to_bits(), center.z.to_bits())
+            })
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn test_rebuilding_index() {
+        let spheres: Vec<Arc<Sphere>> = random_spheres(3000, 81).into_iter().map(Arc::new).collect();
+        let mut index = RebuildingIndex::bulk_load(spheres[..2000].to_vec());
+        let mut live: Vec<(ItemId, Arc<Sphere>)> = spheres[..2000].iter().cloned().enumerate()
+            .map(|(id, sphere)| (ItemId(id as u64), sphere))
+            .collect();
+        let query = BBox { min: Vec3::xyz(100.0, 200.0, 300.0), max: Vec3::xyz(600.0, 700.0, 800.0) };
+        let check = |index: &RebuildingIndex<Arc<Sphere>>, live: &[(ItemId, Arc<Sphere>)]| {
+            assert_eq!(index.len(), live.len());
+            assert_eq!(index.iter().count(), live.len());
+            let plain = RebuildingIndex::bulk_load(live.iter().map(|(_, s)| s.clone()).collect());
+            assert_eq!(found(index, &query), found(&plain, &query));
+            let ray = Ray::new(Vec3::xyz(-10.0, 500.0, 500.0), Vec3::xyz(1.0, 0.1, 0.05));
+            assert_eq!(index.iter_ray(&ray).count(), plain.iter_ray(&ray).count());
+        };
+
+        // Changes pile up on top of the packed tree.
+        for sphere in spheres[2000..2500].iter() {
+            live.push((index.insert(sphere.clone()), sphere.clone()));
+        }
+        for (id, _) in live.iter().step_by(3) {
+            assert!(index.remove(*id));
+            assert!(!index.remove(*id));
+        }
+        live = live.into_iter().enumerate().filter(|&(idx, _)| idx % 3 != 0).map(|(_, entry)| entry).collect();
+        assert_eq!(index.pending_changes(), 1000);
+        check(&index, &live);
+
+        // Queries see changes made while the rebuild runs, before and
+        // after it is swapped in.
+        assert!(index.start_rebuild());
+        assert!(!index.start_rebuild());
+        for sphere in spheres[2500..].iter() {
+            live.push((index.insert(sphere.clone()), sphere.clone()));
+        }
+        for (id, _) in live.iter().step_by(5) {
+            assert!(index.remove(*id));
+            assert!(!index.remove(*id));
+        }
+        live = live.into_iter().enumerate().filter(|&(idx, _)| idx % 5 != 0).map(|(_, entry)| entry).collect();
+        check(&index, &live);
+        while !index.poll() {
+            ::std::thread::yield_now();
+        }
+        assert!(!index.is_rebuilding());
+        assert!(index.pending_changes() < 1000);
+        check(&index, &live);
+
+        // A rebuild with nothing happening meanwhile folds every change in.
+        index.start_rebuild();
+        index.finish_rebuild();
+        assert_eq!(index.pending_changes(), 0);
+        check(&index, &live);
+        assert!(!index.poll());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Serializer};
+
+use aabb::Aabb;
+use arena::{Arena, NodeId};
+use super::{Mbr, RTree, RTreeConfig, NodeStorage, LeafItem};
+
+// Trees are written as a nested hierarchy of nodes, as if each node owned
+// its children, rather than as the arena they are stored in.  The format
+// does not depend on which slots the nodes happen to occupy.
+
+/// The serialized fields of an `RTree`.  The id index and the nodes' item
+/// counts are left out and rebuilt from the leaves when a tree is loaded.
+#[derive(Serialize)]
+#[serde(rename = "RTree")]
+struct TreeView<'a, T, const N: usize> where T: Mbr<N> + 'a {
+    root: Option<NodeView<'a, T, N>>,
+    config: &'a RTreeConfig,
+    next_id: u64,
+}
+
+struct NodeView<'a, T, const N: usize> where T: Mbr<N> + 'a {
+    nodes: &'a Arena<T, N>,
+    node: NodeId,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "RTreeNode")]
+struct NodeFields<'a, T, const N: usize> where T: Mbr<N> + 'a {
+    bbox: &'a Aabb<N>,
+    storage: StorageView<'a, T, N>,
+}
+
+struct StorageView<'a, T, const N: usize> where T: Mbr<N> + 'a {
+    nodes: &'a Arena<T, N>,
+    storage: &'a NodeStorage<T, N>,
+}
+
+struct ChildrenView<'a, T, const N: usize> where T: Mbr<N> + 'a {
+    nodes: &'a Arena<T, N>,
+    children: &'a [NodeId],
+}
+
+impl<'a, T, const N: usize> Serialize for NodeView<'a, T, N> where T: Mbr<N> + Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let node = &self.nodes[self.node];
+        NodeFields {
+            bbox: &node.bbox,
+            storage: StorageView { nodes: self.nodes, storage: &node.storage },
+        }.serialize(serializer)
+    }
+}
+
+impl<'a, T, const N: usize> Serialize for StorageView<'a, T, N> where T: Mbr<N> + Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match *self.storage {
+            NodeStorage::Interior(ref children) => {
+                let children = ChildrenView { nodes: self.nodes, children };
+                serializer.serialize_newtype_variant("NodeStorage", 0, "Interior", &children)
+            },
+            NodeStorage::Leaf(ref items) => serializer.serialize_newtype_variant("NodeStorage", 1, "Leaf", &items[..]),
+        }
+    }
+}
+
+impl<'a, T, const N: usize> Serialize for ChildrenView<'a, T, N> where T: Mbr<N> + Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.collect_seq(self.children.iter().map(|&node| NodeView { nodes: self.nodes, node }))
+    }
+}
+
+impl<T, const N: usize> Serialize for RTree<T, N> where T: Mbr<N> + Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        TreeView {
+            root: self.root.map(|node| NodeView { nodes: &self.nodes, node }),
+            config: &self.config,
+            next_id: self.next_id,
+        }.serialize(serializer)
+    }
+}
+
+/// The serialized fields of an `RTree`, as they are loaded.
+#[derive(Debug, Deserialize)]
+pub struct TreeParts<T, const N: usize> where T: Mbr<N> {
+    root: Option<NodeParts<T, N>>,
+    config: RTreeConfig,
+    next_id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeParts<T, const N: usize> where T: Mbr<N> {
+    bbox: Aabb<N>,
+    storage: StorageParts<T, N>,
+}
+
+#[derive(Debug, Deserialize)]
+enum StorageParts<T, const N: usize> where T: Mbr<N> {
+    Interior(Vec<NodeParts<T, N>>),
+    Leaf(Vec<LeafItem<T, N>>),
+}
+
+/// Move `node` and everything below it into `nodes`.
+fn store<T, const N: usize>(nodes: &mut Arena<T, N>, node: NodeParts<T, N>) -> NodeId where T: Mbr<N> {
+    let storage = match node.storage {
+        StorageParts::Interior(children) => NodeStorage::Interior(children.into_iter().map(|c| store(nodes, c)).collect()),
+        StorageParts::Leaf(items) => NodeStorage::leaf(items),
+    };
+    nodes.add(node.bbox, storage)
+}
+
+impl<T, const N: usize> From<TreeParts<T, N>> for RTree<T, N> where T: Mbr<N> {
+    fn from(parts: TreeParts<T, N>) -> RTree<T, N> {
+        let mut nodes = Arena::new();
+        let root = parts.root.map(|root| store(&mut nodes, root));
+        let mut tree = RTree {
+            root,
+            nodes,
+            config: parts.config,
+            ids: HashMap::new(),
+            next_id: parts.next_id,
+        };
+        tree.reindex();
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::super::{RTree, RTreeConfig, SplitStrategy};
+    use super::super::test_helpers::{Sphere, random_spheres, check_tree};
+
+    #[test]
+    fn test_round_trip() {
+        let config = RTreeConfig::new(8, 3).unwrap().with_split_strategy(SplitStrategy::RStar);
+        let mut tree: RTree<Sphere> = RTree::with_config(config);
+        let ids: Vec<_> = random_spheres(1000, 21).into_iter().map(|s| tree.insert(s)).collect();
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let mut loaded: RTree<Sphere> = serde_json::from_str(&json).unwrap();
+        check_tree(&loaded);
+        assert_eq!(loaded.len(), tree.len());
+        assert_eq!(loaded.depth(), tree.depth());
+        assert_eq!(loaded.config(), tree.config());
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+
+        for id in ids.iter() {
+            assert_eq!(loaded.get(*id), tree.get(*id));
+        }
+        let envelope = BBox {
+            min: Vec3::xyz(100.0, 100.0, 100.0),
+            max: Vec3::xyz(400.0, 400.0, 400.0),
+        };
+        assert_eq!(loaded.iter_bbox(&envelope).count(), tree.iter_bbox(&envelope).count());
+
+        let fresh = loaded.insert(Sphere::new(Vec3::zero(), 1.0).unwrap());
+        assert!(!ids.contains(&fresh));
+        assert!(loaded.remove_by_id(ids[0]).is_some());
+        check_tree(&loaded);
+    }
+}
@@ -0,0 +1,487 @@
+//! Ready-made primitives which can be stored in an `RTree` and traced
+//! against rays:
+//!
+//! ```
+//! use rtree::prelude::*;
+//! use rtree::shapes::Triangle;
+//!
+//! let mut tree = RTree::new();
+//! tree.insert(Triangle::new(Vec3::xyz(0.0, 0.0, 5.0), Vec3::xyz(1.0, 0.0, 5.0), Vec3::xyz(0.0, 1.0, 5.0)));
+//! let ray = Ray::new(Vec3::xyz(0.2, 0.2, 0.0), Vec3::xyz(0.0, 0.0, 1.0));
+//! let (t, _, hit) = tree.closest_hit(&ray).unwrap();
+//! assert_eq!(t, 5.0);
+//! assert_eq!(hit.normal, Vec3::xyz(0.0, 0.0, 1.0));
+//! ```
+
+use std::f64;
+
+use bbox::BBox;
+use hit::{Intersectable, Intersection};
+use nearest::ClosestPoint;
+use ray::Ray;
+use vec3::Vec3;
+use super::Mbr;
+
+/// Determinants and denominators below this are treated as a ray running
+/// parallel to the surface.
+const EPSILON: f64 = 1e-12;
+
+const SPHERE_RADIUS_TOO_SMALL: &str = "sphere radius must be above zero";
+const PLANE_NORMAL_IS_ZERO: &str = "plane normal must not be zero";
+
+/// A triangle, intersected with the Möller–Trumbore algorithm.  Its normal
+/// follows the right hand rule: it faces the side from which `a`, `b` and
+/// `c` run counter-clockwise.  Both sides can be hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3) -> Triangle {
+        Triangle { a, b, c }
+    }
+
+    /// The unit normal, or NaNs for a degenerate triangle.
+    pub fn normal(&self) -> Vec3 {
+        (self.b - self.a).cross(&(self.c - self.a)).unit()
+    }
+}
+
+impl Mbr for Triangle {
+    fn mbr(&self) -> BBox {
+        ::bbox::union_point(&::bbox::union_points(&self.a, &self.b), &self.c)
+    }
+}
+
+impl Intersectable for Triangle {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let e1 = self.b - self.a;
+        let e2 = self.c - self.a;
+        let p = ray.direction.cross(&e2);
+        let det = e1.dot(&p);
+        if det.abs() < EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = ray.origin - self.a;
+        let u = s.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&e1);
+        let v = ray.direction.dot(&q) * inv_det;
+        if v < 0.0 || 1.0 < u + v {
+            return None;
+        }
+        let t = e2.dot(&q) * inv_det;
+        if !ray.in_range(t) {
+            return None;
+        }
+        Some(Intersection {
+            t,
+            point: ray.point_at(t),
+            normal: e1.cross(&e2).unit(),
+        })
+    }
+}
+
+impl ClosestPoint for Triangle {
+    // From Ericson, "Real-Time Collision Detection", section 5.1.5: find
+    // the Voronoi region of the triangle the point lies in.
+    fn closest_point(&self, point: &Vec3) -> Vec3 {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let ab = b - a;
+        let ac = c - a;
+        let ap = *point - a;
+        let d1 = ab.dot(&ap);
+        let d2 = ac.dot(&ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a;
+        }
+
+        let bp = *point - b;
+        let d3 = ab.dot(&bp);
+        let d4 = ac.dot(&bp);
+        if 0.0 <= d3 && d4 <= d3 {
+            return b;
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && 0.0 <= d1 && d3 <= 0.0 {
+            return a + ab.scale(d1 / (d1 - d3));
+        }
+
+        let cp = *point - c;
+        let d5 = ab.dot(&cp);
+        let d6 = ac.dot(&cp);
+        if 0.0 <= d6 && d5 <= d6 {
+            return c;
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && 0.0 <= d2 && d6 <= 0.0 {
+            return a + ac.scale(d2 / (d2 - d6));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && 0.0 <= d4 - d3 && 0.0 <= d5 - d6 {
+            return b + (c - b).scale((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+
+        let denom = 1.0 / (va + vb + vc);
+        a + ab.scale(vb * denom) + ac.scale(vc * denom)
+    }
+}
+
+/// A sphere.  Its normals face outwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sphere {
+    center: Vec3,
+    radius: f64,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f64) -> Result<Sphere, &'static str> {
+        if radius <= 0.0 {
+            return Err(SPHERE_RADIUS_TOO_SMALL);
+        }
+        Ok(Sphere {
+            center,
+            radius,
+        })
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.center
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+impl Mbr for Sphere {
+    fn mbr(&self) -> BBox {
+        BBox {
+            min: self.center - self.radius,
+            max: self.center + self.radius,
+        }
+    }
+}
+
+impl Intersectable for Sphere {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let oc = ray.origin - self.center;
+        let a = ray.direction.dot(&ray.direction);
+        let b = oc.dot(&ray.direction);
+        let c = oc.dot(&oc) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // The near root, or the far one if the ray starts inside.
+        let root = discriminant.sqrt();
+        let t = if ray.min_t < (-b - root) / a { (-b - root) / a } else { (-b + root) / a };
+        if !ray.in_range(t) {
+            return None;
+        }
+        let point = ray.point_at(t);
+        Some(Intersection {
+            t,
+            point,
+            normal: (point - self.center).scale(1.0 / self.radius),
+        })
+    }
+}
+
+impl ClosestPoint for Sphere {
+    fn closest_point(&self, point: &Vec3) -> Vec3 {
+        self.center + (*point - self.center).unit().scale(self.radius)
+    }
+}
+
+/// A solid axis-aligned box.  Its normals face outwards, along the axis of
+/// the face that was hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Aabb {
+    pub bbox: BBox,
+}
+
+impl Aabb {
+    pub fn new(bbox: BBox) -> Aabb {
+        Aabb { bbox }
+    }
+
+    /// The outward normal of the face nearest to `point`.
+    fn face_normal(&self, point: &Vec3) -> Vec3 {
+        let faces = [
+            (point.x - self.bbox.min.x, Vec3::xyz(-1.0, 0.0, 0.0)),
+            (self.bbox.max.x - point.x, Vec3::xyz(1.0, 0.0, 0.0)),
+            (point.y - self.bbox.min.y, Vec3::xyz(0.0, -1.0, 0.0)),
+            (self.bbox.max.y - point.y, Vec3::xyz(0.0, 1.0, 0.0)),
+            (point.z - self.bbox.min.z, Vec3::xyz(0.0, 0.0, -1.0)),
+            (self.bbox.max.z - point.z, Vec3::xyz(0.0, 0.0, 1.0)),
+        ];
+        faces.iter()
+            .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+            .map(|&(_, normal)| normal)
+            .unwrap()
+    }
+}
+
+impl Mbr for Aabb {
+    fn mbr(&self) -> BBox {
+        self.bbox
+    }
+}
+
+impl Intersectable for Aabb {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (t_min, t_max) = self.bbox.intersect_ray_t(ray)?;
+        let t = if ray.min_t < t_min { t_min } else { t_max };
+        if !ray.in_range(t) {
+            return None;
+        }
+        let point = ray.point_at(t);
+        Some(Intersection {
+            t,
+            point,
+            normal: self.face_normal(&point),
+        })
+    }
+}
+
+impl ClosestPoint for Aabb {
+    fn closest_point(&self, point: &Vec3) -> Vec3 {
+        let (min, max) = (self.bbox.min, self.bbox.max);
+        if !self.bbox.inside(point) {
+            return Vec3::xyz(
+                point.x.max(min.x).min(max.x),
+                point.y.max(min.y).min(max.y),
+                point.z.max(min.z).min(max.z));
+        }
+
+        // Inside, so push the point out through the nearest face.
+        let normal = self.face_normal(point);
+        let mut surface = *point;
+        if normal.x != 0.0 {
+            surface.x = if normal.x < 0.0 { min.x } else { max.x };
+        } else if normal.y != 0.0 {
+            surface.y = if normal.y < 0.0 { min.y } else { max.y };
+        } else {
+            surface.z = if normal.z < 0.0 { min.z } else { max.z };
+        }
+        surface
+    }
+}
+
+/// An infinite plane through `point`.  Hits report the plane's own unit
+/// normal, whichever side the ray comes from.
+///
+/// Unless the plane is perpendicular to an axis its bounding box is
+/// infinite, which makes it a poor fit for a tree; test planes against rays
+/// separately instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Plane {
+    point: Vec3,
+    normal: Vec3,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3) -> Result<Plane, &'static str> {
+        if normal.dot(&normal) == 0.0 {
+            return Err(PLANE_NORMAL_IS_ZERO);
+        }
+        Ok(Plane {
+            point,
+            normal: normal.unit(),
+        })
+    }
+
+    pub fn point(&self) -> Vec3 {
+        self.point
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    /// How far `point` lies in front of the plane, on the side its normal
+    /// points to.  Negative behind it.
+    pub fn signed_distance(&self, point: &Vec3) -> f64 {
+        (*point - self.point).dot(&self.normal)
+    }
+}
+
+impl Mbr for Plane {
+    fn mbr(&self) -> BBox {
+        let n = self.normal;
+        let extent = |flat: bool, at: f64| if flat { (at, at) } else { (f64::NEG_INFINITY, f64::INFINITY) };
+        let (min_x, max_x) = extent(n.y == 0.0 && n.z == 0.0, self.point.x);
+        let (min_y, max_y) = extent(n.x == 0.0 && n.z == 0.0, self.point.y);
+        let (min_z, max_z) = extent(n.x == 0.0 && n.y == 0.0, self.point.z);
+        BBox {
+            min: Vec3::xyz(min_x, min_y, min_z),
+            max: Vec3::xyz(max_x, max_y, max_z),
+        }
+    }
+}
+
+impl Intersectable for Plane {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() < EPSILON {
+            return None;
+        }
+        let t = (self.point - ray.origin).dot(&self.normal) / denom;
+        if !ray.in_range(t) {
+            return None;
+        }
+        Some(Intersection {
+            t,
+            point: ray.point_at(t),
+            normal: self.normal,
+        })
+    }
+}
+
+impl ClosestPoint for Plane {
+    fn closest_point(&self, point: &Vec3) -> Vec3 {
+        *point - self.normal.scale(self.signed_distance(point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::super::{Intersectable, ClosestPoint, Mbr, RTree};
+    use super::{Aabb, Plane, Sphere, Triangle};
+
+    fn unit_triangle() -> Triangle {
+        Triangle::new(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0), Vec3::xyz(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn test_triangle() {
+        let triangle = unit_triangle();
+        let bbox = triangle.mbr();
+        assert_eq!(bbox.min, Vec3::zero());
+        assert_eq!(bbox.max, Vec3::xyz(1.0, 1.0, 0.0));
+
+        let hit = triangle.intersect(&Ray::new(Vec3::xyz(0.25, 0.25, 2.0), Vec3::xyz(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.point, Vec3::xyz(0.25, 0.25, 0.0));
+        assert_eq!(hit.normal, Vec3::xyz(0.0, 0.0, 1.0));
+
+        // Hit from behind, past an edge, and parallel.
+        assert!(triangle.intersect(&Ray::new(Vec3::xyz(0.25, 0.25, -2.0), Vec3::xyz(0.0, 0.0, 1.0))).is_some());
+        assert!(triangle.intersect(&Ray::new(Vec3::xyz(0.75, 0.75, 2.0), Vec3::xyz(0.0, 0.0, -1.0))).is_none());
+        assert!(triangle.intersect(&Ray::new(Vec3::xyz(0.25, 0.25, 2.0), Vec3::xyz(1.0, 0.0, 0.0))).is_none());
+        assert!(triangle.intersect(&Ray::new(Vec3::xyz(0.25, 0.25, 2.0), Vec3::xyz(0.0, 0.0, 1.0))).is_none());
+
+        assert_eq!(triangle.closest_point(&Vec3::xyz(0.25, 0.25, 3.0)), Vec3::xyz(0.25, 0.25, 0.0));
+        assert_eq!(triangle.closest_point(&Vec3::xyz(-1.0, -1.0, 0.0)), Vec3::zero());
+        assert_eq!(triangle.closest_point(&Vec3::xyz(1.0, 1.0, 0.0)), Vec3::xyz(0.5, 0.5, 0.0));
+        assert_eq!(triangle.closest_point(&Vec3::xyz(0.5, -1.0, 1.0)), Vec3::xyz(0.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_sphere() {
+        assert!(Sphere::new(Vec3::zero(), 0.0).is_err());
+        let sphere = Sphere::new(Vec3::xyz(5.0, 0.0, 0.0), 1.0).unwrap();
+        let hit = sphere.intersect(&Ray::new(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0))).unwrap();
+        assert_eq!(hit.t, 4.0);
+        assert_eq!(hit.normal, Vec3::xyz(-1.0, 0.0, 0.0));
+
+        let inside = sphere.intersect(&Ray::new(Vec3::xyz(5.0, 0.0, 0.0), Vec3::xyz(0.0, 1.0, 0.0))).unwrap();
+        assert_eq!(inside.t, 1.0);
+        assert!(sphere.intersect(&Ray::new(Vec3::zero(), Vec3::xyz(-1.0, 0.0, 0.0))).is_none());
+
+        // A segment ending inside the sphere hits the near side; one
+        // starting inside hits only the far side.
+        let x = Vec3::xyz(1.0, 0.0, 0.0);
+        assert!(sphere.intersect(&Ray::with_max_t(Vec3::zero(), x, 3.5)).is_none());
+        assert_eq!(sphere.intersect(&Ray::with_max_t(Vec3::zero(), x, 5.0)).unwrap().t, 4.0);
+        let from_inside = Ray { min_t: 4.5, ..Ray::new(Vec3::zero(), x) };
+        assert_eq!(sphere.intersect(&from_inside).unwrap().t, 6.0);
+        assert_eq!(sphere.closest_point(&Vec3::zero()), Vec3::xyz(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_aabb() {
+        let aabb = Aabb::new(BBox { min: Vec3::xyz(2.0, -1.0, -1.0), max: Vec3::xyz(4.0, 1.0, 1.0) });
+        let hit = aabb.intersect(&Ray::new(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0))).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.normal, Vec3::xyz(-1.0, 0.0, 0.0));
+
+        let inside = aabb.intersect(&Ray::new(Vec3::xyz(3.0, 0.0, 0.0), Vec3::xyz(0.0, 0.0, 2.0))).unwrap();
+        assert_eq!(inside.t, 0.5);
+        assert_eq!(inside.normal, Vec3::xyz(0.0, 0.0, 1.0));
+        assert!(aabb.intersect(&Ray::new(Vec3::zero(), Vec3::xyz(-1.0, 0.0, 0.0))).is_none());
+        assert!(aabb.intersect(&Ray::with_max_t(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0), 1.5)).is_none());
+        assert!(aabb.intersect(&Ray::with_max_t(Vec3::xyz(3.0, 0.0, 0.0), Vec3::xyz(1.0, 0.0, 0.0), 0.5)).is_none());
+
+        assert_eq!(aabb.closest_point(&Vec3::xyz(0.0, 5.0, 0.5)), Vec3::xyz(2.0, 1.0, 0.5));
+        assert_eq!(aabb.closest_point(&Vec3::xyz(3.9, 0.0, 0.0)), Vec3::xyz(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_plane() {
+        assert!(Plane::new(Vec3::zero(), Vec3::zero()).is_err());
+        let floor = Plane::new(Vec3::xyz(0.0, -2.0, 0.0), Vec3::xyz(0.0, 3.0, 0.0)).unwrap();
+        assert_eq!(floor.normal(), Vec3::xyz(0.0, 1.0, 0.0));
+        let bbox = floor.mbr();
+        assert_eq!((bbox.min.y, bbox.max.y), (-2.0, -2.0));
+        assert!(bbox.min.x.is_infinite() && bbox.max.z.is_infinite());
+
+        let hit = floor.intersect(&Ray::new(Vec3::xyz(1.0, 2.0, 1.0), Vec3::xyz(0.0, -2.0, 0.0))).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.point, Vec3::xyz(1.0, -2.0, 1.0));
+        assert!(floor.intersect(&Ray::new(Vec3::zero(), Vec3::xyz(1.0, 0.0, 0.0))).is_none());
+        assert!(floor.intersect(&Ray::new(Vec3::zero(), Vec3::xyz(0.0, 1.0, 0.0))).is_none());
+        assert_eq!(floor.closest_point(&Vec3::xyz(3.0, 4.0, 5.0)), Vec3::xyz(3.0, -2.0, 5.0));
+        assert_eq!(floor.signed_distance(&Vec3::xyz(3.0, 4.0, 5.0)), 6.0);
+        assert_eq!(floor.signed_distance(&Vec3::xyz(0.0, -3.0, 0.0)), -1.0);
+    }
+
+    #[test]
+    fn test_trace_mesh() {
+        // A unit cube of twelve triangles, traced from outside each face.
+        let corner = |i: usize| Vec3::xyz((i & 1) as f64, ((i >> 1) & 1) as f64, ((i >> 2) & 1) as f64);
+        let faces = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+        let mut mesh = RTree::new();
+        for face in faces.iter() {
+            let [a, b, c, d] = face.map(corner);
+            mesh.insert(Triangle::new(a, b, c));
+            mesh.insert(Triangle::new(a, c, d));
+        }
+
+        let ray = Ray::new(Vec3::xyz(0.3, 0.6, -4.0), Vec3::xyz(0.0, 0.0, 1.0));
+        let (t, _, hit) = mesh.closest_hit(&ray).unwrap();
+        assert_eq!(t, 4.0);
+        assert_eq!(hit.normal, Vec3::xyz(0.0, 0.0, -1.0));
+
+        let ray = Ray::new(Vec3::xyz(3.0, 0.5, 0.5), Vec3::xyz(-1.0, 0.0, 0.0));
+        let (t, _, hit) = mesh.closest_hit(&ray).unwrap();
+        assert_eq!(t, 2.0);
+        assert_eq!(hit.normal, Vec3::xyz(1.0, 0.0, 0.0));
+
+        // Shadow rays: the light at x = 2.5 is visible from outside the
+        // cube, but not from behind it.
+        let light = Vec3::xyz(2.5, 0.5, 0.5);
+        let shadow = |from: Vec3| Ray::between(from, light);
+        assert!(mesh.closest_hit(&shadow(Vec3::xyz(4.0, 0.5, 0.5))).is_none());
+        assert!(mesh.closest_hit(&shadow(Vec3::xyz(-1.0, 0.5, 0.5))).is_some());
+    }
+}
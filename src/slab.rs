@@ -0,0 +1,439 @@
+use std::f64;
+
+use bbox::BBox;
+use ray::Ray;
+use vec3::Vec3;
+
+/// How many boxes a `BoxGroup` holds.
+pub const LANES: usize = 4;
+
+/// The bounds of up to four boxes stored axis by axis, so one ray can be
+/// tested against all of them in a single pass.  Unused lanes hold an
+/// inverted box that no ray enters.
+#[derive(Clone, Copy)]
+pub struct BoxGroup {
+    min: [[f64; LANES]; 3],
+    max: [[f64; LANES]; 3],
+}
+
+impl BoxGroup {
+    /// Pack `boxes`, at most `LANES` of them.
+    pub fn new(boxes: &[BBox]) -> BoxGroup {
+        assert!(boxes.len() <= LANES);
+        let mut group = BoxGroup {
+            min: [[f64::INFINITY; LANES]; 3],
+            max: [[f64::NEG_INFINITY; LANES]; 3],
+        };
+        for (lane, bbox) in boxes.iter().enumerate() {
+            group.min[0][lane] = bbox.min.x;
+            group.min[1][lane] = bbox.min.y;
+            group.min[2][lane] = bbox.min.z;
+            group.max[0][lane] = bbox.max.x;
+            group.max[1][lane] = bbox.max.y;
+            group.max[2][lane] = bbox.max.z;
+        }
+        group
+    }
+
+    /// Pack `boxes` into as many groups as they need.
+    pub fn pack(boxes: &[BBox], out: &mut Vec<BoxGroup>) {
+        out.extend(boxes.chunks(LANES).map(BoxGroup::new));
+    }
+
+    /// The box in `lane`.
+    pub fn bbox(&self, lane: usize) -> BBox {
+        BBox {
+            min: Vec3::xyz(self.min[0][lane], self.min[1][lane], self.min[2][lane]),
+            max: Vec3::xyz(self.max[0][lane], self.max[1][lane], self.max[2][lane]),
+        }
+    }
+
+    /// For each lane, the ray parameter at which `ray` enters the box, as
+    /// `BBox::intersect_ray_t` would give it, or infinity for a miss.
+    pub fn intersect_ray(&self, ray: &Ray) -> [f64; LANES] {
+        if ray.has_nan() {
+            return [f64::INFINITY; LANES];
+        }
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx") {
+                return unsafe { self.intersect_ray_avx(ray) };
+            }
+        }
+        self.intersect_ray_scalar(ray)
+    }
+
+    fn intersect_ray_scalar(&self, ray: &Ray) -> [f64; LANES] {
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let inverse = [ray.inverse_dir.x, ray.inverse_dir.y, ray.inverse_dir.z];
+        let mut t_min = [f64::NEG_INFINITY; LANES];
+        let mut t_max = [f64::INFINITY; LANES];
+        for axis in 0..3 {
+            let (near, far) = if ray.signs[axis] {
+                (&self.min[axis], &self.max[axis])
+            } else {
+                (&self.max[axis], &self.min[axis])
+            };
+            for lane in 0..LANES {
+                let t0 = (near[lane] - origin[axis]) * inverse[axis];
+                let t1 = (far[lane] - origin[axis]) * inverse[axis];
+                if t0 > t_min[lane] {
+                    t_min[lane] = t0;
+                }
+                if t1 < t_max[lane] {
+                    t_max[lane] = t1;
+                }
+            }
+        }
+
+        let mut entry = [f64::INFINITY; LANES];
+        for lane in 0..LANES {
+            if t_min[lane] <= t_max[lane] && t_max[lane] > ray.min_t && t_min[lane] <= ray.max_t && t_min[lane] < f64::INFINITY {
+                entry[lane] = t_min[lane];
+            }
+        }
+        entry
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[target_feature(enable = "avx")]
+    unsafe fn intersect_ray_avx(&self, ray: &Ray) -> [f64; LANES] {
+        use std::arch::x86_64::*;
+
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let inverse = [ray.inverse_dir.x, ray.inverse_dir.y, ray.inverse_dir.z];
+        let mut t_min = _mm256_set1_pd(f64::NEG_INFINITY);
+        let mut t_max = _mm256_set1_pd(f64::INFINITY);
+        for axis in 0..3 {
+            let (near, far) = if ray.signs[axis] {
+                (&self.min[axis], &self.max[axis])
+            } else {
+                (&self.max[axis], &self.min[axis])
+            };
+            let o = _mm256_set1_pd(origin[axis]);
+            let inv = _mm256_set1_pd(inverse[axis]);
+            let t0 = _mm256_mul_pd(_mm256_sub_pd(_mm256_loadu_pd(near.as_ptr()), o), inv);
+            let t1 = _mm256_mul_pd(_mm256_sub_pd(_mm256_loadu_pd(far.as_ptr()), o), inv);
+            // With a NaN operand these return the second one, which keeps
+            // the running bound just like the scalar comparisons do.
+            t_min = _mm256_max_pd(t0, t_min);
+            t_max = _mm256_min_pd(t1, t_max);
+        }
+
+        let infinity = _mm256_set1_pd(f64::INFINITY);
+        let hit = _mm256_and_pd(
+            _mm256_and_pd(
+                _mm256_cmp_pd(t_min, t_max, _CMP_LE_OQ),
+                _mm256_cmp_pd(t_max, _mm256_set1_pd(ray.min_t), _CMP_GT_OQ)),
+            _mm256_and_pd(
+                _mm256_cmp_pd(t_min, _mm256_set1_pd(ray.max_t), _CMP_LE_OQ),
+                _mm256_cmp_pd(t_min, infinity, _CMP_LT_OQ)));
+        let mut entry = [0.0; LANES];
+        _mm256_storeu_pd(entry.as_mut_ptr(), _mm256_blendv_pd(infinity, t_min, hit));
+        entry
+    }
+}
+
+/// A `BoxGroup` with its bounds rounded outward to `f32`, in half the
+/// memory.  Each box contains the one it was made from, so anything which
+/// reaches a box also reaches its compact copy, only sometimes sooner.
+#[derive(Clone, Copy)]
+pub struct CompactBoxGroup {
+    min: [[f32; LANES]; 3],
+    max: [[f32; LANES]; 3],
+}
+
+impl CompactBoxGroup {
+    /// Pack `boxes`, at most `LANES` of them.
+    pub fn new(boxes: &[BBox]) -> CompactBoxGroup {
+        let full = BoxGroup::new(boxes);
+        let mut group = CompactBoxGroup {
+            min: [[0.0; LANES]; 3],
+            max: [[0.0; LANES]; 3],
+        };
+        for axis in 0..3 {
+            for lane in 0..LANES {
+                group.min[axis][lane] = round_down(full.min[axis][lane]);
+                group.max[axis][lane] = round_up(full.max[axis][lane]);
+            }
+        }
+        group
+    }
+
+    /// Pack `boxes` into as many groups as they need.
+    pub fn pack(boxes: &[BBox], out: &mut Vec<CompactBoxGroup>) {
+        out.extend(boxes.chunks(LANES).map(CompactBoxGroup::new));
+    }
+
+    /// The boxes at full precision, which converts them exactly.
+    pub fn widen(&self) -> BoxGroup {
+        let mut group = BoxGroup {
+            min: [[0.0; LANES]; 3],
+            max: [[0.0; LANES]; 3],
+        };
+        for axis in 0..3 {
+            for lane in 0..LANES {
+                group.min[axis][lane] = self.min[axis][lane] as f64;
+                group.max[axis][lane] = self.max[axis][lane] as f64;
+            }
+        }
+        group
+    }
+
+    /// Like `BoxGroup::intersect_ray`, for the rounded boxes.
+    pub fn intersect_ray(&self, ray: &Ray) -> [f64; LANES] {
+        self.widen().intersect_ray(ray)
+    }
+}
+
+/// The largest `f32` no greater than `x`.
+fn round_down(x: f64) -> f32 {
+    let rounded = x as f32;
+    if rounded as f64 > x { rounded.next_down() } else { rounded }
+}
+
+/// The smallest `f32` no less than `x`.
+fn round_up(x: f64) -> f32 {
+    let rounded = x as f32;
+    if (rounded as f64) < x { rounded.next_up() } else { rounded }
+}
+
+/// Up to four rays stored axis by axis, so one box can be tested against
+/// all of them in a single pass.  Each lane keeps its own `max_t`, which a
+/// traversal lowers as it finds hits.  Unused lanes, and lanes holding a
+/// NaN ray, have a NaN `max_t` and enter no box.
+#[derive(Clone, Copy)]
+pub struct RayPacket {
+    origin: [[f64; LANES]; 3],
+    inverse: [[f64; LANES]; 3],
+    signs: [[bool; LANES]; 3],
+    min_t: [f64; LANES],
+    pub max_t: [f64; LANES],
+}
+
+impl RayPacket {
+    /// Pack `rays`, at most `LANES` of them.
+    pub fn new(rays: &[Ray]) -> RayPacket {
+        assert!(rays.len() <= LANES);
+        let mut packet = RayPacket {
+            origin: [[0.0; LANES]; 3],
+            inverse: [[0.0; LANES]; 3],
+            signs: [[false; LANES]; 3],
+            min_t: [0.0; LANES],
+            max_t: [f64::NAN; LANES],
+        };
+        for (lane, ray) in rays.iter().enumerate() {
+            let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+            let inverse = [ray.inverse_dir.x, ray.inverse_dir.y, ray.inverse_dir.z];
+            for axis in 0..3 {
+                packet.origin[axis][lane] = origin[axis];
+                packet.inverse[axis][lane] = inverse[axis];
+                packet.signs[axis][lane] = ray.signs[axis];
+            }
+            packet.min_t[lane] = ray.min_t;
+            if !ray.has_nan() {
+                packet.max_t[lane] = ray.max_t;
+            }
+        }
+        packet
+    }
+
+    /// For each lane, the ray parameter at which that ray enters `bbox`, as
+    /// `BBox::intersect_ray_t` would give it with the lane's `max_t`, or
+    /// infinity for a miss.
+    pub fn intersect_box(&self, bbox: &BBox) -> [f64; LANES] {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx") {
+                return unsafe { self.intersect_box_avx(bbox) };
+            }
+        }
+        self.intersect_box_scalar(bbox)
+    }
+
+    fn intersect_box_scalar(&self, bbox: &BBox) -> [f64; LANES] {
+        let mins = [bbox.min.x, bbox.min.y, bbox.min.z];
+        let maxs = [bbox.max.x, bbox.max.y, bbox.max.z];
+        let mut t_min = [f64::NEG_INFINITY; LANES];
+        let mut t_max = [f64::INFINITY; LANES];
+        for axis in 0..3 {
+            for lane in 0..LANES {
+                let (near, far) = if self.signs[axis][lane] {
+                    (mins[axis], maxs[axis])
+                } else {
+                    (maxs[axis], mins[axis])
+                };
+                let t0 = (near - self.origin[axis][lane]) * self.inverse[axis][lane];
+                let t1 = (far - self.origin[axis][lane]) * self.inverse[axis][lane];
+                if t0 > t_min[lane] {
+                    t_min[lane] = t0;
+                }
+                if t1 < t_max[lane] {
+                    t_max[lane] = t1;
+                }
+            }
+        }
+
+        let mut entry = [f64::INFINITY; LANES];
+        for lane in 0..LANES {
+            if t_min[lane] <= t_max[lane] && t_max[lane] > self.min_t[lane] && t_min[lane] <= self.max_t[lane] && t_min[lane] < f64::INFINITY {
+                entry[lane] = t_min[lane];
+            }
+        }
+        entry
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[target_feature(enable = "avx")]
+    unsafe fn intersect_box_avx(&self, bbox: &BBox) -> [f64; LANES] {
+        use std::arch::x86_64::*;
+
+        let mins = [bbox.min.x, bbox.min.y, bbox.min.z];
+        let maxs = [bbox.max.x, bbox.max.y, bbox.max.z];
+        let mut t_min = _mm256_set1_pd(f64::NEG_INFINITY);
+        let mut t_max = _mm256_set1_pd(f64::INFINITY);
+        for axis in 0..3 {
+            let signs = &self.signs[axis];
+            let positive = _mm256_castsi256_pd(_mm256_set_epi64x(
+                -(signs[3] as i64), -(signs[2] as i64), -(signs[1] as i64), -(signs[0] as i64)));
+            let (min, max) = (_mm256_set1_pd(mins[axis]), _mm256_set1_pd(maxs[axis]));
+            let near = _mm256_blendv_pd(max, min, positive);
+            let far = _mm256_blendv_pd(min, max, positive);
+            let o = _mm256_loadu_pd(self.origin[axis].as_ptr());
+            let inv = _mm256_loadu_pd(self.inverse[axis].as_ptr());
+            let t0 = _mm256_mul_pd(_mm256_sub_pd(near, o), inv);
+            let t1 = _mm256_mul_pd(_mm256_sub_pd(far, o), inv);
+            t_min = _mm256_max_pd(t0, t_min);
+            t_max = _mm256_min_pd(t1, t_max);
+        }
+
+        let infinity = _mm256_set1_pd(f64::INFINITY);
+        let hit = _mm256_and_pd(
+            _mm256_and_pd(
+                _mm256_cmp_pd(t_min, t_max, _CMP_LE_OQ),
+                _mm256_cmp_pd(t_max, _mm256_loadu_pd(self.min_t.as_ptr()), _CMP_GT_OQ)),
+            _mm256_and_pd(
+                _mm256_cmp_pd(t_min, _mm256_loadu_pd(self.max_t.as_ptr()), _CMP_LE_OQ),
+                _mm256_cmp_pd(t_min, infinity, _CMP_LT_OQ)));
+        let mut entry = [0.0; LANES];
+        _mm256_storeu_pd(entry.as_mut_ptr(), _mm256_blendv_pd(infinity, t_min, hit));
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::{BoxGroup, CompactBoxGroup, RayPacket, LANES};
+    use super::super::test_helpers::Lcg;
+
+    #[test]
+    fn test_group_matches_scalar() {
+        let mut rng = Lcg::new(51);
+        let mut point = || Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 20.0 - 10.0;
+        let boxes: Vec<BBox> = (0..63).map(|_| {
+            let (a, b) = (point(), point());
+            ::bbox::union_points(&a, &b)
+        }).collect();
+        let mut groups = Vec::new();
+        BoxGroup::pack(&boxes, &mut groups);
+        assert_eq!(groups.len(), 16);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            // Every other ray is a short segment.
+            let max_t = if i % 2 == 0 { f64::INFINITY } else { 0.5 };
+            let ray = Ray::with_max_t(point(), point(), max_t);
+            for (i, group) in groups.iter().enumerate() {
+                let entries = group.intersect_ray(&ray);
+                assert_eq!(entries, group.intersect_ray_scalar(&ray));
+                for (lane, &entry) in entries.iter().enumerate() {
+                    let expected = boxes.get(i * LANES + lane).and_then(|b| b.intersect_ray_t(&ray));
+                    match expected {
+                        Some((t, _)) => {
+                            assert_eq!(entry, t);
+                            hits += 1;
+                        },
+                        None => assert_eq!(entry, f64::INFINITY),
+                    }
+                }
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn test_compact_group_contains() {
+        let mut rng = Lcg::new(53);
+        let mut point = || Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 2e4 - 1e4;
+        let mut boxes: Vec<BBox> = (0..62).map(|_| {
+            let (a, b) = (point(), point());
+            ::bbox::union_points(&a, &b)
+        }).collect();
+        // A box beyond the range of f32, and a point.
+        boxes.push(BBox { min: Vec3::xyz(-1e300, 0.1, 0.1), max: Vec3::xyz(1e300, 0.2, 0.2) });
+        boxes.push(BBox::from_point(Vec3::xyz(0.1, 1.0 / 3.0, 1e-50)));
+        let mut groups = Vec::new();
+        CompactBoxGroup::pack(&boxes, &mut groups);
+        assert_eq!(groups.len(), 16);
+
+        for (i, bbox) in boxes.iter().enumerate() {
+            let rounded = groups[i / LANES].widen().bbox(i % LANES);
+            assert!(rounded.contains(bbox), "{:?} {:?}", rounded, bbox);
+            assert_ne!(rounded, *bbox);
+        }
+        for _ in 0..200 {
+            let ray = Ray::new(point(), point());
+            for (i, group) in groups.iter().enumerate() {
+                let entries = group.intersect_ray(&ray);
+                for (lane, &entry) in entries.iter().enumerate() {
+                    // Any box the ray enters, it enters no later rounded.
+                    if let Some((t, _)) = boxes[i * LANES + lane].intersect_ray_t(&ray) {
+                        assert!(entry <= t);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_packet_matches_scalar() {
+        let mut rng = Lcg::new(52);
+        let mut point = || Vec3::xyz(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 20.0 - 10.0;
+        let boxes: Vec<BBox> = (0..50).map(|_| {
+            let (a, b) = (point(), point());
+            ::bbox::union_points(&a, &b)
+        }).collect();
+        let mut rays: Vec<Ray> = (0..198).map(|i| {
+            let max_t = if i % 2 == 0 { f64::INFINITY } else { 0.5 };
+            Ray::with_max_t(point(), point(), max_t)
+        }).collect();
+        // Axis-aligned rays starting on a face, and a NaN ray.
+        rays.push(Ray::new(Vec3::xyz(boxes[0].min.x, 0.0, 0.0), Vec3::xyz(0.0, 1.0, 0.0)));
+        rays.push(Ray::new(Vec3::zero(), Vec3::xyz(f64::NAN, 1.0, 0.0)));
+
+        let mut hits = 0;
+        // The last packet is partly empty.
+        for chunk in rays.chunks(3) {
+            let packet = RayPacket::new(chunk);
+            for bbox in boxes.iter() {
+                let entries = packet.intersect_box(bbox);
+                assert_eq!(entries, packet.intersect_box_scalar(bbox));
+                for (lane, &entry) in entries.iter().enumerate() {
+                    match chunk.get(lane).and_then(|ray| bbox.intersect_ray_t(ray)) {
+                        Some((t, _)) => {
+                            assert_eq!(entry, t);
+                            hits += 1;
+                        },
+                        None => assert_eq!(entry, f64::INFINITY),
+                    }
+                }
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+use std::vec::IntoIter as VecIntoIter;
+
+use aabb::Aabb;
+use arena::{Arena, NodeId};
+use bbox::BBox;
+use curve::morton_key;
+use ray::Ray;
+use super::{Mbr, RTree, NodeStorage, LeafItem};
+
+/// Items in a stable order: by the Morton code of the center of their
+/// bounding box, then by when they were added.  Created by
+/// `RTree::iter_sorted` and the other `_sorted` queries.
+///
+/// The order depends only on the items, their boxes and ids, never on the
+/// shape of the tree, so the same items come out in the same order however
+/// the tree was built or edited, and however it splits ties.  Accumulating
+/// floating point results in that order gives the same sums from run to
+/// run.  The matching items are collected and sorted up front.
+pub struct SortedIter<'a, T> {
+    items: VecIntoIter<&'a T>,
+}
+
+impl<'a, T> Iterator for SortedIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.items.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<'a, T> fmt::Debug for SortedIter<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SortedIter").field("remaining", &self.items.len()).finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for SortedIter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        self.items.next_back()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for SortedIter<'a, T> {}
+
+impl<T> RTree<T> where T: Mbr {
+    /// The items whose bounding box intersects `ray`, like `iter_ray`, but
+    /// in the stable order of `SortedIter`.
+    pub fn iter_ray_sorted(&self, ray: &Ray) -> SortedIter<'_, T> {
+        self.sorted(&|bbox: &BBox| bbox.intersects(ray))
+    }
+}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    /// Every item, like `iter`, but in the stable order of `SortedIter`.
+    pub fn iter_sorted(&self) -> SortedIter<'_, T> {
+        self.sorted(&|_: &Aabb<N>| true)
+    }
+
+    /// The items whose bounding box overlaps `query`, like `iter_bbox`,
+    /// but in the stable order of `SortedIter`.
+    pub fn iter_bbox_sorted(&self, query: &Aabb<N>) -> SortedIter<'_, T> {
+        self.sorted(&|bbox: &Aabb<N>| bbox.overlaps(query))
+    }
+
+    fn sorted<P>(&self, matches: &P) -> SortedIter<'_, T> where P: Fn(&Aabb<N>) -> bool {
+        let mut found = Vec::new();
+        if let Some(root) = self.root {
+            if matches(&self.nodes[root].bbox) {
+                self.nodes.collect_leaves(root, matches, &mut found);
+            }
+        }
+        found.sort_by_cached_key(|leaf| (morton_key(&leaf.bbox.center()), leaf.id));
+        SortedIter {
+            items: found.into_iter().map(|leaf| &leaf.item).collect::<Vec<_>>().into_iter(),
+        }
+    }
+}
+
+impl<T, const N: usize> Arena<T, N> where T: Mbr<N> {
+    /// Push every entry below `node` whose bounding box `matches` onto
+    /// `found`, descending only into matching nodes.
+    fn collect_leaves<'a, P>(&'a self, node: NodeId, matches: &P, found: &mut Vec<&'a LeafItem<T, N>>)
+        where
+            P: Fn(&Aabb<N>) -> bool {
+
+        match self[node].storage {
+            NodeStorage::Interior(ref children) => {
+                for &child in children.iter() {
+                    if matches(&self[child].bbox) {
+                        self.collect_leaves(child, matches, found);
+                    }
+                }
+            },
+            NodeStorage::Leaf(ref items) => found.extend(items.iter().filter(|leaf| matches(&leaf.bbox))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree, RTreeConfig, SplitStrategy};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_iter_sorted() {
+        // The same items, inserted in opposite orders into differently
+        // shaped trees, or bulk loaded.
+        let spheres = random_spheres(2000, 47);
+        let mut forward: RTree<&Sphere> = RTree::new();
+        let mut backward: RTree<&Sphere> = RTree::with_config(RTreeConfig::new(6, 2).unwrap().with_split_strategy(SplitStrategy::RStar));
+        forward.extend(spheres.iter());
+        backward.extend(spheres.iter().rev());
+        let packed: RTree<&Sphere> = RTree::bulk_load(spheres.iter().collect());
+        let centers = |items: Vec<&&Sphere>| -> Vec<Vec3> { items.into_iter().map(|s| s.mbr().center()).collect() };
+        assert_ne!(centers(forward.iter().collect()), centers(backward.iter().collect()));
+
+        let sorted = centers(forward.iter_sorted().collect());
+        assert_eq!(sorted.len(), 2000);
+        assert_eq!(centers(backward.iter_sorted().collect()), sorted);
+        assert_eq!(centers(packed.iter_sorted().collect()), sorted);
+
+        let query = BBox {
+            min: Vec3::xyz(100.0, 200.0, 300.0),
+            max: Vec3::xyz(600.0, 700.0, 800.0),
+        };
+        let in_query = centers(forward.iter_bbox_sorted(&query).collect());
+        assert_eq!(in_query.len(), forward.iter_bbox(&query).count());
+        assert_eq!(centers(backward.iter_bbox_sorted(&query).collect()), in_query);
+        let in_order: Vec<Vec3> = sorted.iter().cloned().filter(|c| {
+            spheres.iter().any(|s| s.mbr().center() == *c && s.mbr().overlaps(&query))
+        }).collect();
+        assert_eq!(in_query, in_order);
+
+        let ray = Ray::new(Vec3::xyz(-10.0, 500.0, 500.0), Vec3::xyz(1.0, 0.1, 0.05));
+        let hit = centers(packed.iter_ray_sorted(&ray).collect());
+        assert_eq!(hit.len(), packed.iter_ray(&ray).count());
+        assert_eq!(centers(forward.iter_ray_sorted(&ray).collect()), hit);
+
+        // Equal boxes come in the order they were added.
+        let mut boxes: RTree<(BBox, usize)> = RTree::new();
+        let unit = BBox { min: Vec3::zero(), max: Vec3::one() };
+        for label in 0..100 {
+            boxes.insert((unit, label));
+        }
+        let labels: Vec<usize> = boxes.iter_sorted().map(|&(_, label)| label).collect();
+        assert_eq!(labels, (0..100).collect::<Vec<_>>());
+        assert_eq!(boxes.iter_sorted().next_back(), Some(&(unit, 99)));
+    }
+}
@@ -0,0 +1,421 @@
+use arena::Arena;
+use bbox::BBox;
+use super::{Mbr, RTree, RTreeNode, NodeStorage};
+use super::util::overlap_volume;
+
+/// The fraction of `bbox` covered by `query` along one axis.  A degenerate
+/// (zero-width) extent counts as fully covered, since it was already found
+/// to overlap.
+fn axis_fraction(min: f64, max: f64, query_min: f64, query_max: f64) -> f64 {
+    let len = max - min;
+    if len <= 0.0 {
+        return 1.0;
+    }
+    let overlap = max.min(query_max) - min.max(query_min);
+    (overlap / len).clamp(0.0, 1.0)
+}
+
+/// The fraction of `bbox`'s volume which lies inside `query`.
+fn covered_fraction(bbox: &BBox, query: &BBox) -> f64 {
+    axis_fraction(bbox.min.x, bbox.max.x, query.min.x, query.max.x) *
+    axis_fraction(bbox.min.y, bbox.max.y, query.min.y, query.max.y) *
+    axis_fraction(bbox.min.z, bbox.max.z, query.min.z, query.max.z)
+}
+
+fn estimate_node<T>(nodes: &Arena<T, 3>, node: &RTreeNode<T>, query: &BBox) -> f64 where T: Mbr {
+    if !node.bbox.overlaps(query) {
+        return 0.0;
+    }
+    if query.contains(&node.bbox) {
+        return node.deep_len() as f64;
+    }
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            nodes.children(children).map(|c| estimate_node(nodes, c, query)).sum()
+        },
+        NodeStorage::Leaf(ref items) => {
+            items.len() as f64 * covered_fraction(&node.bbox, query)
+        },
+    }
+}
+
+/// An equi-depth histogram over one axis: every bucket holds (roughly) the
+/// same number of values, so narrow buckets indicate dense regions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Bucket boundaries in ascending order.  Bucket `i` spans
+    /// `bounds[i]..=bounds[i + 1]`, so there is one more bound than buckets.
+    pub bounds: Vec<f64>,
+    /// Number of values which fell into each bucket.
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    /// Build a histogram with at most `buckets` buckets from `values`.
+    pub fn equi_depth(mut values: Vec<f64>, buckets: usize) -> Histogram {
+        values.sort_by(|a, b| a.partial_cmp(b).expect("histogram values must not be NaN"));
+
+        let buckets = buckets.min(values.len());
+        let mut bounds = Vec::with_capacity(buckets + 1);
+        let mut counts = Vec::with_capacity(buckets);
+        for i in 0..buckets {
+            let start = i * values.len() / buckets;
+            let end = (i + 1) * values.len() / buckets;
+            bounds.push(values[start]);
+            counts.push(end - start);
+        }
+        if let Some(&last) = values.last() {
+            bounds.push(last);
+        }
+
+        Histogram { bounds, counts }
+    }
+
+    /// Total number of values in the histogram.
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Estimate the fraction of values within `lo..=hi`, assuming values are
+    /// spread uniformly inside each bucket.
+    pub fn fraction_between(&self, lo: f64, hi: f64) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+        let mut matched = 0.0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            let (b_lo, b_hi) = (self.bounds[i], self.bounds[i + 1]);
+            if b_hi < lo || hi < b_lo {
+                continue;
+            }
+            matched += count as f64 * axis_fraction(b_lo, b_hi, lo, hi);
+        }
+        matched / total as f64
+    }
+}
+
+/// A summary of how well a tree is laid out, for catching regressions in
+/// index quality.  Created by `RTree::quality`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QualityReport {
+    /// Total volume shared between sibling nodes, divided by the total
+    /// volume of all non-root nodes.  Zero means siblings never overlap.
+    pub overlap_factor: f64,
+    /// Total volume of the item bounding boxes divided by the total volume of
+    /// the leaves holding them.  Lower values mean more dead space; values
+    /// above one mean items overlap each other.
+    pub coverage_ratio: f64,
+    /// The average number of items per leaf, as a fraction of node capacity.
+    pub average_leaf_fill: f64,
+    /// Number of leaves in the tree.
+    pub leaf_count: usize,
+    /// Number of leaves a perfectly packed tree would need for the same items.
+    pub optimal_leaf_count: usize,
+}
+
+#[derive(Default)]
+struct QualityTotals {
+    sibling_overlap: f64,
+    node_volume: f64,
+    item_volume: f64,
+    leaf_volume: f64,
+    items: usize,
+    leaves: usize,
+}
+
+fn accumulate_quality<T>(nodes: &Arena<T, 3>, node: &RTreeNode<T>, totals: &mut QualityTotals) where T: Mbr {
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            for (i, a) in nodes.children(children).enumerate() {
+                totals.node_volume += a.bbox.volume();
+                for b in nodes.children(&children[i + 1..]) {
+                    totals.sibling_overlap += overlap_volume(&a.bbox, &b.bbox);
+                }
+                accumulate_quality(nodes, a, totals);
+            }
+        },
+        NodeStorage::Leaf(ref items) => {
+            totals.leaves += 1;
+            totals.items += items.len();
+            totals.leaf_volume += node.bbox.volume();
+            totals.item_volume += items.iter().map(|i| i.bbox.volume()).sum::<f64>();
+        },
+    }
+}
+
+/// The shape of a tree, level by level, for tuning node sizes and
+/// comparing split strategies.  Created by `RTree::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeStats {
+    /// Number of levels, as reported by `RTree::depth`.
+    pub height: usize,
+    /// Number of nodes on each level, from the root down to the leaves.
+    pub nodes_per_level: Vec<usize>,
+    /// The fewest items held by any leaf, as a fraction of node capacity.
+    pub min_leaf_fill: f64,
+    /// The most items held by any leaf, as a fraction of node capacity.
+    pub max_leaf_fill: f64,
+    /// The average number of items per leaf, as a fraction of node capacity.
+    pub average_leaf_fill: f64,
+    /// Total volume shared between pairs of sibling nodes, on all levels.
+    pub sibling_overlap: f64,
+    /// The fraction of leaf volume not covered by item boxes.  Overlapping
+    /// items are counted twice, so this underestimates dead space when
+    /// items overlap; it never goes below zero.
+    pub dead_space_ratio: f64,
+}
+
+#[derive(Default)]
+struct StatsTotals {
+    nodes_per_level: Vec<usize>,
+    min_leaf_items: Option<usize>,
+    max_leaf_items: usize,
+    items: usize,
+    leaves: usize,
+    sibling_overlap: f64,
+    item_volume: f64,
+    leaf_volume: f64,
+}
+
+fn accumulate_stats<T>(nodes: &Arena<T, 3>, node: &RTreeNode<T>, level: usize, totals: &mut StatsTotals) where T: Mbr {
+    if totals.nodes_per_level.len() <= level {
+        totals.nodes_per_level.push(0);
+    }
+    totals.nodes_per_level[level] += 1;
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            for (i, a) in nodes.children(children).enumerate() {
+                for b in nodes.children(&children[i + 1..]) {
+                    totals.sibling_overlap += overlap_volume(&a.bbox, &b.bbox);
+                }
+                accumulate_stats(nodes, a, level + 1, totals);
+            }
+        },
+        NodeStorage::Leaf(ref items) => {
+            totals.min_leaf_items = Some(totals.min_leaf_items.map_or(items.len(), |min| min.min(items.len())));
+            totals.max_leaf_items = totals.max_leaf_items.max(items.len());
+            totals.items += items.len();
+            totals.leaves += 1;
+            totals.leaf_volume += node.bbox.volume();
+            totals.item_volume += items.iter().map(|i| i.bbox.volume()).sum::<f64>();
+        },
+    }
+}
+
+fn ratio(numerator: f64, denominator: f64, otherwise: f64) -> f64 {
+    if denominator > 0.0 {
+        numerator / denominator
+    } else {
+        otherwise
+    }
+}
+
+fn collect_centers<T>(nodes: &Arena<T, 3>, node: &RTreeNode<T>, out: &mut [Vec<f64>; 3]) where T: Mbr {
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            for child in nodes.children(children) {
+                collect_centers(nodes, child, out);
+            }
+        },
+        NodeStorage::Leaf(ref items) => {
+            for leaf in items.iter() {
+                let center = leaf.bbox.center();
+                out[0].push(center.x);
+                out[1].push(center.y);
+                out[2].push(center.z);
+            }
+        },
+    }
+}
+
+impl<T> RTree<T> where T: Mbr {
+    /// Estimate how many items `locate_in_envelope(query)` would yield,
+    /// without looking at any items.
+    ///
+    /// Subtrees entirely inside the query contribute their full count, and
+    /// partially covered leaves contribute in proportion to the fraction of
+    /// their volume that the query covers, assuming items are spread
+    /// uniformly within a leaf.  Useful for ordering filters when joining
+    /// several indexes.
+    pub fn estimate_matches(&self, query: &BBox) -> f64 {
+        match self.root_node() {
+            Some(root) => estimate_node(&self.nodes, root, query),
+            None => 0.0,
+        }
+    }
+
+    /// Report overlap, coverage and fill statistics for the tree as built.
+    pub fn quality(&self) -> QualityReport {
+        let mut totals = QualityTotals::default();
+        if let Some(root) = self.root_node() {
+            accumulate_quality(&self.nodes, root, &mut totals);
+        }
+
+        QualityReport {
+            overlap_factor: ratio(totals.sibling_overlap, totals.node_volume, 0.0),
+            coverage_ratio: ratio(totals.item_volume, totals.leaf_volume, 1.0),
+            average_leaf_fill: ratio(
+                totals.items as f64,
+                (totals.leaves * self.config.max_entries()) as f64,
+                0.0),
+            leaf_count: totals.leaves,
+            optimal_leaf_count: totals.items.div_ceil(self.config.max_entries()),
+        }
+    }
+
+    /// Report the tree's height, node counts per level, leaf fill, sibling
+    /// overlap and dead space.  This walks every node, but not the items.
+    pub fn stats(&self) -> TreeStats {
+        let mut totals = StatsTotals::default();
+        if let Some(root) = self.root_node() {
+            accumulate_stats(&self.nodes, root, 0, &mut totals);
+        }
+
+        let capacity = self.config.max_entries() as f64;
+        TreeStats {
+            height: totals.nodes_per_level.len(),
+            min_leaf_fill: totals.min_leaf_items.unwrap_or(0) as f64 / capacity,
+            max_leaf_fill: totals.max_leaf_items as f64 / capacity,
+            average_leaf_fill: ratio(totals.items as f64, totals.leaves as f64 * capacity, 0.0),
+            sibling_overlap: totals.sibling_overlap,
+            dead_space_ratio: (1.0 - ratio(totals.item_volume, totals.leaf_volume, 1.0)).max(0.0),
+            nodes_per_level: totals.nodes_per_level,
+        }
+    }
+
+    /// Compute equi-depth histograms of the item bounding box centers along
+    /// the x, y and z axes, with at most `buckets` buckets each.
+    ///
+    /// This walks every leaf, so callers embedding the tree in a query
+    /// optimizer should compute it once after building and cache it.
+    pub fn center_histograms(&self, buckets: usize) -> [Histogram; 3] {
+        let mut centers = [Vec::new(), Vec::new(), Vec::new()];
+        if let Some(root) = self.root_node() {
+            collect_centers(&self.nodes, root, &mut centers);
+        }
+        let [xs, ys, zs] = centers;
+        [
+            Histogram::equi_depth(xs, buckets),
+            Histogram::equi_depth(ys, buckets),
+            Histogram::equi_depth(zs, buckets),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::super::{RTree, RTreeConfig};
+    use super::super::test_helpers::{Sphere, random_spheres};
+    use super::super::NODE_SIZE;
+    use super::Histogram;
+
+    #[test]
+    fn test_estimate_matches() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let everything = BBox {
+            min: Vec3::xyz(-100.0, -100.0, -100.0),
+            max: Vec3::xyz(100.0, 100.0, 100.0),
+        };
+        assert_eq!(spheres.estimate_matches(&everything), 0.0);
+
+        for i in 0..10 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 0.0, 0.0), 1.0).unwrap());
+        }
+        assert_eq!(spheres.estimate_matches(&everything), 10.0);
+
+        let nothing = BBox {
+            min: Vec3::xyz(0.0, 50.0, 0.0),
+            max: Vec3::xyz(10.0, 60.0, 10.0),
+        };
+        assert_eq!(spheres.estimate_matches(&nothing), 0.0);
+
+        // Covers the lower half of the leaf along x.
+        let half = BBox {
+            min: Vec3::xyz(-1.0, -5.0, -5.0),
+            max: Vec3::xyz(45.0, 5.0, 5.0),
+        };
+        assert!((spheres.estimate_matches(&half) - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_equi_depth_histogram() {
+        let values = vec![0.0, 1.0, 2.0, 3.0, 10.0, 20.0, 30.0, 40.0];
+        let hist = Histogram::equi_depth(values, 2);
+        assert_eq!(hist.bounds, vec![0.0, 10.0, 40.0]);
+        assert_eq!(hist.counts, vec![4, 4]);
+        assert_eq!(hist.fraction_between(0.0, 40.0), 1.0);
+        assert_eq!(hist.fraction_between(0.0, 10.0), 0.5);
+        assert_eq!(hist.fraction_between(50.0, 60.0), 0.0);
+
+        let empty = Histogram::equi_depth(Vec::new(), 4);
+        assert_eq!(empty.total(), 0);
+        assert_eq!(empty.fraction_between(0.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_center_histograms() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        for i in 0..8 {
+            let x = 10.0 * i as f64;
+            spheres.insert(Sphere::new(Vec3::xyz(x, 5.0, 0.0), 1.0).unwrap());
+        }
+
+        let [x, y, _] = spheres.center_histograms(4);
+        assert_eq!(x.bounds, vec![0.0, 20.0, 40.0, 60.0, 70.0]);
+        assert_eq!(x.counts, vec![2, 2, 2, 2]);
+        assert_eq!(y.bounds, vec![5.0, 5.0, 5.0, 5.0, 5.0]);
+        assert_eq!(y.fraction_between(4.0, 6.0), 1.0);
+    }
+
+    #[test]
+    fn test_quality() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let report = spheres.quality();
+        assert_eq!(report.leaf_count, 0);
+        assert_eq!(report.optimal_leaf_count, 0);
+
+        spheres.insert(Sphere::new(Vec3::xyz(0.0, 0.0, 0.0), 1.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(1.0, 0.0, 0.0), 1.0).unwrap());
+
+        // One leaf spanning x = -1..2 holding two 2x2x2 boxes.
+        let report = spheres.quality();
+        assert_eq!(report.overlap_factor, 0.0);
+        assert_eq!(report.coverage_ratio, 16.0 / 12.0);
+        assert_eq!(report.average_leaf_fill, 2.0 / NODE_SIZE as f64);
+        assert_eq!(report.leaf_count, 1);
+        assert_eq!(report.optimal_leaf_count, 1);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut spheres: RTree<Sphere> = RTree::new();
+        let stats = spheres.stats();
+        assert_eq!((stats.height, stats.nodes_per_level.len()), (0, 0));
+        assert_eq!(stats.dead_space_ratio, 0.0);
+
+        // One leaf spanning x = -1..5 holding two 2x2x2 boxes.
+        spheres.insert(Sphere::new(Vec3::xyz(0.0, 0.0, 0.0), 1.0).unwrap());
+        spheres.insert(Sphere::new(Vec3::xyz(4.0, 0.0, 0.0), 1.0).unwrap());
+        let stats = spheres.stats();
+        assert_eq!(stats.height, 1);
+        assert_eq!(stats.nodes_per_level, vec![1]);
+        assert_eq!(stats.min_leaf_fill, 2.0 / NODE_SIZE as f64);
+        assert_eq!(stats.max_leaf_fill, stats.min_leaf_fill);
+        assert_eq!(stats.sibling_overlap, 0.0);
+        assert_eq!(stats.dead_space_ratio, 1.0 - 16.0 / 24.0);
+
+        let spheres = RTree::bulk_load_with_config(random_spheres(5000, 47), RTreeConfig::with_max_entries(16).unwrap());
+        let stats = spheres.stats();
+        assert_eq!(stats.height, spheres.depth());
+        assert_eq!(stats.nodes_per_level[0], 1);
+        assert_eq!(*stats.nodes_per_level.last().unwrap(), spheres.quality().leaf_count);
+        assert!(stats.min_leaf_fill <= stats.average_leaf_fill && stats.average_leaf_fill <= stats.max_leaf_fill);
+        assert_eq!(stats.max_leaf_fill, 1.0);
+        assert!(stats.sibling_overlap > 0.0);
+        assert!(0.0 < stats.dead_space_ratio && stats.dead_space_ratio < 1.0);
+    }
+}
@@ -1,11 +1,13 @@
-use std::cmp::{min, max};
-
-use super::{BBox, Object, Mbr, Intersection};
-use ::vec3::Vec3;
+use super::{BBox, ClosestPoint, Intersectable, Intersection, Mbr, RTree, RTreeConfig, RTreeNode, NodeStorage, LeafItem};
+use super::util;
+use ::arena::Arena;
 use ::ray::Ray;
+use ::vec3::Vec3;
 
-const SPHERE_RADIUS_TOO_SMALL: &'static str = "sphere radius must be above zero";
+const SPHERE_RADIUS_TOO_SMALL: &str = "sphere radius must be above zero";
 
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Sphere {
     origin: Vec3,
     radius: f64,
@@ -17,10 +19,42 @@ impl Sphere {
             return Err(SPHERE_RADIUS_TOO_SMALL);
         }
         Ok(Sphere {
-            origin: origin,
-            radius: radius,
+            origin,
+            radius,
         })
     }
+
+    pub fn translate(&mut self, offset: Vec3) {
+        self.origin = self.origin + offset;
+    }
+}
+
+/// A small deterministic generator, so tests are reproducible without
+/// pulling in a random number crate.
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Lcg {
+        Lcg(seed)
+    }
+
+    /// A float in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// `count` spheres scattered through a 1000-unit cube, with radii up to 5.
+pub fn random_spheres(count: usize, seed: u64) -> Vec<Sphere> {
+    let mut rng = Lcg::new(seed);
+    (0..count).map(|_| {
+        let origin = Vec3::xyz(
+            rng.next_f64() * 1000.0,
+            rng.next_f64() * 1000.0,
+            rng.next_f64() * 1000.0);
+        Sphere::new(origin, 0.5 + rng.next_f64() * 4.5).unwrap()
+    }).collect()
 }
 
 impl Mbr for Sphere {
@@ -31,3 +65,95 @@ impl Mbr for Sphere {
         }
     }
 }
+
+impl ClosestPoint for Sphere {
+    fn closest_point(&self, point: &Vec3) -> Vec3 {
+        self.origin + (*point - self.origin).unit().scale(self.radius)
+    }
+}
+
+impl Intersectable for Sphere {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let oc = ray.origin - self.origin;
+        let a = ray.direction.dot(&ray.direction);
+        let b = oc.dot(&ray.direction);
+        let c = oc.dot(&oc) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let t = if 0.0 < (-b - root) / a { (-b - root) / a } else { (-b + root) / a };
+        if t <= 0.0 {
+            return None;
+        }
+        let point = ray.point_at(t);
+        Some(Intersection {
+            t,
+            point,
+            normal: (point - self.origin).scale(1.0 / self.radius),
+        })
+    }
+}
+
+/// Check that every node's box is exactly the union of its children and
+/// its cached item count is right, that no node is overfull, that non-root nodes are not underfull, and that all
+/// leaves sit at the same depth.  Returns the depth of the leaves.
+fn check_node<T: Mbr<N>, const N: usize>(nodes: &Arena<T, N>, node: &RTreeNode<T, N>, config: &RTreeConfig, is_root: bool) -> usize {
+    let len = node.shallow_len();
+    assert!(len <= config.max_entries(), "node holds {} entries", len);
+    assert!(is_root || len >= config.min_entries(), "node holds {} entries", len);
+    let tight = match node.storage {
+        NodeStorage::Interior(ref children) => util::union_all(nodes.children(children).map(|c| c.bbox)),
+        NodeStorage::Leaf(ref items) => util::union_all(items.iter().map(|i| i.item.mbr())),
+    }.unwrap();
+    assert!(node.bbox.min == tight.min && node.bbox.max == tight.max, "bbox is not tight");
+    assert_eq!(node.count, nodes.deep_len(&node.storage), "stale item count");
+
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            let depths: Vec<usize> = nodes.children(children).map(|child| {
+                check_node(nodes, child, config, false)
+            }).collect();
+            assert!(depths.iter().all(|&d| d == depths[0]), "unbalanced: {:?}", depths);
+            depths[0] + 1
+        },
+        NodeStorage::Leaf(_) => 0,
+    }
+}
+
+/// Run `check_node` over the whole tree, and check that the id index
+/// matches the stored items and that every node in use is reachable from
+/// the root.  Returns the depth of its leaves (zero for an empty tree).
+pub fn check_tree<T: Mbr<N>, const N: usize>(tree: &RTree<T, N>) -> usize {
+    let mut leaves = Vec::new();
+    let mut reachable = 0;
+    let depth = match tree.root_node() {
+        Some(root) => {
+            reachable = collect_leaves(&tree.nodes, root, &mut leaves);
+            check_node(&tree.nodes, root, &tree.config, true)
+        },
+        None => 0,
+    };
+    assert_eq!(tree.nodes.len(), reachable, "arena holds unreachable nodes");
+    assert_eq!(tree.ids.len(), leaves.len(), "id index is out of sync");
+    for leaf in leaves {
+        let bbox = tree.ids[&leaf.id];
+        assert!(bbox.min == leaf.bbox.min && bbox.max == leaf.bbox.max, "stale id index entry");
+    }
+    depth
+}
+
+/// Collect the items below `node`, returning the number of nodes visited.
+fn collect_leaves<'a, T: Mbr<N>, const N: usize>(nodes: &'a Arena<T, N>, node: &'a RTreeNode<T, N>, out: &mut Vec<&'a LeafItem<T, N>>) -> usize {
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            1 + nodes.children(children).map(|child| collect_leaves(nodes, child, out)).sum::<usize>()
+        },
+        NodeStorage::Leaf(ref items) => {
+            out.extend(items.iter());
+            1
+        },
+    }
+}
@@ -0,0 +1,164 @@
+use std::ops::Mul;
+
+use vec3::Vec3;
+
+/// An affine transform: rotation, scale, shear and translation.  Stored as
+/// the top three rows of a row-major 4×4 matrix, whose bottom row is always
+/// `0 0 0 1`.
+///
+/// `a * b` applies `b` first, then `a`, as with matrices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Transform {
+    rows: [[f64; 4]; 3],
+}
+
+impl Transform {
+    /// The transform from the top three rows of a 4×4 matrix.
+    pub fn from_rows(rows: [[f64; 4]; 3]) -> Transform {
+        Transform { rows }
+    }
+
+    pub fn rows(&self) -> &[[f64; 4]; 3] {
+        &self.rows
+    }
+
+    pub fn identity() -> Transform {
+        Transform::scale(Vec3::one())
+    }
+
+    pub fn translation(offset: Vec3) -> Transform {
+        Transform::from_rows([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+        ])
+    }
+
+    pub fn scale(factors: Vec3) -> Transform {
+        Transform::from_rows([
+            [factors.x, 0.0, 0.0, 0.0],
+            [0.0, factors.y, 0.0, 0.0],
+            [0.0, 0.0, factors.z, 0.0],
+        ])
+    }
+
+    /// A rotation by `angle` radians about `axis`, counter-clockwise when
+    /// looking down the axis towards the origin.
+    pub fn rotation(axis: Vec3, angle: f64) -> Transform {
+        let [x, y, z] = axis.unit().coords;
+        let (sin, cos) = angle.sin_cos();
+        let t = 1.0 - cos;
+        Transform::from_rows([
+            [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y, 0.0],
+            [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x, 0.0],
+            [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos, 0.0],
+        ])
+    }
+
+    /// The transform which undoes this one, or `None` if it collapses
+    /// space onto a plane, line or point.
+    pub fn inverse(&self) -> Option<Transform> {
+        let m = &self.rows;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+        // The adjugate of the upper 3×3 block, transposed as we go.
+        let adj = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let det = m[0][0] * adj[0][0] + m[0][1] * adj[1][0] + m[0][2] * adj[2][0];
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+
+        let mut rows = [[0.0; 4]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                rows[i][j] = adj[i][j] / det;
+            }
+            rows[i][3] = -(rows[i][0] * m[0][3] + rows[i][1] * m[1][3] + rows[i][2] * m[2][3]);
+        }
+        Some(Transform { rows })
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        self.transform_vector(p) + Vec3::xyz(self.rows[0][3], self.rows[1][3], self.rows[2][3])
+    }
+
+    /// Transform a direction or offset, which ignores translation.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let row = |r: &[f64; 4]| r[0] * v.x + r[1] * v.y + r[2] * v.z;
+        Vec3::xyz(row(&self.rows[0]), row(&self.rows[1]), row(&self.rows[2]))
+    }
+
+    /// Transform a direction by the transpose of this transform.  Surface
+    /// normals are carried by the transpose of the inverse, so call this on
+    /// the inverse of the transform applied to the geometry.
+    pub fn transform_vector_transposed(&self, v: &Vec3) -> Vec3 {
+        let m = &self.rows;
+        Vec3::xyz(
+            m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+            m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+            m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        let (a, b) = (&self.rows, &other.rows);
+        let mut rows = [[0.0; 4]; 3];
+        for i in 0..3 {
+            for j in 0..4 {
+                rows[i][j] = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+            }
+            rows[i][3] += a[i][3];
+        }
+        Transform { rows }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use ::vec3::Vec3;
+    use super::Transform;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).len() < 1e-12, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_transform() {
+        let p = Vec3::xyz(1.0, 2.0, 3.0);
+        assert_eq!(Transform::identity().transform_point(&p), p);
+        assert_eq!(Transform::translation(Vec3::one()).transform_point(&p), Vec3::xyz(2.0, 3.0, 4.0));
+        assert_eq!(Transform::translation(Vec3::one()).transform_vector(&p), p);
+
+        let quarter = Transform::rotation(Vec3::xyz(0.0, 0.0, 2.0), FRAC_PI_2);
+        assert_near(quarter.transform_point(&Vec3::xyz(1.0, 0.0, 0.0)), Vec3::xyz(0.0, 1.0, 0.0));
+
+        // Scale, then rotate, then move.
+        let m = Transform::translation(Vec3::xyz(10.0, 0.0, 0.0)) * quarter * Transform::scale(Vec3::xyz(2.0, 3.0, 4.0));
+        assert_near(m.transform_point(&Vec3::xyz(1.0, 1.0, 1.0)), Vec3::xyz(7.0, 2.0, 4.0));
+
+        let inverse = m.inverse().unwrap();
+        assert_near(inverse.transform_point(&m.transform_point(&p)), p);
+        let identity = (m * inverse).rows;
+        for (i, row) in identity.iter().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                assert!((value - if i == j { 1.0 } else { 0.0 }).abs() < 1e-12);
+            }
+        }
+        assert_eq!(Transform::scale(Vec3::xyz(1.0, 0.0, 1.0)).inverse(), None);
+    }
+}
@@ -0,0 +1,162 @@
+use std::error::Error;
+use std::fmt;
+
+use arena::{Arena, NodeId};
+use super::{ItemId, Mbr, RTree, RTreeConfig, NodeStorage};
+
+/// A broken tree invariant, found by `RTree::validate`.  Depths count down
+/// from the root at zero.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// A node's bounding box does not contain one of its entries' boxes,
+    /// or contains NaN.
+    BBoxNotContained { depth: usize },
+    /// An item's `mbr()` no longer matches the box it was stored with,
+    /// e.g. because it was moved in place or its `mbr()` returns NaN.
+    StaleItemBBox { id: ItemId },
+    /// A node holds more entries than the configured maximum.
+    Overfull { depth: usize, len: usize },
+    /// A node other than the root holds fewer entries than the configured
+    /// minimum.
+    Underfull { depth: usize, len: usize },
+    /// Leaves sit at different depths.
+    Unbalanced { depth: usize },
+    /// A node's cached item count is wrong.
+    StaleCount { depth: usize },
+    /// The id index does not match the items stored in the leaves.
+    StaleIndex,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::BBoxNotContained { depth } => write!(f, "node at depth {} does not contain its entries", depth),
+            ValidationError::StaleItemBBox { id } => write!(f, "item {:?} no longer matches its stored bounding box", id),
+            ValidationError::Overfull { depth, len } => write!(f, "node at depth {} holds {} entries", depth, len),
+            ValidationError::Underfull { depth, len } => write!(f, "node at depth {} holds only {} entries", depth, len),
+            ValidationError::Unbalanced { depth } => write!(f, "leaf at depth {} is not at the same depth as the others", depth),
+            ValidationError::StaleCount { depth } => write!(f, "node at depth {} has a stale item count", depth),
+            ValidationError::StaleIndex => write!(f, "id index does not match the stored items"),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    /// Check the tree's structure: every node's box contains its entries,
+    /// stored item boxes match `mbr()`, nodes respect the configured fill,
+    /// and all leaves sit at the same depth.  This walks the whole tree and
+    /// calls `mbr()` on every item, so it is meant for debugging, e.g. an
+    /// `Mbr` implementation which returns NaN or changes under the tree.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut leaf_depth = None;
+        let mut items = 0;
+        if let Some(root) = self.root {
+            validate_node(&self.nodes, root, &self.config, 0, &mut leaf_depth, &mut items)?;
+        }
+        if items != self.ids.len() {
+            return Err(ValidationError::StaleIndex);
+        }
+        Ok(())
+    }
+}
+
+fn validate_node<T, const N: usize>(nodes: &Arena<T, N>, node: NodeId, config: &RTreeConfig, depth: usize,
+                    leaf_depth: &mut Option<usize>, items: &mut usize) -> Result<(), ValidationError>
+    where
+        T: Mbr<N> {
+
+    let node = &nodes[node];
+    let len = node.shallow_len();
+    if len > config.max_entries() {
+        return Err(ValidationError::Overfull { depth, len });
+    }
+    if depth > 0 && len < config.min_entries() {
+        return Err(ValidationError::Underfull { depth, len });
+    }
+    if node.count != nodes.deep_len(&node.storage) {
+        return Err(ValidationError::StaleCount { depth });
+    }
+
+    match node.storage {
+        NodeStorage::Interior(ref children) => {
+            for &child in children.iter() {
+                if !node.bbox.contains(&nodes[child].bbox) {
+                    return Err(ValidationError::BBoxNotContained { depth });
+                }
+                validate_node(nodes, child, config, depth + 1, leaf_depth, items)?;
+            }
+        },
+        NodeStorage::Leaf(ref leaves) => {
+            if *leaf_depth.get_or_insert(depth) != depth {
+                return Err(ValidationError::Unbalanced { depth });
+            }
+            for leaf in leaves.iter() {
+                let mbr = leaf.item.mbr();
+                if !(mbr.min == leaf.bbox.min && mbr.max == leaf.bbox.max) {
+                    return Err(ValidationError::StaleItemBBox { id: ItemId(leaf.id) });
+                }
+                if !node.bbox.contains(&leaf.bbox) {
+                    return Err(ValidationError::BBoxNotContained { depth });
+                }
+            }
+            *items += leaves.len();
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use ::bbox::BBox;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree, RTreeConfig};
+    use super::super::test_helpers::{Sphere, random_spheres};
+    use super::ValidationError;
+
+    struct Movable(Cell<f64>);
+
+    impl Mbr for Movable {
+        fn mbr(&self) -> BBox {
+            let at = Vec3::xyz(self.0.get(), 0.0, 0.0);
+            BBox { min: at, max: at }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut spheres: RTree<Sphere> = RTree::with_config(RTreeConfig::with_max_entries(8).unwrap());
+        assert_eq!(spheres.validate(), Ok(()));
+        for sphere in random_spheres(500, 53) {
+            spheres.insert(sphere);
+        }
+        assert_eq!(spheres.validate(), Ok(()));
+
+        // Shrink the root so it no longer covers its children.
+        let root = spheres.root.unwrap();
+        let bbox = spheres.nodes[root].bbox;
+        spheres.nodes[root].bbox.max = bbox.min;
+        assert_eq!(spheres.validate(), Err(ValidationError::BBoxNotContained { depth: 0 }));
+        spheres.nodes[root].bbox = bbox;
+
+        // Miscount a child, which the root's count no longer adds up to.
+        let child = spheres.nodes.child_ids(root)[0];
+        spheres.nodes[child].count += 1;
+        assert_eq!(spheres.validate(), Err(ValidationError::StaleCount { depth: 0 }));
+    }
+
+    #[test]
+    fn test_validate_items() {
+        let mut tree = RTree::new();
+        let ids: Vec<_> = (0..10).map(|i| tree.insert(Movable(Cell::new(i as f64)))).collect();
+        assert_eq!(tree.validate(), Ok(()));
+
+        tree.get(ids[3]).unwrap().0.set(f64::NAN);
+        let err = tree.validate().unwrap_err();
+        assert_eq!(err, ValidationError::StaleItemBBox { id: ids[3] });
+        assert!(err.to_string().contains("no longer matches"));
+    }
+}
@@ -0,0 +1,126 @@
+use std::ops::ControlFlow;
+
+use aabb::Aabb;
+use arena::{Arena, NodeId};
+use bbox::BBox;
+use ray::Ray;
+use super::{Mbr, RTree, NodeStorage};
+
+impl<T> RTree<T> where T: Mbr {
+    /// Call `f` on every item whose bounding box intersects `ray`, in no
+    /// particular order, until it returns `ControlFlow::Break`.  Returns
+    /// `Break` if `f` did.
+    ///
+    /// The tree is walked recursively rather than through an iterator's
+    /// explicit stack, which is cheaper in tight loops such as shadow ray
+    /// tests that stop at the first blocker.
+    pub fn visit_ray<'a, F>(&'a self, ray: &Ray, mut f: F) -> ControlFlow<()>
+        where
+            F: FnMut(&'a T) -> ControlFlow<()> {
+
+        self.visit(&|bbox: &BBox| bbox.intersects(ray), &mut f)
+    }
+}
+
+impl<T, const N: usize> RTree<T, N> where T: Mbr<N> {
+    /// Call `f` on every item whose bounding box overlaps `query`, like
+    /// `visit_ray`.
+    pub fn visit_bbox<'a, F>(&'a self, query: &Aabb<N>, mut f: F) -> ControlFlow<()>
+        where
+            F: FnMut(&'a T) -> ControlFlow<()> {
+
+        self.visit(&|bbox: &Aabb<N>| bbox.overlaps(query), &mut f)
+    }
+
+    fn visit<'a, P, F>(&'a self, matches: &P, f: &mut F) -> ControlFlow<()>
+        where
+            P: Fn(&Aabb<N>) -> bool,
+            F: FnMut(&'a T) -> ControlFlow<()> {
+
+        match self.root {
+            Some(root) if matches(&self.nodes[root].bbox) => self.nodes.visit(root, matches, f),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+impl<T, const N: usize> Arena<T, N> where T: Mbr<N> {
+    fn visit<'a, P, F>(&'a self, node: NodeId, matches: &P, f: &mut F) -> ControlFlow<()>
+        where
+            P: Fn(&Aabb<N>) -> bool,
+            F: FnMut(&'a T) -> ControlFlow<()> {
+
+        match self[node].storage {
+            NodeStorage::Interior(ref children) => {
+                for &child in children.iter() {
+                    if matches(&self[child].bbox) {
+                        self.visit(child, matches, f)?;
+                    }
+                }
+            },
+            NodeStorage::Leaf(ref items) => {
+                for leaf in items.iter() {
+                    if matches(&leaf.bbox) {
+                        f(&leaf.item)?;
+                    }
+                }
+            },
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::ControlFlow;
+
+    use ::bbox::BBox;
+    use ::ray::Ray;
+    use ::vec3::Vec3;
+    use super::super::{Mbr, RTree};
+    use super::super::test_helpers::{Sphere, random_spheres};
+
+    #[test]
+    fn test_visitors_match_iterators() {
+        let spheres = random_spheres(3000, 37);
+        // Aim through one sphere's centre so the ray has candidates.
+        let ray = Ray::new(Vec3::zero(), spheres[0].mbr().lerp(0.5, 0.5, 0.5));
+        let tree: RTree<Sphere> = RTree::bulk_load(spheres);
+        let query = BBox { min: Vec3::xyz(100.0, 200.0, 300.0), max: Vec3::xyz(400.0, 500.0, 600.0) };
+
+        let mut on_ray = vec![];
+        assert_eq!(tree.visit_ray(&ray, |s| { on_ray.push(s); ControlFlow::Continue(()) }), ControlFlow::Continue(()));
+        assert!(!on_ray.is_empty());
+        assert_eq!(on_ray.len(), tree.iter_ray(&ray).count());
+
+        let mut in_box = 0;
+        let _ = tree.visit_bbox(&query, |s| {
+            assert!(s.mbr().overlaps(&query));
+            in_box += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(in_box, tree.iter_bbox(&query).count());
+
+        let mut nearest = vec![];
+        let _ = tree.visit_nearest(Vec3::xyz(500.0, 500.0, 500.0), |s| { nearest.push(s); ControlFlow::Continue(()) });
+        assert_eq!(nearest.len(), 3000);
+        assert_eq!(&nearest[..20], &tree.nearest_n(Vec3::xyz(500.0, 500.0, 500.0), 20)[..]);
+    }
+
+    #[test]
+    fn test_visitors_stop_early() {
+        let tree: RTree<Sphere> = RTree::bulk_load(random_spheres(3000, 41));
+        let everything = BBox { min: Vec3::zero(), max: Vec3::one() * 1000.0 };
+        let mut visited = 0;
+        let flow = tree.visit_bbox(&everything, |_| {
+            visited += 1;
+            if visited == 5 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        assert_eq!((flow, visited), (ControlFlow::Break(()), 5));
+
+        let mut visited = 0;
+        let flow = tree.visit_nearest(Vec3::zero(), |_| { visited += 1; ControlFlow::Break(()) });
+        assert_eq!((flow, visited), (ControlFlow::Break(()), 1));
+        assert_eq!(RTree::<Sphere>::new().visit_ray(&Ray::new(Vec3::zero(), Vec3::one()), |_| ControlFlow::Break(())), ControlFlow::Continue(()));
+    }
+}